@@ -1,9 +1,12 @@
 mod storage;
-pub use storage::StorageConfig;
+pub use storage::{MemTableRepKind, StorageConfig, WriteStallConfig};
 
 mod server;
 pub use server::ServerConfig;
 
+mod wasm;
+pub use wasm::{WasmConfig, WasmModuleConfig};
+
 use serde::Deserialize;
 use std::env;
 use std::path::PathBuf;
@@ -29,6 +32,10 @@ pub enum ConfigError {
     /// Error in storage configuration validation.
     #[error(transparent)]
     Storage(#[from] storage::StorageConfigError),
+
+    /// Error in WebAssembly configuration validation.
+    #[error(transparent)]
+    Wasm(#[from] wasm::WasmConfigError),
 }
 
 /// The global configuration for the BoxKV server.
@@ -44,6 +51,10 @@ pub struct Config {
     /// Configuration for the network server.
     #[serde(default)]
     pub server: ServerConfig,
+
+    /// Configuration for the WebAssembly plugin runtime.
+    #[serde(default)]
+    pub wasm: WasmConfig,
 }
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
@@ -119,6 +130,7 @@ impl Config {
     fn validate(&self) -> Result<(), ConfigError> {
         self.storage.validate()?;
         self.server.validate()?;
+        self.wasm.validate()?;
         Ok(())
     }
 
@@ -1,5 +1,7 @@
+use crate::env::{FileSystem, PosixFileSystem};
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tracing::info;
 
@@ -17,10 +19,69 @@ pub enum StorageConfigError {
         #[source]
         error: std::io::Error,
     },
+
+    /// The global write buffer can't hold even a single MemTable.
+    #[error(
+        "Invalid write buffer size: {size} MB, must be 0 or at least the memtable size ({memtable_size} MB)"
+    )]
+    InvalidWriteBufferSize { size: usize, memtable_size: usize },
+
+    /// A write stall soft limit is above its hard limit.
+    #[error("Invalid write stall limits for {name}: soft limit {soft} above hard limit {hard}")]
+    InvalidStallLimits {
+        name: &'static str,
+        soft: u64,
+        hard: u64,
+    },
+
+    /// The hash-prefix MemTable needs a prefix of at least one byte.
+    #[error("Invalid memtable prefix length: {len}, must be at least 1")]
+    InvalidMemtablePrefixLen { len: usize },
+
+    /// The blob garbage ratio is not a fraction in `(0, 1]`.
+    #[error("Invalid blob GC garbage ratio: {ratio}, must be above 0 and at most 1")]
+    InvalidBlobGcRatio { ratio: f64 },
+}
+
+/// In-memory data structure backing a MemTable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemTableRepKind {
+    /// Ordered `BTreeMap` behind a read-write lock.
+    #[default]
+    #[serde(rename = "btree")]
+    BTree,
+    /// Lock-free skiplist, suited to many concurrent writers.
+    #[serde(rename = "skiplist")]
+    SkipList,
+    /// Hash table indexed by key prefix, suited to point lookups.
+    HashPrefix,
+}
+
+impl MemTableRepKind {
+    /// Returns the name used in the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            MemTableRepKind::BTree => "btree",
+            MemTableRepKind::SkipList => "skiplist",
+            MemTableRepKind::HashPrefix => "hash_prefix",
+        }
+    }
+
+    /// Parses a name returned by `name()`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            MemTableRepKind::BTree,
+            MemTableRepKind::SkipList,
+            MemTableRepKind::HashPrefix,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
 }
 
 /// Configuration for the storage engine.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StorageConfig {
     /// The path to the directory where data files will be stored.
     /// Defaults to "./data".
@@ -32,12 +93,165 @@ pub struct StorageConfig {
     /// Defaults to 4 MB.
     #[serde(default = "default_memtable_size")]
     pub memtable_size_mb: usize,
+
+    /// Global memory budget in megabytes for all MemTables (active and immutable).
+    /// Close to the budget the largest MemTables are flushed early; writes stall
+    /// while it is exceeded.
+    /// 0 disables the global limit. Otherwise must be at least `memtable_size_mb`.
+    /// Defaults to 0.
+    #[serde(default)]
+    pub write_buffer_size_mb: usize,
+
+    /// The data structure backing each MemTable, unless its column family
+    /// selects another one.
+    /// One of "btree", "skiplist" or "hash_prefix".
+    /// Defaults to "btree".
+    #[serde(default)]
+    pub memtable_rep: MemTableRepKind,
+
+    /// Length of the key prefix used to bucket keys in the "hash_prefix" MemTable.
+    /// Must be at least 1.
+    /// Defaults to 8.
+    #[serde(default = "default_memtable_prefix_len")]
+    pub memtable_prefix_len: usize,
+
+    /// Limits at which writes are slowed down or stopped when flushes or
+    /// compactions fall behind.
+    #[serde(default)]
+    pub write_stall: WriteStallConfig,
+
+    /// Directory where obsolete WAL files are moved instead of being deleted,
+    /// enabling point-in-time recovery.
+    /// Archiving is disabled when unset.
+    #[serde(default)]
+    pub wal_archive_dir: Option<PathBuf>,
+
+    /// Maximum age of archived WAL files in seconds.
+    /// 0 keeps archived files regardless of age.
+    /// Defaults to 0.
+    #[serde(default)]
+    pub wal_archive_ttl_secs: u64,
+
+    /// Maximum total size of archived WAL files in megabytes.
+    /// The oldest files are removed first once the limit is exceeded.
+    /// 0 means unlimited.
+    /// Defaults to 0.
+    #[serde(default)]
+    pub wal_archive_size_limit_mb: u64,
+
+    /// How long a pessimistic transaction waits for a key lock, in milliseconds.
+    /// 0 fails immediately when the lock is held.
+    /// Defaults to 1000.
+    #[serde(default = "default_lock_timeout")]
+    pub lock_timeout_ms: u64,
+
+    /// Values of at least this many bytes are written once to blob files,
+    /// the LSM tree only storing their location.
+    /// 0 keeps every value in the LSM tree.
+    /// Defaults to 0.
+    #[serde(default)]
+    pub min_blob_size: usize,
+
+    /// Size in megabytes past which a new blob file is started.
+    /// Defaults to 256.
+    #[serde(default = "default_blob_file_size")]
+    pub blob_file_size_mb: u64,
+
+    /// Fraction of dead bytes past which blob GC rewrites a blob file.
+    /// Must be above 0 and at most 1.
+    /// Defaults to 0.5.
+    #[serde(default = "default_blob_gc_garbage_ratio")]
+    pub blob_gc_garbage_ratio: f64,
+}
+
+/// Backpressure applied to writers when background work falls behind.
+///
+/// Each pressure source has a soft limit, past which every write is delayed
+/// (longer the closer it gets to the hard limit), and a hard limit, past which
+/// writes block until the pressure drops, or fail once `stop_timeout_ms` expires.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WriteStallConfig {
+    /// Immutable MemTables waiting to be flushed before writes are delayed.
+    /// Defaults to 3.
+    pub immutable_memtables_slowdown: u64,
+    /// Immutable MemTables waiting to be flushed before writes stop.
+    /// Defaults to 5.
+    pub immutable_memtables_stop: u64,
+
+    /// SSTables in level 0 before writes are delayed.
+    /// Defaults to 20.
+    pub l0_files_slowdown: u64,
+    /// SSTables in level 0 before writes stop.
+    /// Defaults to 36.
+    pub l0_files_stop: u64,
+
+    /// Bytes compaction is behind on (in MB) before writes are delayed.
+    /// Defaults to 65536 (64 GB).
+    pub pending_compaction_slowdown_mb: u64,
+    /// Bytes compaction is behind on (in MB) before writes stop.
+    /// Defaults to 262144 (256 GB).
+    pub pending_compaction_stop_mb: u64,
+
+    /// Delay applied to each write when a hard limit is about to be reached, in microseconds.
+    /// Defaults to 1000.
+    pub max_delay_us: u64,
+
+    /// How long a write waits past a hard limit before failing, in milliseconds.
+    /// 0 fails immediately.
+    /// Defaults to 10000.
+    pub stop_timeout_ms: u64,
+}
+
+impl Default for WriteStallConfig {
+    fn default() -> Self {
+        Self {
+            immutable_memtables_slowdown: 3,
+            immutable_memtables_stop: 5,
+            l0_files_slowdown: 20,
+            l0_files_stop: 36,
+            pending_compaction_slowdown_mb: 64 * 1024,
+            pending_compaction_stop_mb: 256 * 1024,
+            max_delay_us: 1000,
+            stop_timeout_ms: 10_000,
+        }
+    }
+}
+
+impl WriteStallConfig {
+    fn validate(&self) -> Result<(), StorageConfigError> {
+        let limits = [
+            (
+                "immutable memtables",
+                self.immutable_memtables_slowdown,
+                self.immutable_memtables_stop,
+            ),
+            ("L0 files", self.l0_files_slowdown, self.l0_files_stop),
+            (
+                "pending compaction",
+                self.pending_compaction_slowdown_mb,
+                self.pending_compaction_stop_mb,
+            ),
+        ];
+
+        for (name, soft, hard) in limits {
+            if soft > hard {
+                return Err(StorageConfigError::InvalidStallLimits { name, soft, hard });
+            }
+        }
+
+        Ok(())
+    }
 }
 
 const DEFAULT_DATA_DIR: &str = "./data";
 const DEFAULT_MEMTABLE_SIZE_MB: usize = 4;
 const MIN_MEMTABLE_SIZE_MB: usize = 1;
 const MAX_MEMTABLE_SIZE_MB: usize = 1024;
+const DEFAULT_MEMTABLE_PREFIX_LEN: usize = 8;
+const DEFAULT_LOCK_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_BLOB_FILE_SIZE_MB: u64 = 256;
+const DEFAULT_BLOB_GC_GARBAGE_RATIO: f64 = 0.5;
 
 fn default_data_dir() -> PathBuf {
     PathBuf::from(DEFAULT_DATA_DIR)
@@ -45,12 +259,35 @@ fn default_data_dir() -> PathBuf {
 fn default_memtable_size() -> usize {
     DEFAULT_MEMTABLE_SIZE_MB
 }
+fn default_memtable_prefix_len() -> usize {
+    DEFAULT_MEMTABLE_PREFIX_LEN
+}
+fn default_lock_timeout() -> u64 {
+    DEFAULT_LOCK_TIMEOUT_MS
+}
+fn default_blob_file_size() -> u64 {
+    DEFAULT_BLOB_FILE_SIZE_MB
+}
+fn default_blob_gc_garbage_ratio() -> f64 {
+    DEFAULT_BLOB_GC_GARBAGE_RATIO
+}
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
             data_dir: default_data_dir(),
             memtable_size_mb: default_memtable_size(),
+            write_buffer_size_mb: 0,
+            memtable_rep: MemTableRepKind::default(),
+            memtable_prefix_len: default_memtable_prefix_len(),
+            write_stall: WriteStallConfig::default(),
+            wal_archive_dir: None,
+            wal_archive_ttl_secs: 0,
+            wal_archive_size_limit_mb: 0,
+            lock_timeout_ms: default_lock_timeout(),
+            min_blob_size: 0,
+            blob_file_size_mb: default_blob_file_size(),
+            blob_gc_garbage_ratio: default_blob_gc_garbage_ratio(),
         }
     }
 }
@@ -60,10 +297,24 @@ impl StorageConfig {
     ///
     /// Checks:
     /// 1. `memtable_size_mb` is within the valid range (1-1024).
-    /// 2. `data_dir` is writable (creates the directory if it doesn't exist).
+    /// 2. `write_buffer_size_mb` is 0 or at least `memtable_size_mb`.
+    /// 3. `memtable_prefix_len` is at least 1 when the hash-prefix MemTable is used.
+    /// 4. Every write stall soft limit is at most its hard limit.
+    /// 5. `blob_gc_garbage_ratio` is in `(0, 1]`.
+    /// 6. `data_dir` is writable (creates the directory if it doesn't exist).
+    /// 7. `wal_archive_dir`, if set, is writable.
+    ///
+    /// Directories are checked on the local disk (`PosixFileSystem`).
     pub(crate) fn validate(&self) -> Result<(), StorageConfigError> {
         self.check_memtable_size()?;
-        self.check_data_dir()?;
+        self.check_write_buffer_size()?;
+        self.check_memtable_prefix_len()?;
+        self.write_stall.validate()?;
+        self.check_blob_gc_garbage_ratio()?;
+        Self::check_dir_writable(&PosixFileSystem, &self.data_dir)?;
+        if let Some(archive_dir) = &self.wal_archive_dir {
+            Self::check_dir_writable(&PosixFileSystem, archive_dir)?;
+        }
 
         Ok(())
     }
@@ -78,25 +329,52 @@ impl StorageConfig {
         }
     }
 
-    fn check_data_dir(&self) -> Result<(), StorageConfigError> {
-        if !self.data_dir.exists() {
-            info!(?self.data_dir, "Creating data directory");
-            std::fs::create_dir_all(&self.data_dir).map_err(|e| {
-                StorageConfigError::DirNotWritable {
-                    path: self.data_dir.clone(),
-                    error: e,
-                }
-            })?;
+    fn check_write_buffer_size(&self) -> Result<(), StorageConfigError> {
+        if self.write_buffer_size_mb != 0 && self.write_buffer_size_mb < self.memtable_size_mb {
+            return Err(StorageConfigError::InvalidWriteBufferSize {
+                size: self.write_buffer_size_mb,
+                memtable_size: self.memtable_size_mb,
+            });
         }
 
-        let test_file = self.data_dir.join(".write_test");
-        std::fs::write(&test_file, b"test").map_err(|error| {
-            StorageConfigError::DirNotWritable {
-                path: self.data_dir.clone(),
-                error,
-            }
-        })?;
-        std::fs::remove_file(test_file).ok();
+        Ok(())
+    }
+
+    fn check_memtable_prefix_len(&self) -> Result<(), StorageConfigError> {
+        if self.memtable_rep == MemTableRepKind::HashPrefix && self.memtable_prefix_len == 0 {
+            return Err(StorageConfigError::InvalidMemtablePrefixLen {
+                len: self.memtable_prefix_len,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn check_blob_gc_garbage_ratio(&self) -> Result<(), StorageConfigError> {
+        let ratio = self.blob_gc_garbage_ratio;
+        if ratio <= 0.0 || ratio > 1.0 || ratio.is_nan() {
+            return Err(StorageConfigError::InvalidBlobGcRatio { ratio });
+        }
+
+        Ok(())
+    }
+
+    fn check_dir_writable(fs: &dyn FileSystem, dir: &Path) -> Result<(), StorageConfigError> {
+        let not_writable = |error| StorageConfigError::DirNotWritable {
+            path: dir.to_path_buf(),
+            error,
+        };
+
+        if !fs.exists(dir) {
+            info!(?dir, "Creating data directory");
+            fs.create_dir_all(dir).map_err(not_writable)?;
+        }
+
+        let test_file = dir.join(".write_test");
+        fs.create(&test_file)
+            .and_then(|mut file| file.write_all(b"test"))
+            .map_err(not_writable)?;
+        fs.remove_file(&test_file).ok();
 
         Ok(())
     }
@@ -105,12 +383,23 @@ impl StorageConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::env::MemoryFileSystem;
 
     #[test]
     fn test_default_values() {
         let config = StorageConfig::default();
         assert_eq!(config.data_dir, PathBuf::from("./data"));
         assert_eq!(config.memtable_size_mb, 4);
+        assert_eq!(config.write_buffer_size_mb, 0);
+        assert_eq!(config.memtable_rep, MemTableRepKind::BTree);
+        assert_eq!(config.memtable_prefix_len, 8);
+        assert_eq!(config.wal_archive_dir, None);
+        assert_eq!(config.wal_archive_ttl_secs, 0);
+        assert_eq!(config.wal_archive_size_limit_mb, 0);
+        assert_eq!(config.lock_timeout_ms, 1000);
+        assert_eq!(config.min_blob_size, 0);
+        assert_eq!(config.blob_file_size_mb, 256);
+        assert_eq!(config.blob_gc_garbage_ratio, 0.5);
     }
 
     #[test]
@@ -120,6 +409,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir.path().to_path_buf(),
             memtable_size_mb: 64,
+            ..Default::default()
         };
 
         let result = config.validate();
@@ -133,6 +423,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir.path().to_path_buf(),
             memtable_size_mb: 0,
+            ..Default::default()
         };
 
         let result = config.validate();
@@ -152,6 +443,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir.path().to_path_buf(),
             memtable_size_mb: 2048,
+            ..Default::default()
         };
 
         let result = config.validate();
@@ -171,6 +463,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir_1.path().to_path_buf(),
             memtable_size_mb: 1,
+            ..Default::default()
         };
         let result = config.validate();
         assert!(result.is_ok(), "Size 1 should be valid");
@@ -180,6 +473,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir_1024.path().to_path_buf(),
             memtable_size_mb: 1024,
+            ..Default::default()
         };
         let result = config.validate();
         assert!(result.is_ok(), "Size 1024 should be valid");
@@ -189,6 +483,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir_0.path().to_path_buf(),
             memtable_size_mb: 0,
+            ..Default::default()
         };
         let result = config.validate();
         assert!(result.is_err(), "Size 0 should be invalid");
@@ -198,11 +493,125 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir_1025.path().to_path_buf(),
             memtable_size_mb: 1025,
+            ..Default::default()
         };
         let result = config.validate();
         assert!(result.is_err(), "Size 1025 should be invalid");
     }
 
+    #[test]
+    fn test_write_buffer_size_validation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_size_mb: 64,
+            write_buffer_size_mb: 32,
+            ..Default::default()
+        };
+        match config.validate() {
+            Err(StorageConfigError::InvalidWriteBufferSize {
+                size,
+                memtable_size,
+            }) => {
+                assert_eq!(size, 32);
+                assert_eq!(memtable_size, 64);
+            }
+            other => panic!("Expected InvalidWriteBufferSize error, got: {:?}", other),
+        }
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_size_mb: 64,
+            write_buffer_size_mb: 256,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_write_stall_limits_validation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            write_stall: WriteStallConfig {
+                l0_files_slowdown: 40,
+                l0_files_stop: 36,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        match config.validate() {
+            Err(StorageConfigError::InvalidStallLimits { name, soft, hard }) => {
+                assert_eq!(name, "L0 files");
+                assert_eq!(soft, 40);
+                assert_eq!(hard, 36);
+            }
+            other => panic!("Expected InvalidStallLimits error, got: {:?}", other),
+        }
+
+        // Equal limits skip the slowdown phase
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            write_stall: WriteStallConfig {
+                l0_files_slowdown: 36,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_memtable_prefix_len() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_rep: MemTableRepKind::HashPrefix,
+            memtable_prefix_len: 0,
+            ..Default::default()
+        };
+        match config.validate() {
+            Err(StorageConfigError::InvalidMemtablePrefixLen { len }) => assert_eq!(len, 0),
+            other => panic!("Expected InvalidMemtablePrefixLen error, got: {:?}", other),
+        }
+
+        // The prefix length is ignored by the other representations
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_rep: MemTableRepKind::SkipList,
+            memtable_prefix_len: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_blob_gc_garbage_ratio() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        for ratio in [0.0, 1.5, f64::NAN] {
+            let config = StorageConfig {
+                data_dir: temp_dir.path().to_path_buf(),
+                blob_gc_garbage_ratio: ratio,
+                ..Default::default()
+            };
+            match config.validate() {
+                Err(StorageConfigError::InvalidBlobGcRatio { .. }) => {}
+                other => panic!("Expected InvalidBlobGcRatio error, got: {:?}", other),
+            }
+        }
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            blob_gc_garbage_ratio: 1.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_data_dir_creation() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -211,6 +620,7 @@ mod tests {
         let config = StorageConfig {
             data_dir: test_path.clone(),
             memtable_size_mb: 64,
+            ..Default::default()
         };
 
         // Should succeed and create directory
@@ -226,12 +636,46 @@ mod tests {
         let config = StorageConfig {
             data_dir: temp_dir.path().to_path_buf(),
             memtable_size_mb: 64,
+            ..Default::default()
         };
 
         let result = config.validate();
         assert!(result.is_ok(), "Validation failed: {:?}", result.err());
     }
 
+    #[test]
+    fn test_wal_archive_dir_creation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("archive");
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            wal_archive_dir: Some(archive_path.clone()),
+            ..Default::default()
+        };
+
+        let result = config.validate();
+        assert!(result.is_ok(), "Validation failed: {:?}", result.err());
+        assert!(archive_path.exists(), "Archive directory was not created");
+    }
+
+    #[test]
+    fn test_dir_writable_on_memory_fs() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/data/boxkv");
+
+        StorageConfig::check_dir_writable(&fs, dir).unwrap();
+        assert!(fs.is_dir(dir), "Directory was not created");
+        assert_eq!(fs.list_dir(dir).unwrap(), Vec::<PathBuf>::new());
+
+        fs.create(Path::new("/file")).unwrap();
+        let result = StorageConfig::check_dir_writable(&fs, Path::new("/file/boxkv"));
+        assert!(matches!(
+            result,
+            Err(StorageConfigError::DirNotWritable { .. })
+        ));
+    }
+
     #[test]
     fn test_error_display() {
         let err = StorageConfigError::InvalidMemtableSize { size: 0 };
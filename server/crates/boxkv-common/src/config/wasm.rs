@@ -0,0 +1,225 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors that can occur during WebAssembly configuration validation.
+#[derive(Debug, Error)]
+pub enum WasmConfigError {
+    /// The fuel budget of a call is 0, so no module could ever run.
+    #[error("Invalid fuel per call: must be greater than 0")]
+    InvalidFuel,
+
+    /// The memory limit is too small to hold a single WebAssembly page.
+    #[error("Invalid max memory: {size} MB, must be at least 1")]
+    InvalidMaxMemory { size: usize },
+
+    /// A module has an empty name.
+    #[error("Invalid module name: must not be empty")]
+    EmptyModuleName,
+
+    /// Stored procedures would time out before running.
+    #[error("Invalid procedure timeout: must be greater than 0")]
+    InvalidProcedureTimeout,
+
+    /// The memory limit of stored procedures is too small to hold a single
+    /// WebAssembly page.
+    #[error("Invalid procedure max memory: {size} MB, must be at least 1")]
+    InvalidProcedureMaxMemory { size: usize },
+
+    /// Two modules are registered under the same name.
+    #[error("Duplicate module name: {name}")]
+    DuplicateModule { name: String },
+}
+
+/// Configuration of the WebAssembly runtimes hosting user plugins
+/// (compaction filters and merge operators) and stored procedures.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WasmConfig {
+    /// Fuel (roughly, WebAssembly instructions) a module may burn in a
+    /// single callback before it is aborted.
+    /// Must be greater than 0.
+    /// Defaults to 10000000.
+    pub fuel_per_call: u64,
+
+    /// Linear memory a module instance may grow to, in megabytes.
+    /// Must be at least 1.
+    /// Defaults to 16.
+    pub max_memory_mb: usize,
+
+    /// Modules loaded at startup.
+    /// Defaults to none.
+    pub modules: Vec<WasmModuleConfig>,
+
+    /// Wall-clock time a stored procedure invocation may run for, in milliseconds.
+    /// Must be greater than 0.
+    /// Defaults to 1000.
+    pub procedure_timeout_ms: u64,
+
+    /// Linear memory a stored procedure invocation may grow to, in megabytes.
+    /// Must be at least 1.
+    /// Defaults to 64.
+    pub procedure_max_memory_mb: usize,
+}
+
+/// A WebAssembly module loaded at startup.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WasmModuleConfig {
+    /// Name the module is registered under, e.g. in column family options.
+    pub name: String,
+
+    /// Path of the module, either a binary (`.wasm`) or text (`.wat`) file.
+    pub path: PathBuf,
+}
+
+const DEFAULT_FUEL_PER_CALL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY_MB: usize = 16;
+const DEFAULT_PROCEDURE_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_PROCEDURE_MAX_MEMORY_MB: usize = 64;
+
+impl WasmConfig {
+    /// Validates the WebAssembly configuration.
+    ///
+    /// Checks:
+    /// 1. `fuel_per_call` is greater than 0.
+    /// 2. `max_memory_mb` is at least 1.
+    /// 3. `procedure_timeout_ms` is greater than 0.
+    /// 4. `procedure_max_memory_mb` is at least 1.
+    /// 5. Module names are non-empty and unique.
+    ///
+    /// Module files are only read when the runtime loads them.
+    pub(crate) fn validate(&self) -> Result<(), WasmConfigError> {
+        if self.fuel_per_call == 0 {
+            return Err(WasmConfigError::InvalidFuel);
+        }
+        if self.max_memory_mb == 0 {
+            return Err(WasmConfigError::InvalidMaxMemory {
+                size: self.max_memory_mb,
+            });
+        }
+        if self.procedure_timeout_ms == 0 {
+            return Err(WasmConfigError::InvalidProcedureTimeout);
+        }
+        if self.procedure_max_memory_mb == 0 {
+            return Err(WasmConfigError::InvalidProcedureMaxMemory {
+                size: self.procedure_max_memory_mb,
+            });
+        }
+
+        let mut names = HashSet::new();
+        for module in &self.modules {
+            if module.name.is_empty() {
+                return Err(WasmConfigError::EmptyModuleName);
+            }
+            if !names.insert(module.name.as_str()) {
+                return Err(WasmConfigError::DuplicateModule {
+                    name: module.name.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        Self {
+            fuel_per_call: DEFAULT_FUEL_PER_CALL,
+            max_memory_mb: DEFAULT_MAX_MEMORY_MB,
+            modules: Vec::new(),
+            procedure_timeout_ms: DEFAULT_PROCEDURE_TIMEOUT_MS,
+            procedure_max_memory_mb: DEFAULT_PROCEDURE_MAX_MEMORY_MB,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str) -> WasmModuleConfig {
+        WasmModuleConfig {
+            name: name.to_string(),
+            path: PathBuf::from(format!("{}.wasm", name)),
+        }
+    }
+
+    #[test]
+    fn test_default_values() {
+        let config = WasmConfig::default();
+        assert_eq!(config.fuel_per_call, 10_000_000);
+        assert_eq!(config.max_memory_mb, 16);
+        assert!(config.modules.is_empty());
+        assert_eq!(config.procedure_timeout_ms, 1000);
+        assert_eq!(config.procedure_max_memory_mb, 64);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let config = WasmConfig {
+            fuel_per_call: 0,
+            ..WasmConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(WasmConfigError::InvalidFuel)
+        ));
+
+        let config = WasmConfig {
+            max_memory_mb: 0,
+            ..WasmConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(WasmConfigError::InvalidMaxMemory { size: 0 })
+        ));
+
+        let config = WasmConfig {
+            procedure_timeout_ms: 0,
+            ..WasmConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(WasmConfigError::InvalidProcedureTimeout)
+        ));
+
+        let config = WasmConfig {
+            procedure_max_memory_mb: 0,
+            ..WasmConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(WasmConfigError::InvalidProcedureMaxMemory { size: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_module_names() {
+        let config = WasmConfig {
+            modules: vec![module("ttl"), module("counter")],
+            ..WasmConfig::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = WasmConfig {
+            modules: vec![module("ttl"), module("")],
+            ..WasmConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(WasmConfigError::EmptyModuleName)
+        ));
+
+        let config = WasmConfig {
+            modules: vec![module("ttl"), module("counter"), module("ttl")],
+            ..WasmConfig::default()
+        };
+        match config.validate() {
+            Err(WasmConfigError::DuplicateModule { name }) => assert_eq!(name, "ttl"),
+            other => panic!("Expected DuplicateModule error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! File system abstraction of the storage engine.
+//!
+//! Every file the engine reads or writes goes through a `FileSystem`, so the
+//! same code runs on disk (`PosixFileSystem`), fully in memory
+//! (`MemoryFileSystem`, used by tests) or on a wrapper injecting faults.
+//!
+//! Files are opened as one of three abstractions:
+//!
+//! - `WritableFile`: created empty and appended to. Writes are unbuffered
+//!   (callers wrap the file in a `BufWriter`) and only durable after `sync()`.
+//! - `SequentialFile`: read from start to end, with seeks to re-read a tail
+//!   that is still being appended to.
+//! - `RandomAccessFile`: read at arbitrary offsets, shared between threads.
+//!
+//! Like on POSIX, a file that is removed or renamed stays readable through
+//! the handles opened before.
+
+mod fault;
+mod memory;
+mod posix;
+
+pub use fault::{FaultInjectionFs, FaultOp};
+pub use memory::MemoryFileSystem;
+pub use posix::PosixFileSystem;
+
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A file being written, opened by `FileSystem::create()`.
+pub trait WritableFile: Write + Send {
+    /// Makes all data written so far durable (fsync).
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// A file read sequentially, opened by `FileSystem::open_sequential()`.
+pub trait SequentialFile: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> SequentialFile for T {}
+
+/// A file read at arbitrary offsets, opened by
+/// `FileSystem::open_random_access()`.
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads exactly `buf.len()` bytes starting at `offset`.
+    ///
+    /// Fails with `ErrorKind::UnexpectedEof` if the file is too short.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Returns the current size of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// The file and directory operations used by the storage engine.
+///
+/// Implementations must be usable from several threads at once. Errors follow
+/// `std::fs`: a missing file or directory is reported as
+/// `ErrorKind::NotFound`.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Creates the file `path` for writing, truncating it if it exists.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Opens the existing file `path` for sequential reads.
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>>;
+
+    /// Opens the existing file `path` for random reads.
+    fn open_random_access(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>>;
+
+    /// Returns the size of the file `path` in bytes.
+    fn file_size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Returns the last modification time of the file `path`.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+
+    /// Sets the last modification time of the file `path`.
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>;
+
+    /// Returns whether `path` is an existing file or directory.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns whether `path` is an existing directory.
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Returns the paths of the files and directories directly inside `dir`,
+    /// in no particular order.
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Creates `dir` and all of its missing parents.
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+
+    /// Removes the file `path`.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes `dir` and everything it contains.
+    fn remove_dir_all(&self, dir: &Path) -> io::Result<()>;
+
+    /// Renames a file or directory, replacing the file `to` if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Makes `dst` a new name of the existing file `src`.
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()>;
+
+    /// Makes the creations, renames and removals of entries of `dir` durable.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+
+    /// Reads the whole file `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open_sequential(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Copies the file `src` to `dst` and syncs the copy.
+    ///
+    /// Returns the number of bytes copied.
+    fn copy(&self, src: &Path, dst: &Path) -> io::Result<u64> {
+        let mut reader = self.open_sequential(src)?;
+        let mut writer = self.create(dst)?;
+        let copied = io::copy(&mut reader, &mut writer)?;
+        writer.sync()?;
+        Ok(copied)
+    }
+}
+
+/// Returns the file system of the local disk.
+pub fn default_fs() -> Arc<dyn FileSystem> {
+    Arc::new(PosixFileSystem)
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+
+use super::{FileSystem, RandomAccessFile, SequentialFile, WritableFile};
+
+/// An operation `FaultInjectionFs` can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultOp {
+    /// `FileSystem::create()`.
+    Create,
+    /// A write to a `WritableFile`.
+    Write,
+    /// `WritableFile::sync()`.
+    Sync,
+    /// `FileSystem::open_sequential()` and `open_random_access()`.
+    Open,
+    /// `FileSystem::rename()`.
+    Rename,
+    /// `FileSystem::hard_link()`.
+    HardLink,
+    /// `FileSystem::remove_file()` and `remove_dir_all()`.
+    Remove,
+    /// `FileSystem::create_dir_all()`.
+    CreateDir,
+    /// `FileSystem::sync_dir()`.
+    SyncDir,
+}
+
+/// A file system wrapper simulating crashes and I/O errors, for tests.
+///
+/// Like a disk with a volatile cache, it tracks what is durable: the data of
+/// a file up to its last `sync()`, and the files created, renamed or linked
+/// in a directory up to its last `sync_dir()`. `crash()` discards everything
+/// else, as a power failure would, then brings the file system back up;
+/// handles opened before the crash fail from then on.
+///
+/// Faults are injected with:
+///
+/// - `inject_error()`: an operation fails once, the file system keeps working.
+/// - `halt_after_ops()`: the power fails after a number of operations. Every
+///   operation fails until `crash()`.
+/// - `halt_after_bytes()`: the power fails in the middle of a write, torn at
+///   the given byte offset. The file is persisted up to the tear, as if the
+///   disk was writing it out when the power failed.
+///
+/// Removals and directory creations are durable right away, and files not
+/// written through the wrapper are assumed durable.
+#[derive(Debug)]
+pub struct FaultInjectionFs {
+    base: Arc<dyn FileSystem>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Incremented by every crash; handles of an older epoch are stale.
+    epoch: u64,
+    next_file_id: u64,
+    /// Files written since the last crash, by name; hard links share an id.
+    names: HashMap<PathBuf, u64>,
+    files: HashMap<u64, FileState>,
+    /// Directory changes not synced yet, oldest first.
+    dir_changes: Vec<DirChange>,
+    /// Number of successful calls before the operation fails.
+    errors: HashMap<FaultOp, u64>,
+    ops_left: Option<u64>,
+    bytes_left: Option<u64>,
+    halted: bool,
+}
+
+#[derive(Debug, Default)]
+struct FileState {
+    written: u64,
+    synced: u64,
+}
+
+#[derive(Debug)]
+enum DirChange {
+    /// `path` was created, replacing the durable contents `previous`.
+    Create {
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    /// `from` was renamed to `to`, replacing the durable contents `previous`.
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    /// `path` was linked to an existing file.
+    HardLink { path: PathBuf },
+}
+
+impl DirChange {
+    fn dir(&self) -> &Path {
+        let path = match self {
+            DirChange::Create { path, .. } | DirChange::HardLink { path } => path,
+            DirChange::Rename { to, .. } => to,
+        };
+        path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+fn halted() -> io::Error {
+    io::Error::other("File system halted by a simulated power failure")
+}
+
+impl State {
+    /// Fails if the file system is halted, without counting an operation.
+    fn check_running(&self) -> io::Result<()> {
+        if self.halted { Err(halted()) } else { Ok(()) }
+    }
+
+    /// Counts an `op` call, failing it if a fault applies.
+    fn check(&mut self, op: FaultOp) -> io::Result<()> {
+        self.check_running()?;
+        match self.ops_left.as_mut() {
+            Some(0) if op != FaultOp::Open => {
+                self.halted = true;
+                return Err(halted());
+            }
+            Some(left) if op != FaultOp::Open => *left -= 1,
+            _ => {}
+        }
+        if let Some(after) = self.errors.get_mut(&op) {
+            if *after == 0 {
+                self.errors.remove(&op);
+                return Err(io::Error::other(format!("Injected {:?} error", op)));
+            }
+            *after -= 1;
+        }
+        Ok(())
+    }
+
+    fn check_handle(&mut self, epoch: u64, op: FaultOp) -> io::Result<()> {
+        if epoch != self.epoch {
+            return Err(io::Error::other("File handle opened before a crash"));
+        }
+        self.check(op)
+    }
+
+    /// Returns the contents `path` would have after a crash, if it exists.
+    fn durable_contents(&self, base: &dyn FileSystem, path: &Path) -> Option<Vec<u8>> {
+        let mut data = base.read(path).ok()?;
+        if let Some(id) = self.names.get(path) {
+            data.truncate(self.files[id].synced as usize);
+        }
+        Some(data)
+    }
+}
+
+/// Replaces the contents of `path`, durably.
+fn rewrite(base: &dyn FileSystem, path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = base.create(path)?;
+    file.write_all(data)?;
+    file.sync()
+}
+
+/// Ignores the error of undoing a change to a file removed since.
+fn ignore_not_found(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl FaultInjectionFs {
+    /// Wraps `base`, whose current contents are all considered durable.
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        Self {
+            base,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Makes the next `op` call fail once, after `after` successful ones.
+    pub fn inject_error(&self, op: FaultOp, after: u64) {
+        self.state.lock().errors.insert(op, after);
+    }
+
+    /// Simulates a power failure after `ops` more operations (reads don't
+    /// count): every operation fails from then on until `crash()`.
+    pub fn halt_after_ops(&self, ops: u64) {
+        self.state.lock().ops_left = Some(ops);
+    }
+
+    /// Simulates a power failure after `bytes` more bytes are written: the
+    /// write reaching the limit is torn, then every operation fails until
+    /// `crash()`.
+    pub fn halt_after_bytes(&self, bytes: u64) {
+        self.state.lock().bytes_left = Some(bytes);
+    }
+
+    /// Returns whether a simulated power failure happened.
+    pub fn is_halted(&self) -> bool {
+        self.state.lock().halted
+    }
+
+    /// Discards the data and directory changes that are not durable, and
+    /// clears the injected faults.
+    ///
+    /// Fails only if the wrapped file system does.
+    pub fn crash(&self) -> io::Result<()> {
+        let mut state = self.state.lock();
+        let base = &*self.base;
+        for (path, id) in &state.names {
+            let synced = state.files[id].synced;
+            if base.file_size(path)? > synced {
+                let mut data = base.read(path)?;
+                data.truncate(synced as usize);
+                rewrite(base, path, &data)?;
+            }
+        }
+        for change in state.dir_changes.drain(..).rev() {
+            match change {
+                DirChange::Create { path, previous } => match previous {
+                    Some(data) => rewrite(base, &path, &data)?,
+                    None => ignore_not_found(base.remove_file(&path))?,
+                },
+                DirChange::Rename { from, to, previous } => {
+                    ignore_not_found(base.rename(&to, &from))?;
+                    if let Some(data) = previous {
+                        rewrite(base, &to, &data)?;
+                    }
+                }
+                DirChange::HardLink { path } => ignore_not_found(base.remove_file(&path))?,
+            }
+        }
+        *state = State {
+            epoch: state.epoch + 1,
+            ..State::default()
+        };
+        Ok(())
+    }
+}
+
+impl FileSystem for FaultInjectionFs {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::Create)?;
+        let previous = state.durable_contents(&*self.base, path);
+        let inner = self.base.create(path)?;
+
+        let id = state.next_file_id;
+        state.next_file_id += 1;
+        state.names.insert(path.to_path_buf(), id);
+        state.files.insert(id, FileState::default());
+        state.dir_changes.push(DirChange::Create {
+            path: path.to_path_buf(),
+            previous,
+        });
+        Ok(Box::new(FaultWritableFile {
+            inner,
+            state: self.state.clone(),
+            epoch: state.epoch,
+            file_id: id,
+        }))
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        self.state.lock().check(FaultOp::Open)?;
+        self.base.open_sequential(path)
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>> {
+        self.state.lock().check(FaultOp::Open)?;
+        self.base.open_random_access(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        self.state.lock().check_running()?;
+        self.base.file_size(path)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.state.lock().check_running()?;
+        self.base.modified(path)
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        self.state.lock().check_running()?;
+        self.base.set_modified(path, time)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.base.exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.base.is_dir(path)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.state.lock().check_running()?;
+        self.base.list_dir(dir)
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        self.state.lock().check(FaultOp::CreateDir)?;
+        self.base.create_dir_all(dir)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::Remove)?;
+        self.base.remove_file(path)?;
+        state.names.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::Remove)?;
+        self.base.remove_dir_all(dir)?;
+        state.names.retain(|path, _| !path.starts_with(dir));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::Rename)?;
+        let previous = state.durable_contents(&*self.base, to);
+        self.base.rename(from, to)?;
+
+        let moved: Vec<(PathBuf, u64)> = state
+            .names
+            .extract_if(|path, _| path.starts_with(from))
+            .collect();
+        state.names.remove(to);
+        for (path, id) in moved {
+            state
+                .names
+                .insert(to.join(path.strip_prefix(from).unwrap()), id);
+        }
+        state.dir_changes.push(DirChange::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            previous,
+        });
+        Ok(())
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::HardLink)?;
+        self.base.hard_link(src, dst)?;
+        if let Some(&id) = state.names.get(src) {
+            state.names.insert(dst.to_path_buf(), id);
+        }
+        state.dir_changes.push(DirChange::HardLink {
+            path: dst.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check(FaultOp::SyncDir)?;
+        self.base.sync_dir(dir)?;
+        state.dir_changes.retain(|change| change.dir() != dir);
+        Ok(())
+    }
+}
+
+struct FaultWritableFile {
+    inner: Box<dyn WritableFile>,
+    state: Arc<Mutex<State>>,
+    epoch: u64,
+    file_id: u64,
+}
+
+impl Write for FaultWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock();
+        state.check_handle(self.epoch, FaultOp::Write)?;
+        if let Some(left) = state.bytes_left.as_mut() {
+            if *left < buf.len() as u64 {
+                let torn = *left as usize;
+                self.inner.write_all(&buf[..torn])?;
+                state.halted = true;
+                let file = state.files.get_mut(&self.file_id).unwrap();
+                file.written += torn as u64;
+                file.synced = file.written;
+                return Err(io::Error::other(format!(
+                    "Write torn after {} of {} bytes",
+                    torn,
+                    buf.len()
+                )));
+            }
+            *left -= buf.len() as u64;
+        }
+        self.inner.write_all(buf)?;
+        state.files.get_mut(&self.file_id).unwrap().written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WritableFile for FaultWritableFile {
+    fn sync(&mut self) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_handle(self.epoch, FaultOp::Sync)?;
+        self.inner.sync()?;
+        let file = state.files.get_mut(&self.file_id).unwrap();
+        file.synced = file.written;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::MemoryFileSystem;
+
+    fn setup() -> FaultInjectionFs {
+        let base = MemoryFileSystem::new();
+        base.create_dir_all(Path::new("/db")).unwrap();
+        FaultInjectionFs::new(Arc::new(base))
+    }
+
+    fn write_synced(fs: &FaultInjectionFs, path: &Path, data: &[u8]) -> Box<dyn WritableFile> {
+        let mut file = fs.create(path).unwrap();
+        file.write_all(data).unwrap();
+        file.sync().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_crash_discards_what_is_not_durable() {
+        let fs = setup();
+        let dir = Path::new("/db");
+        let mut wal = write_synced(&fs, &dir.join("1.wal"), b"synced");
+        write_synced(&fs, &dir.join("CURRENT"), b"old");
+        fs.sync_dir(dir).unwrap();
+
+        wal.write_all(b" lost").unwrap();
+        write_synced(&fs, &dir.join("2.wal"), b"unlisted");
+        write_synced(&fs, &dir.join("CURRENT.tmp"), b"new");
+        fs.rename(&dir.join("CURRENT.tmp"), &dir.join("CURRENT"))
+            .unwrap();
+        assert_eq!(fs.read(&dir.join("CURRENT")).unwrap(), b"new");
+
+        fs.crash().unwrap();
+        assert_eq!(fs.read(&dir.join("1.wal")).unwrap(), b"synced");
+        assert!(!fs.exists(&dir.join("2.wal")));
+        assert_eq!(fs.read(&dir.join("CURRENT")).unwrap(), b"old");
+        // Its creation was never durable either
+        assert!(!fs.exists(&dir.join("CURRENT.tmp")));
+        // Handles don't survive the crash
+        assert!(wal.write_all(b"more").is_err());
+        assert_eq!(fs.read(&dir.join("1.wal")).unwrap(), b"synced");
+    }
+
+    #[test]
+    fn test_injected_errors_and_torn_writes() {
+        let fs = setup();
+        let path = Path::new("/db/1.wal");
+        fs.inject_error(FaultOp::Sync, 1);
+        let mut file = write_synced(&fs, path, b"abc");
+        fs.sync_dir(Path::new("/db")).unwrap();
+        assert!(file.sync().is_err());
+        file.sync().unwrap();
+
+        fs.halt_after_bytes(2);
+        assert!(file.write_all(b"defgh").is_err());
+        assert!(fs.is_halted());
+        assert!(fs.create(Path::new("/db/2.wal")).is_err());
+        fs.crash().unwrap();
+        assert!(!fs.is_halted());
+        assert_eq!(fs.read(path).unwrap(), b"abcde");
+
+        fs.halt_after_ops(2);
+        let mut file = fs.create(path).unwrap();
+        file.write_all(b"x").unwrap();
+        assert!(file.sync().is_err());
+        fs.crash().unwrap();
+        // The truncation was never synced
+        assert_eq!(fs.read(path).unwrap(), b"abcde");
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::{Mutex, RwLock};
+
+use super::{FileSystem, RandomAccessFile, SequentialFile, WritableFile};
+
+/// A file, shared by its names (hard links) and open handles.
+type FileData = Arc<RwLock<Contents>>;
+
+#[derive(Debug)]
+struct Contents {
+    bytes: Vec<u8>,
+    modified: SystemTime,
+}
+
+impl Contents {
+    fn new() -> FileData {
+        Arc::new(RwLock::new(Contents {
+            bytes: Vec::new(),
+            modified: SystemTime::now(),
+        }))
+    }
+}
+
+/// A file system held entirely in memory.
+///
+/// Every write is immediately visible and "durable": syncs do nothing, and
+/// the data lives as long as the `MemoryFileSystem`. Files and directories
+/// behave as on POSIX: hard links share their contents, and removing a file
+/// keeps it readable through the handles already open.
+///
+/// The root and relative paths without a parent directory always exist.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    files: HashMap<PathBuf, FileData>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl State {
+    fn dir_exists(&self, dir: &Path) -> bool {
+        dir.as_os_str().is_empty() || dir.parent().is_none() || self.dirs.contains(dir)
+    }
+
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.dir_exists(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<&FileData> {
+        self.files.get(path).ok_or_else(|| not_found(path))
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+fn is_a_directory(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::IsADirectory,
+        format!("{} is a directory", path.display()),
+    )
+}
+
+impl MemoryFileSystem {
+    /// Creates an empty file system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        if state.dirs.contains(path) {
+            return Err(is_a_directory(path));
+        }
+        state.check_parent(path)?;
+        // Truncate in place, as other names of the file see it too
+        let data = state
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(Contents::new)
+            .clone();
+        let mut contents = data.write();
+        contents.bytes.clear();
+        contents.modified = SystemTime::now();
+        drop(contents);
+        Ok(Box::new(MemoryWritableFile(data)))
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        let data = self.state.lock().file(path)?.clone();
+        Ok(Box::new(MemorySequentialFile { data, pos: 0 }))
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Box<dyn RandomAccessFile>> {
+        let data = self.state.lock().file(path)?.clone();
+        Ok(Box::new(MemoryRandomAccessFile(data)))
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.state.lock().file(path)?.read().bytes.len() as u64)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        Ok(self.state.lock().file(path)?.read().modified)
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        self.state.lock().file(path)?.write().modified = time;
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock();
+        state.files.contains_key(path) || state.dir_exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.state.lock().dir_exists(path)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state.lock();
+        if !state.dir_exists(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(state
+            .files
+            .keys()
+            .chain(&state.dirs)
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        for ancestor in dir.ancestors() {
+            if state.dir_exists(ancestor) {
+                break;
+            }
+            if state.files.contains_key(ancestor) {
+                return Err(io::Error::new(
+                    ErrorKind::NotADirectory,
+                    format!("{} is a file", ancestor.display()),
+                ));
+            }
+            state.dirs.insert(ancestor.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if state.dirs.contains(path) {
+            return Err(is_a_directory(path));
+        }
+        state
+            .files
+            .remove(path)
+            .map(drop)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        state.files.retain(|path, _| !path.starts_with(dir));
+        state.dirs.retain(|path| !path.starts_with(dir));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_parent(to)?;
+        if state.files.contains_key(from) {
+            if state.dirs.contains(to) {
+                return Err(is_a_directory(to));
+            }
+            let data = state.files.remove(from).unwrap();
+            state.files.insert(to.to_path_buf(), data);
+            return Ok(());
+        }
+
+        if !state.dirs.contains(from) {
+            return Err(not_found(from));
+        }
+        if state.files.contains_key(to) {
+            return Err(io::Error::new(
+                ErrorKind::NotADirectory,
+                format!("{} is a file", to.display()),
+            ));
+        }
+        let to_has_children = state
+            .files
+            .keys()
+            .chain(&state.dirs)
+            .any(|path| path.parent() == Some(to));
+        if to_has_children || to.starts_with(from) {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                format!("Can't rename {} to {}", from.display(), to.display()),
+            ));
+        }
+        let moved = |path: &Path| to.join(path.strip_prefix(from).unwrap());
+        let (files, kept) = std::mem::take(&mut state.files)
+            .into_iter()
+            .partition::<HashMap<_, _>, _>(|(path, _)| path.starts_with(from));
+        state.files = kept;
+        state
+            .files
+            .extend(files.into_iter().map(|(path, data)| (moved(&path), data)));
+        let (dirs, kept) = std::mem::take(&mut state.dirs)
+            .into_iter()
+            .partition::<HashSet<_>, _>(|path| path.starts_with(from));
+        state.dirs = kept;
+        state.dirs.extend(dirs.iter().map(|path| moved(path)));
+        Ok(())
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        let data = state.file(src)?.clone();
+        state.check_parent(dst)?;
+        if state.files.contains_key(dst) || state.dirs.contains(dst) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", dst.display()),
+            ));
+        }
+        state.files.insert(dst.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        if self.is_dir(dir) {
+            Ok(())
+        } else {
+            Err(not_found(dir))
+        }
+    }
+}
+
+struct MemoryWritableFile(FileData);
+
+impl Write for MemoryWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut contents = self.0.write();
+        contents.bytes.extend_from_slice(buf);
+        contents.modified = SystemTime::now();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for MemoryWritableFile {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the live contents, so data appended after opening is seen.
+struct MemorySequentialFile {
+    data: FileData,
+    pos: u64,
+}
+
+impl Read for MemorySequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = &self.data.read().bytes;
+        let start = (self.pos as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for MemorySequentialFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.data.read().bytes.len() as u64, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        self.pos = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+struct MemoryRandomAccessFile(FileData);
+
+impl RandomAccessFile for MemoryRandomAccessFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let data = &self.0.read().bytes;
+        let range = usize::try_from(offset)
+            .ok()
+            .and_then(|start| Some(start..start.checked_add(buf.len())?))
+            .filter(|range| range.end <= data.len())
+            .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(&data[range]);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.read().bytes.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_and_directories() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db/wal");
+        assert_eq!(
+            fs.create(&dir.join("1.wal")).err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        fs.create_dir_all(dir).unwrap();
+        assert!(fs.is_dir(Path::new("/db")) && fs.is_dir(dir));
+        let mut file = fs.create(&dir.join("1.wal")).unwrap();
+        file.write_all(b"hello").unwrap();
+        file.sync().unwrap();
+        fs.create(&dir.join("2.wal")).unwrap();
+
+        let mut listed = fs.list_dir(dir).unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![dir.join("1.wal"), dir.join("2.wal")]);
+        assert_eq!(
+            fs.list_dir(Path::new("/db")).unwrap(),
+            vec![dir.to_path_buf()]
+        );
+        assert_eq!(fs.file_size(&dir.join("1.wal")).unwrap(), 5);
+
+        // Open handles keep reading the file after it is removed
+        let mut reader = fs.open_sequential(&dir.join("1.wal")).unwrap();
+        fs.remove_file(&dir.join("1.wal")).unwrap();
+        assert!(!fs.exists(&dir.join("1.wal")));
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello");
+
+        fs.rename(Path::new("/db"), Path::new("/moved")).unwrap();
+        assert!(!fs.exists(dir));
+        assert!(fs.exists(Path::new("/moved/wal/2.wal")));
+        fs.remove_dir_all(Path::new("/moved")).unwrap();
+        assert!(!fs.exists(Path::new("/moved/wal")));
+        assert!(fs.list_dir(Path::new("/")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hard_links_share_contents() {
+        let fs = MemoryFileSystem::new();
+        let mut file = fs.create(Path::new("a")).unwrap();
+        file.write_all(b"0123456789").unwrap();
+        fs.hard_link(Path::new("a"), Path::new("b")).unwrap();
+        assert_eq!(
+            fs.hard_link(Path::new("a"), Path::new("b"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+
+        // Appends through the first name are visible through the link
+        let mut reader = fs.open_sequential(Path::new("b")).unwrap();
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        file.write_all(b"ab").unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, b"ab");
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        tail.clear();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, b"89ab");
+
+        let table = fs.open_random_access(Path::new("b")).unwrap();
+        let mut buf = [0; 3];
+        table.read_exact_at(&mut buf, 2).unwrap();
+        assert_eq!(&buf, b"234");
+        assert_eq!(
+            table.read_exact_at(&mut buf, 10).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert_eq!(table.size().unwrap(), 12);
+
+        fs.copy(Path::new("a"), Path::new("c")).unwrap();
+        fs.create(Path::new("a")).unwrap();
+        assert_eq!(fs.file_size(Path::new("b")).unwrap(), 0);
+        assert_eq!(fs.read(Path::new("c")).unwrap(), b"0123456789ab");
+    }
+}
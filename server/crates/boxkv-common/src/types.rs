@@ -13,6 +13,58 @@ const MAX_VALUE_DEBUG_LEN: usize = 64;
 pub const NORMAL_VALUE_TYPE: u8 = 0;
 pub const TOMBSTONE_VALUE_TYPE: u8 = 1;
 pub const EXPIRING_VALUE_TYPE: u8 = 2;
+pub const MERGE_VALUE_TYPE: u8 = 3;
+pub const RANGE_TOMBSTONE_VALUE_TYPE: u8 = 4;
+pub const BLOB_INDEX_VALUE_TYPE: u8 = 5;
+
+/// Identifier of a column family (an independent keyspace of the engine).
+pub type ColumnFamilyId = u32;
+
+/// The column family every engine has, used by writes that don't name one.
+pub const DEFAULT_COLUMN_FAMILY_ID: ColumnFamilyId = 0;
+
+/// Location of a value stored out of the LSM tree, in a blob file.
+///
+/// Serialized as `FileId (8B) | Offset (8B) | Size (8B)`, big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlobIndex {
+    /// Blob file holding the value.
+    pub file_id: u64,
+    /// Offset of the value's record in the file.
+    pub offset: u64,
+    /// Length of the value in bytes.
+    pub size: u64,
+}
+
+impl BlobIndex {
+    /// Length of the serialized index.
+    pub const ENCODED_LEN: usize = 3 * size_of::<u64>();
+
+    pub fn new(file_id: u64, offset: u64, size: u64) -> Self {
+        Self {
+            file_id,
+            offset,
+            size,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0; Self::ENCODED_LEN];
+        buf[..8].copy_from_slice(&self.file_id.to_be_bytes());
+        buf[8..16].copy_from_slice(&self.offset.to_be_bytes());
+        buf[16..].copy_from_slice(&self.size.to_be_bytes());
+        buf
+    }
+
+    /// Parses a serialized index; returns `None` if `data` has the wrong length.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        let field = |i: usize| u64::from_be_bytes(data[i * 8..(i + 1) * 8].try_into().unwrap());
+        Some(Self::new(field(0), field(1), field(2)))
+    }
+}
 
 /// Represents the type of value stored in an LSM-tree entry.
 ///
@@ -20,12 +72,18 @@ pub const EXPIRING_VALUE_TYPE: u8 = 2;
 /// - `Normal`: A standard key-value pair (PUT operation).
 /// - `Tombstone`: A deletion marker (DELETE operation). No actual data is stored.
 /// - `Expiring`: A value with an expiration timestamp (TTL support).
+/// - `Merge`: An operand combined with older versions by a merge operator (MERGE operation).
+/// - `RangeTombstone`: Deletes every key from the entry's key up to `end` (DELETE_RANGE operation).
+/// - `BlobIndex`: Points to a large value stored in a blob file.
 ///
 /// # Serialization
 /// Each variant has a unique type tag for wire format encoding:
 /// - Normal = 0
 /// - Tombstone = 1
 /// - Expiring = 2
+/// - Merge = 3
+/// - RangeTombstone = 4
+/// - BlobIndex = 5
 #[derive(Clone, PartialEq)]
 #[repr(u8)]
 pub enum ValueType {
@@ -40,6 +98,20 @@ pub enum ValueType {
         data: Bytes,
         expire_at: u64, // Unix timestamp in seconds
     } = EXPIRING_VALUE_TYPE,
+
+    /// Merge operand. Resolved lazily against older versions of the key on read
+    /// and during compaction.
+    Merge(Bytes) = MERGE_VALUE_TYPE,
+
+    /// Deletion marker for the key range `[key, end)`. Kept apart from point
+    /// entries (see `boxkv_core::range_tombstone`).
+    RangeTombstone {
+        end: Bytes, // Exclusive upper bound
+    } = RANGE_TOMBSTONE_VALUE_TYPE,
+
+    /// Reference to a value written once to a blob file, so compactions only
+    /// rewrite the small index (see `boxkv_core::blob`).
+    BlobIndex(BlobIndex) = BLOB_INDEX_VALUE_TYPE,
 }
 
 const VALUE_TOMBSTONE_LEN: usize = 0;
@@ -54,6 +126,9 @@ impl ValueType {
             ValueType::Normal(_) => NORMAL_VALUE_TYPE,
             ValueType::Tombstone => TOMBSTONE_VALUE_TYPE,
             ValueType::Expiring { .. } => EXPIRING_VALUE_TYPE,
+            ValueType::Merge(_) => MERGE_VALUE_TYPE,
+            ValueType::RangeTombstone { .. } => RANGE_TOMBSTONE_VALUE_TYPE,
+            ValueType::BlobIndex(_) => BLOB_INDEX_VALUE_TYPE,
         }
     }
 
@@ -66,18 +141,24 @@ impl ValueType {
     /// - Normal("hello") → 5 bytes
     /// - Tombstone → 0 bytes
     /// - Expiring { data: "hello", expire_at: 123 } → 13 bytes (8 + 5)
+    /// - RangeTombstone { end: "user:9" } → 6 bytes
+    /// - BlobIndex → 24 bytes
     pub fn serialized_len(&self) -> usize {
         self.data_len() + self.meta_len()
     }
 
     /// Returns the length of the user data in bytes.
     ///
-    /// For Tombstone, this is always 0.
+    /// For Tombstone and BlobIndex, this is always 0. For RangeTombstone, this
+    /// is the end key.
     pub fn data_len(&self) -> usize {
         match self {
             ValueType::Normal(bytes) => bytes.len(),
             ValueType::Tombstone => VALUE_TOMBSTONE_LEN,
             ValueType::Expiring { data, .. } => data.len(),
+            ValueType::Merge(operand) => operand.len(),
+            ValueType::RangeTombstone { end } => end.len(),
+            ValueType::BlobIndex(_) => 0,
         }
     }
 
@@ -86,11 +167,17 @@ impl ValueType {
     /// - Normal: 0 (no metadata)
     /// - Tombstone: 0 (no metadata)
     /// - Expiring: 8 (expire_at timestamp)
+    /// - Merge: 0 (no metadata)
+    /// - RangeTombstone: 0 (no metadata)
+    /// - BlobIndex: 24 (file id, offset and size)
     pub fn meta_len(&self) -> usize {
         match self {
             ValueType::Normal(_) => 0,
             ValueType::Tombstone => 0,
             ValueType::Expiring { .. } => VALUE_EXPIRING_AT_LEN,
+            ValueType::Merge(_) => 0,
+            ValueType::RangeTombstone { .. } => 0,
+            ValueType::BlobIndex(_) => BlobIndex::ENCODED_LEN,
         }
     }
 
@@ -98,6 +185,21 @@ impl ValueType {
     pub fn is_tombstone(&self) -> bool {
         matches!(self, ValueType::Tombstone)
     }
+
+    /// Checks if this value deletes a key range.
+    pub fn is_range_tombstone(&self) -> bool {
+        matches!(self, ValueType::RangeTombstone { .. })
+    }
+
+    /// Checks if this value is a merge operand.
+    pub fn is_merge(&self) -> bool {
+        matches!(self, ValueType::Merge(_))
+    }
+
+    /// Checks if this value is stored in a blob file.
+    pub fn is_blob_index(&self) -> bool {
+        matches!(self, ValueType::BlobIndex(_))
+    }
 }
 
 impl Debug for ValueType {
@@ -123,6 +225,28 @@ impl Debug for ValueType {
                     &String::from_utf8_lossy(&data[..debug_len])
                 )
             }
+            Self::Merge(operand) => {
+                let debug_len = min(operand.len(), MAX_VALUE_DEBUG_LEN);
+                write!(
+                    f,
+                    "Merge(len={}, data={:?})",
+                    operand.len(),
+                    &String::from_utf8_lossy(&operand[..debug_len])
+                )
+            }
+            Self::RangeTombstone { end } => {
+                let debug_len = min(end.len(), MAX_KEY_DEBUG_LEN);
+                write!(
+                    f,
+                    "RangeTombstone(end={:?})",
+                    &String::from_utf8_lossy(&end[..debug_len])
+                )
+            }
+            Self::BlobIndex(index) => write!(
+                f,
+                "BlobIndex(file={}, offset={}, size={})",
+                index.file_id, index.offset, index.size
+            ),
         }
     }
 }
@@ -131,7 +255,7 @@ impl Debug for ValueType {
 ///
 /// An `Entry` is the fundamental unit of data stored in the engine. It consists of:
 /// - A key (arbitrary bytes)
-/// - A value (Normal data, Tombstone, or Expiring value)
+/// - A value (Normal data, Tombstone, Expiring value, Merge operand, RangeTombstone, or BlobIndex)
 /// - A sequence number (monotonically increasing, used for MVCC)
 ///
 /// # Ordering Semantics
@@ -163,8 +287,8 @@ const ENTRY_SEQ_LEN: usize = size_of::<u64>();
 impl Entry {
     /// Creates a new entry with the given sequence number, key, and value type.
     ///
-    /// This is the internal constructor. Use `new_normal`, `new_tombstone`, or
-    /// `new_expiring` for specific value types.
+    /// This is the internal constructor. Use `new_normal`, `new_tombstone`,
+    /// `new_expiring`, `new_merge`, or `new_range_tombstone` for specific value types.
     pub fn new(seq: u64, key: Bytes, val: ValueType) -> Self {
         Self { key, val, seq }
     }
@@ -200,11 +324,36 @@ impl Entry {
         )
     }
 
+    /// Creates a merge operand entry.
+    ///
+    /// The operand is combined with the older versions of the key by the
+    /// keyspace's merge operator when the key is read or compacted.
+    pub fn new_merge(seq: u64, key: Bytes, operand: Bytes) -> Self {
+        Self::new(seq, key, ValueType::Merge(operand))
+    }
+
+    /// Creates a range deletion entry covering `[start, end)`.
+    ///
+    /// The entry's key is the (inclusive) start of the range.
+    pub fn new_range_tombstone(seq: u64, start: Bytes, end: Bytes) -> Self {
+        Self::new(seq, start, ValueType::RangeTombstone { end })
+    }
+
+    /// Creates an entry whose value is stored in a blob file.
+    pub fn new_blob_index(seq: u64, key: Bytes, index: BlobIndex) -> Self {
+        Self::new(seq, key, ValueType::BlobIndex(index))
+    }
+
     /// Returns `true` if this entry is a deletion marker.
     pub fn is_tombstone(&self) -> bool {
         self.val.is_tombstone()
     }
 
+    /// Returns `true` if this entry is a merge operand.
+    pub fn is_merge(&self) -> bool {
+        self.val.is_merge()
+    }
+
     /// Returns the estimated memory size of this entry in bytes.
     ///
     /// This includes:
@@ -333,6 +482,20 @@ mod tests {
         let tombstone = Entry::new_tombstone(1, Bytes::from("key"));
         // 3 (key) + 0 (tombstone) + 8 (seq) = 11
         assert_eq!(tombstone.estimated_size(), 11);
+
+        let merge = Entry::new_merge(1, Bytes::from("key"), Bytes::from("+1"));
+        // 3 (key) + 2 (operand) + 8 (seq) = 13
+        assert_eq!(merge.estimated_size(), 13);
+        assert!(merge.is_merge());
+        assert_eq!(merge.val().type_tag(), MERGE_VALUE_TYPE);
+
+        let index = BlobIndex::new(7, 4096, 1 << 20);
+        let blob = Entry::new_blob_index(1, Bytes::from("key"), index);
+        // 3 (key) + 24 (index) + 8 (seq) = 35
+        assert_eq!(blob.estimated_size(), 35);
+        assert_eq!(blob.val().type_tag(), BLOB_INDEX_VALUE_TYPE);
+        assert_eq!(BlobIndex::decode(&index.encode()), Some(index));
+        assert_eq!(BlobIndex::decode(&index.encode()[1..]), None);
     }
 
     #[test]
@@ -0,0 +1,152 @@
+//! Benchmarks comparing the BTreeMap `MemTable` with the concurrent `SkipListMemTable`.
+//!
+//! Run with `cargo bench -p boxkv-core --bench memtable`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::thread;
+
+use bytes::Bytes;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use parking_lot::Mutex;
+
+use boxkv_core::memtable::{MemTable, SkipListMemTable};
+
+const ENTRIES: u64 = 10_000;
+const VALUE_SIZE: usize = 100;
+
+fn keys() -> Vec<Bytes> {
+    // Multiplicative hash spreads inserts across the key space.
+    (0..ENTRIES)
+        .map(|i| {
+            Bytes::from(format!(
+                "key_{:016x}",
+                i.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ))
+        })
+        .collect()
+}
+
+fn bench_put(c: &mut Criterion) {
+    let keys = keys();
+    let value = Bytes::from(vec![b'v'; VALUE_SIZE]);
+
+    let mut group = c.benchmark_group("memtable_put");
+    group.throughput(Throughput::Elements(ENTRIES));
+
+    group.bench_function("btree", |b| {
+        b.iter(|| {
+            let mut memtable = MemTable::new();
+            for (seq, key) in keys.iter().enumerate() {
+                memtable.put(seq as u64, key.clone(), value.clone());
+            }
+            black_box(memtable.size())
+        })
+    });
+
+    group.bench_function("skiplist", |b| {
+        b.iter(|| {
+            let memtable = SkipListMemTable::new();
+            for (seq, key) in keys.iter().enumerate() {
+                memtable.put(seq as u64, key.clone(), value.clone());
+            }
+            black_box(memtable.size())
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_get(c: &mut Criterion) {
+    let keys = keys();
+    let value = Bytes::from(vec![b'v'; VALUE_SIZE]);
+
+    let mut btree = MemTable::new();
+    let skiplist = SkipListMemTable::new();
+    for (seq, key) in keys.iter().enumerate() {
+        btree.put(seq as u64, key.clone(), value.clone());
+        skiplist.put(seq as u64, key.clone(), value.clone());
+    }
+
+    let mut group = c.benchmark_group("memtable_get");
+    group.throughput(Throughput::Elements(ENTRIES));
+
+    group.bench_function("btree", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(btree.get(key));
+            }
+        })
+    });
+
+    group.bench_function("skiplist", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(skiplist.get(key));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_concurrent_put(c: &mut Criterion) {
+    let keys = Arc::new(keys());
+    let value = Bytes::from(vec![b'v'; VALUE_SIZE]);
+
+    let mut group = c.benchmark_group("memtable_concurrent_put");
+    group.throughput(Throughput::Elements(ENTRIES));
+
+    for threads in [2u64, 4, 8] {
+        let per_thread = ENTRIES / threads;
+
+        // The BTreeMap MemTable needs `&mut self`, so writers share it behind a lock.
+        group.bench_with_input(
+            BenchmarkId::new("btree", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let memtable = Mutex::new(MemTable::new());
+                    thread::scope(|s| {
+                        for t in 0..threads {
+                            let (memtable, keys, value) = (&memtable, &keys, &value);
+                            s.spawn(move || {
+                                for i in t * per_thread..(t + 1) * per_thread {
+                                    let key = keys[i as usize].clone();
+                                    memtable.lock().put(i, key, value.clone());
+                                }
+                            });
+                        }
+                    });
+                    black_box(memtable.lock().size())
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("skiplist", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let memtable = SkipListMemTable::new();
+                    thread::scope(|s| {
+                        for t in 0..threads {
+                            let (memtable, keys, value) = (&memtable, &keys, &value);
+                            s.spawn(move || {
+                                for i in t * per_thread..(t + 1) * per_thread {
+                                    memtable.put(i, keys[i as usize].clone(), value.clone());
+                                }
+                            });
+                        }
+                    });
+                    black_box(memtable.size())
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_put, bench_get, bench_concurrent_put);
+criterion_main!(benches);
@@ -0,0 +1,676 @@
+//! Incremental backups of an engine into a local backup directory.
+//!
+//! # Overview
+//!
+//! Every backup starts as an engine checkpoint whose files are added to a
+//! content-addressed store: a file is stored once per `(name, checksum,
+//! size)`, so files that never change (SSTables and sealed blob files)
+//! are shared by every backup that contains them and only new data is copied.
+//!
+//! # Layout
+//!
+//! ```text
+//! backup_dir/
+//!   shared/<name>.<crc32>.<size>   file contents, shared between backups
+//!   meta/<id>                      one metadata file per backup
+//! ```
+//!
+//! A backup exists once its metadata file is written (atomically, after every
+//! file it lists is durable). The metadata is a small text file:
+//!
+//! ```text
+//! timestamp=1767225600
+//! sequence=42
+//! file=1a2b3c4d 4096 000000001.sst
+//! file=5e6f7a8b 1048576 blob/000000001.blob
+//! ```
+//!
+//! with one `file=<crc32> <size> <path>` line per file of the checkpoint,
+//! `path` being relative to the database directory.
+//!
+//! The checkpoint is written by the engine into the backup directory, so both
+//! must be on the same `FileSystem`.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! let backups = BackupEngine::open(backup_dir)?;
+//! let info = backups.create_backup(&engine)?;
+//! backups.verify_backup(info.id)?;
+//! backups.purge_old_backups(7)?;
+//! backups.restore(info.id, restore_dir)?;
+//! ```
+
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::engine::{Engine, EngineError, temp_sibling};
+use boxkv_common::env::{FileSystem, default_fs};
+
+const SHARED_DIR_NAME: &str = "shared";
+const META_DIR_NAME: &str = "meta";
+const CHECKPOINT_DIR_NAME: &str = "checkpoint.tmp";
+
+/// Size of the buffer files are copied and checksummed with.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number identifying a backup, increasing with every backup created.
+pub type BackupId = u32;
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("Backup I/O error at {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+
+    #[error("Backup {0} does not exist")]
+    NotFound(BackupId),
+
+    #[error("Invalid metadata for backup {id}: {reason}")]
+    InvalidMetadata { id: BackupId, reason: String },
+
+    /// A stored file doesn't match the checksum or size recorded at backup time.
+    #[error("Backup file {path} is corrupted: {reason}")]
+    Corrupted { path: PathBuf, reason: String },
+
+    #[error("Restore target {0:?} already exists")]
+    TargetExists(PathBuf),
+}
+
+pub type Result<T> = std::result::Result<T, BackupError>;
+
+trait BackupContext<T> {
+    fn with_path(self, path: &Path) -> Result<T>;
+}
+
+impl<T> BackupContext<T> for io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T> {
+        self.map_err(|source| BackupError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Summary of a backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: BackupId,
+    /// Creation time, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Sequence number of the last write included.
+    pub sequence: u64,
+    /// Total size of the backed up files in bytes (shared files included).
+    pub size: u64,
+    pub num_files: usize,
+}
+
+/// One file of a backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupFile {
+    /// Path relative to the database directory.
+    path: PathBuf,
+    crc: u32,
+    size: u64,
+}
+
+impl BackupFile {
+    /// Name of the file in the shared directory.
+    fn shared_name(&self) -> String {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        format!("{}.{:08x}.{}", name, self.crc, self.size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupMeta {
+    timestamp: u64,
+    sequence: u64,
+    files: Vec<BackupFile>,
+}
+
+impl BackupMeta {
+    fn encode(&self) -> String {
+        let mut out = format!("timestamp={}\nsequence={}\n", self.timestamp, self.sequence);
+        for file in &self.files {
+            out.push_str(&format!(
+                "file={:08x} {} {}\n",
+                file.crc,
+                file.size,
+                file.path.display()
+            ));
+        }
+        out
+    }
+
+    fn decode(id: BackupId, text: &str) -> Result<Self> {
+        let invalid = |reason: String| BackupError::InvalidMetadata { id, reason };
+        let mut timestamp = None;
+        let mut sequence = None;
+        let mut files = Vec::new();
+
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("malformed line {:?}", line)))?;
+            let number = |v: &str| {
+                v.parse::<u64>()
+                    .map_err(|_| invalid(format!("invalid number in {:?}", line)))
+            };
+            match key {
+                "timestamp" => timestamp = Some(number(value)?),
+                "sequence" => sequence = Some(number(value)?),
+                "file" => {
+                    let mut parts = value.splitn(3, ' ');
+                    let (Some(crc), Some(size), Some(path)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(invalid(format!("malformed file line {:?}", line)));
+                    };
+                    files.push(BackupFile {
+                        path: PathBuf::from(path),
+                        crc: u32::from_str_radix(crc, 16)
+                            .map_err(|_| invalid(format!("invalid checksum in {:?}", line)))?,
+                        size: number(size)?,
+                    });
+                }
+                _ => return Err(invalid(format!("unknown key {:?}", key))),
+            }
+        }
+
+        Ok(Self {
+            timestamp: timestamp.ok_or_else(|| invalid("missing timestamp".to_string()))?,
+            sequence: sequence.ok_or_else(|| invalid("missing sequence".to_string()))?,
+            files,
+        })
+    }
+
+    fn info(&self, id: BackupId) -> BackupInfo {
+        BackupInfo {
+            id,
+            timestamp: self.timestamp,
+            sequence: self.sequence,
+            size: self.files.iter().map(|f| f.size).sum(),
+            num_files: self.files.len(),
+        }
+    }
+}
+
+/// Numbered, deduplicated backups in a local directory.
+///
+/// Operations are not synchronized with each other: a single `BackupEngine`
+/// should manage a backup directory at a time.
+pub struct BackupEngine {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+}
+
+impl BackupEngine {
+    /// Opens the backup directory `dir`, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_with_fs(default_fs(), dir)
+    }
+
+    /// Opens the backup directory `dir` on `fs`, creating it if needed.
+    pub fn open_with_fs(fs: Arc<dyn FileSystem>, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        for sub in [SHARED_DIR_NAME, META_DIR_NAME] {
+            let path = dir.join(sub);
+            fs.create_dir_all(&path).with_path(&path)?;
+        }
+        Ok(Self { fs, dir })
+    }
+
+    fn shared_dir(&self) -> PathBuf {
+        self.dir.join(SHARED_DIR_NAME)
+    }
+
+    fn meta_path(&self, id: BackupId) -> PathBuf {
+        self.dir.join(META_DIR_NAME).join(id.to_string())
+    }
+
+    /// Backs up `engine`, copying only the files not stored yet.
+    pub fn create_backup(&self, engine: &Engine) -> Result<BackupInfo> {
+        let fs = &*self.fs;
+        let checkpoint_dir = self.dir.join(CHECKPOINT_DIR_NAME);
+        if fs.exists(&checkpoint_dir) {
+            fs.remove_dir_all(&checkpoint_dir)
+                .with_path(&checkpoint_dir)?;
+        }
+        let sequence = engine.checkpoint(&checkpoint_dir)?;
+
+        let result = self.store_checkpoint(&checkpoint_dir, sequence);
+        fs.remove_dir_all(&checkpoint_dir)
+            .with_path(&checkpoint_dir)?;
+        result
+    }
+
+    fn store_checkpoint(&self, checkpoint_dir: &Path, sequence: u64) -> Result<BackupInfo> {
+        let fs = &*self.fs;
+        let shared_dir = self.shared_dir();
+        let mut files = Vec::new();
+        let mut copied_bytes = 0;
+
+        for path in list_files(fs, checkpoint_dir)? {
+            let src = checkpoint_dir.join(&path);
+            let (crc, size) = checksum(fs, &src)?;
+            let file = BackupFile { path, crc, size };
+
+            let dst = shared_dir.join(file.shared_name());
+            if fs.exists(&dst) {
+                debug!(?dst, "File already backed up");
+            } else {
+                let tmp = dst.with_extension("tmp");
+                copy_with_checksum(fs, &src, &tmp)?;
+                fs.rename(&tmp, &dst).with_path(&dst)?;
+                copied_bytes += size;
+            }
+            files.push(file);
+        }
+        sync_dir(fs, &shared_dir)?;
+
+        let id = self.list_ids()?.last().map_or(1, |id| id + 1);
+        let meta = BackupMeta {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            sequence,
+            files,
+        };
+        let meta_path = self.meta_path(id);
+        let tmp = meta_path.with_extension("tmp");
+        let mut file = fs.create(&tmp).with_path(&tmp)?;
+        file.write_all(meta.encode().as_bytes()).with_path(&tmp)?;
+        file.sync().with_path(&tmp)?;
+        fs.rename(&tmp, &meta_path).with_path(&meta_path)?;
+        sync_dir(fs, &self.dir.join(META_DIR_NAME))?;
+
+        let info = meta.info(id);
+        info!(
+            id,
+            sequence,
+            size = info.size,
+            copied_bytes,
+            "Backup created"
+        );
+        Ok(info)
+    }
+
+    /// Returns the ids of the backups, oldest first.
+    fn list_ids(&self) -> Result<Vec<BackupId>> {
+        let meta_dir = self.dir.join(META_DIR_NAME);
+        let mut ids = Vec::new();
+        for path in self.fs.list_dir(&meta_dir).with_path(&meta_dir)? {
+            if let Some(id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<BackupId>().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn read_meta(&self, id: BackupId) -> Result<BackupMeta> {
+        let path = self.meta_path(id);
+        let data = match self.fs.read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(BackupError::NotFound(id)),
+            Err(e) => return Err(e).with_path(&path),
+        };
+        let text = String::from_utf8(data).map_err(|_| BackupError::InvalidMetadata {
+            id,
+            reason: "not UTF-8".to_string(),
+        })?;
+        BackupMeta::decode(id, &text)
+    }
+
+    /// Lists the backups, oldest first.
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        self.list_ids()?
+            .into_iter()
+            .map(|id| Ok(self.read_meta(id)?.info(id)))
+            .collect()
+    }
+
+    /// Checks that every file of a backup is present with the recorded size
+    /// and checksum.
+    ///
+    /// # Errors
+    /// Returns `BackupError::Corrupted` for the first file that doesn't match.
+    pub fn verify_backup(&self, id: BackupId) -> Result<()> {
+        let meta = self.read_meta(id)?;
+        for file in &meta.files {
+            let path = self.shared_dir().join(file.shared_name());
+            let (crc, size) = checksum(&*self.fs, &path)?;
+            check_file(&path, file, crc, size)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes a backup and the shared files no other backup uses.
+    pub fn delete_backup(&self, id: BackupId) -> Result<()> {
+        let path = self.meta_path(id);
+        if !self.fs.exists(&path) {
+            return Err(BackupError::NotFound(id));
+        }
+        self.fs.remove_file(&path).with_path(&path)?;
+        info!(id, "Backup deleted");
+        self.purge_shared_files()
+    }
+
+    /// Deletes all backups but the `keep` newest ones, returning the ids of
+    /// the deleted backups.
+    pub fn purge_old_backups(&self, keep: usize) -> Result<Vec<BackupId>> {
+        let ids = self.list_ids()?;
+        let old = &ids[..ids.len().saturating_sub(keep)];
+        for &id in old {
+            let path = self.meta_path(id);
+            self.fs.remove_file(&path).with_path(&path)?;
+            info!(id, "Backup deleted");
+        }
+        self.purge_shared_files()?;
+        Ok(old.to_vec())
+    }
+
+    /// Removes the shared files no backup refers to anymore.
+    fn purge_shared_files(&self) -> Result<()> {
+        let mut used = HashSet::new();
+        for id in self.list_ids()? {
+            used.extend(
+                self.read_meta(id)?
+                    .files
+                    .iter()
+                    .map(BackupFile::shared_name),
+            );
+        }
+
+        let shared_dir = self.shared_dir();
+        for path in self.fs.list_dir(&shared_dir).with_path(&shared_dir)? {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !used.contains(&name) {
+                debug!(name, "Removing unused backup file");
+                self.fs.remove_file(&path).with_path(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores a backup into `target_dir`, which must not exist yet.
+    ///
+    /// Every file is checksummed while it is copied into a new temporary
+    /// sibling of `target_dir`; the restored directory only appears once all
+    /// of them matched.
+    ///
+    /// # Errors
+    /// Returns `BackupError::Corrupted` if a file doesn't match its checksum.
+    pub fn restore(&self, id: BackupId, target_dir: impl AsRef<Path>) -> Result<()> {
+        let fs = &*self.fs;
+        let target_dir = target_dir.as_ref();
+        if fs.exists(target_dir) {
+            return Err(BackupError::TargetExists(target_dir.to_path_buf()));
+        }
+        let meta = self.read_meta(id)?;
+
+        let tmp_dir = temp_sibling(fs, target_dir, "restoring").with_path(target_dir)?;
+        let result = (|| {
+            for file in &meta.files {
+                let src = self.shared_dir().join(file.shared_name());
+                let dst = tmp_dir.join(&file.path);
+                if let Some(parent) = dst.parent() {
+                    fs.create_dir_all(parent).with_path(parent)?;
+                }
+                let (crc, size) = copy_with_checksum(fs, &src, &dst)?;
+                check_file(&src, file, crc, size)?;
+            }
+            fs.create_dir_all(&tmp_dir).with_path(&tmp_dir)?;
+            for dir in list_dirs(fs, &tmp_dir)? {
+                sync_dir(fs, &dir)?;
+            }
+            fs.rename(&tmp_dir, target_dir).with_path(target_dir)
+        })();
+        if result.is_err() && fs.exists(&tmp_dir) {
+            warn!(id, ?target_dir, "Restore failed, removing partial copy");
+            fs.remove_dir_all(&tmp_dir).ok();
+        }
+        result?;
+
+        info!(id, ?target_dir, sequence = meta.sequence, "Backup restored");
+        Ok(())
+    }
+}
+
+fn check_file(path: &Path, file: &BackupFile, crc: u32, size: u64) -> Result<()> {
+    if (crc, size) != (file.crc, file.size) {
+        return Err(BackupError::Corrupted {
+            path: path.to_path_buf(),
+            reason: format!(
+                "expected crc {:08x} and {} bytes, found crc {:08x} and {} bytes",
+                file.crc, file.size, crc, size
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the CRC32 and size of a file.
+fn checksum(fs: &dyn FileSystem, path: &Path) -> Result<(u32, u64)> {
+    let mut reader = BufReader::new(fs.open_sequential(path).with_path(path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf).with_path(path)?;
+        if n == 0 {
+            return Ok((hasher.finalize(), size));
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+}
+
+/// Copies `src` to `dst` (synced), returning the CRC32 and size of the data copied.
+fn copy_with_checksum(fs: &dyn FileSystem, src: &Path, dst: &Path) -> Result<(u32, u64)> {
+    let mut reader = BufReader::new(fs.open_sequential(src).with_path(src)?);
+    let mut writer = BufWriter::new(fs.create(dst).with_path(dst)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf).with_path(src)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n]).with_path(dst)?;
+        size += n as u64;
+    }
+    let mut file = writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .with_path(dst)?;
+    file.sync().with_path(dst)?;
+    Ok((hasher.finalize(), size))
+}
+
+/// Lists the files under `root`, recursively, as paths relative to it.
+fn list_files(fs: &dyn FileSystem, root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel) = dirs.pop() {
+        let dir = root.join(&rel);
+        for entry in fs.list_dir(&dir).with_path(&dir)? {
+            let path = rel.join(entry.file_name().unwrap());
+            if fs.is_dir(&entry) {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Lists `root` and its subdirectories.
+fn list_dirs(fs: &dyn FileSystem, root: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_path_buf()];
+    let mut i = 0;
+    while let Some(dir) = dirs.get(i).cloned() {
+        for entry in fs.list_dir(&dir).with_path(&dir)? {
+            if fs.is_dir(&entry) {
+                dirs.push(entry);
+            }
+        }
+        i += 1;
+    }
+    Ok(dirs)
+}
+
+fn sync_dir(fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+    fs.sync_dir(dir).with_path(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ColumnFamilyOptions;
+    use crate::engine::DEFAULT_COLUMN_FAMILY_NAME;
+    use boxkv_common::config::StorageConfig;
+    use boxkv_common::env::MemoryFileSystem;
+    use bytes::Bytes;
+
+    fn open_engine(fs: &Arc<dyn FileSystem>, dir: &str) -> Engine {
+        let column_families = vec![(
+            DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            ColumnFamilyOptions::default(),
+        )];
+        Engine::open_with_fs(fs.clone(), dir, &StorageConfig::default(), column_families).unwrap()
+    }
+
+    fn open_backups(fs: &Arc<dyn FileSystem>) -> BackupEngine {
+        BackupEngine::open_with_fs(fs.clone(), "/backup").unwrap()
+    }
+
+    #[test]
+    fn test_incremental_backups_and_restore() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let backups = open_backups(&fs);
+
+        open_engine(&fs, "/db")
+            .put(Bytes::from("a"), Bytes::from("1"))
+            .unwrap();
+        let engine = open_engine(&fs, "/db");
+        engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+        let first = backups.create_backup(&engine).unwrap();
+        engine.put(Bytes::from("c"), Bytes::from("3")).unwrap();
+        let second = backups.create_backup(&engine).unwrap();
+
+        assert_eq!((first.id, first.sequence), (1, 2));
+        assert_eq!((second.id, second.sequence), (2, 3));
+        assert_eq!(
+            backups.list_backups().unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        // Each checkpoint flushed: the second backup adds its SSTable and a
+        // new manifest, and shares the rest
+        let shared = fs.list_dir(&backups.shared_dir()).unwrap().len();
+        assert_eq!(second.num_files, first.num_files + 1);
+        assert_eq!(shared, first.num_files + 2);
+
+        backups.verify_backup(1).unwrap();
+        // Unrelated siblings of the target are left alone
+        let sibling = Path::new("/restore/prod.restoring");
+        fs.create_dir_all(sibling).unwrap();
+        let target = Path::new("/restore/prod.db");
+        backups.restore(1, target).unwrap();
+        assert!(fs.is_dir(sibling));
+        assert_eq!(fs.list_dir(Path::new("/restore")).unwrap().len(), 2);
+        let restored = open_engine(&fs, "/restore/prod.db");
+        assert_eq!(restored.last_seq(), 2);
+        assert_eq!(
+            restored.get(&Bytes::from("a")).unwrap(),
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(restored.get(&Bytes::from("c")).unwrap(), None);
+        assert!(matches!(
+            backups.restore(1, target),
+            Err(BackupError::TargetExists(_))
+        ));
+
+        // Purging the first backup keeps the files the second one shares
+        assert_eq!(backups.purge_old_backups(1).unwrap(), vec![1]);
+        let shared = fs.list_dir(&backups.shared_dir()).unwrap().len();
+        assert_eq!(shared, second.num_files);
+        backups.verify_backup(2).unwrap();
+        assert!(matches!(
+            backups.verify_backup(1),
+            Err(BackupError::NotFound(1))
+        ));
+    }
+
+    #[test]
+    fn test_corrupted_backup_fails_verify_and_restore() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let backups = open_backups(&fs);
+
+        let engine = open_engine(&fs, "/db");
+        engine.put(Bytes::from("k"), Bytes::from("v")).unwrap();
+        let info = backups.create_backup(&engine).unwrap();
+
+        let meta = backups.read_meta(info.id).unwrap();
+        let table = meta
+            .files
+            .iter()
+            .find(|f| f.path.extension().is_some_and(|ext| ext == "sst"))
+            .unwrap();
+        let path = backups.shared_dir().join(table.shared_name());
+        let mut data = fs.read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        fs.create(&path).unwrap().write_all(&data).unwrap();
+
+        assert!(matches!(
+            backups.verify_backup(info.id),
+            Err(BackupError::Corrupted { .. })
+        ));
+        let target = Path::new("/restore/db");
+        assert!(matches!(
+            backups.restore(info.id, target),
+            Err(BackupError::Corrupted { .. })
+        ));
+        assert!(fs.list_dir(Path::new("/restore")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backup_meta_round_trip() {
+        let meta = BackupMeta {
+            timestamp: 1_767_225_600,
+            sequence: 42,
+            files: vec![BackupFile {
+                path: PathBuf::from("blob/000000001.blob"),
+                crc: 0x1a2b3c4d,
+                size: 4096,
+            }],
+        };
+        assert_eq!(BackupMeta::decode(1, &meta.encode()).unwrap(), meta);
+        assert!(matches!(
+            BackupMeta::decode(1, "sequence=1\n"),
+            Err(BackupError::InvalidMetadata { .. })
+        ));
+    }
+}
@@ -0,0 +1,498 @@
+//! Key-value separation: large values written once to blob files (WiscKey style).
+//!
+//! # Overview
+//!
+//! Compactions rewrite every value they keep, so a large value is rewritten
+//! once per level it moves through. Values of at least `min_blob_size` bytes
+//! are instead appended to the active blob file when they are written, and
+//! the LSM tree (WAL, MemTables and SSTables) only stores a 24-byte
+//! `ValueType::BlobIndex` pointing to them:
+//!
+//! ```text
+//! put(key, 1 MiB) → BlobStore::add → blob fsync → WAL append(BlobIndex) + fsync → MemTable insert
+//! ```
+//!
+//! The blob is synced before the WAL record referencing it, so a recovered
+//! index never points past the end of a blob file.
+//!
+//! # File Format
+//!
+//! Blob files are named `{:09}.blob` in the `blob` directory of the engine and
+//! are append-only sequences of records:
+//!
+//! ```text
+//! +----------+-----------+-------------+-------------+-----+-------+
+//! | CRC (4B) | CfId (4B) | KeyLen (4B) | ValLen (8B) | Key | Value |
+//! +----------+-----------+-------------+-------------+-----+-------+
+//! ```
+//!
+//! Integers are big-endian and the CRC32 covers every field after it. A
+//! `BlobIndex` holds the offset of the record and the length of the value.
+//! The key and column family let garbage collection find the LSM entry
+//! referencing a record.
+//!
+//! # Garbage Collection
+//!
+//! Overwritten and deleted values stay in their blob file. A record is live
+//! while the latest full version of its key still holds its `BlobIndex`.
+//! `Engine::collect_blob_garbage()` scans the sealed blob files and, for each
+//! one whose fraction of dead bytes reached `blob_gc_garbage_ratio`, writes
+//! the live values again (to the active blob file) and deletes the file.
+
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tracing::{debug, info};
+
+use boxkv_common::env::{FileSystem, SequentialFile, WritableFile};
+use boxkv_common::types::{BlobIndex, ColumnFamilyId};
+
+/// Directory of the blob files, inside the engine directory.
+pub const BLOB_DIR_NAME: &str = "blob";
+
+const BLOB_CRC_SIZE: usize = 4;
+const BLOB_CF_ID_SIZE: usize = 4;
+const BLOB_KEY_LEN_SIZE: usize = 4;
+const BLOB_VAL_LEN_SIZE: usize = 8;
+const BLOB_HEADER_SIZE: usize =
+    BLOB_CRC_SIZE + BLOB_CF_ID_SIZE + BLOB_KEY_LEN_SIZE + BLOB_VAL_LEN_SIZE;
+
+#[derive(Debug, Error)]
+pub enum BlobError {
+    #[error("Failed to access blob file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The record checksum doesn't match (data corruption).
+    #[error("CRC mismatch in blob file {file_id} at offset {offset}")]
+    CrcMismatch { file_id: u64, offset: u64 },
+
+    /// The index doesn't point to a record holding a value of its size.
+    #[error("Invalid blob index: {0:?}")]
+    InvalidIndex(BlobIndex),
+}
+
+pub type Result<T> = std::result::Result<T, BlobError>;
+
+/// Extension trait adding the file path to I/O errors.
+trait BlobContext<T> {
+    fn with_path(self, path: &Path) -> Result<T>;
+}
+
+impl<T> BlobContext<T> for io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T> {
+        self.map_err(|source| BlobError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// One value of a blob file, as read back by `BlobStore::records()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobRecord {
+    pub cf_id: ColumnFamilyId,
+    pub key: Bytes,
+    pub value: Bytes,
+    /// Location of the record, as stored in the LSM tree.
+    pub index: BlobIndex,
+}
+
+impl BlobRecord {
+    /// Returns the number of bytes the record occupies in its file.
+    pub fn encoded_len(&self) -> u64 {
+        (BLOB_HEADER_SIZE + self.key.len() + self.value.len()) as u64
+    }
+}
+
+/// Counters reported by a blob garbage collection run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobGcStats {
+    /// Sealed blob files whose records were checked.
+    pub files_scanned: u64,
+    /// Files rewritten and deleted.
+    pub files_collected: u64,
+    /// Live values written again to the active blob file.
+    pub records_relocated: u64,
+    /// Dead bytes freed by deleting the collected files.
+    pub bytes_reclaimed: u64,
+}
+
+/// Blob file being appended to.
+struct BlobFileWriter {
+    file_id: u64,
+    path: PathBuf,
+    writer: BufWriter<Box<dyn WritableFile>>,
+    /// Size of the file once buffered data is written.
+    offset: u64,
+}
+
+impl BlobFileWriter {
+    fn create(fs: &dyn FileSystem, path: PathBuf, file_id: u64) -> Result<Self> {
+        info!(file_id, ?path, "Creating blob file");
+        let file = fs.create(&path).with_path(&path)?;
+        // The entry must survive a crash once the values it holds are synced
+        let dir = path.parent().unwrap();
+        fs.sync_dir(dir).with_path(dir)?;
+        Ok(Self {
+            file_id,
+            path,
+            writer: BufWriter::new(file),
+            offset: 0,
+        })
+    }
+
+    fn add(&mut self, cf_id: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<BlobIndex> {
+        let cf_id = cf_id.to_be_bytes();
+        let key_len = (key.len() as u32).to_be_bytes();
+        let val_len = (value.len() as u64).to_be_bytes();
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&cf_id);
+        hasher.update(&key_len);
+        hasher.update(&val_len);
+        hasher.update(key);
+        hasher.update(value);
+
+        let write = |w: &mut BufWriter<Box<dyn WritableFile>>| -> io::Result<()> {
+            w.write_all(&hasher.finalize().to_be_bytes())?;
+            w.write_all(&cf_id)?;
+            w.write_all(&key_len)?;
+            w.write_all(&val_len)?;
+            w.write_all(key)?;
+            w.write_all(value)
+        };
+        write(&mut self.writer).with_path(&self.path)?;
+
+        let index = BlobIndex::new(self.file_id, self.offset, value.len() as u64);
+        self.offset += (BLOB_HEADER_SIZE + key.len() + value.len()) as u64;
+        Ok(index)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush().with_path(&self.path)?;
+        self.writer.get_mut().sync().with_path(&self.path)
+    }
+}
+
+/// Returns the path of blob file `file_id` in the blob directory `dir`.
+pub(crate) fn blob_file_path(dir: &Path, file_id: u64) -> PathBuf {
+    dir.join(format!("{:09}.blob", file_id))
+}
+
+/// The blob files of an engine: appends to the active file and reads values
+/// back by `BlobIndex`.
+///
+/// A new file is started on open and whenever the active one reaches the
+/// configured size, so existing files are never appended to again.
+///
+/// # Examples
+///
+/// ```ignore
+/// let blobs = BlobStore::open(&fs, dir.join(BLOB_DIR_NAME), 256 * 1024 * 1024)?;
+/// let index = blobs.add(cf_id, &key, &large_value)?;
+/// blobs.sync()?;
+/// assert_eq!(blobs.get(&index)?, large_value);
+/// ```
+pub struct BlobStore {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    file_size_limit: u64,
+    /// Active file, created on the first write.
+    active: Mutex<Option<BlobFileWriter>>,
+    /// Id of the next file to create.
+    next_file_id: AtomicU64,
+}
+
+impl BlobStore {
+    /// Opens the blob files of `dir` on `fs`, creating the directory if needed.
+    pub fn open(fs: &Arc<dyn FileSystem>, dir: PathBuf, file_size_limit: u64) -> Result<Self> {
+        fs.create_dir_all(&dir).with_path(&dir)?;
+        let next_file_id = Self::list_dir(&**fs, &dir)?
+            .last()
+            .map_or(1, |(id, _)| id + 1);
+        Ok(Self {
+            fs: fs.clone(),
+            dir,
+            file_size_limit,
+            active: Mutex::new(None),
+            next_file_id: AtomicU64::new(next_file_id),
+        })
+    }
+
+    /// Returns the directory holding the blob files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of a blob file.
+    pub fn file_path(&self, file_id: u64) -> PathBuf {
+        blob_file_path(&self.dir, file_id)
+    }
+
+    /// Appends `value` to the active file, starting a new one if it is full.
+    ///
+    /// The value is only durable once `sync()` returns.
+    pub fn add(&self, cf_id: ColumnFamilyId, key: &[u8], value: &[u8]) -> Result<BlobIndex> {
+        let mut active = self.active.lock();
+        if let Some(writer) = active.as_mut()
+            && writer.offset >= self.file_size_limit
+        {
+            // Seal the full file; it may already be referenced by synced writes
+            writer.sync()?;
+            *active = None;
+        }
+        let writer = match active.as_mut() {
+            Some(writer) => writer,
+            None => {
+                let file_id = self.next_file_id.fetch_add(1, Ordering::Relaxed);
+                active.insert(BlobFileWriter::create(
+                    &*self.fs,
+                    self.file_path(file_id),
+                    file_id,
+                )?)
+            }
+        };
+        writer.add(cf_id, key, value)
+    }
+
+    /// Makes every value added so far durable.
+    pub fn sync(&self) -> Result<()> {
+        match self.active.lock().as_mut() {
+            Some(writer) => writer.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Syncs and seals the active file, if any: the next value starts a new
+    /// one, so the sealed file is never modified again.
+    pub fn seal(&self) -> Result<()> {
+        let mut active = self.active.lock();
+        if let Some(writer) = active.as_mut() {
+            writer.sync()?;
+            debug!(file_id = writer.file_id, "Blob file sealed");
+        }
+        *active = None;
+        Ok(())
+    }
+
+    /// Returns the id of the file being appended to, if any.
+    pub fn active_file_id(&self) -> Option<u64> {
+        self.active.lock().as_ref().map(|w| w.file_id)
+    }
+
+    /// Reads the value `index` points to.
+    ///
+    /// Only indexes returned by `add()` before a `sync()` are valid.
+    ///
+    /// # Errors
+    /// Returns `BlobError::InvalidIndex` if the record at `index.offset` doesn't
+    /// hold a value of `index.size` bytes, and `BlobError::CrcMismatch` if the
+    /// record is corrupted.
+    pub fn get(&self, index: &BlobIndex) -> Result<Bytes> {
+        let path = self.file_path(index.file_id);
+        let mut file = self.fs.open_sequential(&path).with_path(&path)?;
+        file.seek(SeekFrom::Start(index.offset)).with_path(&path)?;
+
+        let record = match read_record(&mut file, &path, index.file_id, index.offset) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Err(BlobError::InvalidIndex(*index)),
+            Err(BlobError::Io { source, .. }) if source.kind() == ErrorKind::UnexpectedEof => {
+                return Err(BlobError::InvalidIndex(*index));
+            }
+            Err(e) => return Err(e),
+        };
+        if record.value.len() as u64 != index.size {
+            return Err(BlobError::InvalidIndex(*index));
+        }
+        Ok(record.value)
+    }
+
+    /// Lists the blob files as `(file_id, size)`, oldest first.
+    pub fn list_files(&self) -> Result<Vec<(u64, u64)>> {
+        Self::list_dir(&*self.fs, &self.dir)
+    }
+
+    fn list_dir(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<(u64, u64)>> {
+        let mut files = Vec::new();
+        for path in fs.list_dir(dir).with_path(dir)? {
+            if path.extension().and_then(|s| s.to_str()) != Some("blob") {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+                && let Ok(id) = stem.parse::<u64>()
+            {
+                files.push((id, fs.file_size(&path).with_path(&path)?));
+            }
+        }
+        files.sort_unstable();
+        Ok(files)
+    }
+
+    /// Iterates over the records of a sealed blob file.
+    ///
+    /// A record cut short by a crash ends the iteration: it was never synced,
+    /// so no index refers to it.
+    pub fn records(&self, file_id: u64) -> Result<BlobFileIterator> {
+        let path = self.file_path(file_id);
+        let file = self.fs.open_sequential(&path).with_path(&path)?;
+        Ok(BlobFileIterator {
+            reader: BufReader::new(file),
+            path,
+            file_id,
+            offset: 0,
+        })
+    }
+
+    /// Deletes a blob file none of whose records is referenced anymore.
+    pub fn delete_file(&self, file_id: u64) -> Result<()> {
+        let path = self.file_path(file_id);
+        debug!(file_id, ?path, "Deleting blob file");
+        self.fs.remove_file(&path).with_path(&path)
+    }
+}
+
+/// Reads the record at the current position of `reader`.
+///
+/// Returns `Ok(None)` at the end of the file.
+fn read_record(
+    reader: &mut impl Read,
+    path: &Path,
+    file_id: u64,
+    offset: u64,
+) -> Result<Option<BlobRecord>> {
+    let mut header = [0u8; BLOB_HEADER_SIZE];
+    match reader.read(&mut header[..1]).with_path(path)? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut header[1..]).with_path(path)?,
+    }
+
+    let crc = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let cf_id = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let key_len = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+    let val_len = u64::from_be_bytes(header[12..20].try_into().unwrap()) as usize;
+
+    let mut body = vec![0u8; key_len + val_len];
+    reader.read_exact(&mut body).with_path(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[BLOB_CRC_SIZE..]);
+    hasher.update(&body);
+    if hasher.finalize() != crc {
+        return Err(BlobError::CrcMismatch { file_id, offset });
+    }
+
+    let body = Bytes::from(body);
+    Ok(Some(BlobRecord {
+        cf_id,
+        key: body.slice(..key_len),
+        value: body.slice(key_len..),
+        index: BlobIndex::new(file_id, offset, val_len as u64),
+    }))
+}
+
+/// Iterator over the records of a blob file, see `BlobStore::records()`.
+pub struct BlobFileIterator {
+    reader: BufReader<Box<dyn SequentialFile>>,
+    path: PathBuf,
+    file_id: u64,
+    offset: u64,
+}
+
+impl Iterator for BlobFileIterator {
+    type Item = Result<BlobRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_record(&mut self.reader, &self.path, self.file_id, self.offset) {
+            Ok(Some(record)) => {
+                self.offset += record.encoded_len();
+                Some(Ok(record))
+            }
+            Ok(None) => None,
+            Err(BlobError::Io { source, .. }) if source.kind() == ErrorKind::UnexpectedEof => {
+                debug!(
+                    self.file_id,
+                    self.offset, "Torn record at the end of blob file"
+                );
+                None
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::env::MemoryFileSystem;
+
+    fn mem_fs() -> Arc<dyn FileSystem> {
+        Arc::new(MemoryFileSystem::new())
+    }
+
+    #[test]
+    fn test_blob_store_add_get_and_rotate() {
+        let fs = mem_fs();
+        let blobs = BlobStore::open(&fs, PathBuf::from("/db/blob"), 64).unwrap();
+
+        let value = Bytes::from(vec![7u8; 100]);
+        let first = blobs.add(0, b"a", &value).unwrap();
+        let second = blobs.add(1, b"b", b"small").unwrap();
+        blobs.sync().unwrap();
+
+        // The first record filled the file, the second one started a new one
+        assert_eq!(first, BlobIndex::new(1, 0, 100));
+        assert_eq!(second, BlobIndex::new(2, 0, 5));
+        assert_eq!(blobs.get(&first).unwrap(), value);
+        assert_eq!(blobs.get(&second).unwrap(), Bytes::from("small"));
+
+        let bad = BlobIndex::new(1, 0, 99);
+        assert!(matches!(blobs.get(&bad), Err(BlobError::InvalidIndex(_))));
+
+        let records: Vec<BlobRecord> = blobs.records(1).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, Bytes::from("a"));
+        assert_eq!(records[0].index, first);
+
+        // Reopening starts a new file after the existing ones
+        drop(blobs);
+        let blobs = BlobStore::open(&fs, PathBuf::from("/db/blob"), 64).unwrap();
+        assert_eq!(blobs.add(0, b"c", b"v").unwrap().file_id, 3);
+    }
+
+    #[test]
+    fn test_blob_file_torn_tail_and_corruption() {
+        let fs = mem_fs();
+        let blobs = BlobStore::open(&fs, PathBuf::from("/db/blob"), 1 << 20).unwrap();
+        let kept = blobs.add(0, b"a", b"kept").unwrap();
+        blobs.add(0, b"b", b"torn").unwrap();
+        blobs.sync().unwrap();
+
+        let path = blobs.file_path(kept.file_id);
+        let mut data = fs.read(&path).unwrap();
+        fs.create(&path)
+            .unwrap()
+            .write_all(&data[..data.len() - 2])
+            .unwrap();
+        let records: Vec<BlobRecord> = blobs
+            .records(kept.file_id)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+
+        data[BLOB_HEADER_SIZE] ^= 0xFF;
+        fs.create(&path).unwrap().write_all(&data).unwrap();
+        assert!(matches!(
+            blobs.get(&kept),
+            Err(BlobError::CrcMismatch { offset: 0, .. })
+        ));
+    }
+}
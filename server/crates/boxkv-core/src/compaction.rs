@@ -0,0 +1,34 @@
+//! Compaction of sorted runs (MemTables and SSTables) into new SSTables.
+//!
+//! # Overview
+//!
+//! Every input run is sorted by `(key ASC, seq DESC)`, keys being ordered by the
+//! table's `Comparator`. `MergingIterator` interleaves the runs into a single
+//! stream in that same order, so all versions of a key come out adjacent,
+//! newest first. `CompactionIterator` then drops shadowed versions and
+//! combines merge operands before the entries are written out.
+//!
+//! # Range Tombstones
+//!
+//! The range tombstones of the inputs are fragmented together and handed to
+//! `CompactionIterator`, which drops the keys they cover; the tombstones
+//! themselves are written to the output's meta block (unless it is the
+//! bottommost level). Files entirely covered by newer range tombstones are
+//! dropped without being read (see `covered_files`).
+//!
+//! # Compaction Filters
+//!
+//! A column family may register a `CompactionFilter`, which sees the latest
+//! value of every key written out and keeps, removes or rewrites it, e.g. to
+//! purge soft-deleted rows. `CompactionStats` reports how many values it
+//! removed and changed.
+
+mod filter;
+mod merge;
+mod range_del;
+mod resolve;
+
+pub use filter::{CompactionFilter, FilterDecision};
+pub use merge::MergingIterator;
+pub use range_del::covered_files;
+pub use resolve::{CompactionIterator, CompactionStats};
@@ -0,0 +1,62 @@
+use bytes::Bytes;
+
+/// What a `CompactionFilter` does with a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Writes the value unchanged.
+    Keep,
+    /// Deletes the key: the value is replaced by a tombstone, or dropped
+    /// altogether at the bottommost level.
+    Remove,
+    /// Writes this value instead (keeping the TTL of an expiring value).
+    ChangeValue(Bytes),
+}
+
+/// User hook deciding, during compaction, whether each value is kept,
+/// removed or rewritten.
+///
+/// Called once per key with its latest full value (`Normal` or `Expiring`,
+/// after merge operands are resolved), at the level the compaction writes to.
+/// Tombstones, unresolved merge operands and values stored in blob files are
+/// passed through without calling the filter.
+///
+/// # Contract
+///
+/// - Compactions run synchronously, one at a time, in the thread whose flush
+///   triggered them or that called `Engine::compact()`. That thread changes
+///   over time, so the filter must be `Send + Sync`.
+/// - Values are only filtered once compacted: those still in a MemTable or
+///   in a level-0 file that was never compacted are read unfiltered.
+/// - A key is filtered every time it is compacted, so decisions should not
+///   depend on how many times that happened.
+///
+/// # Examples
+///
+/// ```ignore
+/// struct PurgeSoftDeleted;
+///
+/// impl CompactionFilter for PurgeSoftDeleted {
+///     fn name(&self) -> &str {
+///         "app.PurgeSoftDeleted"
+///     }
+///
+///     fn filter(&self, _level: usize, _key: &[u8], value: &[u8]) -> FilterDecision {
+///         if value.starts_with(b"deleted:") {
+///             FilterDecision::Remove
+///         } else {
+///             FilterDecision::Keep
+///         }
+///     }
+/// }
+///
+/// let options = ColumnFamilyOptions::default()
+///     .with_compaction_filter(Arc::new(PurgeSoftDeleted));
+/// ```
+pub trait CompactionFilter: Send + Sync {
+    /// Identifier of the filter, for logs.
+    fn name(&self) -> &str;
+
+    /// Decides what happens to `value`, the latest value of `key`, when it is
+    /// written to `level`.
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> FilterDecision;
+}
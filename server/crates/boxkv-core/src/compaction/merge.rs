@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use crate::comparator::{Comparator, compare_entries};
+use boxkv_common::types::Entry;
+
+/// K-way merge of sorted entry streams.
+///
+/// Each source must yield entries sorted by `(key ASC, seq DESC)` according to
+/// `comparator`; the merged stream keeps that order and yields every version.
+/// When two sources hold the exact same `(key, seq)`, the one listed first wins
+/// the tie and is yielded first.
+///
+/// Picking the next entry scans the head of every source, so each step costs
+/// O(k) comparisons for k sources, which is cheap for the handful of runs a
+/// compaction merges.
+///
+/// # Examples
+///
+/// ```ignore
+/// let merged: Vec<Entry> =
+///     MergingIterator::new(vec![memtable.snapshot().into_iter(), older.into_iter()], comparator)
+///         .collect();
+/// ```
+pub struct MergingIterator<I: Iterator<Item = Entry>> {
+    sources: Vec<Peekable<I>>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl<I: Iterator<Item = Entry>> MergingIterator<I> {
+    /// Creates a merging iterator over `sources`, ordered by `comparator`.
+    pub fn new(sources: Vec<I>, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+            comparator,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Entry>> Iterator for MergingIterator<I> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let mut smallest: Option<(usize, &Entry)> = None;
+        for (index, source) in self.sources.iter_mut().enumerate() {
+            let Some(entry) = source.peek() else {
+                continue;
+            };
+            let is_smaller = smallest.is_none_or(|(_, current)| {
+                compare_entries(&*self.comparator, entry, current) == Ordering::Less
+            });
+            if is_smaller {
+                smallest = Some((index, entry));
+            }
+        }
+
+        let (index, _) = smallest?;
+        self.sources[index].next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+    use bytes::Bytes;
+
+    fn run(entries: &[(&'static str, u64)]) -> std::vec::IntoIter<Entry> {
+        entries
+            .iter()
+            .map(|&(key, seq)| Entry::new_normal(seq, Bytes::from(key), Bytes::from("v")))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn keys_and_seqs(merged: impl Iterator<Item = Entry>) -> Vec<(Bytes, u64)> {
+        merged.map(|e| (e.key().clone(), e.seq())).collect()
+    }
+
+    fn expected(pairs: &[(&'static str, u64)]) -> Vec<(Bytes, u64)> {
+        pairs
+            .iter()
+            .map(|&(key, seq)| (Bytes::from(key), seq))
+            .collect()
+    }
+
+    #[test]
+    fn test_merging_iterator_interleaves_runs() {
+        let merged = MergingIterator::new(
+            vec![
+                run(&[("a", 7), ("c", 9), ("e", 5)]),
+                run(&[("b", 2), ("c", 3)]),
+                run(&[]),
+                run(&[("a", 1), ("d", 4)]),
+            ],
+            default_comparator(),
+        );
+
+        assert_eq!(
+            keys_and_seqs(merged),
+            expected(&[
+                ("a", 7),
+                ("a", 1),
+                ("b", 2),
+                ("c", 9),
+                ("c", 3),
+                ("d", 4),
+                ("e", 5),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merging_iterator_custom_comparator() {
+        let merged = MergingIterator::new(
+            vec![run(&[("c", 1), ("a", 1)]), run(&[("b", 2), ("a", 2)])],
+            Arc::new(ReverseBytewiseComparator),
+        );
+
+        assert_eq!(
+            keys_and_seqs(merged),
+            expected(&[("c", 1), ("b", 2), ("a", 2), ("a", 1)])
+        );
+    }
+}
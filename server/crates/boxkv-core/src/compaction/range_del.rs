@@ -0,0 +1,66 @@
+use crate::range_tombstone::FragmentedRangeTombstones;
+use crate::sstable::FileMetadata;
+
+/// Returns the files whose every record is deleted by `tombstones`.
+///
+/// Such files can be removed from the LSM-tree without being read or
+/// rewritten. A file's own range tombstones never count: its `largest_seq`
+/// includes them, so only strictly newer tombstones can cover it.
+pub fn covered_files<'a>(
+    files: &'a [FileMetadata],
+    tombstones: &FragmentedRangeTombstones,
+) -> Vec<&'a FileMetadata> {
+    files
+        .iter()
+        .filter(|file| tombstones.covers(&file.smallest_key, &file.largest_key, file.largest_seq))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::default_comparator;
+    use crate::range_tombstone::RangeTombstone;
+    use bytes::Bytes;
+
+    fn file(
+        file_id: u64,
+        smallest: &'static str,
+        largest: &'static str,
+        seqs: (u64, u64),
+    ) -> FileMetadata {
+        FileMetadata {
+            file_id,
+            level: 1,
+            file_size: 4096,
+            smallest_key: Bytes::from(smallest),
+            largest_key: Bytes::from(largest),
+            smallest_seq: seqs.0,
+            largest_seq: seqs.1,
+        }
+    }
+
+    #[test]
+    fn test_covered_files() {
+        let tombstones = FragmentedRangeTombstones::from_tombstones(
+            vec![RangeTombstone::new(
+                Bytes::from("tenant1:"),
+                Bytes::from("tenant1;"),
+                50,
+            )],
+            default_comparator(),
+        );
+        let files = vec![
+            file(1, "tenant1:a", "tenant1:m", (1, 20)),
+            file(2, "tenant1:n", "tenant2:a", (1, 20)),
+            file(3, "tenant1:n", "tenant1:z", (30, 60)),
+            file(4, "tenant1:x", "tenant1:z", (10, 49)),
+        ];
+
+        let dropped: Vec<u64> = covered_files(&files, &tombstones)
+            .iter()
+            .map(|f| f.file_id)
+            .collect();
+        assert_eq!(dropped, vec![1, 4]);
+    }
+}
@@ -0,0 +1,482 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use crate::compaction::{CompactionFilter, FilterDecision};
+use crate::comparator::Comparator;
+use crate::merge::{MergeContext, MergeError, MergeOperator};
+use crate::range_tombstone::FragmentedRangeTombstones;
+use boxkv_common::types::{Entry, ValueType};
+
+/// Turns the merged input of a compaction into its output.
+///
+/// The input must be sorted by `(key ASC, seq DESC)` (e.g. a `MergingIterator`).
+/// For every key:
+///
+/// - Versions shadowed by a newer full value or tombstone are dropped, as well
+///   as versions deleted by a range tombstone (see `with_range_tombstones`).
+/// - Merge operands are combined with the value below them by the merge
+///   operator. If that value lives in an older level (no base in the input and
+///   the output is not the bottommost level), operands are combined with
+///   `partial_merge` instead, or kept as-is if the operator can't.
+///
+/// Without a merge operator, operands and their base are passed through.
+///
+/// Finally, the resulting values go through the column family's
+/// `CompactionFilter`, if any (see `with_compaction_filter`); `stats()` counts
+/// what it changed.
+///
+/// # Examples
+///
+/// ```ignore
+/// let input = MergingIterator::new(runs, comparator.clone());
+/// for entry in CompactionIterator::new(input, comparator, Some(operator), is_bottommost) {
+///     builder.add(&entry?);
+/// }
+/// ```
+pub struct CompactionIterator<I: Iterator<Item = Entry>> {
+    input: Peekable<I>,
+    comparator: Arc<dyn Comparator>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// No older level can hold a base value for the operands.
+    bottommost: bool,
+    /// Range tombstones of every input.
+    range_tombstones: Option<FragmentedRangeTombstones>,
+    /// Filter applied to output values, with the output level.
+    filter: Option<(Arc<dyn CompactionFilter>, usize)>,
+    /// Output entries of the current key not yet returned.
+    pending: VecDeque<Entry>,
+    stats: CompactionStats,
+}
+
+/// Counters of a compaction's output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Entries returned by the `CompactionIterator`.
+    pub entries_written: u64,
+    /// Values the compaction filter removed.
+    pub filter_removed: u64,
+    /// Values the compaction filter replaced.
+    pub filter_changed: u64,
+}
+
+impl std::ops::AddAssign for CompactionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.entries_written += other.entries_written;
+        self.filter_removed += other.filter_removed;
+        self.filter_changed += other.filter_changed;
+    }
+}
+
+impl<I: Iterator<Item = Entry>> CompactionIterator<I> {
+    /// Creates a compaction iterator over `input`.
+    pub fn new(
+        input: I,
+        comparator: Arc<dyn Comparator>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        bottommost: bool,
+    ) -> Self {
+        Self {
+            input: input.peekable(),
+            comparator,
+            merge_operator,
+            bottommost,
+            range_tombstones: None,
+            filter: None,
+            pending: VecDeque::new(),
+            stats: CompactionStats::default(),
+        }
+    }
+
+    /// Passes every output value to `filter`, as written to `level`.
+    pub fn with_compaction_filter(
+        mut self,
+        filter: Arc<dyn CompactionFilter>,
+        level: usize,
+    ) -> Self {
+        self.filter = Some((filter, level));
+        self
+    }
+
+    /// Returns the counters of the entries returned so far.
+    pub fn stats(&self) -> CompactionStats {
+        self.stats
+    }
+
+    /// Drops the versions deleted by `range_tombstones`.
+    ///
+    /// Merge operands above a range tombstone are resolved as if the key had
+    /// been deleted with a point tombstone.
+    pub fn with_range_tombstones(mut self, range_tombstones: FragmentedRangeTombstones) -> Self {
+        self.range_tombstones = Some(range_tombstones);
+        self
+    }
+
+    /// Returns a point tombstone standing for the range tombstone deleting `entry`.
+    fn range_deletion(&self, entry: &Entry) -> Option<Entry> {
+        let covering = self.range_tombstones.as_ref()?.covering_seq(entry.key())?;
+        (entry.seq() < covering).then(|| Entry::new_tombstone(covering, entry.key().clone()))
+    }
+
+    /// Consumes the remaining (shadowed) versions of `key`.
+    fn skip_key(&mut self, key: &[u8]) {
+        while self
+            .input
+            .next_if(|e| self.comparator.compare(e.key(), key) == Ordering::Equal)
+            .is_some()
+        {}
+    }
+
+    /// Collects the operands on top of `first` and their base, if in the input.
+    fn collect_merge(&mut self, first: Entry) -> (Vec<Entry>, Option<Entry>) {
+        let mut operands = vec![first];
+        let mut base = None;
+        while let Some(entry) = self
+            .input
+            .next_if(|e| self.comparator.compare(e.key(), operands[0].key()) == Ordering::Equal)
+        {
+            if let Some(deletion) = self.range_deletion(&entry) {
+                base = Some(deletion);
+                break;
+            }
+            if entry.is_merge() {
+                operands.push(entry);
+            } else {
+                base = Some(entry);
+                break;
+            }
+        }
+        let key = operands[0].key().clone();
+        self.skip_key(&key);
+        (operands, base)
+    }
+
+    /// Combines `operands` (newest first) and their optional `base`.
+    fn combine(
+        &self,
+        operator: &dyn MergeOperator,
+        operands: Vec<Entry>,
+        base: Option<Entry>,
+    ) -> Result<Vec<Entry>, MergeError> {
+        let newest = operands[0].clone();
+
+        if base.is_none() && !self.bottommost {
+            let data: Vec<_> = operands
+                .iter()
+                .rev()
+                .filter_map(|e| match e.val() {
+                    ValueType::Merge(operand) => Some(operand.clone()),
+                    _ => None,
+                })
+                .collect();
+            return Ok(match operator.partial_merge(newest.key(), &data)? {
+                Some(operand) => vec![Entry::new_merge(
+                    newest.seq(),
+                    newest.key().clone(),
+                    operand,
+                )],
+                None => operands,
+            });
+        }
+
+        let mut context = MergeContext::new(operator);
+        for entry in operands.into_iter().chain(base) {
+            if let Some(resolved) = context.push(entry)? {
+                return Ok(vec![resolved]);
+            }
+        }
+        Ok(context.finish()?.into_iter().collect())
+    }
+
+    /// Applies the compaction filter to an output entry, returning `None` if
+    /// it is dropped.
+    fn apply_filter(&mut self, entry: Entry) -> Option<Entry> {
+        let Some((filter, level)) = self.filter.clone() else {
+            return Some(entry);
+        };
+        let (data, expire_at) = match entry.val() {
+            ValueType::Normal(data) => (data, None),
+            ValueType::Expiring { data, expire_at } => (data, Some(*expire_at)),
+            _ => return Some(entry),
+        };
+
+        match filter.filter(level, entry.key(), data) {
+            FilterDecision::Keep => Some(entry),
+            FilterDecision::Remove => {
+                self.stats.filter_removed += 1;
+                // Older versions may still live in deeper levels
+                (!self.bottommost).then(|| Entry::new_tombstone(entry.seq(), entry.key().clone()))
+            }
+            FilterDecision::ChangeValue(data) => {
+                self.stats.filter_changed += 1;
+                let value = match expire_at {
+                    Some(expire_at) => ValueType::Expiring { data, expire_at },
+                    None => ValueType::Normal(data),
+                };
+                Some(Entry::new(entry.seq(), entry.key().clone(), value))
+            }
+        }
+    }
+
+    /// Returns the next output entry, before filtering.
+    fn next_resolved(&mut self) -> Option<Result<Entry, MergeError>> {
+        if let Some(entry) = self.pending.pop_front() {
+            return Some(Ok(entry));
+        }
+
+        let mut first = self.input.next()?;
+        while self.range_deletion(&first).is_some() {
+            // Every version is deleted; the range tombstone is written on its own
+            let key = first.key().clone();
+            self.skip_key(&key);
+            first = self.input.next()?;
+        }
+
+        if !first.is_merge() {
+            let key = first.key().clone();
+            self.skip_key(&key);
+            return Some(Ok(first));
+        }
+
+        let (operands, base) = self.collect_merge(first);
+        let output = match &self.merge_operator {
+            Some(operator) => match self.combine(&**operator, operands, base) {
+                Ok(output) => output,
+                Err(e) => return Some(Err(e)),
+            },
+            None => operands.into_iter().chain(base).collect(),
+        };
+        self.pending.extend(output);
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<I: Iterator<Item = Entry>> Iterator for CompactionIterator<I> {
+    type Item = Result<Entry, MergeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.next_resolved()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(entry) = self.apply_filter(entry) {
+                self.stats.entries_written += 1;
+                return Some(Ok(entry));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::MergingIterator;
+    use crate::comparator::default_comparator;
+    use crate::merge::U64AddOperator;
+    use crate::range_tombstone::RangeTombstone;
+    use bytes::Bytes;
+
+    fn counter(value: u64) -> Bytes {
+        Bytes::copy_from_slice(&value.to_be_bytes())
+    }
+
+    fn compact(runs: Vec<Vec<Entry>>, bottommost: bool) -> Vec<Entry> {
+        let comparator = default_comparator();
+        let input = MergingIterator::new(
+            runs.into_iter().map(Vec::into_iter).collect(),
+            comparator.clone(),
+        );
+        CompactionIterator::new(
+            input,
+            comparator,
+            Some(Arc::new(U64AddOperator)),
+            bottommost,
+        )
+        .map(|e| e.unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_compaction_drops_shadowed_versions() {
+        let k = Bytes::from("k");
+        let output = compact(
+            vec![
+                vec![Entry::new_tombstone(5, k.clone())],
+                vec![
+                    Entry::new_normal(3, k.clone(), Bytes::from("old")),
+                    Entry::new_normal(4, Bytes::from("z"), Bytes::from("v")),
+                ],
+            ],
+            false,
+        );
+
+        assert_eq!(output.len(), 2);
+        assert!(output[0].is_tombstone());
+        assert_eq!(output[1].key(), &Bytes::from("z"));
+    }
+
+    #[test]
+    fn test_compaction_merges_operands_across_runs() {
+        let k = Bytes::from("counter");
+        let newer = vec![Entry::new_merge(9, k.clone(), counter(2))];
+        let older = vec![
+            Entry::new_merge(6, k.clone(), counter(3)),
+            Entry::new_normal(4, k.clone(), counter(10)),
+            Entry::new_normal(1, k.clone(), counter(1000)),
+        ];
+
+        let output = compact(vec![newer, older], false);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].seq(), 9);
+        assert!(matches!(output[0].val(), ValueType::Normal(v) if *v == counter(15)));
+    }
+
+    #[test]
+    fn test_compaction_partial_merge_without_base() {
+        let k = Bytes::from("counter");
+        let run = || {
+            vec![
+                Entry::new_merge(9, k.clone(), counter(2)),
+                Entry::new_merge(6, k.clone(), counter(3)),
+            ]
+        };
+
+        // The base may live in an older level: keep a single combined operand
+        let output = compact(vec![run()], false);
+        assert_eq!(output.len(), 1);
+        assert!(matches!(output[0].val(), ValueType::Merge(v) if *v == counter(5)));
+
+        // Nothing below the bottommost level: resolve to a full value
+        let output = compact(vec![run()], true);
+        assert_eq!(output.len(), 1);
+        assert!(matches!(output[0].val(), ValueType::Normal(v) if *v == counter(5)));
+    }
+
+    #[test]
+    fn test_compaction_drops_range_deleted_keys() {
+        let comparator = default_comparator();
+        let tombstones = FragmentedRangeTombstones::from_tombstones(
+            vec![RangeTombstone::new(Bytes::from("a"), Bytes::from("m"), 10)],
+            comparator.clone(),
+        );
+        let input = vec![
+            Entry::new_normal(3, Bytes::from("b"), Bytes::from("v")),
+            Entry::new_normal(12, Bytes::from("c"), Bytes::from("v")),
+            Entry::new_normal(2, Bytes::from("c"), Bytes::from("old")),
+            Entry::new_merge(11, Bytes::from("d"), counter(1)),
+            Entry::new_normal(5, Bytes::from("d"), counter(100)),
+            Entry::new_normal(4, Bytes::from("x"), Bytes::from("v")),
+        ];
+
+        let output: Vec<Entry> = CompactionIterator::new(
+            input.into_iter(),
+            comparator,
+            Some(Arc::new(U64AddOperator)),
+            false,
+        )
+        .with_range_tombstones(tombstones)
+        .map(|e| e.unwrap())
+        .collect();
+
+        let keys: Vec<(Bytes, u64)> = output.iter().map(|e| (e.key().clone(), e.seq())).collect();
+        assert_eq!(
+            keys,
+            vec![
+                (Bytes::from("c"), 12),
+                (Bytes::from("d"), 11),
+                (Bytes::from("x"), 4),
+            ]
+        );
+        // The operand applies to the deleted key, not to the old value
+        assert!(matches!(output[1].val(), ValueType::Normal(v) if *v == counter(1)));
+    }
+
+    /// Removes `soft-deleted` values and strips a `v1:` prefix.
+    struct TestFilter;
+
+    impl CompactionFilter for TestFilter {
+        fn name(&self) -> &str {
+            "test.TestFilter"
+        }
+
+        fn filter(&self, level: usize, _key: &[u8], value: &[u8]) -> FilterDecision {
+            assert_eq!(level, 3);
+            match value {
+                b"soft-deleted" => FilterDecision::Remove,
+                _ => match value.strip_prefix(b"v1:") {
+                    Some(rest) => FilterDecision::ChangeValue(Bytes::copy_from_slice(rest)),
+                    None => FilterDecision::Keep,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter_removes_and_rewrites_values() {
+        let input = || {
+            vec![
+                Entry::new_normal(5, Bytes::from("a"), Bytes::from("soft-deleted")),
+                Entry::new_normal(1, Bytes::from("a"), Bytes::from("live")),
+                Entry::new_expiring(6, Bytes::from("b"), Bytes::from("v1:data"), 99),
+                Entry::new_tombstone(7, Bytes::from("c")),
+                Entry::new_normal(2, Bytes::from("d"), Bytes::from("kept")),
+            ]
+        };
+        let compact = |bottommost| {
+            let mut iter = CompactionIterator::new(
+                input().into_iter(),
+                default_comparator(),
+                None,
+                bottommost,
+            )
+            .with_compaction_filter(Arc::new(TestFilter), 3);
+            let output: Vec<Entry> = iter.by_ref().map(|e| e.unwrap()).collect();
+            (output, iter.stats())
+        };
+
+        // The removed value hides older versions in deeper levels
+        let (output, stats) = compact(false);
+        assert_eq!(output.len(), 4);
+        assert!(output[0].is_tombstone());
+        assert_eq!(output[0].seq(), 5);
+        assert!(matches!(
+            output[1].val(),
+            ValueType::Expiring { data, expire_at: 99 } if *data == "data"
+        ));
+        assert!(output[2].is_tombstone());
+        assert!(matches!(output[3].val(), ValueType::Normal(v) if *v == "kept"));
+        assert_eq!(
+            stats,
+            CompactionStats {
+                entries_written: 4,
+                filter_removed: 1,
+                filter_changed: 1,
+            }
+        );
+
+        // Nothing to hide at the bottommost level
+        let (output, stats) = compact(true);
+        let keys: Vec<&Bytes> = output.iter().map(|e| e.key()).collect();
+        assert_eq!(keys, vec!["b", "c", "d"]);
+        assert_eq!(stats.entries_written, 3);
+        assert_eq!(stats.filter_removed, 1);
+    }
+
+    #[test]
+    fn test_compaction_without_operator_keeps_operands() {
+        let k = Bytes::from("k");
+        let input = vec![
+            Entry::new_merge(3, k.clone(), Bytes::from("b")),
+            Entry::new_normal(2, k.clone(), Bytes::from("a")),
+            Entry::new_normal(1, k.clone(), Bytes::from("old")),
+        ];
+
+        let output: Vec<u64> =
+            CompactionIterator::new(input.into_iter(), default_comparator(), None, true)
+                .map(|e| e.unwrap().seq())
+                .collect();
+        assert_eq!(output, vec![3, 2]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Key ordering used by every sorted structure of the storage engine.
+//!
+//! # Overview
+//!
+//! MemTables, SSTable index blocks and compaction merges all order keys with
+//! the same `Comparator`. The default is plain lexicographic byte order, which
+//! already sorts big-endian unsigned integers numerically; other orderings
+//! (e.g. reverse timestamps) plug in their own implementation.
+//!
+//! # Persistence
+//!
+//! Data written with one ordering is unreadable with another: binary searches
+//! in index blocks would silently miss keys. Every comparator therefore has a
+//! stable `name()` that is recorded in the data directory on first open and
+//! checked on every later open (see `check_persisted`).
+
+use std::cmp::Ordering;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use thiserror::Error;
+use tracing::info;
+
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::Entry;
+
+/// Name of the file recording the comparator of a data directory.
+const COMPARATOR_FILE_NAME: &str = "COMPARATOR";
+
+#[derive(Debug, Error)]
+pub enum ComparatorError {
+    /// The data was written with a different comparator.
+    #[error("Comparator mismatch: data was written with {persisted:?}, opened with {requested:?}")]
+    Mismatch {
+        persisted: String,
+        requested: String,
+    },
+
+    #[error("Failed to access comparator file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Total order over keys.
+///
+/// # Contract
+///
+/// - `compare` must be a total order and stay the same for the lifetime of the data.
+/// - `name` must uniquely identify the ordering; change it whenever the ordering changes.
+pub trait Comparator: Send + Sync {
+    /// Stable identifier persisted alongside the data.
+    fn name(&self) -> &str;
+
+    /// Compares two keys.
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+/// Lexicographic byte order (the default).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "boxkv.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Reverse lexicographic byte order, e.g. for newest-first timestamp keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "boxkv.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Returns the default comparator (`BytewiseComparator`).
+pub fn default_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+/// Returns the built-in comparator registered under `name`, if any.
+pub fn builtin_comparator(name: &str) -> Option<Arc<dyn Comparator>> {
+    match name {
+        "boxkv.BytewiseComparator" => Some(Arc::new(BytewiseComparator)),
+        "boxkv.ReverseBytewiseComparator" => Some(Arc::new(ReverseBytewiseComparator)),
+        _ => None,
+    }
+}
+
+/// Orders entries by key (using `comparator`), then by sequence number descending.
+///
+/// This is the comparator-aware counterpart of `Entry`'s `Ord` implementation,
+/// which always compares raw bytes.
+pub fn compare_entries(comparator: &dyn Comparator, a: &Entry, b: &Entry) -> Ordering {
+    comparator
+        .compare(a.key(), b.key())
+        .then_with(|| b.seq().cmp(&a.seq()))
+}
+
+/// Records the comparator of a data directory, or checks it against the recorded one.
+///
+/// # Errors
+///
+/// Returns `ComparatorError::Mismatch` if the directory was created with a
+/// comparator of a different name.
+pub fn check_persisted(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    comparator: &dyn Comparator,
+) -> Result<(), ComparatorError> {
+    let path = dir.join(COMPARATOR_FILE_NAME);
+    let io_error = |source| ComparatorError::Io {
+        path: path.clone(),
+        source,
+    };
+
+    match fs.read(&path) {
+        Ok(persisted) => {
+            let persisted = String::from_utf8_lossy(&persisted);
+            let persisted = persisted.trim_end();
+            if persisted != comparator.name() {
+                return Err(ComparatorError::Mismatch {
+                    persisted: persisted.to_string(),
+                    requested: comparator.name().to_string(),
+                });
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!(?dir, comparator = comparator.name(), "Recording comparator");
+            fs.create(&path)
+                .and_then(|mut file| writeln!(file, "{}", comparator.name()))
+                .map_err(io_error)
+        }
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::env::MemoryFileSystem;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_builtin_comparators() {
+        let bytewise = BytewiseComparator;
+        assert_eq!(bytewise.compare(b"a", b"b"), Ordering::Less);
+        assert_eq!(bytewise.compare(b"ab", b"a"), Ordering::Greater);
+        // Big-endian integers sort numerically
+        assert_eq!(
+            bytewise.compare(&255u64.to_be_bytes(), &256u64.to_be_bytes()),
+            Ordering::Less
+        );
+
+        let reverse = ReverseBytewiseComparator;
+        assert_eq!(reverse.compare(b"a", b"b"), Ordering::Greater);
+        assert_ne!(bytewise.name(), reverse.name());
+
+        for name in [bytewise.name(), reverse.name()] {
+            assert_eq!(builtin_comparator(name).unwrap().name(), name);
+        }
+        assert!(builtin_comparator("unknown").is_none());
+    }
+
+    #[test]
+    fn test_compare_entries_orders_newest_first() {
+        let reverse = ReverseBytewiseComparator;
+        let a1 = Entry::new_normal(1, Bytes::from("a"), Bytes::from("v"));
+        let a2 = Entry::new_normal(2, Bytes::from("a"), Bytes::from("v"));
+        let b1 = Entry::new_normal(1, Bytes::from("b"), Bytes::from("v"));
+
+        let mut entries = vec![a1.clone(), b1.clone(), a2.clone()];
+        entries.sort_by(|x, y| compare_entries(&reverse, x, y));
+        assert_eq!(entries, vec![b1, a2, a1]);
+    }
+
+    #[test]
+    fn test_check_persisted_comparator() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+
+        // First open records the name, later opens with the same comparator succeed
+        check_persisted(&fs, dir, &BytewiseComparator).unwrap();
+        check_persisted(&fs, dir, &BytewiseComparator).unwrap();
+
+        match check_persisted(&fs, dir, &ReverseBytewiseComparator) {
+            Err(ComparatorError::Mismatch {
+                persisted,
+                requested,
+            }) => {
+                assert_eq!(persisted, "boxkv.BytewiseComparator");
+                assert_eq!(requested, "boxkv.ReverseBytewiseComparator");
+            }
+            other => panic!("Expected Mismatch error, got {:?}", other),
+        }
+    }
+}
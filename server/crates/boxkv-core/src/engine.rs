@@ -0,0 +1,1760 @@
+//! The storage engine: write and read path over the WAL and the active MemTables.
+//!
+//! # Overview
+//!
+//! Every write is assigned the next sequence number, appended to the WAL and
+//! synced, then inserted into the active MemTable of its column family:
+//!
+//! ```text
+//! put(key, value) → lock WAL → seq = last_seq + 1 → WAL append + fsync → MemTable insert
+//! ```
+//!
+//! # Column Families
+//!
+//! The keyspace is split into column families, each with its own MemTable and
+//! `ColumnFamilyOptions` (comparator, merge operator, compaction filter).
+//! They share the WAL and the sequence numbers: every WAL record carries the
+//! id of its column family, so a `WriteBatch` spanning several of them stays
+//! atomic.
+//!
+//! The `default` column family always exists and is the one used by the
+//! methods without a `_cf` suffix. `create_column_family()` and
+//! `drop_column_family()` durably update the `COLUMN_FAMILIES` registry of the
+//! data directory before returning.
+//!
+//! # Blob Files
+//!
+//! With `min_blob_size` set, large `put` values are written once to a blob
+//! file before the WAL record, which then only holds their `BlobIndex` (see
+//! `crate::blob`). Reads resolve the index transparently.
+//! `collect_blob_garbage()` rewrites the live values of blob files holding
+//! too much dead data; writers only wait while their new indexes are applied.
+//!
+//! # Flushes
+//!
+//! A write that fills the MemTable of its column family (`memtable_size_mb`)
+//! flushes it to a new level-0 SSTable before returning, and starts an empty
+//! one; so does a write that brings all MemTables close to
+//! `write_buffer_size_mb`, for the largest ones (see `WriteBufferManager`).
+//! `flush()` and `flush_cf()` do it on demand. The manifest listing the new
+//! SSTable also records the sequence number its column family is flushed up
+//! to, and the MemTable and SSTables of the column family are swapped at once
+//! for readers.
+//!
+//! Each flush also starts a new WAL file. Older files are retired (deleted, or
+//! moved to the WAL archive if `wal_archive_dir` is set) once every column
+//! family has flushed the records they hold.
+//!
+//! # Compactions
+//!
+//! The flush that brings level 0 of a column family to
+//! `L0_COMPACTION_TRIGGER` files merges them, with the level-1 files they
+//! overlap, into a single level-1 SSTable. `compact()` and `compact_cf()`
+//! merge every SSTable into one file of the last level. Both drop shadowed
+//! and deleted versions, resolve merge operands and run the column family's
+//! `CompactionFilter`.
+//!
+//! Like flushes, compactions run in the calling thread under the WAL lock;
+//! there are no background threads. Outputs are not split by size, so each
+//! compaction rewrites all of its inputs into one file.
+//!
+//! Writes then go through the `WriteController`, which delays and stops them
+//! past the `write_stall` limits on full MemTables and level-0 files. These
+//! are only reached while flushes or compactions fail, and a stalled write
+//! retries them first; `stall_stats()` counts the stalls.
+//!
+//! # External Files
+//!
+//! `ingest_external_files()` bulk loads SSTables built by `SstFileWriter`
+//! without going through the WAL and MemTables. Each file is assigned the next
+//! sequence number for all of its records, hard-linked (or copied) into the
+//! data directory and placed at the deepest level it can go to: right above
+//! the first level holding overlapping keys. The new files become visible
+//! together once the `MANIFEST` listing them is durably replaced.
+//!
+//! The live SSTables of a column family form its `Version`. Reads gather the
+//! versions of a key from the MemTable and every SSTable that may hold it and
+//! resolve them in sequence number order, so an ingested file shadows older
+//! MemTable entries and the other way around.
+//!
+//! # Scans
+//!
+//! `scan()` returns the visible key-value pairs of a key range in comparator
+//! order. It collects the keys in range from the MemTable and the SSTables,
+//! then reads each one as `get()` does.
+//!
+//! # Conditional Writes
+//!
+//! Writers are serialized by the WAL lock, so a read followed by a write under
+//! that lock is atomic. `compare_and_swap()`, `put_if_absent()` and
+//! `delete_if_equals()` evaluate their condition against the latest visible
+//! value this way, and fail with `EngineError::ConditionFailed` carrying the
+//! current value when it doesn't hold.
+//!
+//! # Batches and Transactions
+//!
+//! A `WriteBatch` is logged as a single WAL batch record, so recovery applies
+//! all of its writes or none. Readers may still see a batch partially while
+//! its writes are being inserted into the MemTables.
+//!
+//! `begin_optimistic()` starts an `OptimisticTransaction`: its reads record the
+//! sequence number of the version they saw, and its commit checks under the
+//! WAL lock that none of these keys were written since before logging its
+//! buffered writes as one batch. Otherwise it fails with
+//! `EngineError::TransactionConflict`.
+//!
+//! `begin_pessimistic()` starts a `PessimisticTransaction`, which instead
+//! takes shared (reads) and exclusive (writes) key locks from the engine's
+//! `LockManager` and holds them until it ends, so its commit can't conflict.
+//! Deadlocks are detected when a lock request would wait; time spent waiting
+//! is reported by `lock_stats()`.
+//!
+//! Conditional writes and transactions operate on the default column family.
+//!
+//! # Recovery
+//!
+//! On open, the column families are loaded from the registry, their SSTables
+//! from the manifest, and the WAL files of the directory are replayed into new
+//! MemTables; records of dropped column families, and those already flushed,
+//! are skipped. A new WAL file is then started after the last one.
+//!
+//! All files are accessed through the engine's `FileSystem`, the local disk
+//! unless opened with `open_with_fs()`.
+//!
+//! # Change Feed
+//!
+//! `changes_since()` streams the mutations logged after a sequence number
+//! from the WAL files (and their archive), with the column family of each one
+//! and the values stored in blob files read back (see `ChangeFeed`).
+//!
+//! # Checkpoints
+//!
+//! `checkpoint()` flushes the MemTables and seals the active blob file, then
+//! writes a consistent, openable copy of the data directory, hard-linking the
+//! SSTables and blob files after writers resumed.
+//! `restore_point_in_time()` rebuilds a database from a checkpoint and the
+//! archived WAL records that followed it, up to a `RecoveryTarget`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use thiserror::Error;
+use tracing::info;
+
+use crate::blob::{BLOB_DIR_NAME, BlobError, BlobGcStats, BlobStore};
+use crate::comparator::{self, ComparatorError};
+use crate::memtable::WriteBufferManager;
+use crate::merge::{self, MergeContext, MergeError};
+use crate::sstable::{SSTableError, Table};
+use crate::wal::{Wal, WalArchive, WalError};
+use crate::write_controller::{StallStats, WriteController, WriteStallError};
+use boxkv_common::config::StorageConfig;
+use boxkv_common::env::{FileSystem, default_fs};
+use boxkv_common::types::{BlobIndex, ColumnFamilyId, DEFAULT_COLUMN_FAMILY_ID, Entry, ValueType};
+
+mod batch;
+mod changes;
+mod checkpoint;
+mod column_family;
+mod compact;
+mod flush;
+mod ingest;
+mod lock_manager;
+mod optimistic;
+mod pessimistic;
+mod restore;
+mod scan;
+#[cfg(test)]
+mod simulation;
+mod version;
+
+pub use batch::WriteBatch;
+pub use changes::ChangeFeed;
+pub(crate) use checkpoint::temp_sibling;
+pub use column_family::{
+    ColumnFamily, ColumnFamilyHandle, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME,
+};
+pub use lock_manager::{LockError, LockManager, LockMode, LockStats, TxnId};
+pub use optimistic::OptimisticTransaction;
+pub use pessimistic::PessimisticTransaction;
+
+use checkpoint::TableRemovals;
+use column_family::{ColumnFamilyDescriptor, ColumnFamilyRegistry};
+use version::{Manifest, TableFile, Version};
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Wal(#[from] WalError),
+
+    #[error(transparent)]
+    Merge(#[from] MergeError),
+
+    #[error(transparent)]
+    Lock(#[from] LockError),
+
+    #[error(transparent)]
+    Comparator(#[from] ComparatorError),
+
+    #[error(transparent)]
+    Blob(#[from] BlobError),
+
+    #[error(transparent)]
+    SSTable(#[from] SSTableError),
+
+    #[error(transparent)]
+    WriteStall(#[from] WriteStallError),
+
+    /// Merge operands were written but no merge operator is configured.
+    #[error("Merge operands found for a key but no merge operator is configured")]
+    NoMergeOperator,
+
+    /// The condition of a conditional write did not hold; nothing was written.
+    #[error("Condition failed, current value: {current:?}")]
+    ConditionFailed { current: Option<Bytes> },
+
+    /// A key read by a transaction was written before it committed.
+    #[error(
+        "Transaction conflict on key {key:?}: read at seq {read_seq}, now at seq {current_seq}"
+    )]
+    TransactionConflict {
+        key: Bytes,
+        read_seq: u64,
+        current_seq: u64,
+    },
+
+    #[error("Column family {name:?} already exists")]
+    ColumnFamilyExists { name: String },
+
+    #[error("Column family {name:?} does not exist")]
+    ColumnFamilyNotFound { name: String },
+
+    /// The handle refers to a column family dropped since.
+    #[error("Column family {name:?} was dropped")]
+    ColumnFamilyDropped { name: String },
+
+    #[error("The default column family can't be dropped")]
+    CannotDropDefaultColumnFamily,
+
+    /// A persisted column family uses a comparator or merge operator that is
+    /// neither built in nor passed to `open_with_column_families()`.
+    #[error("No options given for column family {name:?} and {reason}")]
+    MissingColumnFamilyOptions { name: String, reason: String },
+
+    #[error("Corrupted column family registry: {0}")]
+    CorruptedColumnFamilies(String),
+
+    #[error("Corrupted manifest: {0}")]
+    CorruptedManifest(String),
+
+    /// Files passed to `ingest_external_files()` can't be ingested together.
+    #[error("Invalid external files: {0}")]
+    InvalidExternalFiles(String),
+
+    #[error("Checkpoint directory {0:?} already exists")]
+    CheckpointExists(PathBuf),
+
+    #[error("Restore target directory {0:?} already exists")]
+    RestoreTargetExists(PathBuf),
+}
+
+pub type Result<T> = std::result::Result<T, EngineError>;
+
+/// Live column families, by id.
+struct ColumnFamilySet {
+    families: HashMap<ColumnFamilyId, ColumnFamilyHandle>,
+    /// Persisted counterpart of `families`.
+    registry: ColumnFamilyRegistry,
+    /// Persisted list of the SSTables of every column family.
+    manifest: Manifest,
+}
+
+impl ColumnFamilySet {
+    fn by_name(&self, name: &str) -> Option<&ColumnFamilyHandle> {
+        self.families.values().find(|cf| cf.name() == name)
+    }
+}
+
+/// Storage engine over a set of column families.
+///
+/// # Thread Safety
+///
+/// Shared behind an `Arc`: reads go straight to the MemTables and SSTables;
+/// writes, flushes, compactions, ingestions and column family changes are
+/// serialized by the WAL lock.
+///
+/// # Examples
+///
+/// ```ignore
+/// let engine = Engine::open(dir, &config.storage, ColumnFamilyOptions::default())?;
+/// engine.put(Bytes::from("lock"), Bytes::from("owner-1"))?;
+///
+/// match engine.compare_and_swap(key, Some(Bytes::from("owner-1")), Bytes::from("owner-2")) {
+///     Ok(seq) => println!("Lock taken over at seq {}", seq),
+///     Err(EngineError::ConditionFailed { current }) => println!("Held by {:?}", current),
+///     Err(e) => return Err(e),
+/// }
+///
+/// let counters = engine.create_column_family(
+///     "counters",
+///     ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+/// )?;
+/// engine.merge_cf(&counters, Bytes::from("hits"), encode(1))?;
+/// ```
+pub struct Engine {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    config: StorageConfig,
+    default_cf: ColumnFamilyHandle,
+    column_families: RwLock<ColumnFamilySet>,
+    /// Active WAL file; its lock serializes writers.
+    wal: Mutex<Wal>,
+    /// Older WAL files not retired yet, oldest first, with the last sequence
+    /// number each may hold. Only changed under the WAL lock.
+    sealed_wal_files: Mutex<Vec<(u64, u64)>>,
+    /// Receives retired WAL files, if `wal_archive_dir` is set.
+    archive: Option<WalArchive>,
+    /// Sequence number of the last applied write.
+    last_seq: AtomicU64,
+    /// Large values, referenced by `ValueType::BlobIndex` entries.
+    blobs: Arc<BlobStore>,
+    /// Held by blob garbage collection, so runs don't overlap, and by
+    /// checkpoints while they link blob files.
+    blob_gc: Mutex<()>,
+    /// SSTables whose removal waits for checkpoints to link them.
+    table_removals: Mutex<TableRemovals>,
+    /// Memory budget of all MemTables, if `write_buffer_size_mb` is set.
+    write_buffer: Option<Arc<WriteBufferManager>>,
+    /// Delays and stops writes while flushes and compactions are behind.
+    write_controller: WriteController,
+
+    /// Key locks of pessimistic transactions.
+    lock_manager: LockManager,
+    lock_timeout: Duration,
+    next_txn_id: AtomicU64,
+}
+
+impl Engine {
+    /// Opens the engine stored in `dir` with `options` for the default column
+    /// family, replaying its WAL files.
+    ///
+    /// Other column families of the directory must use built-in comparators
+    /// and merge operators, and no compaction filter (see
+    /// `open_with_column_families()`).
+    ///
+    /// # Errors
+    /// Returns `EngineError::Wal` if a WAL file is unreadable or corrupted.
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        config: &StorageConfig,
+        options: ColumnFamilyOptions,
+    ) -> Result<Self> {
+        Self::open_with_column_families(
+            dir,
+            config,
+            vec![(DEFAULT_COLUMN_FAMILY_NAME.to_string(), options)],
+        )
+    }
+
+    /// Opens the engine stored in `dir` with the given options per column family.
+    ///
+    /// Column families of the directory without options get the built-in
+    /// comparator and merge operator recorded at their creation; those
+    /// created with a compaction filter must be given their options. The
+    /// default column family gets `ColumnFamilyOptions::default()` on a new
+    /// directory.
+    ///
+    /// # Errors
+    /// Returns `EngineError::ColumnFamilyNotFound` for options of a column
+    /// family that doesn't exist, `EngineError::Comparator` if a comparator
+    /// differs from the persisted one, and
+    /// `EngineError::MissingColumnFamilyOptions` if options can't be resolved.
+    pub fn open_with_column_families(
+        dir: impl Into<PathBuf>,
+        config: &StorageConfig,
+        column_families: Vec<(String, ColumnFamilyOptions)>,
+    ) -> Result<Self> {
+        Self::open_with_fs(default_fs(), dir, config, column_families)
+    }
+
+    /// Opens the engine stored in `dir` on `fs`, as
+    /// `open_with_column_families()` does on the local disk.
+    pub fn open_with_fs(
+        fs: Arc<dyn FileSystem>,
+        dir: impl Into<PathBuf>,
+        config: &StorageConfig,
+        column_families: Vec<(String, ColumnFamilyOptions)>,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs.create_dir_all(&dir)?;
+
+        let mut options: HashMap<String, ColumnFamilyOptions> =
+            column_families.into_iter().collect();
+        let registry = match ColumnFamilyRegistry::load(&*fs, &dir)? {
+            Some(registry) => registry,
+            None => {
+                let default_options = options
+                    .get(DEFAULT_COLUMN_FAMILY_NAME)
+                    .cloned()
+                    .unwrap_or_default();
+                let registry = ColumnFamilyRegistry::new(&default_options, config.memtable_rep);
+                registry.store(&*fs, &dir)?;
+                registry
+            }
+        };
+        if let Some(name) = options.keys().find(|name| registry.find(name).is_none()) {
+            return Err(EngineError::ColumnFamilyNotFound { name: name.clone() });
+        }
+
+        let write_buffer = WriteBufferManager::from_config(config);
+        let mut families = HashMap::new();
+        for descriptor in &registry.families {
+            let mut cf_options = match options.remove(&descriptor.name) {
+                Some(cf_options) => cf_options,
+                None => builtin_options(descriptor)?,
+            };
+            cf_options
+                .memtable_rep
+                .get_or_insert(descriptor.memtable_rep);
+            if cf_options.comparator.name() != descriptor.comparator {
+                return Err(ComparatorError::Mismatch {
+                    persisted: descriptor.comparator.clone(),
+                    requested: cf_options.comparator.name().to_string(),
+                }
+                .into());
+            }
+            let (memtable, buffer_id) =
+                flush::new_memtable(config, write_buffer.as_deref(), &cf_options);
+            let cf = ColumnFamily::new(
+                descriptor.id,
+                descriptor.name.clone(),
+                cf_options,
+                memtable,
+                buffer_id,
+            );
+            families.insert(descriptor.id, Arc::new(cf));
+        }
+
+        let (mut manifest, stored) = match Manifest::load(&*fs, &dir)? {
+            Some(manifest) => (manifest, true),
+            None => (Manifest::default(), false),
+        };
+        let listed = (manifest.files.len(), manifest.flushed_seqs.len());
+        // Left by a crash while dropping a column family
+        manifest.files.retain(|f| families.contains_key(&f.cf_id));
+        manifest
+            .flushed_seqs
+            .retain(|cf_id, _| families.contains_key(cf_id));
+        let mut versions: HashMap<ColumnFamilyId, Version> = HashMap::new();
+        let mut max_table_seq = 0;
+        for file in &manifest.files {
+            let cf = &families[&file.cf_id];
+            let comparator = cf.options().comparator.clone();
+            let mut table = Table::open(
+                &*fs,
+                version::sst_file_path(&dir, file.file_id),
+                comparator.clone(),
+            )?;
+            if file.global_seq > 0 {
+                table = table.with_global_seq(file.global_seq);
+            }
+            let table = TableFile::new(file.file_id, file.level, table, &*comparator)?.ok_or_else(
+                || EngineError::CorruptedManifest(format!("SSTable {} is empty", file.file_id)),
+            )?;
+            max_table_seq = max_table_seq.max(table.meta.largest_seq);
+            versions
+                .entry(file.cf_id)
+                .or_default()
+                .add(Arc::new(table), &*comparator);
+        }
+        for (cf_id, version) in versions {
+            families[&cf_id].install_version(version);
+        }
+        if !stored || (manifest.files.len(), manifest.flushed_seqs.len()) != listed {
+            manifest.store(&*fs, &dir)?;
+        }
+        version::remove_unlisted_tables(&*fs, &dir, &manifest)?;
+
+        let blobs = Arc::new(BlobStore::open(
+            &fs,
+            dir.join(BLOB_DIR_NAME),
+            config.blob_file_size_mb * 1024 * 1024,
+        )?);
+
+        let mut replay = Wal::replay(&fs, dir.clone(), 0)?;
+        for record in replay.by_ref() {
+            let (cf_id, entry) = record?;
+            // Records of dropped column families have no live id
+            if let Some(cf) = families.get(&cf_id)
+                && entry.seq() > manifest.flushed_seq(cf_id)
+            {
+                cf.memtable()
+                    .insert(entry.seq(), entry.key().clone(), entry.val().clone());
+            }
+        }
+        // The WAL records of ingestions may be retired with their file
+        let max_flushed_seq = manifest.flushed_seqs.values().copied().max().unwrap_or(0);
+        let last_seq = replay
+            .progress()
+            .max_seq
+            .max(max_table_seq)
+            .max(max_flushed_seq);
+
+        let sealed_wal_files: Vec<(u64, u64)> = Wal::list_files(&*fs, &dir)?
+            .into_iter()
+            .map(|(file_id, _)| (file_id, last_seq))
+            .collect();
+        let file_id = sealed_wal_files
+            .last()
+            .map_or(1, |&(file_id, _)| file_id + 1);
+        let wal = Wal::create(&*fs, dir.clone(), file_id)?;
+        let archive = WalArchive::from_config(&fs, config)?;
+
+        info!(
+            ?dir,
+            file_id,
+            last_seq,
+            column_families = families.len(),
+            tables = manifest.files.len(),
+            "Engine opened"
+        );
+
+        let engine = Self {
+            fs,
+            dir,
+            config: config.clone(),
+            default_cf: families[&DEFAULT_COLUMN_FAMILY_ID].clone(),
+            column_families: RwLock::new(ColumnFamilySet {
+                families,
+                registry,
+                manifest,
+            }),
+            wal: Mutex::new(wal),
+            sealed_wal_files: Mutex::new(sealed_wal_files),
+            archive,
+            last_seq: AtomicU64::new(last_seq),
+            blobs,
+            blob_gc: Mutex::new(()),
+            table_removals: Mutex::new(TableRemovals::default()),
+            write_buffer,
+            write_controller: WriteController::from_config(config),
+            lock_manager: LockManager::new(),
+            lock_timeout: Duration::from_millis(config.lock_timeout_ms),
+            next_txn_id: AtomicU64::new(1),
+        };
+        // Those left by a crash after a flush
+        engine.retire_wal_files()?;
+        engine.update_write_pressure();
+        Ok(engine)
+    }
+
+    /// Returns the file system holding the engine's files.
+    pub fn fs(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    /// Returns the directory holding the engine's files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the sequence number of the last applied write.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq.load(Ordering::Acquire)
+    }
+
+    /// Returns the default column family.
+    pub fn default_column_family(&self) -> ColumnFamilyHandle {
+        self.default_cf.clone()
+    }
+
+    /// Returns the column family named `name`, if it exists.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamilyHandle> {
+        self.column_families.read().by_name(name).cloned()
+    }
+
+    /// Returns the names of the column families, sorted.
+    pub fn list_column_families(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .column_families
+            .read()
+            .families
+            .values()
+            .map(|cf| cf.name().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Creates an empty column family, durably recorded before returning.
+    ///
+    /// # Errors
+    /// Returns `EngineError::ColumnFamilyExists` if the name is taken.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamilyHandle> {
+        let _wal = self.wal.lock();
+        let mut set = self.column_families.write();
+        if set.by_name(name).is_some() {
+            return Err(EngineError::ColumnFamilyExists {
+                name: name.to_string(),
+            });
+        }
+
+        let mut registry = set.registry.clone();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let descriptor = ColumnFamilyDescriptor::new(id, name, &options, self.config.memtable_rep);
+        let memtable_rep = descriptor.memtable_rep;
+        registry.families.push(descriptor);
+        registry.store(&*self.fs, &self.dir)?;
+
+        let options = ColumnFamilyOptions {
+            memtable_rep: Some(memtable_rep),
+            ..options
+        };
+        let (memtable, buffer_id) =
+            flush::new_memtable(&self.config, self.write_buffer.as_deref(), &options);
+        let cf = Arc::new(ColumnFamily::new(
+            id,
+            name.to_string(),
+            options,
+            memtable,
+            buffer_id,
+        ));
+        set.families.insert(id, cf.clone());
+        set.registry = registry;
+
+        info!(name, id, "Column family created");
+        Ok(cf)
+    }
+
+    /// Drops a column family, durably recorded before returning.
+    ///
+    /// Its data is discarded and its SSTables deleted; existing handles fail with
+    /// `EngineError::ColumnFamilyDropped` from then on.
+    ///
+    /// # Errors
+    /// Returns `EngineError::CannotDropDefaultColumnFamily` for the default
+    /// column family and `EngineError::ColumnFamilyNotFound` for an unknown one.
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        let _wal = self.wal.lock();
+        let mut set = self.column_families.write();
+        let cf = set
+            .by_name(name)
+            .cloned()
+            .ok_or_else(|| EngineError::ColumnFamilyNotFound {
+                name: name.to_string(),
+            })?;
+        if cf.id() == DEFAULT_COLUMN_FAMILY_ID {
+            return Err(EngineError::CannotDropDefaultColumnFamily);
+        }
+
+        let mut registry = set.registry.clone();
+        registry.families.retain(|f| f.id != cf.id());
+        registry.store(&*self.fs, &self.dir)?;
+        let mut manifest = set.manifest.clone();
+        manifest.files.retain(|f| f.cf_id != cf.id());
+        manifest.flushed_seqs.remove(&cf.id());
+        manifest.store(&*self.fs, &self.dir)?;
+
+        set.families.remove(&cf.id());
+        set.registry = registry;
+        set.manifest = manifest;
+        drop(set);
+        cf.mark_dropped();
+        for file in cf.current_version().files() {
+            self.remove_table_file(file.table.path())?;
+        }
+        cf.install_version(Version::default());
+        if let (Some(manager), Some(id)) = (&self.write_buffer, cf.data().buffer_id) {
+            manager.unregister(id);
+        }
+        self.update_write_pressure();
+
+        info!(name, id = cf.id(), "Column family dropped");
+        Ok(())
+    }
+
+    /// Returns the latest visible value of `key`.
+    ///
+    /// Deleted and expired keys have no value; merge operands are resolved.
+    pub fn get(&self, key: &Bytes) -> Result<Option<Bytes>> {
+        self.get_cf(&self.default_cf, key)
+    }
+
+    /// Returns the latest visible value of `key` in `cf`.
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &Bytes) -> Result<Option<Bytes>> {
+        Ok(self.get_entry(cf, key)?.and_then(|e| visible_value(&e)))
+    }
+
+    /// Returns the latest version of `key` in `cf`, with merge operands and
+    /// blob indexes resolved.
+    fn get_entry(&self, cf: &ColumnFamily, key: &Bytes) -> Result<Option<Entry>> {
+        cf.check_live()?;
+        let versions = self.get_versions(cf, key)?;
+        if !versions.first().is_some_and(Entry::is_merge) {
+            return versions
+                .into_iter()
+                .next()
+                .map(|e| self.read_blob(e))
+                .transpose();
+        }
+        let operator = cf
+            .options()
+            .merge_operator
+            .as_deref()
+            .ok_or(EngineError::NoMergeOperator)?;
+        let mut context = MergeContext::new(operator);
+        for entry in versions {
+            // Only the base below the operands is read from its blob file
+            if let Some(resolved) = context.push(self.read_blob(entry)?)? {
+                return Ok(Some(resolved));
+            }
+        }
+        Ok(context.finish()?)
+    }
+
+    /// Returns the versions of `key` in the MemTable and SSTables of `cf`,
+    /// newest first, down to the first one that is not a merge operand.
+    ///
+    /// Range tombstones show as point tombstones (see
+    /// `FragmentedRangeTombstones::shadow_versions()`).
+    fn get_versions(&self, cf: &ColumnFamily, key: &Bytes) -> Result<Vec<Entry>> {
+        let data = cf.data();
+        let mut versions = data.memtable.get_versions(key);
+        let tables = &data.version;
+        if tables.files().next().is_none() {
+            return Ok(versions);
+        }
+        versions.extend(tables.get_versions(key, &*cf.options().comparator)?);
+        versions.sort_by_key(|e| std::cmp::Reverse(e.seq()));
+        if let Some(end) = versions.iter().position(|e| !e.is_merge()) {
+            versions.truncate(end + 1);
+        }
+        Ok(versions)
+    }
+
+    /// Returns `true` if the latest full version of `key` in `cf` still
+    /// points to the blob record at `index`.
+    fn is_live_blob(&self, cf: &ColumnFamily, key: &Bytes, index: &BlobIndex) -> Result<bool> {
+        Ok(self
+            .get_versions(cf, key)?
+            .into_iter()
+            .find(|e| !e.is_merge())
+            .is_some_and(|e| matches!(e.val(), ValueType::BlobIndex(i) if i == index)))
+    }
+
+    /// Replaces a blob index by the value it points to.
+    fn read_blob(&self, entry: Entry) -> Result<Entry> {
+        resolve_blob(&self.blobs, entry)
+    }
+
+    /// Moves a value of at least `min_blob_size` bytes to the active blob
+    /// file, returning its index instead.
+    ///
+    /// The caller syncs the blob store before logging the index.
+    fn separate_value(
+        &self,
+        cf: &ColumnFamily,
+        key: &Bytes,
+        value: ValueType,
+    ) -> Result<ValueType> {
+        let min_size = self.config.min_blob_size;
+        match value {
+            ValueType::Normal(data) if min_size > 0 && data.len() >= min_size => {
+                Ok(ValueType::BlobIndex(self.blobs.add(cf.id(), key, &data)?))
+            }
+            value => Ok(value),
+        }
+    }
+
+    /// Rewrites the sealed blob files whose fraction of dead bytes reached
+    /// `blob_gc_garbage_ratio`, then deletes them.
+    ///
+    /// Live values are copied to the active blob file without blocking
+    /// writers (merge operands on top of them are folded in). The WAL lock is
+    /// then only taken to log and apply their new indexes, as one relocation
+    /// record, for the keys not written since they were read. Relocated keys
+    /// get new sequence numbers, which makes optimistic transactions that read
+    /// them conflict, but the change feed doesn't report them. A file whose
+    /// values couldn't all be moved is kept for the next run.
+    pub fn collect_blob_garbage(&self) -> Result<BlobGcStats> {
+        let _gc = self.blob_gc.lock();
+        let families = self.column_families.read().families.clone();
+        let active = self.blobs.active_file_id();
+        let mut stats = BlobGcStats::default();
+
+        for (file_id, file_size) in self.blobs.list_files()? {
+            if Some(file_id) == active {
+                continue;
+            }
+            stats.files_scanned += 1;
+
+            let mut live = Vec::new();
+            let mut live_bytes = 0;
+            for record in self.blobs.records(file_id)? {
+                let record = record?;
+                if let Some(cf) = families.get(&record.cf_id)
+                    && self.is_live_blob(cf, &record.key, &record.index)?
+                {
+                    live_bytes += record.encoded_len();
+                    live.push((cf.clone(), record));
+                }
+            }
+            let garbage = file_size.saturating_sub(live_bytes);
+            if (garbage as f64) < self.config.blob_gc_garbage_ratio * file_size as f64 {
+                continue;
+            }
+
+            let mut moves = Vec::with_capacity(live.len());
+            for (cf, record) in live {
+                let Some(newest) = self.get_versions(&cf, &record.key)?.into_iter().next() else {
+                    continue;
+                };
+                let value = match newest.val() {
+                    ValueType::BlobIndex(index) if *index == record.index => {
+                        ValueType::Normal(record.value)
+                    }
+                    ValueType::Merge(_) => match self.get_entry(&cf, &record.key)? {
+                        Some(entry) => entry.val().clone(),
+                        None => continue,
+                    },
+                    // Overwritten since the file was scanned
+                    _ => continue,
+                };
+                let value = self.separate_value(&cf, &record.key, value)?;
+                moves.push(BlobMove {
+                    cf,
+                    key: record.key,
+                    index: record.index,
+                    read_seq: newest.seq(),
+                    value,
+                });
+            }
+            self.blobs.sync()?;
+
+            let (relocated, kept) = self.install_blob_moves(moves)?;
+            stats.records_relocated += relocated;
+            if kept {
+                info!(file_id, "Blob file still referenced, keeping it");
+                continue;
+            }
+            self.blobs.delete_file(file_id)?;
+            stats.files_collected += 1;
+            stats.bytes_reclaimed += garbage;
+        }
+
+        info!(?stats, "Blob garbage collected");
+        Ok(stats)
+    }
+
+    /// Logs and applies the values moved by blob garbage collection whose key
+    /// wasn't written since they were read, as one relocation record.
+    ///
+    /// Returns the number of values moved, and whether one left in place is
+    /// still referenced (e.g. by merge operands written on top of it).
+    fn install_blob_moves(&self, moves: Vec<BlobMove>) -> Result<(u64, bool)> {
+        let mut wal = self.wal.lock();
+        let first_seq = self.last_seq() + 1;
+        let mut families = Vec::with_capacity(moves.len());
+        let mut entries = Vec::with_capacity(moves.len());
+        let mut kept = false;
+        for m in moves {
+            // Values of dropped column families are dead
+            if m.cf.check_live().is_err() {
+                continue;
+            }
+            let current_seq = self
+                .get_versions(&m.cf, &m.key)?
+                .first()
+                .map_or(0, Entry::seq);
+            if current_seq != m.read_seq {
+                kept |= self.is_live_blob(&m.cf, &m.key, &m.index)?;
+                continue;
+            }
+            let seq = first_seq + entries.len() as u64;
+            entries.push((m.cf.id(), Entry::new(seq, m.key, m.value)));
+            families.push(m.cf);
+        }
+        let Some((_, last)) = entries.last() else {
+            return Ok((0, kept));
+        };
+        let last_seq = last.seq();
+
+        wal.append_relocations(&entries)?;
+        wal.sync()?;
+
+        for (cf, (_, entry)) in families.iter().zip(&entries) {
+            cf.memtable()
+                .insert(entry.seq(), entry.key().clone(), entry.val().clone());
+        }
+        self.last_seq.store(last_seq, Ordering::Release);
+        let written: Vec<&ColumnFamily> = families.iter().map(|cf| &**cf).collect();
+        self.flush_if_needed(&mut wal, &written);
+        Ok((entries.len() as u64, kept))
+    }
+
+    /// Stores `value` for `key`, returning the write's sequence number.
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<u64> {
+        self.write_one(&self.default_cf, key, ValueType::Normal(value))
+    }
+
+    /// Stores `value` for `key` until the Unix time `expire_at` (in seconds),
+    /// returning the write's sequence number. Reads treat the key as deleted
+    /// from then on.
+    pub fn put_expiring(&self, key: Bytes, value: Bytes, expire_at: u64) -> Result<u64> {
+        self.write_one(
+            &self.default_cf,
+            key,
+            ValueType::Expiring {
+                data: value,
+                expire_at,
+            },
+        )
+    }
+
+    /// Deletes `key`, returning the write's sequence number.
+    pub fn delete(&self, key: Bytes) -> Result<u64> {
+        self.write_one(&self.default_cf, key, ValueType::Tombstone)
+    }
+
+    /// Appends a merge operand to `key`, returning the write's sequence number.
+    pub fn merge(&self, key: Bytes, operand: Bytes) -> Result<u64> {
+        self.write_one(&self.default_cf, key, ValueType::Merge(operand))
+    }
+
+    /// Deletes every key in `[start, end)`, returning the write's sequence number.
+    pub fn delete_range(&self, start: Bytes, end: Bytes) -> Result<u64> {
+        self.write_one(&self.default_cf, start, ValueType::RangeTombstone { end })
+    }
+
+    /// Stores `value` for `key` in `cf`, returning the write's sequence number.
+    pub fn put_cf(&self, cf: &ColumnFamily, key: Bytes, value: Bytes) -> Result<u64> {
+        self.write_one(cf, key, ValueType::Normal(value))
+    }
+
+    /// Deletes `key` from `cf`, returning the write's sequence number.
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: Bytes) -> Result<u64> {
+        self.write_one(cf, key, ValueType::Tombstone)
+    }
+
+    /// Appends a merge operand to `key` in `cf`, returning the write's sequence number.
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: Bytes, operand: Bytes) -> Result<u64> {
+        self.write_one(cf, key, ValueType::Merge(operand))
+    }
+
+    /// Deletes every key of `cf` in `[start, end)`, returning the write's sequence number.
+    pub fn delete_range_cf(&self, cf: &ColumnFamily, start: Bytes, end: Bytes) -> Result<u64> {
+        self.write_one(cf, start, ValueType::RangeTombstone { end })
+    }
+
+    /// Applies every write of `batch` atomically, returning the sequence number
+    /// of the last one.
+    ///
+    /// # Errors
+    /// Returns `EngineError::ColumnFamilyDropped` (writing nothing) if one of
+    /// the column families written to was dropped.
+    pub fn write(&self, batch: WriteBatch) -> Result<u64> {
+        self.throttle_write()?;
+        let mut wal = self.wal.lock();
+        self.write_batch_locked(&mut wal, batch)
+    }
+
+    /// Starts an optimistic transaction (see `OptimisticTransaction`).
+    pub fn begin_optimistic(&self) -> OptimisticTransaction<'_> {
+        OptimisticTransaction::new(self)
+    }
+
+    /// Starts an optimistic transaction holding `engine` instead of borrowing it.
+    pub fn begin_optimistic_shared(engine: &Arc<Self>) -> OptimisticTransaction<'static> {
+        OptimisticTransaction::shared(engine.clone())
+    }
+
+    /// Starts a pessimistic transaction (see `PessimisticTransaction`).
+    pub fn begin_pessimistic(&self) -> PessimisticTransaction<'_> {
+        let id = self.next_txn_id.fetch_add(1, Ordering::Relaxed);
+        PessimisticTransaction::new(self, id)
+    }
+
+    /// Returns the lock contention counters of pessimistic transactions.
+    pub fn lock_stats(&self) -> LockStats {
+        self.lock_manager.stats()
+    }
+
+    /// Returns the counters of the write delays and stops applied so far.
+    pub fn stall_stats(&self) -> StallStats {
+        self.write_controller.stats()
+    }
+
+    /// Applies `batch` if every key of `reads` is still at the recorded
+    /// sequence number (0 for a missing key) in the default column family.
+    fn commit_validated(&self, reads: &HashMap<Bytes, u64>, batch: WriteBatch) -> Result<u64> {
+        self.throttle_write()?;
+        let mut wal = self.wal.lock();
+        for (key, &read_seq) in reads {
+            let current_seq = self
+                .get_versions(&self.default_cf, key)?
+                .first()
+                .map_or(0, |e| e.seq());
+            if current_seq != read_seq {
+                return Err(EngineError::TransactionConflict {
+                    key: key.clone(),
+                    read_seq,
+                    current_seq,
+                });
+            }
+        }
+        self.write_batch_locked(&mut wal, batch)
+    }
+
+    /// Stores `new` if the current value of `key` is `expected` (`None` for a
+    /// missing key).
+    ///
+    /// # Errors
+    /// Returns `EngineError::ConditionFailed` with the current value otherwise.
+    pub fn compare_and_swap(&self, key: Bytes, expected: Option<Bytes>, new: Bytes) -> Result<u64> {
+        self.write_if(key, expected.as_ref(), ValueType::Normal(new))
+    }
+
+    /// Stores `value` if `key` has no value (missing, deleted or expired).
+    ///
+    /// # Errors
+    /// Returns `EngineError::ConditionFailed` with the current value otherwise.
+    pub fn put_if_absent(&self, key: Bytes, value: Bytes) -> Result<u64> {
+        self.write_if(key, None, ValueType::Normal(value))
+    }
+
+    /// Deletes `key` if its current value is `expected`.
+    ///
+    /// # Errors
+    /// Returns `EngineError::ConditionFailed` with the current value otherwise.
+    pub fn delete_if_equals(&self, key: Bytes, expected: Bytes) -> Result<u64> {
+        self.write_if(key, Some(&expected), ValueType::Tombstone)
+    }
+
+    /// Writes `value` if the current value of `key` is `expected`, atomically.
+    fn write_if(&self, key: Bytes, expected: Option<&Bytes>, value: ValueType) -> Result<u64> {
+        self.throttle_write()?;
+        let mut wal = self.wal.lock();
+        let current = self.get(&key)?;
+        if current.as_ref() != expected {
+            return Err(EngineError::ConditionFailed { current });
+        }
+        self.write_locked(&mut wal, &self.default_cf, key, value)
+    }
+
+    fn write_one(&self, cf: &ColumnFamily, key: Bytes, value: ValueType) -> Result<u64> {
+        self.throttle_write()?;
+        let mut wal = self.wal.lock();
+        self.write_locked(&mut wal, cf, key, value)
+    }
+
+    /// Logs and applies a write to `cf` at the next sequence number.
+    ///
+    /// The caller holds the WAL lock, so no other write (or drop of `cf`)
+    /// can interleave.
+    fn write_locked(
+        &self,
+        wal: &mut Wal,
+        cf: &ColumnFamily,
+        key: Bytes,
+        value: ValueType,
+    ) -> Result<u64> {
+        cf.check_live()?;
+        let value = self.separate_value(cf, &key, value)?;
+        if value.is_blob_index() {
+            self.blobs.sync()?;
+        }
+        let seq = self.last_seq() + 1;
+        let entry = Entry::new(seq, key, value);
+        wal.append(cf.id(), &entry)?;
+        wal.sync()?;
+
+        cf.memtable()
+            .insert(seq, entry.key().clone(), entry.val().clone());
+        self.last_seq.store(seq, Ordering::Release);
+        self.flush_if_needed(wal, &[cf]);
+        Ok(seq)
+    }
+
+    /// Logs `batch` as one WAL record and applies it.
+    ///
+    /// The caller holds the WAL lock. An empty batch writes nothing.
+    fn write_batch_locked(&self, wal: &mut Wal, batch: WriteBatch) -> Result<u64> {
+        let first_seq = self.last_seq() + 1;
+        let mut families = Vec::with_capacity(batch.len());
+        let mut entries = Vec::with_capacity(batch.len());
+        let ops = batch.into_ops();
+        for (cf, ..) in &ops {
+            cf.as_deref().unwrap_or(&self.default_cf).check_live()?;
+        }
+        for ((cf, key, value), seq) in ops.into_iter().zip(first_seq..) {
+            let cf = cf.unwrap_or_else(|| self.default_cf.clone());
+            let value = self.separate_value(&cf, &key, value)?;
+            entries.push((cf.id(), Entry::new(seq, key, value)));
+            families.push(cf);
+        }
+        let Some((_, last)) = entries.last() else {
+            return Ok(self.last_seq());
+        };
+        let last_seq = last.seq();
+        if entries.iter().any(|(_, e)| e.val().is_blob_index()) {
+            self.blobs.sync()?;
+        }
+
+        wal.append_batch(&entries)?;
+        wal.sync()?;
+
+        for (cf, (_, entry)) in families.iter().zip(entries) {
+            cf.memtable()
+                .insert(entry.seq(), entry.key().clone(), entry.val().clone());
+        }
+        self.last_seq.store(last_seq, Ordering::Release);
+        let written: Vec<&ColumnFamily> = families.iter().map(|cf| &**cf).collect();
+        self.flush_if_needed(wal, &written);
+        Ok(last_seq)
+    }
+}
+
+/// Resolves the options of a persisted column family from built-ins.
+fn builtin_options(descriptor: &ColumnFamilyDescriptor) -> Result<ColumnFamilyOptions> {
+    let missing = |reason: String| EngineError::MissingColumnFamilyOptions {
+        name: descriptor.name.clone(),
+        reason,
+    };
+
+    let comparator = comparator::builtin_comparator(&descriptor.comparator).ok_or_else(|| {
+        missing(format!(
+            "comparator {:?} is not built in",
+            descriptor.comparator
+        ))
+    })?;
+    let merge_operator = match &descriptor.merge_operator {
+        Some(name) => Some(
+            merge::builtin_operator(name)
+                .ok_or_else(|| missing(format!("merge operator {:?} is not built in", name)))?,
+        ),
+        None => None,
+    };
+    // Never built in: leaving it out would silently stop filtering
+    if let Some(name) = &descriptor.compaction_filter {
+        return Err(missing(format!(
+            "compaction filter {:?} is not built in",
+            name
+        )));
+    }
+    Ok(ColumnFamilyOptions {
+        comparator,
+        merge_operator,
+        compaction_filter: None,
+        memtable_rep: Some(descriptor.memtable_rep),
+    })
+}
+
+/// Returns the value a reader sees for `entry`, if any.
+fn visible_value(entry: &Entry) -> Option<Bytes> {
+    match entry.val() {
+        ValueType::Normal(data) => Some(data.clone()),
+        ValueType::Expiring { data, expire_at } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            (*expire_at > now).then(|| data.clone())
+        }
+        // Blob indexes are resolved by `Engine::get_entry()`
+        ValueType::Tombstone
+        | ValueType::Merge(_)
+        | ValueType::RangeTombstone { .. }
+        | ValueType::BlobIndex(_) => None,
+    }
+}
+
+/// A live value copied to the active blob file by blob garbage collection,
+/// to be installed if its key wasn't written since.
+struct BlobMove {
+    cf: ColumnFamilyHandle,
+    key: Bytes,
+    /// Old location of the value.
+    index: BlobIndex,
+    /// Sequence number of the newest version of the key when it was read.
+    read_seq: u64,
+    /// New value of the key, usually an index into the active blob file.
+    value: ValueType,
+}
+
+/// Replaces a blob index by the value it points to in `blobs`.
+fn resolve_blob(blobs: &BlobStore, entry: Entry) -> Result<Entry> {
+    match entry.val() {
+        ValueType::BlobIndex(index) => {
+            let value = blobs.get(index)?;
+            Ok(Entry::new_normal(entry.seq(), entry.key().clone(), value))
+        }
+        _ => Ok(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::ReverseBytewiseComparator;
+    use crate::merge::U64AddOperator;
+    use boxkv_common::config::MemTableRepKind;
+    use boxkv_common::env::MemoryFileSystem;
+    use std::thread;
+
+    fn mem_fs() -> Arc<dyn FileSystem> {
+        Arc::new(MemoryFileSystem::new())
+    }
+
+    fn open_with(
+        fs: &Arc<dyn FileSystem>,
+        config: &StorageConfig,
+        options: ColumnFamilyOptions,
+    ) -> Engine {
+        let column_families = vec![(DEFAULT_COLUMN_FAMILY_NAME.to_string(), options)];
+        Engine::open_with_fs(fs.clone(), "/db", config, column_families).unwrap()
+    }
+
+    fn open(fs: &Arc<dyn FileSystem>) -> Engine {
+        let options = ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator));
+        open_with(fs, &StorageConfig::default(), options)
+    }
+
+    fn counter(value: u64) -> Bytes {
+        Bytes::copy_from_slice(&value.to_be_bytes())
+    }
+
+    #[test]
+    fn test_engine_reads_own_writes_and_recovers() {
+        let fs = mem_fs();
+        {
+            let engine = open(&fs);
+            engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+            engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+            engine.delete(Bytes::from("a")).unwrap();
+            engine.merge(Bytes::from("hits"), counter(3)).unwrap();
+            engine.merge(Bytes::from("hits"), counter(4)).unwrap();
+            engine
+                .delete_range(Bytes::from("b"), Bytes::from("c"))
+                .unwrap();
+            assert_eq!(engine.last_seq(), 6);
+        }
+
+        let engine = open(&fs);
+        assert_eq!(engine.last_seq(), 6);
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), None);
+        assert_eq!(engine.get(&Bytes::from("b")).unwrap(), None);
+        assert_eq!(engine.get(&Bytes::from("hits")).unwrap(), Some(counter(7)));
+
+        assert_eq!(engine.put(Bytes::from("b"), Bytes::from("3")).unwrap(), 7);
+        assert_eq!(
+            engine.get(&Bytes::from("b")).unwrap(),
+            Some(Bytes::from("3"))
+        );
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let fs = mem_fs();
+        let engine = open(&fs);
+        let key = Bytes::from("lock");
+
+        engine
+            .put_if_absent(key.clone(), Bytes::from("owner-1"))
+            .unwrap();
+        let err = engine
+            .put_if_absent(key.clone(), Bytes::from("owner-2"))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::ConditionFailed { current: Some(v) } if v == "owner-1"));
+
+        let err = engine
+            .compare_and_swap(key.clone(), Some(Bytes::from("owner-2")), Bytes::from("x"))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::ConditionFailed { current: Some(_) }
+        ));
+        engine
+            .compare_and_swap(
+                key.clone(),
+                Some(Bytes::from("owner-1")),
+                Bytes::from("owner-2"),
+            )
+            .unwrap();
+
+        let err = engine
+            .delete_if_equals(key.clone(), Bytes::from("owner-1"))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::ConditionFailed { .. }));
+        engine
+            .delete_if_equals(key.clone(), Bytes::from("owner-2"))
+            .unwrap();
+
+        // Deleted keys are absent again
+        let err = engine
+            .delete_if_equals(key.clone(), Bytes::from("owner-2"))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::ConditionFailed { current: None }
+        ));
+        engine
+            .compare_and_swap(key.clone(), None, Bytes::from("owner-3"))
+            .unwrap();
+        assert_eq!(engine.get(&key).unwrap(), Some(Bytes::from("owner-3")));
+    }
+
+    #[test]
+    fn test_write_batch_recovers_atomically() {
+        let fs = mem_fs();
+        {
+            let engine = open(&fs);
+            let mut batch = WriteBatch::new();
+            batch.put(Bytes::from("from"), Bytes::from("90"));
+            batch.put(Bytes::from("to"), Bytes::from("110"));
+            batch.merge(Bytes::from("transfers"), counter(1));
+            assert_eq!(engine.write(batch).unwrap(), 3);
+            assert_eq!(engine.write(WriteBatch::new()).unwrap(), 3);
+        }
+
+        let engine = open(&fs);
+        assert_eq!(engine.last_seq(), 3);
+        assert_eq!(
+            engine.get(&Bytes::from("to")).unwrap(),
+            Some(Bytes::from("110"))
+        );
+        assert_eq!(
+            engine.get(&Bytes::from("transfers")).unwrap(),
+            Some(counter(1))
+        );
+    }
+
+    #[test]
+    fn test_optimistic_transaction_commit() {
+        let fs = mem_fs();
+        let engine = open(&fs);
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+
+        let mut txn = engine.begin_optimistic();
+        assert_eq!(txn.get(&Bytes::from("a")).unwrap(), Some(Bytes::from("1")));
+        assert_eq!(txn.get(&Bytes::from("b")).unwrap(), None);
+        txn.put(Bytes::from("b"), Bytes::from("2"));
+        txn.delete(Bytes::from("a"));
+        // Reads see the transaction's own writes
+        assert_eq!(txn.get(&Bytes::from("a")).unwrap(), None);
+        assert_eq!(txn.get(&Bytes::from("b")).unwrap(), Some(Bytes::from("2")));
+        // Writes stay buffered until commit
+        assert_eq!(engine.get(&Bytes::from("b")).unwrap(), None);
+
+        assert_eq!(txn.commit().unwrap(), 3);
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), None);
+        assert_eq!(
+            engine.get(&Bytes::from("b")).unwrap(),
+            Some(Bytes::from("2"))
+        );
+    }
+
+    #[test]
+    fn test_optimistic_transaction_conflict() {
+        let fs = mem_fs();
+        let engine = open(&fs);
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+
+        let mut txn = engine.begin_optimistic();
+        txn.get(&Bytes::from("a")).unwrap();
+        txn.get(&Bytes::from("missing")).unwrap();
+        txn.put(Bytes::from("c"), Bytes::from("3"));
+        engine
+            .put(Bytes::from("missing"), Bytes::from("now here"))
+            .unwrap();
+
+        let err = txn.commit().unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::TransactionConflict { key, read_seq: 0, current_seq: 2 } if key == "missing"
+        ));
+        assert_eq!(engine.get(&Bytes::from("c")).unwrap(), None);
+
+        // Writes to keys only written (not read) don't conflict
+        let mut txn = engine.begin_optimistic();
+        txn.put(Bytes::from("a"), Bytes::from("txn"));
+        engine.put(Bytes::from("a"), Bytes::from("other")).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(
+            engine.get(&Bytes::from("a")).unwrap(),
+            Some(Bytes::from("txn"))
+        );
+    }
+
+    #[test]
+    fn test_optimistic_transaction_scan() {
+        let fs = mem_fs();
+        let engine = Arc::new(open(&fs));
+        for key in ["a", "b", "c", "d"] {
+            engine.put(Bytes::from(key), Bytes::from(key)).unwrap();
+        }
+
+        let mut txn = Engine::begin_optimistic_shared(&engine);
+        txn.delete(Bytes::from("a"));
+        txn.put(Bytes::from("bb"), Bytes::from("txn"));
+        let rows = txn.scan(&Bytes::from("a"), None, 3).unwrap();
+        let keys: Vec<&[u8]> = rows.iter().map(|(k, _)| k.as_ref()).collect();
+        assert_eq!(keys, vec![b"b".as_ref(), b"bb", b"c"]);
+        assert_eq!(rows[1].1, Bytes::from("txn"));
+
+        // Keys returned by the scan are validated at commit
+        engine.put(Bytes::from("c"), Bytes::from("other")).unwrap();
+        assert!(matches!(
+            txn.commit(),
+            Err(EngineError::TransactionConflict { key, .. }) if key == "c"
+        ));
+        assert_eq!(
+            engine.get(&Bytes::from("a")).unwrap(),
+            Some(Bytes::from("a"))
+        );
+    }
+
+    #[test]
+    fn test_pessimistic_transactions_serialize_updates() {
+        let fs = mem_fs();
+        let engine = Arc::new(open(&fs));
+        let key = Bytes::from("stock");
+        engine.put(key.clone(), counter(100)).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let engine = engine.clone();
+                let key = key.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let mut txn = engine.begin_pessimistic();
+                        let stock = txn.get_for_update(&key).unwrap().unwrap();
+                        let stock = u64::from_be_bytes(stock[..].try_into().unwrap());
+                        txn.put(key.clone(), counter(stock - 1)).unwrap();
+                        txn.commit().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(engine.get(&key).unwrap(), Some(counter(60)));
+    }
+
+    #[test]
+    fn test_pessimistic_transaction_lock_timeout_and_rollback() {
+        let fs = mem_fs();
+        let config = StorageConfig {
+            lock_timeout_ms: 10,
+            ..StorageConfig::default()
+        };
+        let engine = open_with(&fs, &config, ColumnFamilyOptions::default());
+
+        let mut reader = engine.begin_pessimistic();
+        assert_eq!(reader.get(&Bytes::from("a")).unwrap(), None);
+
+        let mut writer = engine.begin_pessimistic();
+        let err = writer.put(Bytes::from("a"), Bytes::from("1")).unwrap_err();
+        assert!(matches!(err, EngineError::Lock(LockError::Timeout { .. })));
+        writer.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+
+        // Dropping a transaction releases its locks and discards its writes
+        drop(reader);
+        writer.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        drop(writer);
+        assert_eq!(engine.get(&Bytes::from("b")).unwrap(), None);
+
+        let stats = engine.lock_stats();
+        assert_eq!(stats.timeouts, 1);
+        assert!(stats.lock_wait_time >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_compare_and_swap_is_atomic() {
+        let fs = mem_fs();
+        let engine = Arc::new(open(&fs));
+        let key = Bytes::from("counter");
+        engine.put(key.clone(), counter(0)).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let engine = engine.clone();
+                let key = key.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        let mut current = engine.get(&key).unwrap();
+                        loop {
+                            let value =
+                                u64::from_be_bytes(current.as_deref().unwrap().try_into().unwrap());
+                            match engine.compare_and_swap(key.clone(), current, counter(value + 1))
+                            {
+                                Ok(_) => break,
+                                Err(EngineError::ConditionFailed { current: latest }) => {
+                                    current = latest
+                                }
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(engine.get(&key).unwrap(), Some(counter(100)));
+    }
+
+    #[test]
+    fn test_column_families_are_independent_and_persisted() {
+        let fs = mem_fs();
+        let key = Bytes::from("k");
+        {
+            let engine = open(&fs);
+            let users = engine
+                .create_column_family("users", ColumnFamilyOptions::default())
+                .unwrap();
+            let err = engine
+                .create_column_family("users", ColumnFamilyOptions::default())
+                .unwrap_err();
+            assert!(matches!(err, EngineError::ColumnFamilyExists { .. }));
+
+            engine.put(key.clone(), Bytes::from("default")).unwrap();
+            engine
+                .put_cf(&users, key.clone(), Bytes::from("users"))
+                .unwrap();
+            assert_eq!(engine.get(&key).unwrap(), Some(Bytes::from("default")));
+            assert_eq!(
+                engine.get_cf(&users, &key).unwrap(),
+                Some(Bytes::from("users"))
+            );
+
+            // A dropped column family rejects its handles and stays dropped
+            let logs = engine
+                .create_column_family("logs", ColumnFamilyOptions::default())
+                .unwrap();
+            engine
+                .put_cf(&logs, key.clone(), Bytes::from("logs"))
+                .unwrap();
+            engine.drop_column_family("logs").unwrap();
+            let err = engine
+                .put_cf(&logs, key.clone(), Bytes::from("x"))
+                .unwrap_err();
+            assert!(matches!(err, EngineError::ColumnFamilyDropped { .. }));
+            assert!(matches!(
+                engine.drop_column_family(DEFAULT_COLUMN_FAMILY_NAME),
+                Err(EngineError::CannotDropDefaultColumnFamily)
+            ));
+        }
+
+        let engine = open(&fs);
+        assert_eq!(engine.list_column_families(), vec!["default", "users"]);
+        let users = engine.column_family("users").unwrap();
+        assert_eq!(
+            engine.get_cf(&users, &key).unwrap(),
+            Some(Bytes::from("users"))
+        );
+
+        // Ids are not reused: the old records of "logs" don't resurface
+        let logs = engine
+            .create_column_family("logs", ColumnFamilyOptions::default())
+            .unwrap();
+        assert_eq!(logs.id(), 3);
+        assert_eq!(engine.get_cf(&logs, &key).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_batch_spans_column_families() {
+        let fs = mem_fs();
+        let counters_options =
+            || ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator));
+        {
+            let engine = open(&fs);
+            let counters = engine
+                .create_column_family("counters", counters_options())
+                .unwrap();
+            let mut batch = WriteBatch::new();
+            batch.put(Bytes::from("order-1"), Bytes::from("paid"));
+            batch.merge_cf(&counters, Bytes::from("orders"), counter(1));
+            batch.merge_cf(&counters, Bytes::from("orders"), counter(1));
+            assert_eq!(engine.write(batch).unwrap(), 3);
+
+            // A batch writing to a dropped column family is rejected whole
+            let mut batch = WriteBatch::new();
+            batch.put(Bytes::from("order-2"), Bytes::from("paid"));
+            batch.merge_cf(&counters, Bytes::from("orders"), counter(1));
+            engine.drop_column_family("counters").unwrap();
+            assert!(engine.write(batch).is_err());
+            assert_eq!(engine.get(&Bytes::from("order-2")).unwrap(), None);
+        }
+
+        let fs2 = mem_fs();
+        {
+            let engine = open(&fs2);
+            let counters = engine
+                .create_column_family("counters", counters_options())
+                .unwrap();
+            let mut batch = WriteBatch::new();
+            batch.put(Bytes::from("order-1"), Bytes::from("paid"));
+            batch.merge_cf(&counters, Bytes::from("orders"), counter(2));
+            engine.write(batch).unwrap();
+        }
+
+        // Built-in merge operators are restored without passing options
+        let engine = open(&fs2);
+        let counters = engine.column_family("counters").unwrap();
+        assert_eq!(
+            engine.get_cf(&counters, &Bytes::from("orders")).unwrap(),
+            Some(counter(2))
+        );
+        assert_eq!(
+            engine.get(&Bytes::from("order-1")).unwrap(),
+            Some(Bytes::from("paid"))
+        );
+    }
+
+    #[test]
+    fn test_column_family_options_are_checked_on_open() {
+        let fs = mem_fs();
+        {
+            let engine = open(&fs);
+            let options =
+                ColumnFamilyOptions::default().with_comparator(Arc::new(ReverseBytewiseComparator));
+            engine.create_column_family("reversed", options).unwrap();
+        }
+
+        let config = StorageConfig::default();
+        let err = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &config,
+            vec![("reversed".to_string(), ColumnFamilyOptions::default())],
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            EngineError::Comparator(ComparatorError::Mismatch { .. })
+        ));
+
+        let err = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &config,
+            vec![("missing".to_string(), ColumnFamilyOptions::default())],
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, EngineError::ColumnFamilyNotFound { .. }));
+
+        let engine = open(&fs);
+        let reversed = engine.column_family("reversed").unwrap();
+        assert_eq!(
+            reversed.options().comparator.name(),
+            "boxkv.ReverseBytewiseComparator"
+        );
+    }
+
+    #[test]
+    fn test_memtable_rep_is_persisted_per_column_family() {
+        let fs = mem_fs();
+        let config = StorageConfig {
+            memtable_rep: MemTableRepKind::HashPrefix,
+            ..StorageConfig::default()
+        };
+        {
+            let engine = open_with(&fs, &config, ColumnFamilyOptions::default());
+            let options =
+                ColumnFamilyOptions::default().with_memtable_rep(MemTableRepKind::SkipList);
+            let cf = engine.create_column_family("skiplist", options).unwrap();
+            engine
+                .put_cf(&cf, Bytes::from("k"), Bytes::from("v"))
+                .unwrap();
+        }
+
+        // The configuration only applies to column families created later
+        let engine = open(&fs);
+        let rep = |name: &str| {
+            let cf = engine.column_family(name).unwrap();
+            cf.options().memtable_rep
+        };
+        assert_eq!(
+            rep(DEFAULT_COLUMN_FAMILY_NAME),
+            Some(MemTableRepKind::HashPrefix)
+        );
+        assert_eq!(rep("skiplist"), Some(MemTableRepKind::SkipList));
+        let cf = engine.column_family("skiplist").unwrap();
+        assert_eq!(
+            engine.get_cf(&cf, &Bytes::from("k")).unwrap(),
+            Some(Bytes::from("v"))
+        );
+        engine
+            .create_column_family("btree", ColumnFamilyOptions::default())
+            .unwrap();
+        assert_eq!(rep("btree"), Some(MemTableRepKind::BTree));
+    }
+
+    #[test]
+    fn test_large_values_go_to_blob_files_and_are_collected() {
+        let fs = mem_fs();
+        let config = StorageConfig {
+            min_blob_size: 64,
+            ..StorageConfig::default()
+        };
+        let open = || open_with(&fs, &config, ColumnFamilyOptions::default());
+        let large = |byte: u8| Bytes::from(vec![byte; 1000]);
+        {
+            let engine = open();
+            engine.put(Bytes::from("a"), large(1)).unwrap();
+            engine.put(Bytes::from("b"), large(2)).unwrap();
+            engine.put(Bytes::from("c"), Bytes::from("small")).unwrap();
+            engine.put(Bytes::from("a"), large(3)).unwrap();
+            engine.delete(Bytes::from("b")).unwrap();
+
+            let entry = engine.default_cf.memtable().get(&Bytes::from("a")).unwrap();
+            assert!(entry.val().is_blob_index());
+            assert_eq!(engine.get(&Bytes::from("a")).unwrap(), Some(large(3)));
+        }
+
+        // Two of the three values of the sealed file are dead
+        let engine = open();
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), Some(large(3)));
+        let stats = engine.collect_blob_garbage().unwrap();
+        assert_eq!(stats.files_scanned, 1);
+        assert_eq!(stats.files_collected, 1);
+        assert_eq!(stats.records_relocated, 1);
+        assert!(stats.bytes_reclaimed >= 2000);
+        assert_eq!(engine.blobs.list_files().unwrap().len(), 1);
+        // The relocation isn't reported as a change
+        let mut feed = engine.changes_since(0).unwrap();
+        assert_eq!(feed.by_ref().count(), 5);
+        assert_eq!(feed.last_seq(), 6);
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), Some(large(3)));
+        assert_eq!(
+            engine.get(&Bytes::from("c")).unwrap(),
+            Some(Bytes::from("small"))
+        );
+        drop(engine);
+
+        // The relocated value shadows the index into the deleted file
+        let engine = open();
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), Some(large(3)));
+        assert_eq!(engine.get(&Bytes::from("b")).unwrap(), None);
+        // The file holding only live data is kept
+        assert_eq!(engine.collect_blob_garbage().unwrap().files_collected, 0);
+    }
+}
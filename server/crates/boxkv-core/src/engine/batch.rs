@@ -0,0 +1,82 @@
+use bytes::Bytes;
+
+use super::ColumnFamilyHandle;
+use boxkv_common::types::ValueType;
+
+/// Group of writes applied atomically by `Engine::write()`.
+///
+/// The writes get consecutive sequence numbers in insertion order and are
+/// logged as a single WAL batch record, even when they span several column
+/// families (the `*_cf` methods; the others write to the default one).
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut batch = WriteBatch::new();
+/// batch.put(Bytes::from("from"), Bytes::from("90"));
+/// batch.put(Bytes::from("to"), Bytes::from("110"));
+/// batch.merge_cf(&transfers, Bytes::from("count"), encode(1));
+/// engine.write(batch)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    /// Writes in order; `None` stands for the default column family.
+    ops: Vec<(Option<ColumnFamilyHandle>, Bytes, ValueType)>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: Bytes, value: Bytes) {
+        self.ops.push((None, key, ValueType::Normal(value)));
+    }
+
+    pub fn delete(&mut self, key: Bytes) {
+        self.ops.push((None, key, ValueType::Tombstone));
+    }
+
+    pub fn merge(&mut self, key: Bytes, operand: Bytes) {
+        self.ops.push((None, key, ValueType::Merge(operand)));
+    }
+
+    pub fn delete_range(&mut self, start: Bytes, end: Bytes) {
+        self.ops
+            .push((None, start, ValueType::RangeTombstone { end }));
+    }
+
+    pub fn put_cf(&mut self, cf: &ColumnFamilyHandle, key: Bytes, value: Bytes) {
+        self.ops
+            .push((Some(cf.clone()), key, ValueType::Normal(value)));
+    }
+
+    pub fn delete_cf(&mut self, cf: &ColumnFamilyHandle, key: Bytes) {
+        self.ops.push((Some(cf.clone()), key, ValueType::Tombstone));
+    }
+
+    pub fn merge_cf(&mut self, cf: &ColumnFamilyHandle, key: Bytes, operand: Bytes) {
+        self.ops
+            .push((Some(cf.clone()), key, ValueType::Merge(operand)));
+    }
+
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamilyHandle, start: Bytes, end: Bytes) {
+        self.ops
+            .push((Some(cf.clone()), start, ValueType::RangeTombstone { end }));
+    }
+
+    /// Returns the number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns `true` if the batch holds no write.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub(super) fn into_ops(self) -> Vec<(Option<ColumnFamilyHandle>, Bytes, ValueType)> {
+        self.ops
+    }
+}
@@ -0,0 +1,319 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::{debug, info, warn};
+
+use super::{Engine, EngineError, Result};
+use crate::blob::BLOB_DIR_NAME;
+use boxkv_common::env::FileSystem;
+
+impl Engine {
+    /// Writes a consistent copy of the engine to `target_dir`, which opens as
+    /// a standalone database, and returns the sequence number it ends at.
+    ///
+    /// The MemTables are flushed first, so the checkpoint holds their records
+    /// as SSTables and needs no WAL file.
+    ///
+    /// Writers only wait while the MemTables are flushed, the active blob file
+    /// is sealed and the files to keep are listed. Those are never modified
+    /// again: they are hard-linked once writers resume, next to the column
+    /// family registry and the manifest as they were when the files were
+    /// listed. Hard links fall back to copies when `target_dir` is on another
+    /// filesystem. Until the checkpoint is done, blob garbage collection waits
+    /// and SSTables compacted away are kept.
+    ///
+    /// The checkpoint is built in a new temporary sibling directory and
+    /// renamed into place, so `target_dir` is either complete or missing. It
+    /// is written on the engine's `FileSystem`.
+    ///
+    /// # Errors
+    /// Returns `EngineError::CheckpointExists` if `target_dir` already exists.
+    pub fn checkpoint(&self, target_dir: impl AsRef<Path>) -> Result<u64> {
+        let target_dir = target_dir.as_ref();
+        let fs = &*self.fs;
+        if fs.exists(target_dir) {
+            return Err(EngineError::CheckpointExists(target_dir.to_path_buf()));
+        }
+        let tmp_dir = temp_sibling(fs, target_dir, "tmp")?;
+        let seq = match self.write_checkpoint(&tmp_dir) {
+            Ok(seq) => seq,
+            Err(e) => {
+                warn!(?target_dir, error = %e, "Checkpoint failed, removing partial copy");
+                fs.remove_dir_all(&tmp_dir).ok();
+                return Err(e);
+            }
+        };
+        fs.rename(&tmp_dir, target_dir)?;
+        if let Some(parent) = target_dir.parent() {
+            sync_dir(fs, parent)?;
+        }
+
+        info!(?target_dir, seq, "Checkpoint created");
+        Ok(seq)
+    }
+
+    /// Fills `tmp_dir` with the checkpoint and syncs it.
+    fn write_checkpoint(&self, tmp_dir: &Path) -> Result<u64> {
+        let fs = &*self.fs;
+        let tmp_blob_dir = tmp_dir.join(BLOB_DIR_NAME);
+        fs.create_dir_all(&tmp_blob_dir)?;
+
+        // Only garbage collection deletes blob files
+        let _gc = self.blob_gc.lock();
+        let (seq, registry, manifest, tables, blob_files, _pause) = {
+            let mut wal = self.wal.lock();
+            self.flush_all_locked(&mut wal)?;
+            self.blobs.seal()?;
+            // SSTables are only removed under the WAL lock
+            let pause = TableRemovalPause::new(self);
+
+            let set = self.column_families.read();
+            let tables: Vec<PathBuf> = set
+                .families
+                .values()
+                .flat_map(|cf| {
+                    cf.current_version()
+                        .files()
+                        .map(|file| file.table.path().to_path_buf())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            let blob_files: Vec<u64> = self
+                .blobs
+                .list_files()?
+                .into_iter()
+                .map(|(file_id, _)| file_id)
+                .collect();
+            (
+                self.last_seq(),
+                set.registry.clone(),
+                set.manifest.clone(),
+                tables,
+                blob_files,
+                pause,
+            )
+        };
+
+        registry.store(fs, tmp_dir)?;
+        manifest.store(fs, tmp_dir)?;
+        for path in &tables {
+            link_or_copy(fs, path, &tmp_dir.join(path.file_name().unwrap()))?;
+        }
+        for file_id in blob_files {
+            let path = self.blobs.file_path(file_id);
+            link_or_copy(fs, &path, &tmp_blob_dir.join(path.file_name().unwrap()))?;
+        }
+
+        sync_dir(fs, &tmp_blob_dir)?;
+        sync_dir(fs, tmp_dir)?;
+        Ok(seq)
+    }
+
+    /// Removes an SSTable no longer listed in any version, or defers it until
+    /// the running checkpoints linked their files. Called under the WAL lock.
+    ///
+    /// A deferred SSTable left behind by a crash is removed on open, as it is
+    /// missing from the manifest.
+    pub(super) fn remove_table_file(&self, path: &Path) -> io::Result<()> {
+        let mut removals = self.table_removals.lock();
+        if removals.checkpoints > 0 {
+            debug!(?path, "Checkpoint running, deferring SSTable removal");
+            removals.deferred.push(path.to_path_buf());
+            return Ok(());
+        }
+        self.fs.remove_file(path)
+    }
+}
+
+/// SSTables compacted away or dropped while checkpoints were linking files.
+#[derive(Default)]
+pub(super) struct TableRemovals {
+    /// Number of checkpoints linking files.
+    checkpoints: usize,
+    /// SSTables to remove once the last of them is done.
+    deferred: Vec<PathBuf>,
+}
+
+/// Defers the removal of SSTables while alive (see `remove_table_file()`).
+struct TableRemovalPause<'a> {
+    engine: &'a Engine,
+}
+
+impl<'a> TableRemovalPause<'a> {
+    fn new(engine: &'a Engine) -> Self {
+        engine.table_removals.lock().checkpoints += 1;
+        Self { engine }
+    }
+}
+
+impl Drop for TableRemovalPause<'_> {
+    fn drop(&mut self) {
+        let deferred = {
+            let mut removals = self.engine.table_removals.lock();
+            removals.checkpoints -= 1;
+            if removals.checkpoints > 0 {
+                return;
+            }
+            std::mem::take(&mut removals.deferred)
+        };
+        for path in deferred {
+            if let Err(e) = self.engine.fs.remove_file(&path) {
+                warn!(?path, error = %e, "Failed to remove a deferred SSTable");
+            }
+        }
+    }
+}
+
+/// Returns an unused sibling path to build `target` in before renaming it
+/// into place, e.g. `/data/db.tmp.<pid>.<n>` for `/data/db`.
+///
+/// The process ID and a counter keep concurrent builds apart. A leftover
+/// from an interrupted build is never reused or removed, and an existing
+/// path is reported as `AlreadyExists`.
+pub(crate) fn temp_sibling(
+    fs: &dyn FileSystem,
+    target: &Path,
+    suffix: &str,
+) -> io::Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut name = target.file_name().map_or_else(OsString::new, |n| n.into());
+    name.push(format!(
+        ".{}.{}.{}",
+        suffix,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let path = target.with_file_name(name);
+    if fs.exists(&path) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("temporary path {:?} already exists", path),
+        ));
+    }
+    Ok(path)
+}
+
+/// Hard-links `src` to `dst`, copying it instead if linking fails (e.g. across
+/// filesystems).
+pub(super) fn link_or_copy(fs: &dyn FileSystem, src: &Path, dst: &Path) -> io::Result<()> {
+    if let Err(e) = fs.hard_link(src, dst) {
+        debug!(error = %e, ?src, ?dst, "Hard link failed, copying file");
+        fs.copy(src, dst)?;
+    }
+    Ok(())
+}
+
+pub(super) fn sync_dir(fs: &dyn FileSystem, dir: &Path) -> io::Result<()> {
+    let dir: PathBuf = if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir.to_path_buf()
+    };
+    fs.sync_dir(&dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ColumnFamilyOptions;
+    use super::*;
+    use boxkv_common::config::StorageConfig;
+    use bytes::Bytes;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checkpoint_opens_as_standalone_database() {
+        let dir = TempDir::new().unwrap();
+        let backups = TempDir::new().unwrap();
+        let config = StorageConfig {
+            min_blob_size: 64,
+            ..StorageConfig::default()
+        };
+        let open =
+            |path: &Path| Engine::open(path, &config, ColumnFamilyOptions::default()).unwrap();
+        let large = Bytes::from(vec![9u8; 500]);
+
+        // Seal the first blob file
+        {
+            let engine = open(dir.path());
+            engine.put(Bytes::from("a"), large.clone()).unwrap();
+        }
+        let engine = open(dir.path());
+        let users = engine
+            .create_column_family("users", ColumnFamilyOptions::default())
+            .unwrap();
+        engine
+            .put_cf(&users, Bytes::from("u1"), Bytes::from("alice"))
+            .unwrap();
+
+        let target = backups.path().join("checkpoint");
+        assert_eq!(engine.checkpoint(&target).unwrap(), 2);
+        assert!(matches!(
+            engine.checkpoint(&target),
+            Err(EngineError::CheckpointExists(_))
+        ));
+        engine.put(Bytes::from("b"), Bytes::from("later")).unwrap();
+
+        // The MemTables were flushed: no WAL file is needed
+        let with_extension = |ext: &str| -> Vec<PathBuf> {
+            fs::read_dir(&target)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|e| e == ext))
+                .collect()
+        };
+        assert!(with_extension("wal").is_empty());
+        assert_eq!(with_extension("sst").len(), 2);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            for table in with_extension("sst") {
+                assert_eq!(fs::metadata(table).unwrap().nlink(), 2);
+            }
+        }
+
+        let copy = open(&target);
+        assert_eq!(copy.last_seq(), 2);
+        assert_eq!(copy.get(&Bytes::from("a")).unwrap(), Some(large));
+        assert_eq!(copy.get(&Bytes::from("b")).unwrap(), None);
+        let users = copy.column_family("users").unwrap();
+        assert_eq!(
+            copy.get_cf(&users, &Bytes::from("u1")).unwrap(),
+            Some(Bytes::from("alice"))
+        );
+    }
+
+    #[test]
+    fn test_compacted_tables_are_kept_while_checkpoints_link_them() {
+        let dir = TempDir::new().unwrap();
+        let engine = Engine::open(
+            dir.path(),
+            &StorageConfig::default(),
+            ColumnFamilyOptions::default(),
+        )
+        .unwrap();
+        let tables = || -> Vec<PathBuf> {
+            engine
+                .default_cf
+                .current_version()
+                .files()
+                .map(|file| file.table.path().to_path_buf())
+                .collect()
+        };
+        for key in ["a", "b"] {
+            engine.put(Bytes::from(key), Bytes::from("1")).unwrap();
+            engine.flush().unwrap();
+        }
+        let inputs = tables();
+        assert_eq!(inputs.len(), 2);
+
+        let pause = TableRemovalPause::new(&engine);
+        engine.compact().unwrap();
+        assert_eq!(tables().len(), 1);
+        assert!(inputs.iter().all(|path| path.exists()));
+        drop(pause);
+        assert!(inputs.iter().all(|path| !path.exists()));
+    }
+}
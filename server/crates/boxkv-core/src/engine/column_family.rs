@@ -0,0 +1,449 @@
+use std::fmt;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::RwLock;
+
+use super::version::Version;
+use super::{EngineError, Result};
+use crate::compaction::CompactionFilter;
+use crate::comparator::{Comparator, default_comparator};
+use crate::memtable::MemTableRep;
+use crate::merge::MergeOperator;
+use boxkv_common::config::MemTableRepKind;
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, DEFAULT_COLUMN_FAMILY_ID};
+
+/// Name of the column family that always exists.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// Name of the file recording the column families of a data directory.
+pub(super) const COLUMN_FAMILIES_FILE_NAME: &str = "COLUMN_FAMILIES";
+
+/// Temporary file the registry is written to before being renamed.
+const COLUMN_FAMILIES_TMP_FILE_NAME: &str = "COLUMN_FAMILIES.tmp";
+
+/// Per column family options.
+///
+/// The comparator name is persisted when the column family is created and
+/// must match on every later open; the merge operator may change. The
+/// compaction filter can't be rebuilt from its persisted name, so a column
+/// family created with one must be given its options on every open.
+///
+/// The MemTable representation is persisted too, and used on later opens
+/// unless the options select another one. `None` stands for
+/// `StorageConfig::memtable_rep` when the column family is created.
+#[derive(Clone)]
+pub struct ColumnFamilyOptions {
+    pub comparator: Arc<dyn Comparator>,
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    pub memtable_rep: Option<MemTableRepKind>,
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        Self {
+            comparator: default_comparator(),
+            merge_operator: None,
+            compaction_filter: None,
+            memtable_rep: None,
+        }
+    }
+}
+
+impl ColumnFamilyOptions {
+    /// Orders the keys of the column family with `comparator`.
+    pub fn with_comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    /// Resolves merge operands of the column family with `operator`.
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Filters the values of the column family during compaction with `filter`.
+    pub fn with_compaction_filter(mut self, filter: Arc<dyn CompactionFilter>) -> Self {
+        self.compaction_filter = Some(filter);
+        self
+    }
+
+    /// Backs the MemTables of the column family with the `kind` representation.
+    pub fn with_memtable_rep(mut self, kind: MemTableRepKind) -> Self {
+        self.memtable_rep = Some(kind);
+        self
+    }
+}
+
+/// Independent keyspace of an engine, with its own MemTable, SSTables and options.
+///
+/// All column families share the engine's WAL and sequence numbers, so a
+/// `WriteBatch` spanning several of them is still applied atomically.
+pub struct ColumnFamily {
+    id: ColumnFamilyId,
+    name: String,
+    options: ColumnFamilyOptions,
+    /// Active MemTable and current SSTables.
+    data: RwLock<ColumnFamilyData>,
+    /// Set once dropped; the handle then rejects reads and writes.
+    dropped: AtomicBool,
+}
+
+/// The data of a column family: its active MemTable and its SSTables.
+///
+/// Both are replaced together when a flush moves the MemTable's records into
+/// a new SSTable, so a reader sees them in exactly one of the two.
+#[derive(Clone)]
+pub(super) struct ColumnFamilyData {
+    pub memtable: Arc<dyn MemTableRep>,
+    /// ID of `memtable` in the engine's `WriteBufferManager`, if any.
+    pub buffer_id: Option<u64>,
+    /// Current SSTables, replaced as a whole when files are added.
+    pub version: Arc<Version>,
+}
+
+/// Shared reference to a column family, returned by the engine.
+pub type ColumnFamilyHandle = Arc<ColumnFamily>;
+
+impl ColumnFamily {
+    pub(super) fn new(
+        id: ColumnFamilyId,
+        name: String,
+        options: ColumnFamilyOptions,
+        memtable: Arc<dyn MemTableRep>,
+        buffer_id: Option<u64>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            options,
+            data: RwLock::new(ColumnFamilyData {
+                memtable,
+                buffer_id,
+                version: Arc::new(Version::default()),
+            }),
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the identifier recorded with every WAL record of the column family.
+    pub fn id(&self) -> ColumnFamilyId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn options(&self) -> &ColumnFamilyOptions {
+        &self.options
+    }
+
+    /// Returns `true` once the column family has been dropped.
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    /// Returns the active MemTable.
+    pub(super) fn memtable(&self) -> Arc<dyn MemTableRep> {
+        self.data.read().memtable.clone()
+    }
+
+    /// Returns the current SSTables; later changes don't affect it.
+    pub(super) fn current_version(&self) -> Arc<Version> {
+        self.data.read().version.clone()
+    }
+
+    /// Returns the active MemTable and the current SSTables, consistent with
+    /// each other.
+    pub(super) fn data(&self) -> ColumnFamilyData {
+        self.data.read().clone()
+    }
+
+    pub(super) fn install_version(&self, version: Version) {
+        self.data.write().version = Arc::new(version);
+    }
+
+    /// Replaces the MemTable and the SSTables at once, returning the previous
+    /// data (see `ColumnFamilyData`).
+    pub(super) fn install_data(&self, data: ColumnFamilyData) -> ColumnFamilyData {
+        std::mem::replace(&mut *self.data.write(), data)
+    }
+
+    pub(super) fn mark_dropped(&self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+
+    /// Fails with `EngineError::ColumnFamilyDropped` once dropped.
+    pub(super) fn check_live(&self) -> Result<()> {
+        if self.is_dropped() {
+            return Err(EngineError::ColumnFamilyDropped {
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ColumnFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColumnFamily")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("dropped", &self.is_dropped())
+            .finish()
+    }
+}
+
+/// Persisted description of one column family.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ColumnFamilyDescriptor {
+    pub id: ColumnFamilyId,
+    pub name: String,
+    pub comparator: String,
+    pub merge_operator: Option<String>,
+    pub memtable_rep: MemTableRepKind,
+    pub compaction_filter: Option<String>,
+}
+
+impl ColumnFamilyDescriptor {
+    /// Describes a new column family, whose MemTable representation defaults
+    /// to `default_rep`.
+    pub fn new(
+        id: ColumnFamilyId,
+        name: &str,
+        options: &ColumnFamilyOptions,
+        default_rep: MemTableRepKind,
+    ) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            comparator: options.comparator.name().to_string(),
+            merge_operator: options
+                .merge_operator
+                .as_ref()
+                .map(|op| op.name().to_string()),
+            memtable_rep: options.memtable_rep.unwrap_or(default_rep),
+            compaction_filter: options
+                .compaction_filter
+                .as_ref()
+                .map(|filter| filter.name().to_string()),
+        }
+    }
+}
+
+/// The column families of a data directory, stored in the `COLUMN_FAMILIES` file.
+///
+/// # File Format
+///
+/// ```text
+/// ┌──────────┬──────────┬─────────────────┬──────────┐
+/// │ NextId   │ Count    │ Descriptors...  │ CRC      │
+/// │ (4B)     │ (4B)     │                 │ (4B)     │
+/// └──────────┴──────────┴─────────────────┴──────────┘
+///
+/// Descriptor:
+/// ┌──────────┬──────────┬──────┬──────────┬────────────┬──────────┬──────────┬──────────┬─────────┬──────────┬────────┐
+/// │ Id       │ NameLen  │ Name │ CmpLen   │ Comparator │ OpLen    │ Operator │ RepLen   │ Rep     │ FltLen   │ Filter │
+/// │ (4B)     │ (4B)     │      │ (4B)     │            │ (4B)     │          │ (4B)     │         │ (4B)     │        │
+/// └──────────┴──────────┴──────┴──────────┴────────────┴──────────┴──────────┴──────────┴─────────┴──────────┴────────┘
+/// ```
+///
+/// Integers are big-endian, an empty operator or filter name means none,
+/// `Rep` is the MemTable representation as named in the configuration, and
+/// the CRC32 covers everything before it. Ids are never reused: the WAL
+/// records of a dropped column family are skipped on replay because their id
+/// is unknown.
+///
+/// The file is replaced atomically (write to a temporary file, fsync, rename,
+/// fsync the directory), so a crash leaves either the old or the new registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ColumnFamilyRegistry {
+    pub next_id: ColumnFamilyId,
+    pub families: Vec<ColumnFamilyDescriptor>,
+}
+
+impl ColumnFamilyRegistry {
+    /// Creates a registry holding only the default column family.
+    pub fn new(default_options: &ColumnFamilyOptions, default_rep: MemTableRepKind) -> Self {
+        Self {
+            next_id: DEFAULT_COLUMN_FAMILY_ID + 1,
+            families: vec![ColumnFamilyDescriptor::new(
+                DEFAULT_COLUMN_FAMILY_ID,
+                DEFAULT_COLUMN_FAMILY_NAME,
+                default_options,
+                default_rep,
+            )],
+        }
+    }
+
+    /// Reads the registry of `dir`, or returns `None` if it has none yet.
+    pub fn load(fs: &dyn FileSystem, dir: &Path) -> Result<Option<Self>> {
+        match fs.read(&dir.join(COLUMN_FAMILIES_FILE_NAME)) {
+            Ok(data) => Self::decode(&data)
+                .map(Some)
+                .map_err(EngineError::CorruptedColumnFamilies),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Durably replaces the registry of `dir`.
+    pub fn store(&self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(COLUMN_FAMILIES_TMP_FILE_NAME);
+        let mut file = fs.create(&tmp_path)?;
+        file.write_all(&self.encode())?;
+        file.sync()?;
+        fs.rename(&tmp_path, &dir.join(COLUMN_FAMILIES_FILE_NAME))?;
+        fs.sync_dir(dir)?;
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        fn put_str(buf: &mut Vec<u8>, s: &str) {
+            buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            buf.extend_from_slice(s.as_bytes());
+        }
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.next_id.to_be_bytes());
+        buf.extend_from_slice(&(self.families.len() as u32).to_be_bytes());
+        for family in &self.families {
+            buf.extend_from_slice(&family.id.to_be_bytes());
+            put_str(&mut buf, &family.name);
+            put_str(&mut buf, &family.comparator);
+            put_str(&mut buf, family.merge_operator.as_deref().unwrap_or(""));
+            put_str(&mut buf, family.memtable_rep.name());
+            put_str(&mut buf, family.compaction_filter.as_deref().unwrap_or(""));
+        }
+        let crc = crc32fast::hash(&buf);
+        buf.extend_from_slice(&crc.to_be_bytes());
+        buf
+    }
+
+    fn decode(data: &[u8]) -> std::result::Result<Self, String> {
+        let Some((body, crc)) = data.split_last_chunk::<4>() else {
+            return Err("file too short".to_string());
+        };
+        if crc32fast::hash(body) != u32::from_be_bytes(*crc) {
+            return Err("checksum mismatch".to_string());
+        }
+
+        let mut offset = 0;
+        let read_u32 = |offset: &mut usize| -> std::result::Result<u32, String> {
+            let bytes = body
+                .get(*offset..*offset + 4)
+                .ok_or_else(|| "truncated".to_string())?;
+            *offset += 4;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let read_str = |offset: &mut usize, len: u32| -> std::result::Result<String, String> {
+            let bytes = body
+                .get(*offset..*offset + len as usize)
+                .ok_or_else(|| "truncated".to_string())?;
+            *offset += len as usize;
+            String::from_utf8(bytes.to_vec()).map_err(|_| "invalid UTF-8 name".to_string())
+        };
+
+        let next_id = read_u32(&mut offset)?;
+        let count = read_u32(&mut offset)?;
+        let mut families = Vec::new();
+        for _ in 0..count {
+            let id = read_u32(&mut offset)?;
+            let len = read_u32(&mut offset)?;
+            let name = read_str(&mut offset, len)?;
+            let len = read_u32(&mut offset)?;
+            let comparator = read_str(&mut offset, len)?;
+            let len = read_u32(&mut offset)?;
+            let merge_operator = Some(read_str(&mut offset, len)?).filter(|op| !op.is_empty());
+            let len = read_u32(&mut offset)?;
+            let rep = read_str(&mut offset, len)?;
+            let memtable_rep = MemTableRepKind::from_name(&rep)
+                .ok_or_else(|| format!("unknown MemTable representation {:?}", rep))?;
+            let len = read_u32(&mut offset)?;
+            let compaction_filter =
+                Some(read_str(&mut offset, len)?).filter(|filter| !filter.is_empty());
+            families.push(ColumnFamilyDescriptor {
+                id,
+                name,
+                comparator,
+                merge_operator,
+                memtable_rep,
+                compaction_filter,
+            });
+        }
+        if offset != body.len() {
+            return Err("trailing bytes".to_string());
+        }
+        if !families.iter().any(|f| f.id == DEFAULT_COLUMN_FAMILY_ID) {
+            return Err("missing default column family".to_string());
+        }
+        Ok(Self { next_id, families })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ColumnFamilyDescriptor> {
+        self.families.iter().find(|f| f.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use boxkv_common::env::MemoryFileSystem;
+
+    #[test]
+    fn test_registry_round_trip() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+        assert_eq!(ColumnFamilyRegistry::load(&fs, dir).unwrap(), None);
+
+        let mut registry =
+            ColumnFamilyRegistry::new(&ColumnFamilyOptions::default(), MemTableRepKind::BTree);
+        let options = ColumnFamilyOptions::default()
+            .with_merge_operator(Arc::new(U64AddOperator))
+            .with_memtable_rep(MemTableRepKind::HashPrefix);
+        registry.families.push(ColumnFamilyDescriptor::new(
+            1,
+            "counters",
+            &options,
+            MemTableRepKind::BTree,
+        ));
+        registry.next_id = 2;
+        registry.store(&fs, dir).unwrap();
+
+        let loaded = ColumnFamilyRegistry::load(&fs, dir).unwrap().unwrap();
+        assert_eq!(loaded, registry);
+        assert_eq!(
+            loaded.find("counters").unwrap().merge_operator.as_deref(),
+            Some("boxkv.U64AddOperator")
+        );
+        assert_eq!(
+            loaded.find("counters").unwrap().memtable_rep,
+            MemTableRepKind::HashPrefix
+        );
+        assert_eq!(
+            loaded
+                .find(DEFAULT_COLUMN_FAMILY_NAME)
+                .unwrap()
+                .merge_operator,
+            None
+        );
+
+        // Any flipped byte is detected
+        let path = dir.join(COLUMN_FAMILIES_FILE_NAME);
+        let mut data = fs.read(&path).unwrap();
+        data[10] ^= 0xFF;
+        fs.create(&path).unwrap().write_all(&data).unwrap();
+        assert!(matches!(
+            ColumnFamilyRegistry::load(&fs, dir),
+            Err(EngineError::CorruptedColumnFamilies(_))
+        ));
+    }
+}
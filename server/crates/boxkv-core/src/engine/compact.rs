@@ -0,0 +1,516 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use super::checkpoint::sync_dir;
+use super::version::{self, ManifestFile, NUM_LEVELS, TableFile, Version};
+use super::{ColumnFamily, ColumnFamilyHandle, Engine, Result};
+use crate::compaction::{self, CompactionIterator, CompactionStats, MergingIterator};
+use crate::comparator::Comparator;
+use crate::range_tombstone::FragmentedRangeTombstones;
+use crate::sstable::{FileMetadata, SstFileWriter, Table};
+
+/// Number of level-0 SSTables of a column family that triggers their
+/// compaction into level 1.
+pub(super) const L0_COMPACTION_TRIGGER: usize = 4;
+
+/// SSTables merged by one compaction, and where the result goes.
+struct Compaction {
+    inputs: Vec<Arc<TableFile>>,
+    output_level: usize,
+    /// No file of a deeper level overlaps the inputs.
+    bottommost: bool,
+}
+
+impl Compaction {
+    /// Every file of `version`, into the last level.
+    fn full(version: &Version) -> Option<Self> {
+        let inputs: Vec<Arc<TableFile>> = version.files().cloned().collect();
+        (!inputs.is_empty()).then_some(Self {
+            inputs,
+            output_level: NUM_LEVELS - 1,
+            bottommost: true,
+        })
+    }
+
+    /// Every level-0 file of `version` and the level-1 files they overlap,
+    /// into level 1, once level 0 holds `L0_COMPACTION_TRIGGER` files.
+    fn level0(version: &Version, comparator: &dyn Comparator) -> Option<Self> {
+        let level0 = version.level(0);
+        if level0.len() < L0_COMPACTION_TRIGGER {
+            return None;
+        }
+        let (smallest, largest) = key_range(level0, comparator);
+        let mut inputs = level0.to_vec();
+        inputs.extend(
+            version
+                .level(1)
+                .iter()
+                .filter(|f| f.overlaps(&smallest, &largest, comparator))
+                .cloned(),
+        );
+        let (smallest, largest) = key_range(&inputs, comparator);
+        let bottommost =
+            (2..NUM_LEVELS).all(|level| !version.overlaps(level, &smallest, &largest, comparator));
+        Some(Self {
+            inputs,
+            output_level: 1,
+            bottommost,
+        })
+    }
+}
+
+impl Engine {
+    /// Compacts every column family (see `compact_cf()`), returning the
+    /// counters of all of them.
+    pub fn compact(&self) -> Result<CompactionStats> {
+        let _wal = self.wal.lock();
+        let families: Vec<ColumnFamilyHandle> = self
+            .column_families
+            .read()
+            .families
+            .values()
+            .cloned()
+            .collect();
+        let mut stats = CompactionStats::default();
+        for cf in families {
+            if let Some(compaction) = Compaction::full(&cf.current_version()) {
+                stats += self.run_compaction(&cf, compaction)?;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Merges every SSTable of `cf` into a single file of the last level,
+    /// and returns what its `CompactionFilter` did.
+    ///
+    /// Shadowed and deleted versions are dropped, merge operands are combined
+    /// with their base, and the filter sees the latest value of every key.
+    /// Writers wait while it runs.
+    ///
+    /// Level 0 is compacted into level 1 on its own, by the flush that brings
+    /// it to `L0_COMPACTION_TRIGGER` files.
+    pub fn compact_cf(&self, cf: &ColumnFamily) -> Result<CompactionStats> {
+        let _wal = self.wal.lock();
+        cf.check_live()?;
+        match Compaction::full(&cf.current_version()) {
+            Some(compaction) => self.run_compaction(cf, compaction),
+            None => Ok(CompactionStats::default()),
+        }
+    }
+
+    /// Compacts level 0 of `cf` into level 1 if it holds enough files. The
+    /// caller holds the WAL lock.
+    pub(super) fn compact_level0_if_needed(&self, cf: &ColumnFamily) -> Result<()> {
+        let comparator = cf.options().comparator.clone();
+        if let Some(compaction) = Compaction::level0(&cf.current_version(), &*comparator) {
+            self.run_compaction(cf, compaction)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the merged inputs of `compaction` to a new SSTable and swaps
+    /// them for it in the manifest and in the current version of `cf`. The
+    /// caller holds the WAL lock, so no other change to the SSTables happens
+    /// meanwhile.
+    fn run_compaction(&self, cf: &ColumnFamily, compaction: Compaction) -> Result<CompactionStats> {
+        let options = cf.options();
+        let comparator = options.comparator.clone();
+        let level = compaction.output_level;
+        let input_ids: HashSet<u64> = compaction.inputs.iter().map(|f| f.meta.file_id).collect();
+        let range_tombstones = FragmentedRangeTombstones::from_tombstones(
+            compaction
+                .inputs
+                .iter()
+                .flat_map(|f| f.table.range_tombstones().fragments().iter().cloned()),
+            comparator.clone(),
+        );
+        // Their own range tombstones count in their key range, or the
+        // deletions they hold would be lost
+        let ranges: Vec<FileMetadata> = compaction
+            .inputs
+            .iter()
+            .map(|f| FileMetadata {
+                smallest_key: f.smallest.clone(),
+                largest_key: f.largest.clone(),
+                ..f.meta.clone()
+            })
+            .collect();
+        let covered: HashSet<u64> = compaction::covered_files(&ranges, &range_tombstones)
+            .iter()
+            .map(|f| f.file_id)
+            .collect();
+
+        let mut manifest = self.column_families.read().manifest.clone();
+        let file_id = manifest.next_file_id;
+        manifest.next_file_id += 1;
+        let path = version::sst_file_path(&self.dir, file_id);
+        let result = (|| -> Result<(Option<TableFile>, CompactionStats)> {
+            // Read errors end their input early; checked once merged
+            let failed = RefCell::new(None);
+            // Files deleted by newer range tombstones are dropped unread
+            let sources = compaction
+                .inputs
+                .iter()
+                .filter(|f| !covered.contains(&f.meta.file_id))
+                .map(|f| {
+                    f.table
+                        .iter()
+                        .map_while(|entry| entry.map_err(|e| *failed.borrow_mut() = Some(e)).ok())
+                })
+                .collect();
+            let mut entries = CompactionIterator::new(
+                MergingIterator::new(sources, comparator.clone()),
+                comparator.clone(),
+                options.merge_operator.clone(),
+                compaction.bottommost,
+            )
+            .with_range_tombstones(range_tombstones.clone());
+            if let Some(filter) = &options.compaction_filter {
+                entries = entries.with_compaction_filter(filter.clone(), level);
+            }
+
+            let mut writer = SstFileWriter::create(&*self.fs, &path, comparator.clone())?;
+            for entry in entries.by_ref() {
+                writer.add_entry(&entry?)?;
+            }
+            if let Some(e) = failed.take() {
+                return Err(e.into());
+            }
+            // Nothing older is left for them to delete
+            if !compaction.bottommost {
+                for tombstone in range_tombstones.fragments() {
+                    writer.add_range_tombstone(tombstone.clone());
+                }
+            }
+            let stats = entries.stats();
+            let file = if stats.entries_written == 0
+                && (compaction.bottommost || range_tombstones.is_empty())
+            {
+                drop(writer);
+                self.fs.remove_file(&path)?;
+                None
+            } else {
+                writer.finish()?;
+                sync_dir(&*self.fs, &self.dir)?;
+                let table = Table::open(&*self.fs, &path, comparator.clone())?;
+                TableFile::new(file_id, level, table, &*comparator)?
+            };
+
+            manifest.files.retain(|f| !input_ids.contains(&f.file_id));
+            if file.is_some() {
+                manifest.files.push(ManifestFile {
+                    cf_id: cf.id(),
+                    level,
+                    file_id,
+                    global_seq: 0,
+                });
+            }
+            manifest.store(&*self.fs, &self.dir)?;
+            Ok((file, stats))
+        })();
+        let (file, stats) = match result {
+            Ok(output) => output,
+            Err(e) => {
+                warn!(cf = cf.name(), error = %e, "Compaction failed, removing its SSTable");
+                self.fs.remove_file(&path).ok();
+                return Err(e);
+            }
+        };
+
+        let mut version = (*cf.current_version()).clone();
+        version.remove(&input_ids);
+        let file_size = file.as_ref().map_or(0, |f| f.meta.file_size);
+        if let Some(file) = file {
+            version.add(Arc::new(file), &*comparator);
+        }
+        cf.install_version(version);
+        self.column_families.write().manifest = manifest;
+        self.update_write_pressure();
+        // Readers of the previous version keep their tables open
+        for input in &compaction.inputs {
+            if let Err(e) = self.remove_table_file(input.table.path()) {
+                warn!(path = ?input.table.path(), error = %e, "Failed to remove a compacted SSTable");
+            }
+        }
+
+        info!(
+            cf = cf.name(),
+            inputs = compaction.inputs.len(),
+            covered = covered.len(),
+            level,
+            file_id,
+            file_size,
+            entries_written = stats.entries_written,
+            filter_removed = stats.filter_removed,
+            filter_changed = stats.filter_changed,
+            "Compaction finished"
+        );
+        Ok(stats)
+    }
+}
+
+/// Returns the smallest and largest keys covered by `files`.
+fn key_range(files: &[Arc<TableFile>], comparator: &dyn Comparator) -> (Bytes, Bytes) {
+    let smallest = files
+        .iter()
+        .map(|f| &f.smallest)
+        .min_by(|a, b| comparator.compare(a, b))
+        .expect("compacting at least one file");
+    let largest = files
+        .iter()
+        .map(|f| &f.largest)
+        .max_by(|a, b| comparator.compare(a, b))
+        .expect("compacting at least one file");
+    (smallest.clone(), largest.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME, EngineError};
+    use super::*;
+    use crate::compaction::{CompactionFilter, FilterDecision};
+    use crate::merge::U64AddOperator;
+    use crate::write_controller::{StallCause, WriteStallError};
+    use boxkv_common::config::{StorageConfig, WriteStallConfig};
+    use boxkv_common::env::{FileSystem, MemoryFileSystem};
+    use parking_lot::Mutex;
+    use std::path::Path;
+
+    /// Removes `deleted:` values and rewrites `old:` ones, recording levels.
+    #[derive(Default)]
+    struct RewriteFilter {
+        levels: Mutex<Vec<usize>>,
+    }
+
+    impl CompactionFilter for RewriteFilter {
+        fn name(&self) -> &str {
+            "test.RewriteFilter"
+        }
+
+        fn filter(&self, level: usize, _key: &[u8], value: &[u8]) -> FilterDecision {
+            self.levels.lock().push(level);
+            if value.starts_with(b"deleted:") {
+                FilterDecision::Remove
+            } else if let Some(rest) = value.strip_prefix(b"old:") {
+                FilterDecision::ChangeValue(Bytes::from([b"new:", rest].concat()))
+            } else {
+                FilterDecision::Keep
+            }
+        }
+    }
+
+    fn open(fs: &Arc<dyn FileSystem>, options: ColumnFamilyOptions) -> Engine {
+        Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &StorageConfig::default(),
+            vec![(DEFAULT_COLUMN_FAMILY_NAME.to_string(), options)],
+        )
+        .unwrap()
+    }
+
+    fn levels(cf: &ColumnFamily) -> Vec<usize> {
+        cf.current_version().files().map(|f| f.meta.level).collect()
+    }
+
+    fn put(engine: &Engine, key: &str, value: &str) {
+        engine
+            .put(Bytes::from(key.to_string()), Bytes::from(value.to_string()))
+            .unwrap();
+    }
+
+    fn get(engine: &Engine, key: &str) -> Option<Bytes> {
+        engine.get(&Bytes::from(key.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_compaction_runs_the_filter_at_the_output_level() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let filter = Arc::new(RewriteFilter::default());
+        let engine = open(
+            &fs,
+            ColumnFamilyOptions::default().with_compaction_filter(filter.clone()),
+        );
+        put(&engine, "a", "old:1");
+        put(&engine, "b", "deleted:2");
+        put(&engine, "c", "3");
+        engine.flush().unwrap();
+        put(&engine, "a", "old:4");
+        engine.flush().unwrap();
+        // Flushes don't filter
+        assert!(filter.levels.lock().is_empty());
+        assert_eq!(get(&engine, "b"), Some(Bytes::from("deleted:2")));
+
+        let stats = engine.compact().unwrap();
+        assert_eq!(
+            stats,
+            CompactionStats {
+                entries_written: 2,
+                filter_removed: 1,
+                filter_changed: 1,
+            }
+        );
+        assert_eq!(*filter.levels.lock(), vec![NUM_LEVELS - 1; 3]);
+        assert_eq!(
+            levels(&engine.default_column_family()),
+            vec![NUM_LEVELS - 1]
+        );
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("new:4")));
+        assert_eq!(get(&engine, "b"), None);
+        assert_eq!(get(&engine, "c"), Some(Bytes::from("3")));
+
+        // Only the output is left on disk
+        let tables = fs
+            .list_dir(Path::new("/db"))
+            .unwrap()
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+            .count();
+        assert_eq!(tables, 1);
+    }
+
+    #[test]
+    fn test_level0_is_compacted_by_the_flush_reaching_the_trigger() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let options = ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator));
+        let engine = open(&fs, options.clone());
+        let counter = |value: u64| Bytes::copy_from_slice(&value.to_le_bytes());
+        let key = Bytes::from("hits");
+
+        for round in 1..=L0_COMPACTION_TRIGGER as u64 {
+            engine.merge(key.clone(), counter(round)).unwrap();
+            put(&engine, &format!("key{}", round), "v");
+            engine.flush().unwrap();
+            let expected = if round < L0_COMPACTION_TRIGGER as u64 {
+                vec![0; round as usize]
+            } else {
+                vec![1]
+            };
+            assert_eq!(levels(&engine.default_column_family()), expected);
+        }
+        assert_eq!(engine.get(&key).unwrap(), Some(counter(10)));
+        drop(engine);
+
+        // Nothing deeper: the operands were combined with their base
+        let engine = open(&fs, options);
+        let file = engine.default_column_family().current_version().level(1)[0].clone();
+        let entries: Vec<_> = file.table.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 1 + L0_COMPACTION_TRIGGER);
+        assert!(entries.iter().all(|e| !e.is_merge()));
+        assert_eq!(engine.get(&key).unwrap(), Some(counter(10)));
+    }
+
+    #[test]
+    fn test_column_family_with_filter_needs_its_options_on_open() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let filtered = ColumnFamilyOptions::default()
+            .with_compaction_filter(Arc::new(RewriteFilter::default()));
+        {
+            let engine = open(&fs, ColumnFamilyOptions::default());
+            engine
+                .create_column_family("filtered", filtered.clone())
+                .unwrap();
+        }
+
+        let result = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &StorageConfig::default(),
+            vec![(
+                DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+                ColumnFamilyOptions::default(),
+            )],
+        );
+        assert!(matches!(
+            result,
+            Err(EngineError::MissingColumnFamilyOptions { name, .. }) if name == "filtered"
+        ));
+
+        let engine = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &StorageConfig::default(),
+            vec![("filtered".to_string(), filtered)],
+        )
+        .unwrap();
+        let cf = engine.column_family("filtered").unwrap();
+        assert!(cf.options().compaction_filter.is_some());
+    }
+
+    #[test]
+    fn test_compaction_drops_files_deleted_by_newer_range_tombstones() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let engine = open(&fs, ColumnFamilyOptions::default());
+        for key in ["a", "b", "c"] {
+            put(&engine, key, "old");
+        }
+        engine.flush().unwrap();
+        put(&engine, "x", "kept");
+        engine
+            .delete_range(Bytes::from("a"), Bytes::from("m"))
+            .unwrap();
+        engine.flush().unwrap();
+
+        // Any read of the deleted file would now fail
+        let cf = engine.default_column_family();
+        let deleted = cf.current_version().level(0)[0].table.path().to_path_buf();
+        fs.create(&deleted).unwrap();
+
+        let stats = engine.compact().unwrap();
+        assert_eq!(stats.entries_written, 1);
+        assert_eq!(levels(&cf), vec![NUM_LEVELS - 1]);
+        assert_eq!(get(&engine, "b"), None);
+        assert_eq!(get(&engine, "x"), Some(Bytes::from("kept")));
+        assert!(!fs.exists(&deleted));
+    }
+
+    #[test]
+    fn test_level0_pressure_stalls_writes_until_compacted() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig {
+            write_stall: WriteStallConfig {
+                l0_files_slowdown: 1,
+                l0_files_stop: 2,
+                stop_timeout_ms: 0,
+                ..WriteStallConfig::default()
+            },
+            ..StorageConfig::default()
+        };
+        let engine = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &config,
+            vec![(
+                DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+                ColumnFamilyOptions::default(),
+            )],
+        )
+        .unwrap();
+
+        put(&engine, "a", "1");
+        engine.flush().unwrap();
+        put(&engine, "b", "2");
+        assert_eq!(engine.stall_stats().delayed_writes, 1);
+        engine.flush().unwrap();
+
+        // Below the compaction trigger: nothing clears the stop but compact()
+        let result = engine.put(Bytes::from("c"), Bytes::from("3"));
+        assert!(matches!(
+            result,
+            Err(EngineError::WriteStall(WriteStallError::Stopped {
+                cause: StallCause::L0Files,
+                value: 2,
+                limit: 2,
+            }))
+        ));
+        assert_eq!(engine.stall_stats().failed_writes, 1);
+
+        engine.compact().unwrap();
+        put(&engine, "c", "3");
+        assert_eq!(get(&engine, "c"), Some(Bytes::from("3")));
+    }
+}
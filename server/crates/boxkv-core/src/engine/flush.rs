@@ -0,0 +1,482 @@
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use super::checkpoint::sync_dir;
+use super::column_family::ColumnFamilyData;
+use super::compact::L0_COMPACTION_TRIGGER;
+use super::version::{self, ManifestFile, TableFile};
+use super::{ColumnFamily, ColumnFamilyHandle, ColumnFamilyOptions, Engine, Result};
+use crate::memtable::{self, MemTableRep, WriteBufferManager};
+use crate::sstable::{SstFileWriter, Table};
+use crate::wal::Wal;
+use crate::write_controller::{StallCondition, WritePressure};
+use boxkv_common::config::StorageConfig;
+
+impl Engine {
+    /// Flushes the MemTable of every column family (see `flush_cf()`).
+    pub fn flush(&self) -> Result<()> {
+        let mut wal = self.wal.lock();
+        self.flush_all_locked(&mut wal)
+    }
+
+    /// Flushes the MemTable of every column family; the caller holds the WAL
+    /// lock.
+    pub(super) fn flush_all_locked(&self, wal: &mut Wal) -> Result<()> {
+        let families: Vec<ColumnFamilyHandle> = self
+            .column_families
+            .read()
+            .families
+            .values()
+            .cloned()
+            .collect();
+        for cf in families {
+            self.flush_locked(wal, &cf)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the MemTable of `cf` to a new level-0 SSTable and replaces it
+    /// with an empty one. Does nothing if the MemTable is empty.
+    ///
+    /// The SSTable becomes visible, and the MemTable's records stop being
+    /// read, once the manifest listing it is durably stored. The manifest also
+    /// records that the WAL records of `cf` up to the last sequence number are
+    /// flushed, so recovery skips them. Writers wait while it runs.
+    ///
+    /// Writes flush a MemTable on their own once it reaches
+    /// `memtable_size_mb`, or when the global write buffer
+    /// (`write_buffer_size_mb`) needs room. The flush that brings level 0 to
+    /// `L0_COMPACTION_TRIGGER` files then compacts it into level 1.
+    pub fn flush_cf(&self, cf: &ColumnFamily) -> Result<()> {
+        let mut wal = self.wal.lock();
+        self.flush_locked(&mut wal, cf)
+    }
+
+    /// Flushes the MemTable of `cf`; the caller holds the WAL lock, so the
+    /// MemTable doesn't change meanwhile.
+    pub(super) fn flush_locked(&self, wal: &mut Wal, cf: &ColumnFamily) -> Result<()> {
+        cf.check_live()?;
+        let data = cf.data();
+        let memtable = &data.memtable;
+        if is_empty(&**memtable) {
+            return Ok(());
+        }
+        let range_tombstones = memtable.range_tombstones();
+        let comparator = cf.options().comparator.clone();
+        let flushed_seq = self.last_seq();
+
+        // Only changed under the WAL lock, which we hold
+        let mut manifest = self.column_families.read().manifest.clone();
+        let file_id = manifest.next_file_id;
+        manifest.next_file_id += 1;
+        let path = version::sst_file_path(&self.dir, file_id);
+        let result = (|| -> Result<TableFile> {
+            let mut writer = SstFileWriter::create(&*self.fs, &path, comparator.clone())?;
+            for entry in memtable.iter() {
+                writer.add_entry(&entry)?;
+            }
+            for tombstone in range_tombstones.fragments() {
+                writer.add_range_tombstone(tombstone.clone());
+            }
+            writer.finish()?;
+            sync_dir(&*self.fs, &self.dir)?;
+            let table = Table::open(&*self.fs, &path, comparator.clone())?;
+            let file = TableFile::new(file_id, 0, table, &*comparator)?
+                .expect("flushed a non-empty MemTable");
+
+            manifest.files.push(ManifestFile {
+                cf_id: cf.id(),
+                level: 0,
+                file_id,
+                global_seq: 0,
+            });
+            manifest.flushed_seqs.insert(cf.id(), flushed_seq);
+            manifest.store(&*self.fs, &self.dir)?;
+            Ok(file)
+        })();
+        let file = match result {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(cf = cf.name(), error = %e, "Flush failed, removing its SSTable");
+                self.fs.remove_file(&path).ok();
+                self.update_write_pressure();
+                return Err(e);
+            }
+        };
+        let file_size = file.meta.file_size;
+
+        let mut version = (*data.version).clone();
+        version.add(Arc::new(file), &*comparator);
+        let (memtable, buffer_id) =
+            new_memtable(&self.config, self.write_buffer.as_deref(), cf.options());
+        let flushed = cf.install_data(ColumnFamilyData {
+            memtable,
+            buffer_id,
+            version: Arc::new(version),
+        });
+        self.column_families.write().manifest = manifest;
+        if let (Some(manager), Some(id)) = (&self.write_buffer, flushed.buffer_id) {
+            manager.unregister(id);
+        }
+
+        info!(
+            cf = cf.name(),
+            file_id, file_size, flushed_seq, "MemTable flushed"
+        );
+        self.rotate_wal(wal)?;
+        if let Err(e) = self.compact_level0_if_needed(cf) {
+            // The flush is done; retried after the next one
+            warn!(cf = cf.name(), error = %e, "Level-0 compaction failed");
+        }
+        self.update_write_pressure();
+        Ok(())
+    }
+
+    /// Starts a new WAL file, then retires the sealed ones that are no longer
+    /// needed (see `retire_wal_files()`).
+    fn rotate_wal(&self, wal: &mut Wal) -> Result<()> {
+        let next = Wal::create(&*self.fs, self.dir.clone(), wal.file_id() + 1)?;
+        let sealed = std::mem::replace(wal, next);
+        self.sealed_wal_files
+            .lock()
+            .push((sealed.file_id(), self.last_seq()));
+        drop(sealed);
+        self.retire_wal_files()
+    }
+
+    /// Retires the sealed WAL files whose records are all flushed (or belong
+    /// to dropped column families), oldest first.
+    ///
+    /// They are moved to the WAL archive if one is configured, which then
+    /// applies its retention limits, and deleted otherwise (see
+    /// `Wal::retire()`).
+    pub(super) fn retire_wal_files(&self) -> Result<()> {
+        let set = self.column_families.read();
+        // Every record up to it is in an SSTable
+        let flushed_seq = set
+            .families
+            .values()
+            .filter(|cf| !is_empty(&*cf.memtable()))
+            .map(|cf| set.manifest.flushed_seq(cf.id()))
+            .min()
+            .unwrap_or_else(|| self.last_seq());
+        drop(set);
+
+        let mut sealed = self.sealed_wal_files.lock();
+        while let Some(&(file_id, last_seq)) = sealed.first()
+            && last_seq <= flushed_seq
+        {
+            Wal::retire(&*self.fs, self.dir.clone(), file_id, self.archive.as_ref())?;
+            sealed.remove(0);
+        }
+        Ok(())
+    }
+
+    /// Flushes the MemTables a write to `written` filled up, and those the
+    /// `WriteBufferManager` picks to get back under its budget.
+    ///
+    /// The write is already applied, so a failed flush is only logged: the
+    /// MemTable stays active and the next write tries again.
+    pub(super) fn flush_if_needed(&self, wal: &mut Wal, written: &[&ColumnFamily]) {
+        let memtable_size = self.config.memtable_size_mb as u64 * 1024 * 1024;
+        let mut flushed = Vec::new();
+        for &cf in written {
+            if !flushed.contains(&cf.id()) && cf.memtable().size() >= memtable_size {
+                flushed.push(cf.id());
+                if let Err(e) = self.flush_locked(wal, cf) {
+                    warn!(cf = cf.name(), error = %e, "Flush of a full MemTable failed");
+                }
+            }
+        }
+
+        let Some(manager) = &self.write_buffer else {
+            return;
+        };
+        for (id, _) in manager.pick_flushes() {
+            let cf = self
+                .column_families
+                .read()
+                .families
+                .values()
+                .find(|cf| cf.data().buffer_id == Some(id))
+                .cloned();
+            if let Some(cf) = cf
+                && let Err(e) = self.flush_locked(wal, &cf)
+            {
+                warn!(cf = cf.name(), error = %e, "Flush picked by the write buffer failed");
+            }
+        }
+    }
+}
+
+impl Engine {
+    /// Applies the `WriteController`'s backpressure to a write, before it
+    /// takes the WAL lock.
+    ///
+    /// Flushes and compactions only run in the writes that need them, so a
+    /// stalled write first retries those that failed (see
+    /// `update_write_pressure()`); nothing else would.
+    pub(super) fn throttle_write(&self) -> Result<()> {
+        if self.write_controller.condition() != StallCondition::Normal {
+            let mut wal = self.wal.lock();
+            let families: Vec<ColumnFamilyHandle> = self
+                .column_families
+                .read()
+                .families
+                .values()
+                .cloned()
+                .collect();
+            let written: Vec<&ColumnFamily> = families.iter().map(|cf| &**cf).collect();
+            self.flush_if_needed(&mut wal, &written);
+            for cf in &families {
+                if let Err(e) = self.compact_level0_if_needed(cf) {
+                    warn!(cf = cf.name(), error = %e, "Level-0 compaction failed");
+                }
+            }
+            self.update_write_pressure();
+        }
+        self.write_controller.before_write()?;
+        Ok(())
+    }
+
+    /// Reports the work the column families are behind on to the
+    /// `WriteController`: MemTables left full by a failed flush (as
+    /// immutable MemTables), the level-0 files of the column family holding
+    /// the most, and the size of the level-0 files due for a compaction.
+    pub(super) fn update_write_pressure(&self) {
+        let memtable_size = self.config.memtable_size_mb as u64 * 1024 * 1024;
+        let mut pressure = WritePressure::default();
+        for cf in self.column_families.read().families.values() {
+            let data = cf.data();
+            if data.memtable.size() >= memtable_size {
+                pressure.immutable_memtables += 1;
+            }
+            let level0 = data.version.level(0);
+            pressure.l0_files = pressure.l0_files.max(level0.len() as u64);
+            if level0.len() >= L0_COMPACTION_TRIGGER {
+                pressure.pending_compaction_bytes +=
+                    level0.iter().map(|f| f.meta.file_size).sum::<u64>();
+            }
+        }
+        self.write_controller.set_pressure(pressure);
+    }
+}
+
+/// Returns `true` if `memtable` holds no record.
+fn is_empty(memtable: &dyn MemTableRep) -> bool {
+    memtable.iter().next().is_none() && memtable.range_tombstones().is_empty()
+}
+
+/// Creates an empty MemTable for a column family with `options`, registered
+/// with `write_buffer` if there is one.
+pub(super) fn new_memtable(
+    config: &StorageConfig,
+    write_buffer: Option<&WriteBufferManager>,
+    options: &ColumnFamilyOptions,
+) -> (Arc<dyn MemTableRep>, Option<u64>) {
+    let memtable: Arc<dyn MemTableRep> = Arc::from(memtable::new_rep(
+        options.memtable_rep.unwrap_or(config.memtable_rep),
+        config.memtable_prefix_len,
+        options.comparator.clone(),
+    ));
+    let buffer_id = write_buffer.map(|manager| manager.register(memtable.clone()));
+    (memtable, buffer_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::DEFAULT_COLUMN_FAMILY_NAME;
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use boxkv_common::env::{FileSystem, MemoryFileSystem};
+    use bytes::Bytes;
+
+    fn open(fs: &Arc<dyn FileSystem>, config: &StorageConfig) -> Engine {
+        let options = ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator));
+        Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            config,
+            vec![(DEFAULT_COLUMN_FAMILY_NAME.to_string(), options)],
+        )
+        .unwrap()
+    }
+
+    fn levels(cf: &ColumnFamily) -> Vec<usize> {
+        cf.current_version().files().map(|f| f.meta.level).collect()
+    }
+
+    fn get(engine: &Engine, key: &str) -> Option<Bytes> {
+        engine.get(&Bytes::from(key.to_string())).unwrap()
+    }
+
+    fn counter(value: u64) -> Bytes {
+        Bytes::copy_from_slice(&value.to_le_bytes())
+    }
+
+    #[test]
+    fn test_flushed_records_are_read_from_sstables_and_not_replayed() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig::default();
+        let engine = open(&fs, &config);
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+        engine.put(Bytes::from("c"), Bytes::from("3")).unwrap();
+        engine.merge(Bytes::from("n"), counter(1)).unwrap();
+        engine
+            .delete_range(Bytes::from("b"), Bytes::from("c"))
+            .unwrap();
+        engine.flush().unwrap();
+
+        let cf = engine.default_column_family();
+        assert_eq!(levels(&cf), vec![0]);
+        assert!(cf.memtable().iter().next().is_none());
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("1")));
+        assert_eq!(get(&engine, "b"), None);
+        assert_eq!(get(&engine, "c"), Some(Bytes::from("3")));
+
+        // Newer writes go on top of the flushed ones
+        engine.merge(Bytes::from("n"), counter(2)).unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("4")).unwrap();
+        assert_eq!(get(&engine, "n"), Some(counter(3)));
+        // Flushing an empty MemTable writes nothing
+        engine.flush().unwrap();
+        engine.flush().unwrap();
+        assert_eq!(levels(&cf), vec![0, 0]);
+        engine.put(Bytes::from("d"), Bytes::from("5")).unwrap();
+        drop(engine);
+
+        // Only the record written after the last flush is replayed
+        let engine = open(&fs, &config);
+        let cf = engine.default_column_family();
+        assert_eq!(engine.last_seq(), 8);
+        assert_eq!(cf.memtable().iter().count(), 1);
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("1")));
+        assert_eq!(get(&engine, "b"), Some(Bytes::from("4")));
+        assert_eq!(get(&engine, "d"), Some(Bytes::from("5")));
+        assert_eq!(get(&engine, "n"), Some(counter(3)));
+        assert_eq!(
+            engine.scan(&Bytes::new(), None, 10).unwrap().len(),
+            5,
+            "a, b, c, d and n"
+        );
+    }
+
+    #[test]
+    fn test_full_memtables_are_flushed_by_writes() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig {
+            memtable_size_mb: 1,
+            ..StorageConfig::default()
+        };
+        let engine = open(&fs, &config);
+        let value = Bytes::from(vec![b'v'; 64 * 1024]);
+        for i in 0..40 {
+            engine
+                .put(Bytes::from(format!("key{:02}", i)), value.clone())
+                .unwrap();
+        }
+
+        let cf = engine.default_column_family();
+        assert_eq!(levels(&cf), vec![0, 0]);
+        assert!(cf.memtable().size() < 1024 * 1024);
+        for i in 0..40 {
+            assert_eq!(get(&engine, &format!("key{:02}", i)), Some(value.clone()));
+        }
+    }
+
+    #[test]
+    fn test_write_buffer_flushes_the_largest_memtable() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig {
+            memtable_size_mb: 1,
+            write_buffer_size_mb: 1,
+            ..StorageConfig::default()
+        };
+        let engine = open(&fs, &config);
+        let small = engine
+            .create_column_family("small", ColumnFamilyOptions::default())
+            .unwrap();
+        let value = Bytes::from(vec![b'v'; 64 * 1024]);
+        for i in 0..9 {
+            let key = Bytes::from(format!("key{}", i));
+            engine.put(key.clone(), value.clone()).unwrap();
+            if i < 5 {
+                engine.put_cf(&small, key, value.clone()).unwrap();
+            }
+        }
+
+        // Together they crossed 90% of the budget, each alone stayed below its limit
+        let default = engine.default_column_family();
+        assert_eq!(levels(&default), vec![0]);
+        assert!(levels(&small).is_empty());
+        assert_eq!(
+            engine.write_buffer.as_ref().unwrap().memory_usage(),
+            small.memtable().size() + default.memtable().size()
+        );
+        assert_eq!(get(&engine, "key8"), Some(value));
+    }
+
+    fn wal_file_ids(fs: &Arc<dyn FileSystem>, dir: &str) -> Vec<u64> {
+        Wal::list_files(&**fs, dir.as_ref())
+            .unwrap()
+            .into_iter()
+            .map(|(file_id, _)| file_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_flushed_wal_files_are_retired() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig::default();
+        let engine = open(&fs, &config);
+        let other = engine
+            .create_column_family("other", ColumnFamilyOptions::default())
+            .unwrap();
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        engine
+            .put_cf(&other, Bytes::from("b"), Bytes::from("2"))
+            .unwrap();
+
+        // The first file still holds the only copy of "b"
+        engine.flush_cf(&engine.default_column_family()).unwrap();
+        assert_eq!(wal_file_ids(&fs, "/db"), vec![1, 2]);
+        engine.flush_cf(&other).unwrap();
+        assert_eq!(wal_file_ids(&fs, "/db"), vec![3]);
+        drop(engine);
+
+        // Nothing is left to replay from the last file
+        let engine = open(&fs, &config);
+        assert_eq!(wal_file_ids(&fs, "/db"), vec![4]);
+        let other = engine.column_family("other").unwrap();
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("1")));
+        assert_eq!(
+            engine.get_cf(&other, &Bytes::from("b")).unwrap(),
+            Some(Bytes::from("2"))
+        );
+    }
+
+    #[test]
+    fn test_retired_wal_files_are_archived_within_limits() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig {
+            wal_archive_dir: Some("/archive".into()),
+            wal_archive_size_limit_mb: 1,
+            ..StorageConfig::default()
+        };
+        let engine = open(&fs, &config);
+        let value = Bytes::from(vec![b'v'; 700 * 1024]);
+        engine.put(Bytes::from("a"), value.clone()).unwrap();
+        engine.flush().unwrap();
+        assert_eq!(wal_file_ids(&fs, "/db"), vec![2]);
+        assert_eq!(wal_file_ids(&fs, "/archive"), vec![1]);
+
+        // Both files don't fit in the archive, the oldest one goes
+        engine.put(Bytes::from("b"), value.clone()).unwrap();
+        engine.flush().unwrap();
+        assert_eq!(wal_file_ids(&fs, "/db"), vec![3]);
+        assert_eq!(wal_file_ids(&fs, "/archive"), vec![2]);
+        assert_eq!(get(&engine, "a"), Some(value));
+    }
+}
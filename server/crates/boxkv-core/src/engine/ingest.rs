@@ -0,0 +1,296 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use super::checkpoint::{link_or_copy, sync_dir};
+use super::version::{self, ManifestFile, TableFile};
+use super::{ColumnFamily, Engine, EngineError, Result};
+use crate::sstable::Table;
+
+impl Engine {
+    /// Ingests SSTables built by `SstFileWriter` into the default column
+    /// family (see `ingest_external_files_cf()`).
+    pub fn ingest_external_files(&self, paths: &[impl AsRef<Path>]) -> Result<u64> {
+        self.ingest_external_files_cf(&self.default_cf, paths)
+    }
+
+    /// Ingests SSTables built by `SstFileWriter` into `cf`, returning the
+    /// sequence number assigned to the last one.
+    ///
+    /// Each file gets the next sequence number for all of its records and is
+    /// placed at the deepest level without overlapping data above it (see the
+    /// module docs). The files are hard-linked into the data directory when
+    /// possible: they must not be modified afterwards. All of them become
+    /// visible at once, when the manifest listing them is durably stored.
+    ///
+    /// Each file is logged to the WAL as an ingestion record carrying its
+    /// sequence number, so the change feed sees no gap in the sequence (it
+    /// doesn't report the records of ingested files, which are not in the
+    /// WAL).
+    ///
+    /// The files must be on the engine's `FileSystem`. Writers wait while
+    /// they are linked; they are opened and checked before that.
+    ///
+    /// # Errors
+    /// Returns `EngineError::SSTable` if a file is unreadable or was written
+    /// with another comparator than `cf`'s, and
+    /// `EngineError::InvalidExternalFiles` if a file is empty or the key
+    /// ranges of two files overlap.
+    pub fn ingest_external_files_cf(
+        &self,
+        cf: &ColumnFamily,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<u64> {
+        cf.check_live()?;
+        let comparator = cf.options().comparator.clone();
+
+        let mut inputs: Vec<(PathBuf, Bytes, Bytes)> = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let table = Table::open(&*self.fs, path, comparator.clone())?;
+            let file = TableFile::new(0, 0, table, &*comparator)?
+                .ok_or_else(|| EngineError::InvalidExternalFiles(format!("{:?} is empty", path)))?;
+            inputs.push((
+                path.to_path_buf(),
+                file.smallest.clone(),
+                file.largest.clone(),
+            ));
+        }
+        inputs.sort_by(|a, b| comparator.compare(&a.1, &b.1));
+        if let Some(pair) = inputs
+            .windows(2)
+            .find(|pair| comparator.compare(&pair[0].2, &pair[1].1).is_ge())
+        {
+            return Err(EngineError::InvalidExternalFiles(format!(
+                "{:?} and {:?} overlap",
+                pair[0].0, pair[1].0
+            )));
+        }
+
+        let mut wal = self.wal.lock();
+        cf.check_live()?;
+        if inputs.is_empty() {
+            return Ok(self.last_seq());
+        }
+
+        let first_seq = self.last_seq() + 1;
+        let last_seq = first_seq + inputs.len() as u64 - 1;
+        for seq in first_seq..=last_seq {
+            wal.append_ingestion(cf.id(), seq)?;
+        }
+        wal.sync()?;
+        // The logged sequence numbers are used up, even if ingestion fails
+        self.last_seq.store(last_seq, Ordering::Release);
+
+        // Only changed under the WAL lock, which we hold
+        let mut manifest = self.column_families.read().manifest.clone();
+        let mut version = (*cf.current_version()).clone();
+        let mut seq = first_seq - 1;
+        let mut linked = Vec::new();
+        let result = (|| -> Result<()> {
+            for (path, smallest, largest) in &inputs {
+                let file_id = manifest.next_file_id;
+                manifest.next_file_id += 1;
+                seq += 1;
+
+                let target = version::sst_file_path(&self.dir, file_id);
+                link_or_copy(&*self.fs, path, &target)?;
+                linked.push(target.clone());
+
+                let level = version.pick_level(smallest, largest, &*comparator);
+                let table =
+                    Table::open(&*self.fs, &target, comparator.clone())?.with_global_seq(seq);
+                let file = TableFile::new(file_id, level, table, &*comparator)?
+                    .expect("checked to hold records");
+                version.add(Arc::new(file), &*comparator);
+                manifest.files.push(ManifestFile {
+                    cf_id: cf.id(),
+                    level,
+                    file_id,
+                    global_seq: seq,
+                });
+            }
+            sync_dir(&*self.fs, &self.dir)?;
+            manifest.store(&*self.fs, &self.dir)
+        })();
+        if let Err(e) = result {
+            warn!(error = %e, "Ingestion failed, removing linked files");
+            for path in linked {
+                self.fs.remove_file(&path).ok();
+            }
+            return Err(e);
+        }
+
+        self.column_families.write().manifest = manifest;
+        cf.install_version(version);
+        self.update_write_pressure();
+
+        info!(
+            cf = cf.name(),
+            files = inputs.len(),
+            seq,
+            "External files ingested"
+        );
+        Ok(seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME};
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+    use crate::sstable::{SSTableError, SstFileWriter};
+    use boxkv_common::config::StorageConfig;
+    use boxkv_common::env::{FileSystem, MemoryFileSystem};
+
+    fn open(fs: &Arc<dyn FileSystem>) -> Engine {
+        let column_families = vec![(
+            DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            ColumnFamilyOptions::default(),
+        )];
+        Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &StorageConfig::default(),
+            column_families,
+        )
+        .unwrap()
+    }
+
+    fn write_table(fs: &dyn FileSystem, name: &str, rows: &[(&str, Option<&str>)]) -> PathBuf {
+        let dir = Path::new("/input");
+        fs.create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        let mut writer = SstFileWriter::create(fs, &path, default_comparator()).unwrap();
+        for &(key, value) in rows {
+            match value {
+                Some(value) => writer
+                    .put(
+                        Bytes::copy_from_slice(key.as_bytes()),
+                        Bytes::copy_from_slice(value.as_bytes()),
+                    )
+                    .unwrap(),
+                None => writer
+                    .delete(Bytes::copy_from_slice(key.as_bytes()))
+                    .unwrap(),
+            }
+        }
+        writer.finish().unwrap().path
+    }
+
+    fn levels(engine: &Engine) -> Vec<usize> {
+        let mut files: Vec<(u64, usize)> = engine
+            .default_cf
+            .current_version()
+            .files()
+            .map(|f| (f.meta.file_id, f.meta.level))
+            .collect();
+        files.sort();
+        files.into_iter().map(|(_, level)| level).collect()
+    }
+
+    #[test]
+    fn test_ingested_files_are_visible_and_persisted() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let get = |engine: &Engine, key: &str| engine.get(&Bytes::from(key.to_string())).unwrap();
+
+        let engine = open(&fs);
+        engine.put(Bytes::from("b"), Bytes::from("old")).unwrap();
+        engine.put(Bytes::from("c"), Bytes::from("old")).unwrap();
+
+        let first = write_table(
+            &*fs,
+            "first.sst",
+            &[("a", Some("1")), ("b", Some("2")), ("c", None)],
+        );
+        let second = write_table(&*fs, "second.sst", &[("x", Some("9"))]);
+        assert_eq!(engine.ingest_external_files(&[first, second]).unwrap(), 4);
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("1")));
+        assert_eq!(get(&engine, "b"), Some(Bytes::from("2")));
+        assert_eq!(get(&engine, "c"), None);
+        assert_eq!(get(&engine, "x"), Some(Bytes::from("9")));
+
+        // Newer writes shadow ingested keys
+        assert_eq!(engine.put(Bytes::from("a"), Bytes::from("new")).unwrap(), 5);
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("new")));
+
+        // Each overlapping file lands right above the previous one
+        let third = write_table(&*fs, "third.sst", &[("b", Some("3"))]);
+        assert_eq!(engine.ingest_external_files(&[third]).unwrap(), 6);
+        assert_eq!(levels(&engine), vec![6, 6, 5]);
+        assert_eq!(get(&engine, "b"), Some(Bytes::from("3")));
+        drop(engine);
+
+        let engine = open(&fs);
+        assert_eq!(engine.last_seq(), 6);
+        assert_eq!(levels(&engine), vec![6, 6, 5]);
+        assert_eq!(get(&engine, "a"), Some(Bytes::from("new")));
+        assert_eq!(get(&engine, "b"), Some(Bytes::from("3")));
+        assert_eq!(get(&engine, "c"), None);
+        assert_eq!(engine.put(Bytes::from("y"), Bytes::from("1")).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_change_feed_accounts_for_ingestions() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let engine = open(&fs);
+
+        let table = write_table(&*fs, "first.sst", &[("a", Some("1"))]);
+        assert_eq!(engine.ingest_external_files(&[table]).unwrap(), 1);
+        assert_eq!(engine.put(Bytes::from("b"), Bytes::from("2")).unwrap(), 2);
+
+        // The ingestion isn't reported, but leaves no gap in the sequence
+        let mut feed = engine.changes_since(0).unwrap();
+        let changes: Vec<u64> = feed.by_ref().map(|r| r.unwrap().1.seq()).collect();
+        assert_eq!(changes, vec![2]);
+        assert_eq!(feed.last_seq(), 2);
+        drop(feed);
+        drop(engine);
+
+        let engine = open(&fs);
+        assert_eq!(engine.last_seq(), 2);
+        let mut feed = engine.changes_since(0).unwrap();
+        assert_eq!(feed.by_ref().count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_external_files_are_rejected() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let engine = open(&fs);
+
+        let first = write_table(&*fs, "first.sst", &[("a", Some("1")), ("m", None)]);
+        let second = write_table(&*fs, "second.sst", &[("k", Some("2"))]);
+        assert!(matches!(
+            engine.ingest_external_files(&[&first, &second]),
+            Err(EngineError::InvalidExternalFiles(_))
+        ));
+
+        let reverse = engine
+            .create_column_family(
+                "reverse",
+                ColumnFamilyOptions::default().with_comparator(Arc::new(ReverseBytewiseComparator)),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.ingest_external_files_cf(&reverse, &[&first]),
+            Err(EngineError::SSTable(
+                SSTableError::ComparatorMismatch { .. }
+            ))
+        ));
+
+        // Nothing was ingested
+        assert_eq!(engine.last_seq(), 0);
+        assert_eq!(engine.get(&Bytes::from("a")).unwrap(), None);
+        let tables = fs
+            .list_dir(Path::new("/db"))
+            .unwrap()
+            .iter()
+            .filter(|path| path.extension() == Some(version::SST_FILE_EXTENSION.as_ref()))
+            .count();
+        assert_eq!(tables, 0);
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+use thiserror::Error;
+use tracing::debug;
+
+/// Number of independently locked partitions of the key space.
+const LOCK_STRIPES: usize = 16;
+
+/// Identifier of a transaction holding or waiting for locks.
+pub type TxnId = u64;
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    /// The lock was not granted before the timeout.
+    #[error("Timed out after {timeout:?} waiting for a {mode:?} lock on key {key:?}")]
+    Timeout {
+        key: Bytes,
+        mode: LockMode,
+        timeout: Duration,
+    },
+
+    /// Waiting for the lock would close a cycle in the wait-for graph.
+    #[error("Deadlock detected while waiting for a {mode:?} lock on key {key:?}")]
+    Deadlock { key: Bytes, mode: LockMode },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Compatible with other shared locks.
+    Shared,
+    /// Compatible with no other lock.
+    Exclusive,
+}
+
+/// Counters describing lock contention so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Lock requests that had to wait.
+    pub lock_waits: u64,
+    /// Total time spent waiting for locks.
+    pub lock_wait_time: Duration,
+    /// Lock requests that failed with `LockError::Timeout`.
+    pub timeouts: u64,
+    /// Lock requests that failed with `LockError::Deadlock`.
+    pub deadlocks: u64,
+}
+
+/// Holders of the lock on one key.
+#[derive(Debug, Default)]
+struct KeyLock {
+    exclusive: Option<TxnId>,
+    shared: HashSet<TxnId>,
+}
+
+impl KeyLock {
+    /// Returns the other transactions preventing `txn` from taking the lock
+    /// in `mode`.
+    fn blockers(&self, txn: TxnId, mode: LockMode) -> Vec<TxnId> {
+        match self.exclusive {
+            Some(holder) if holder == txn => Vec::new(),
+            Some(holder) => vec![holder],
+            None if mode == LockMode::Shared => Vec::new(),
+            // An exclusive request (or upgrade) waits for every other reader
+            None => self.shared.iter().copied().filter(|&t| t != txn).collect(),
+        }
+    }
+
+    fn grant(&mut self, txn: TxnId, mode: LockMode) {
+        match mode {
+            LockMode::Shared if self.exclusive == Some(txn) => {}
+            LockMode::Shared => {
+                self.shared.insert(txn);
+            }
+            LockMode::Exclusive => {
+                self.shared.remove(&txn);
+                self.exclusive = Some(txn);
+            }
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        self.exclusive.is_none() && self.shared.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct Stripe {
+    locks: Mutex<HashMap<Bytes, KeyLock>>,
+    /// Signalled whenever a lock of the stripe is released.
+    released: Condvar,
+}
+
+/// Per-key shared/exclusive locks for pessimistic transactions.
+///
+/// Keys are spread over independently locked stripes, so transactions on
+/// unrelated keys don't contend. A transaction that has to wait records
+/// wait-for edges to the holders; if the new edges close a cycle the request
+/// fails with `LockError::Deadlock` instead of waiting for the timeout.
+///
+/// # Examples
+///
+/// ```ignore
+/// let locks = LockManager::new();
+/// locks.lock(txn, &key, LockMode::Exclusive, Duration::from_secs(1))?;
+/// // ... read and write the key ...
+/// locks.unlock(txn, &key);
+/// ```
+pub struct LockManager {
+    stripes: Vec<Stripe>,
+    /// Transactions each waiting transaction waits for.
+    wait_for: Mutex<HashMap<TxnId, HashSet<TxnId>>>,
+
+    lock_waits: AtomicU64,
+    wait_micros: AtomicU64,
+    timeouts: AtomicU64,
+    deadlocks: AtomicU64,
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockManager {
+    /// Creates a lock manager with no lock held.
+    pub fn new() -> Self {
+        Self {
+            stripes: (0..LOCK_STRIPES).map(|_| Stripe::default()).collect(),
+            wait_for: Mutex::new(HashMap::new()),
+            lock_waits: AtomicU64::new(0),
+            wait_micros: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            deadlocks: AtomicU64::new(0),
+        }
+    }
+
+    fn stripe(&self, key: &[u8]) -> &Stripe {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[hasher.finish() as usize % self.stripes.len()]
+    }
+
+    /// Takes the lock on `key` in `mode` for `txn`, waiting up to `timeout`.
+    ///
+    /// Re-entrant: a transaction holding the lock gets it again, and a
+    /// shared lock is upgraded to exclusive once the other readers release it.
+    ///
+    /// # Errors
+    /// Returns `LockError::Deadlock` if waiting would deadlock, and
+    /// `LockError::Timeout` if the lock is still held after `timeout`.
+    pub fn lock(
+        &self,
+        txn: TxnId,
+        key: &Bytes,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<(), LockError> {
+        let stripe = self.stripe(key);
+        let mut locks = stripe.locks.lock();
+        let start = Instant::now();
+        let deadline = start + timeout;
+        let mut waited = false;
+
+        let result = loop {
+            let lock = locks.entry(key.clone()).or_default();
+            let blockers = lock.blockers(txn, mode);
+            if blockers.is_empty() {
+                lock.grant(txn, mode);
+                break Ok(());
+            }
+
+            if self.add_wait_edges(txn, blockers) {
+                self.deadlocks.fetch_add(1, Ordering::Relaxed);
+                debug!(txn, ?key, ?mode, "Deadlock detected");
+                break Err(LockError::Deadlock {
+                    key: key.clone(),
+                    mode,
+                });
+            }
+            if !waited {
+                waited = true;
+                self.lock_waits.fetch_add(1, Ordering::Relaxed);
+            }
+            if stripe.released.wait_until(&mut locks, deadline).timed_out()
+                && !locks
+                    .get(key)
+                    .is_none_or(|l| l.blockers(txn, mode).is_empty())
+            {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                break Err(LockError::Timeout {
+                    key: key.clone(),
+                    mode,
+                    timeout,
+                });
+            }
+        };
+
+        if waited {
+            self.wait_for.lock().remove(&txn);
+            self.wait_micros
+                .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        }
+        if result.is_err() && locks.get(key).is_some_and(KeyLock::is_free) {
+            locks.remove(key);
+        }
+        result
+    }
+
+    /// Replaces the wait-for edges of `txn`; returns `true` (leaving no edge)
+    /// if one of `blockers` already waits for `txn`, directly or not.
+    fn add_wait_edges(&self, txn: TxnId, blockers: Vec<TxnId>) -> bool {
+        let mut wait_for = self.wait_for.lock();
+
+        let mut visited = HashSet::new();
+        let mut stack = blockers.clone();
+        while let Some(waiter) = stack.pop() {
+            if waiter == txn {
+                wait_for.remove(&txn);
+                return true;
+            }
+            if visited.insert(waiter)
+                && let Some(next) = wait_for.get(&waiter)
+            {
+                stack.extend(next.iter().copied());
+            }
+        }
+
+        wait_for.insert(txn, blockers.into_iter().collect());
+        false
+    }
+
+    /// Releases the lock of `txn` on `key`, waking up waiters.
+    pub fn unlock(&self, txn: TxnId, key: &Bytes) {
+        let stripe = self.stripe(key);
+        let mut locks = stripe.locks.lock();
+        if let Some(lock) = locks.get_mut(key) {
+            if lock.exclusive == Some(txn) {
+                lock.exclusive = None;
+            }
+            lock.shared.remove(&txn);
+            if lock.is_free() {
+                locks.remove(key);
+            }
+        }
+        stripe.released.notify_all();
+    }
+
+    /// Returns the lock contention counters.
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            lock_waits: self.lock_waits.load(Ordering::Relaxed),
+            lock_wait_time: Duration::from_micros(self.wait_micros.load(Ordering::Relaxed)),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            deadlocks: self.deadlocks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn key(name: &'static str) -> Bytes {
+        Bytes::from(name)
+    }
+
+    #[test]
+    fn test_shared_and_exclusive_locks() {
+        let locks = LockManager::new();
+        locks.lock(1, &key("a"), LockMode::Shared, TIMEOUT).unwrap();
+        locks.lock(2, &key("a"), LockMode::Shared, TIMEOUT).unwrap();
+
+        // Readers block writers, even for an upgrade
+        let short = Duration::from_millis(20);
+        let err = locks
+            .lock(3, &key("a"), LockMode::Exclusive, short)
+            .unwrap_err();
+        assert!(matches!(err, LockError::Timeout { .. }));
+        let err = locks
+            .lock(1, &key("a"), LockMode::Exclusive, short)
+            .unwrap_err();
+        assert!(matches!(err, LockError::Timeout { .. }));
+
+        locks.unlock(2, &key("a"));
+        locks
+            .lock(1, &key("a"), LockMode::Exclusive, short)
+            .unwrap();
+        // Re-entrant
+        locks.lock(1, &key("a"), LockMode::Shared, short).unwrap();
+        assert!(locks.lock(2, &key("a"), LockMode::Shared, short).is_err());
+
+        let stats = locks.stats();
+        assert_eq!(stats.timeouts, 3);
+        assert_eq!(stats.lock_waits, 3);
+        assert!(stats.lock_wait_time >= short * 3);
+    }
+
+    #[test]
+    fn test_waiter_is_granted_on_unlock() {
+        let locks = Arc::new(LockManager::new());
+        locks
+            .lock(1, &key("a"), LockMode::Exclusive, TIMEOUT)
+            .unwrap();
+
+        let waiter = {
+            let locks = locks.clone();
+            thread::spawn(move || locks.lock(2, &key("a"), LockMode::Exclusive, TIMEOUT))
+        };
+        thread::sleep(Duration::from_millis(20));
+        locks.unlock(1, &key("a"));
+
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.stats().lock_waits, 1);
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        let locks = Arc::new(LockManager::new());
+        locks
+            .lock(1, &key("a"), LockMode::Exclusive, TIMEOUT)
+            .unwrap();
+        locks
+            .lock(2, &key("b"), LockMode::Exclusive, TIMEOUT)
+            .unwrap();
+
+        // Transaction 1 waits for 2 ...
+        let (started, waiting) = mpsc::channel();
+        let waiter = {
+            let locks = locks.clone();
+            thread::spawn(move || {
+                started.send(()).unwrap();
+                locks.lock(1, &key("b"), LockMode::Exclusive, TIMEOUT)
+            })
+        };
+        waiting.recv().unwrap();
+        while !locks.wait_for.lock().contains_key(&1) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // ... so 2 waiting for 1 is a deadlock, reported without waiting
+        let start = Instant::now();
+        let err = locks
+            .lock(2, &key("a"), LockMode::Exclusive, TIMEOUT)
+            .unwrap_err();
+        assert!(matches!(err, LockError::Deadlock { .. }));
+        assert!(start.elapsed() < TIMEOUT);
+
+        // Aborting 2 lets 1 proceed
+        locks.unlock(2, &key("b"));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.stats().deadlocks, 1);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::{Engine, Result, WriteBatch, visible_value};
+use boxkv_common::types::Entry;
+
+/// Transaction validated at commit time instead of taking locks.
+///
+/// Writes are buffered in the transaction (and visible to its own reads).
+/// Every key read records the sequence number of the version it saw (0 for
+/// a key that was never written); `commit()` checks under the engine's write
+/// lock that none of them changed, then logs all buffered writes as one WAL
+/// batch. Dropping the transaction rolls it back.
+///
+/// Transactions started with `begin_optimistic_shared()` hold an `Arc` of the
+/// engine instead of borrowing it (`'static`), e.g. to be stored in a
+/// WebAssembly store.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut txn = engine.begin_optimistic();
+/// let balance = txn.get(&key)?.map_or(0, decode);
+/// txn.put(key, encode(balance + 10));
+/// match txn.commit() {
+///     Ok(seq) => println!("Committed at seq {}", seq),
+///     Err(EngineError::TransactionConflict { key, .. }) => println!("Retry, {:?} changed", key),
+///     Err(e) => return Err(e),
+/// }
+/// ```
+pub struct OptimisticTransaction<'a> {
+    engine: EngineRef<'a>,
+    /// Sequence number of the version seen by the first read of each key.
+    reads: HashMap<Bytes, u64>,
+    /// Buffered writes, `None` for deletions.
+    writes: BTreeMap<Bytes, Option<Bytes>>,
+}
+
+/// The engine a transaction runs on, borrowed or shared.
+enum EngineRef<'a> {
+    Borrowed(&'a Engine),
+    Shared(Arc<Engine>),
+}
+
+impl Deref for EngineRef<'_> {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        match self {
+            Self::Borrowed(engine) => engine,
+            Self::Shared(engine) => engine,
+        }
+    }
+}
+
+impl OptimisticTransaction<'static> {
+    pub(super) fn shared(engine: Arc<Engine>) -> Self {
+        Self {
+            engine: EngineRef::Shared(engine),
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a> OptimisticTransaction<'a> {
+    pub(super) fn new(engine: &'a Engine) -> Self {
+        Self {
+            engine: EngineRef::Borrowed(engine),
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the value of `key`, as written by this transaction or else as
+    /// currently visible in the engine.
+    ///
+    /// Keys read from the engine are validated at commit.
+    pub fn get(&mut self, key: &Bytes) -> Result<Option<Bytes>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        let entry = self.engine.get_entry(&self.engine.default_cf, key)?;
+        self.reads
+            .entry(key.clone())
+            .or_insert_with(|| entry.as_ref().map_or(0, Entry::seq));
+        Ok(entry.and_then(|e| visible_value(&e)))
+    }
+
+    /// Returns up to `limit` visible key-value pairs with keys in
+    /// `[start, end)`, as written by this transaction or else as currently
+    /// visible in the engine (see `Engine::scan()`).
+    ///
+    /// The keys returned from the engine are validated at commit. Keys that
+    /// other writers add to the range afterwards are not: they don't make the
+    /// commit fail.
+    pub fn scan(
+        &mut self,
+        start: &Bytes,
+        end: Option<&Bytes>,
+        limit: usize,
+    ) -> Result<Vec<(Bytes, Bytes)>> {
+        let cf = &self.engine.default_cf;
+        let comparator = cf.options().comparator.clone();
+        let in_range = |key: &Bytes| {
+            comparator.compare(key, start).is_ge()
+                && end.is_none_or(|end| comparator.compare(key, end).is_lt())
+        };
+
+        // Buffered deletions may hide up to that many keys of the engine
+        let buffered: Vec<(&Bytes, &Option<Bytes>)> = self
+            .writes
+            .iter()
+            .filter(|(key, _)| in_range(key))
+            .collect();
+        let entries =
+            self.engine
+                .scan_entries(cf, start, end, limit.saturating_add(buffered.len()))?;
+
+        let mut pairs: Vec<(Bytes, Option<Bytes>)> = buffered
+            .into_iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for entry in entries {
+            if self.writes.contains_key(entry.key()) {
+                continue;
+            }
+            self.reads
+                .entry(entry.key().clone())
+                .or_insert_with(|| entry.seq());
+            pairs.push((entry.key().clone(), visible_value(&entry)));
+        }
+        pairs.sort_by(|a, b| comparator.compare(&a.0, &b.0));
+        Ok(pairs
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .take(limit)
+            .collect())
+    }
+
+    /// Buffers a write of `value` to `key`.
+    pub fn put(&mut self, key: Bytes, value: Bytes) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Buffers the deletion of `key`.
+    pub fn delete(&mut self, key: Bytes) {
+        self.writes.insert(key, None);
+    }
+
+    /// Validates the keys read and applies the buffered writes atomically.
+    ///
+    /// Returns the sequence number of the last write (or the engine's last
+    /// sequence number for a read-only transaction).
+    ///
+    /// # Errors
+    /// Returns `EngineError::TransactionConflict` if a key read by the
+    /// transaction was written since; nothing is applied then.
+    pub fn commit(self) -> Result<u64> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        self.engine.commit_validated(&self.reads, batch)
+    }
+}
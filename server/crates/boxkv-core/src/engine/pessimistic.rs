@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+
+use super::lock_manager::{LockMode, TxnId};
+use super::{Engine, Result, WriteBatch, visible_value};
+
+/// Transaction serialized with other transactions through per-key locks.
+///
+/// Reads take a shared lock and writes an exclusive one, held until the
+/// transaction commits or is dropped, so a commit never conflicts: buffered
+/// writes are logged as one WAL batch. Lock requests wait up to
+/// `StorageConfig::lock_timeout_ms` and fail early if they would deadlock
+/// (see `LockManager`); the transaction should then be dropped and retried.
+///
+/// Locks only order transactions: plain writes such as `Engine::put()` don't
+/// take them.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut txn = engine.begin_pessimistic();
+/// let stock = txn.get_for_update(&key)?.map_or(0, decode);
+/// txn.put(key, encode(stock - 1))?;
+/// txn.commit()?;
+/// ```
+pub struct PessimisticTransaction<'a> {
+    engine: &'a Engine,
+    id: TxnId,
+    /// Strongest lock held on each key.
+    locks: HashMap<Bytes, LockMode>,
+    /// Buffered writes, `None` for deletions.
+    writes: BTreeMap<Bytes, Option<Bytes>>,
+}
+
+impl<'a> PessimisticTransaction<'a> {
+    pub(super) fn new(engine: &'a Engine, id: TxnId) -> Self {
+        Self {
+            engine,
+            id,
+            locks: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    fn lock(&mut self, key: &Bytes, mode: LockMode) -> Result<()> {
+        if self
+            .locks
+            .get(key)
+            .is_some_and(|&held| held == LockMode::Exclusive || held == mode)
+        {
+            return Ok(());
+        }
+        self.engine
+            .lock_manager
+            .lock(self.id, key, mode, self.engine.lock_timeout)?;
+        self.locks.insert(key.clone(), mode);
+        Ok(())
+    }
+
+    fn read(&mut self, key: &Bytes, mode: LockMode) -> Result<Option<Bytes>> {
+        self.lock(key, mode)?;
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        Ok(self
+            .engine
+            .get_entry(&self.engine.default_cf, key)?
+            .and_then(|e| visible_value(&e)))
+    }
+
+    /// Returns the value of `key` under a shared lock.
+    pub fn get(&mut self, key: &Bytes) -> Result<Option<Bytes>> {
+        self.read(key, LockMode::Shared)
+    }
+
+    /// Returns the value of `key` under an exclusive lock, for a
+    /// read-modify-write without a lock upgrade.
+    pub fn get_for_update(&mut self, key: &Bytes) -> Result<Option<Bytes>> {
+        self.read(key, LockMode::Exclusive)
+    }
+
+    /// Locks `key` exclusively and buffers a write of `value`.
+    pub fn put(&mut self, key: Bytes, value: Bytes) -> Result<()> {
+        self.lock(&key, LockMode::Exclusive)?;
+        self.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    /// Locks `key` exclusively and buffers its deletion.
+    pub fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.lock(&key, LockMode::Exclusive)?;
+        self.writes.insert(key, None);
+        Ok(())
+    }
+
+    /// Applies the buffered writes atomically and releases the locks.
+    ///
+    /// Returns the sequence number of the last write (or the engine's last
+    /// sequence number for a read-only transaction).
+    pub fn commit(mut self) -> Result<u64> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in std::mem::take(&mut self.writes) {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        self.engine.write(batch)
+    }
+}
+
+impl Drop for PessimisticTransaction<'_> {
+    fn drop(&mut self) {
+        for key in self.locks.keys() {
+            self.engine.lock_manager.unlock(self.id, key);
+        }
+    }
+}
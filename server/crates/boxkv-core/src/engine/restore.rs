@@ -0,0 +1,253 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use super::checkpoint::{link_or_copy, sync_dir, temp_sibling};
+use super::column_family::ColumnFamilyRegistry;
+use super::version::Manifest;
+use super::{Engine, EngineError, Result};
+use crate::blob::{BLOB_DIR_NAME, blob_file_path};
+use crate::memtable::MemTable;
+use crate::wal::{RecoveryTarget, ReplayProgress, Wal, WalArchive};
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, Entry, ValueType};
+
+impl Engine {
+    /// Restores the database of `db_dir` as of `target` into `target_dir`,
+    /// from `checkpoint_dir` (a checkpoint or restored backup of it) and the
+    /// WAL files of `archive` and `db_dir`.
+    ///
+    /// Each column family of the checkpoint replays the records logged after
+    /// the sequence number it is flushed up to (see `WalArchive::restore()`),
+    /// so none is applied twice. They are written to a WAL file of
+    /// `target_dir`, which applies them when it is opened. Blob files they
+    /// reference that are not in the checkpoint are linked from `db_dir`.
+    /// SSTables ingested after the checkpoint are not in the WAL and are not
+    /// restored.
+    ///
+    /// `target_dir` is built in a new temporary sibling directory and renamed
+    /// into place, so it is either complete or missing; `checkpoint_dir` is
+    /// left unchanged. Every path is on `fs`, the archive's `FileSystem`.
+    ///
+    /// # Returns
+    /// The replay progress, where `entries_replayed` and `max_seq` only
+    /// account for the records restored.
+    ///
+    /// # Errors
+    /// Returns `EngineError::RestoreTargetExists` if `target_dir` already
+    /// exists, and `WalError::UnknownColumnFamily` for a record to restore
+    /// whose column family was created after the checkpoint.
+    pub fn restore_point_in_time(
+        fs: &Arc<dyn FileSystem>,
+        checkpoint_dir: impl AsRef<Path>,
+        archive: &WalArchive,
+        db_dir: impl AsRef<Path>,
+        target: RecoveryTarget,
+        target_dir: impl AsRef<Path>,
+    ) -> Result<ReplayProgress> {
+        let target_dir = target_dir.as_ref();
+        if fs.exists(target_dir) {
+            return Err(EngineError::RestoreTargetExists(target_dir.to_path_buf()));
+        }
+        let tmp_dir = temp_sibling(&**fs, target_dir, "restoring")?;
+        let progress = match restore_into(
+            fs,
+            checkpoint_dir.as_ref(),
+            archive,
+            db_dir.as_ref(),
+            target,
+            &tmp_dir,
+        ) {
+            Ok(progress) => progress,
+            Err(e) => {
+                warn!(?target_dir, error = %e, "Restore failed, removing partial copy");
+                fs.remove_dir_all(&tmp_dir).ok();
+                return Err(e);
+            }
+        };
+        fs.rename(&tmp_dir, target_dir)?;
+        if let Some(parent) = target_dir.parent() {
+            sync_dir(&**fs, parent)?;
+        }
+
+        info!(
+            ?target_dir,
+            ?target,
+            max_seq = progress.max_seq,
+            "Database restored"
+        );
+        Ok(progress)
+    }
+}
+
+/// Fills `tmp_dir` with the checkpoint and the WAL records to restore, and
+/// syncs it.
+fn restore_into(
+    fs: &Arc<dyn FileSystem>,
+    checkpoint_dir: &Path,
+    archive: &WalArchive,
+    db_dir: &Path,
+    target: RecoveryTarget,
+    tmp_dir: &Path,
+) -> Result<ReplayProgress> {
+    let tmp_blob_dir = tmp_dir.join(BLOB_DIR_NAME);
+    fs.create_dir_all(&tmp_blob_dir)?;
+
+    // The archive and `db_dir` hold every record of the checkpoint's WAL files
+    for (src, dst) in [
+        (checkpoint_dir, tmp_dir),
+        (&checkpoint_dir.join(BLOB_DIR_NAME), &*tmp_blob_dir),
+    ] {
+        if !fs.is_dir(src) {
+            continue;
+        }
+        for path in fs.list_dir(src)? {
+            if fs.is_dir(&path) || path.extension().is_some_and(|ext| ext == "wal") {
+                continue;
+            }
+            link_or_copy(&**fs, &path, &dst.join(path.file_name().unwrap()))?;
+        }
+    }
+
+    let registry = ColumnFamilyRegistry::load(&**fs, tmp_dir)?.ok_or_else(|| {
+        EngineError::CorruptedColumnFamilies(format!("{:?} has no registry", checkpoint_dir))
+    })?;
+    let manifest = Manifest::load(&**fs, tmp_dir)?.unwrap_or_default();
+    let mut memtables: HashMap<ColumnFamilyId, MemTable> = registry
+        .families
+        .iter()
+        .map(|cf| (cf.id, MemTable::new()))
+        .collect();
+    let progress = archive.restore(db_dir, &manifest.flushed_seqs, target, &mut memtables)?;
+
+    let mut entries: Vec<(ColumnFamilyId, Entry)> = memtables
+        .into_iter()
+        .flat_map(|(cf_id, memtable)| {
+            memtable
+                .snapshot()
+                .into_iter()
+                .map(move |entry| (cf_id, entry))
+        })
+        .collect();
+    entries.sort_unstable_by_key(|(_, entry)| entry.seq());
+
+    let mut blob_files = HashSet::new();
+    for (_, entry) in &entries {
+        if let ValueType::BlobIndex(index) = entry.val()
+            && blob_files.insert(index.file_id)
+        {
+            let target = blob_file_path(&tmp_blob_dir, index.file_id);
+            if !fs.exists(&target) {
+                let src = blob_file_path(&db_dir.join(BLOB_DIR_NAME), index.file_id);
+                link_or_copy(&**fs, &src, &target)?;
+            }
+        }
+    }
+
+    let mut wal = Wal::create(&**fs, tmp_dir.to_path_buf(), 1)?;
+    for (cf_id, entry) in &entries {
+        wal.append(*cf_id, entry)?;
+    }
+    wal.sync()?;
+    drop(wal);
+
+    sync_dir(&**fs, &tmp_blob_dir)?;
+    sync_dir(&**fs, tmp_dir)?;
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME};
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use crate::wal::ArchiveRetention;
+    use boxkv_common::config::StorageConfig;
+    use boxkv_common::env::MemoryFileSystem;
+    use bytes::Bytes;
+
+    fn counter(value: u64) -> Bytes {
+        Bytes::copy_from_slice(&value.to_be_bytes())
+    }
+
+    #[test]
+    fn test_checkpoint_restore_and_reopen() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let config = StorageConfig {
+            min_blob_size: 64,
+            wal_archive_dir: Some("/archive".into()),
+            ..StorageConfig::default()
+        };
+        let open = |dir: &str| {
+            let column_families = vec![(
+                DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+                ColumnFamilyOptions::default(),
+            )];
+            Engine::open_with_fs(fs.clone(), dir, &config, column_families).unwrap()
+        };
+        let large = |byte: u8| Bytes::from(vec![byte; 500]);
+
+        let engine = open("/db");
+        engine.put(Bytes::from("a"), large(1)).unwrap();
+        engine.flush().unwrap();
+        let counters = engine
+            .create_column_family(
+                "counters",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+            )
+            .unwrap();
+        engine
+            .merge_cf(&counters, Bytes::from("hits"), counter(1))
+            .unwrap();
+        // Only the counters are flushed: the default column family stays at 1
+        assert_eq!(engine.checkpoint("/checkpoint").unwrap(), 2);
+
+        engine
+            .merge_cf(&counters, Bytes::from("hits"), counter(1))
+            .unwrap();
+        engine.put(Bytes::from("a"), large(2)).unwrap();
+        engine.put(Bytes::from("a"), large(3)).unwrap();
+        engine.flush().unwrap();
+        drop(engine);
+
+        let archive = WalArchive::new(&fs, "/archive".into(), ArchiveRetention::default()).unwrap();
+        let progress = Engine::restore_point_in_time(
+            &fs,
+            "/checkpoint",
+            &archive,
+            "/db",
+            RecoveryTarget::Seq(4),
+            "/restored",
+        )
+        .unwrap();
+        assert_eq!(progress.entries_replayed, 2);
+        assert_eq!(progress.max_seq, 4);
+        assert!(matches!(
+            Engine::restore_point_in_time(
+                &fs,
+                "/checkpoint",
+                &archive,
+                "/db",
+                RecoveryTarget::Latest,
+                "/restored",
+            ),
+            Err(EngineError::RestoreTargetExists(_))
+        ));
+
+        for _ in 0..2 {
+            let restored = open("/restored");
+            assert_eq!(restored.last_seq(), 4);
+            assert_eq!(restored.get(&Bytes::from("a")).unwrap(), Some(large(2)));
+            let counters = restored.column_family("counters").unwrap();
+            assert_eq!(
+                restored.get_cf(&counters, &Bytes::from("hits")).unwrap(),
+                Some(counter(2))
+            );
+        }
+        // The checkpoint is left as it was
+        let checkpoint = open("/checkpoint");
+        assert_eq!(checkpoint.get(&Bytes::from("a")).unwrap(), Some(large(1)));
+    }
+}
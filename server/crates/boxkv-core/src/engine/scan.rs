@@ -0,0 +1,184 @@
+use bytes::Bytes;
+
+use super::{ColumnFamily, Engine, Result, visible_value};
+use boxkv_common::types::Entry;
+
+impl Engine {
+    /// Returns up to `limit` visible key-value pairs of the default column
+    /// family in `[start, end)` (see `scan_cf()`).
+    pub fn scan(
+        &self,
+        start: &Bytes,
+        end: Option<&Bytes>,
+        limit: usize,
+    ) -> Result<Vec<(Bytes, Bytes)>> {
+        self.scan_cf(&self.default_cf, start, end, limit)
+    }
+
+    /// Returns up to `limit` visible key-value pairs of `cf` with keys in
+    /// `[start, end)` (unbounded if `end` is `None`), in comparator order.
+    ///
+    /// Each key is read as by `get_cf()`, so the pairs don't come from a
+    /// single snapshot: writes made during the scan may or may not be seen.
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        start: &Bytes,
+        end: Option<&Bytes>,
+        limit: usize,
+    ) -> Result<Vec<(Bytes, Bytes)>> {
+        Ok(self
+            .scan_entries(cf, start, end, limit)?
+            .into_iter()
+            .filter_map(|e| visible_value(&e).map(|value| (e.key().clone(), value)))
+            .collect())
+    }
+
+    /// Returns the latest version of up to `limit` keys of `cf` in
+    /// `[start, end)` that have a visible value, merge operands and blob
+    /// indexes resolved.
+    ///
+    /// The MemTable has no seek: every scan walks all of it to collect the
+    /// keys in range, while SSTables are only read from the block holding
+    /// `start`.
+    pub(super) fn scan_entries(
+        &self,
+        cf: &ColumnFamily,
+        start: &Bytes,
+        end: Option<&Bytes>,
+        limit: usize,
+    ) -> Result<Vec<Entry>> {
+        cf.check_live()?;
+        let comparator = cf.options().comparator.clone();
+        let before_end = |key: &Bytes| end.is_none_or(|end| comparator.compare(key, end).is_lt());
+        let in_range = |key: &Bytes| comparator.compare(key, start).is_ge() && before_end(key);
+
+        let data = cf.data();
+        let mut keys: Vec<Bytes> = data
+            .memtable
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|key| in_range(key))
+            .collect();
+        for file in data.version.files() {
+            if comparator.compare(&file.largest, start).is_lt() || !before_end(&file.smallest) {
+                continue;
+            }
+            for entry in file.table.iter_from(start) {
+                let entry = entry?;
+                if !before_end(entry.key()) {
+                    break;
+                }
+                if in_range(entry.key()) {
+                    keys.push(entry.key().clone());
+                }
+            }
+        }
+        keys.sort_by(|a, b| comparator.compare(a, b));
+        keys.dedup();
+
+        let mut entries = Vec::new();
+        for key in keys {
+            if entries.len() == limit {
+                break;
+            }
+            if let Some(entry) = self.get_entry(cf, &key)?
+                && visible_value(&entry).is_some()
+            {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY_NAME};
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+    use crate::sstable::SstFileWriter;
+    use boxkv_common::config::StorageConfig;
+    use boxkv_common::env::{FileSystem, MemoryFileSystem};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn pairs(rows: &[(&str, &str)]) -> Vec<(Bytes, Bytes)> {
+        rows.iter()
+            .map(|&(k, v)| {
+                (
+                    Bytes::copy_from_slice(k.as_bytes()),
+                    Bytes::copy_from_slice(v.as_bytes()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_merges_memtable_and_tables() {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let column_families = vec![(
+            DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            ColumnFamilyOptions::default(),
+        )];
+        let engine = Engine::open_with_fs(
+            fs.clone(),
+            "/db",
+            &StorageConfig::default(),
+            column_families,
+        )
+        .unwrap();
+
+        fs.create_dir_all(Path::new("/input")).unwrap();
+        let path = Path::new("/input/bulk.sst").to_path_buf();
+        let mut writer = SstFileWriter::create(&*fs, &path, default_comparator()).unwrap();
+        for key in ["a", "b", "c", "d", "e"] {
+            writer
+                .put(Bytes::from(key), Bytes::from(format!("table-{}", key)))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        engine.ingest_external_files(&[path]).unwrap();
+
+        engine.put(Bytes::from("b"), Bytes::from("mem-b")).unwrap();
+        engine
+            .put(Bytes::from("bb"), Bytes::from("mem-bb"))
+            .unwrap();
+        engine.delete(Bytes::from("c")).unwrap();
+        engine
+            .delete_range(Bytes::from("d"), Bytes::from("e"))
+            .unwrap();
+        engine.put(Bytes::from("z"), Bytes::from("mem-z")).unwrap();
+
+        let start = Bytes::from("b");
+        let end = Bytes::from("z");
+        assert_eq!(
+            engine.scan(&start, Some(&end), 10).unwrap(),
+            pairs(&[("b", "mem-b"), ("bb", "mem-bb"), ("e", "table-e")])
+        );
+        assert_eq!(
+            engine.scan(&start, None, 2).unwrap(),
+            pairs(&[("b", "mem-b"), ("bb", "mem-bb")])
+        );
+        assert_eq!(engine.scan(&Bytes::from("a"), None, 10).unwrap().len(), 5);
+        assert!(engine.scan(&end, Some(&start), 10).unwrap().is_empty());
+
+        let reverse = engine
+            .create_column_family(
+                "reverse",
+                ColumnFamilyOptions::default().with_comparator(Arc::new(ReverseBytewiseComparator)),
+            )
+            .unwrap();
+        for key in ["a", "b", "c"] {
+            engine
+                .put_cf(&reverse, Bytes::from(key), Bytes::from(key))
+                .unwrap();
+        }
+        assert_eq!(
+            engine
+                .scan_cf(&reverse, &Bytes::from("b"), None, 10)
+                .unwrap(),
+            pairs(&[("b", "b"), ("a", "a")])
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tracing::warn;
+
+use super::{EngineError, Result};
+use crate::comparator::Comparator;
+use crate::sstable::{FileMetadata, Table};
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, Entry};
+
+/// Number of levels of the LSM-tree.
+pub(super) const NUM_LEVELS: usize = 7;
+
+/// Name of the file recording the live SSTables of a data directory.
+pub(super) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// Temporary file the manifest is written to before being renamed.
+const MANIFEST_TMP_FILE_NAME: &str = "MANIFEST.tmp";
+
+/// Extension of SSTable files in the data directory.
+pub(super) const SST_FILE_EXTENSION: &str = "sst";
+
+/// Returns the path of the SSTable `file_id` of `dir`.
+pub(super) fn sst_file_path(dir: &Path, file_id: u64) -> PathBuf {
+    dir.join(format!("{:09}.{}", file_id, SST_FILE_EXTENSION))
+}
+
+/// Deletes the SSTables of `dir` that `manifest` doesn't list, left over by
+/// an interrupted flush, compaction, ingestion or column family drop.
+pub(super) fn remove_unlisted_tables(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    manifest: &Manifest,
+) -> Result<()> {
+    let listed: HashSet<u64> = manifest.files.iter().map(|f| f.file_id).collect();
+    for path in fs.list_dir(dir)? {
+        if path.extension().is_none_or(|ext| ext != SST_FILE_EXTENSION) {
+            continue;
+        }
+        let file_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        if file_id.is_none_or(|id| !listed.contains(&id)) {
+            warn!(?path, "Removing SSTable missing from the manifest");
+            fs.remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A live SSTable with its metadata.
+pub(super) struct TableFile {
+    pub meta: FileMetadata,
+    pub table: Table,
+    /// Key range covered by the table, range tombstones included (their
+    /// exclusive end counts as covered).
+    pub smallest: Bytes,
+    pub largest: Bytes,
+}
+
+impl TableFile {
+    /// Wraps an opened table, computing its key range.
+    ///
+    /// Returns `None` for a table without any record.
+    pub fn new(
+        file_id: u64,
+        level: usize,
+        table: Table,
+        comparator: &dyn Comparator,
+    ) -> Result<Option<Self>> {
+        let tombstones = table.range_tombstones().fragments();
+        let smallest_key = table.smallest_key()?;
+        let smallest = smallest_key
+            .clone()
+            .into_iter()
+            .chain(tombstones.first().map(|t| t.start.clone()))
+            .min_by(|a, b| comparator.compare(a, b));
+        let largest = table
+            .largest_key()
+            .cloned()
+            .into_iter()
+            .chain(tombstones.last().map(|t| t.end.clone()))
+            .max_by(|a, b| comparator.compare(a, b));
+        let (Some(smallest), Some(largest)) = (smallest, largest) else {
+            return Ok(None);
+        };
+
+        let (smallest_seq, largest_seq) = match table.global_seq() {
+            Some(seq) => (seq, seq),
+            None => {
+                let seqs = table
+                    .iter()
+                    .map(|e| e.map(|e| e.seq()))
+                    .chain(tombstones.iter().map(|t| Ok(t.seq)))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                (
+                    seqs.iter().copied().min().unwrap_or(0),
+                    seqs.iter().copied().max().unwrap_or(0),
+                )
+            }
+        };
+        let meta = FileMetadata {
+            file_id,
+            level,
+            file_size: table.file_size(),
+            smallest_key: smallest_key.unwrap_or_else(|| smallest.clone()),
+            largest_key: table
+                .largest_key()
+                .cloned()
+                .unwrap_or_else(|| largest.clone()),
+            smallest_seq,
+            largest_seq,
+        };
+        Ok(Some(Self {
+            meta,
+            table,
+            smallest,
+            largest,
+        }))
+    }
+
+    /// Returns `true` if the table's key range intersects `[smallest, largest]`.
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8], comparator: &dyn Comparator) -> bool {
+        comparator.compare(&self.smallest, largest).is_le()
+            && comparator.compare(smallest, &self.largest).is_le()
+    }
+}
+
+/// The SSTables of a column family, by level.
+///
+/// Immutable once installed: changes build a new `Version` that replaces the
+/// column family's current one, so readers holding the old one are unaffected.
+///
+/// Files of level 0 may overlap each other; files of deeper levels don't and
+/// are sorted by key. A file only holds data older than every overlapping file
+/// of the levels above it.
+#[derive(Clone)]
+pub(super) struct Version {
+    levels: Vec<Vec<Arc<TableFile>>>,
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self {
+            levels: vec![Vec::new(); NUM_LEVELS],
+        }
+    }
+}
+
+impl Version {
+    /// Returns every file, level by level.
+    pub fn files(&self) -> impl Iterator<Item = &Arc<TableFile>> {
+        self.levels.iter().flatten()
+    }
+
+    /// Returns the files of `level`.
+    pub fn level(&self, level: usize) -> &[Arc<TableFile>] {
+        &self.levels[level]
+    }
+
+    /// Returns `true` if a file of `level` intersects `[smallest, largest]`.
+    pub fn overlaps(
+        &self,
+        level: usize,
+        smallest: &[u8],
+        largest: &[u8],
+        comparator: &dyn Comparator,
+    ) -> bool {
+        self.levels[level]
+            .iter()
+            .any(|f| f.overlaps(smallest, largest, comparator))
+    }
+
+    /// Returns the deepest level a file covering `[smallest, largest]` can be
+    /// added to with newer data than the files already there: the level
+    /// right above the first one it overlaps, or level 0.
+    pub fn pick_level(
+        &self,
+        smallest: &[u8],
+        largest: &[u8],
+        comparator: &dyn Comparator,
+    ) -> usize {
+        (0..NUM_LEVELS)
+            .take_while(|&level| !self.overlaps(level, smallest, largest, comparator))
+            .last()
+            .unwrap_or(0)
+    }
+
+    /// Adds a file to the level recorded in its metadata.
+    pub fn add(&mut self, file: Arc<TableFile>, comparator: &dyn Comparator) {
+        let level = file.meta.level;
+        self.levels[level].push(file);
+        if level > 0 {
+            self.levels[level].sort_by(|a, b| comparator.compare(&a.smallest, &b.smallest));
+        }
+    }
+
+    /// Removes the files listed in `file_ids`.
+    pub fn remove(&mut self, file_ids: &HashSet<u64>) {
+        for level in &mut self.levels {
+            level.retain(|f| !file_ids.contains(&f.meta.file_id));
+        }
+    }
+
+    /// Returns the versions of `key` from every file that may hold it, each
+    /// file's list newest first (see `Table::get_versions()`).
+    pub fn get_versions(&self, key: &Bytes, comparator: &dyn Comparator) -> Result<Vec<Entry>> {
+        let mut versions = Vec::new();
+        for file in self.files() {
+            if file.overlaps(key, key, comparator) {
+                versions.extend(file.table.get_versions(key)?);
+            }
+        }
+        Ok(versions)
+    }
+}
+
+/// One live SSTable in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ManifestFile {
+    pub cf_id: ColumnFamilyId,
+    pub level: usize,
+    pub file_id: u64,
+    /// Sequence number of every record of an ingested file, 0 otherwise.
+    pub global_seq: u64,
+}
+
+/// The live SSTables of a data directory, stored in the `MANIFEST` file.
+///
+/// # File Format
+///
+/// ```text
+/// ┌────────────┬──────────┬──────────────┬──────────┬──────────────┬──────────┐
+/// │ NextFileId │ Count    │ Files...     │ Count    │ Flushed...   │ CRC      │
+/// │ (8B)       │ (4B)     │              │ (4B)     │              │ (4B)     │
+/// └────────────┴──────────┴──────────────┴──────────┴──────────────┴──────────┘
+///
+/// File:
+/// ┌──────────┬──────────┬──────────┬───────────┐
+/// │ CfId     │ Level    │ FileId   │ GlobalSeq │
+/// │ (4B)     │ (4B)     │ (8B)     │ (8B)      │
+/// └──────────┴──────────┴──────────┴───────────┘
+///
+/// Flushed:
+/// ┌──────────┬────────────┐
+/// │ CfId     │ FlushedSeq │
+/// │ (4B)     │ (8B)       │
+/// └──────────┴────────────┘
+/// ```
+///
+/// Integers are big-endian and the CRC32 covers everything before it. Key
+/// ranges are read back from the tables themselves on open. Manifests written
+/// before flushes existed end after the files and have no flushed sequence
+/// numbers.
+///
+/// Like the column family registry, the file is replaced atomically, so a
+/// crash leaves either the old or the new set of files. SSTables are written
+/// and synced before the manifest that lists them; files of the directory it
+/// doesn't list are leftovers and are deleted on open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Manifest {
+    pub next_file_id: u64,
+    pub files: Vec<ManifestFile>,
+    /// Per column family, the sequence number up to which its WAL records
+    /// are all in SSTables; recovery skips them.
+    pub flushed_seqs: BTreeMap<ColumnFamilyId, u64>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            next_file_id: 1,
+            files: Vec::new(),
+            flushed_seqs: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Returns the sequence number up to which the records of `cf_id` were
+    /// flushed, 0 if none were.
+    pub fn flushed_seq(&self, cf_id: ColumnFamilyId) -> u64 {
+        self.flushed_seqs.get(&cf_id).copied().unwrap_or(0)
+    }
+
+    /// Reads the manifest of `dir`, or returns `None` if it has none yet.
+    pub fn load(fs: &dyn FileSystem, dir: &Path) -> Result<Option<Self>> {
+        match fs.read(&dir.join(MANIFEST_FILE_NAME)) {
+            Ok(data) => Self::decode(&data)
+                .map(Some)
+                .map_err(EngineError::CorruptedManifest),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Durably replaces the manifest of `dir`.
+    pub fn store(&self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(MANIFEST_TMP_FILE_NAME);
+        let mut file = fs.create(&tmp_path)?;
+        file.write_all(&self.encode())?;
+        file.sync()?;
+        fs.rename(&tmp_path, &dir.join(MANIFEST_FILE_NAME))?;
+        fs.sync_dir(dir)?;
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.next_file_id.to_be_bytes());
+        buf.extend_from_slice(&(self.files.len() as u32).to_be_bytes());
+        for file in &self.files {
+            buf.extend_from_slice(&file.cf_id.to_be_bytes());
+            buf.extend_from_slice(&(file.level as u32).to_be_bytes());
+            buf.extend_from_slice(&file.file_id.to_be_bytes());
+            buf.extend_from_slice(&file.global_seq.to_be_bytes());
+        }
+        buf.extend_from_slice(&(self.flushed_seqs.len() as u32).to_be_bytes());
+        for (cf_id, seq) in &self.flushed_seqs {
+            buf.extend_from_slice(&cf_id.to_be_bytes());
+            buf.extend_from_slice(&seq.to_be_bytes());
+        }
+        let crc = crc32fast::hash(&buf);
+        buf.extend_from_slice(&crc.to_be_bytes());
+        buf
+    }
+
+    fn decode(data: &[u8]) -> std::result::Result<Self, String> {
+        let Some((body, crc)) = data.split_last_chunk::<4>() else {
+            return Err("file too short".to_string());
+        };
+        if crc32fast::hash(body) != u32::from_be_bytes(*crc) {
+            return Err("checksum mismatch".to_string());
+        }
+
+        let mut rest = body;
+        let read_u32 =
+            |rest: &mut &[u8]| take(rest, 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+        let read_u64 =
+            |rest: &mut &[u8]| take(rest, 8).map(|b| u64::from_be_bytes(b.try_into().unwrap()));
+
+        let next_file_id = read_u64(&mut rest)?;
+        let count = read_u32(&mut rest)?;
+        let mut files = Vec::new();
+        for _ in 0..count {
+            let cf_id = read_u32(&mut rest)?;
+            let level = read_u32(&mut rest)? as usize;
+            if level >= NUM_LEVELS {
+                return Err(format!("invalid level {}", level));
+            }
+            files.push(ManifestFile {
+                cf_id,
+                level,
+                file_id: read_u64(&mut rest)?,
+                global_seq: read_u64(&mut rest)?,
+            });
+        }
+        let mut flushed_seqs = BTreeMap::new();
+        // Absent from manifests written before flushes
+        if !rest.is_empty() {
+            for _ in 0..read_u32(&mut rest)? {
+                let cf_id = read_u32(&mut rest)?;
+                flushed_seqs.insert(cf_id, read_u64(&mut rest)?);
+            }
+        }
+        if !rest.is_empty() {
+            return Err("trailing bytes".to_string());
+        }
+        Ok(Self {
+            next_file_id,
+            files,
+            flushed_seqs,
+        })
+    }
+}
+
+/// Splits the first `len` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> std::result::Result<&'a [u8], String> {
+    let (bytes, tail) = rest
+        .split_at_checked(len)
+        .ok_or_else(|| "truncated".to_string())?;
+    *rest = tail;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::env::{FaultInjectionFs, MemoryFileSystem};
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let fs = MemoryFileSystem::new();
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir).unwrap();
+        assert_eq!(Manifest::load(&fs, dir).unwrap(), None);
+
+        let manifest = Manifest {
+            next_file_id: 3,
+            files: vec![
+                ManifestFile {
+                    cf_id: 0,
+                    level: 6,
+                    file_id: 1,
+                    global_seq: 12,
+                },
+                ManifestFile {
+                    cf_id: 2,
+                    level: 0,
+                    file_id: 2,
+                    global_seq: 0,
+                },
+            ],
+            flushed_seqs: BTreeMap::from([(0, 7), (2, 11)]),
+        };
+        manifest.store(&fs, dir).unwrap();
+        assert_eq!(Manifest::load(&fs, dir).unwrap(), Some(manifest.clone()));
+        assert_eq!(manifest.flushed_seq(2), 11);
+        assert_eq!(manifest.flushed_seq(1), 0);
+
+        // Manifests from before flushes end after the files
+        let mut old = manifest.encode();
+        old.truncate(old.len() - 4 - (4 + 2 * 12));
+        old.extend_from_slice(&crc32fast::hash(&old).to_be_bytes());
+        let decoded = Manifest::decode(&old).unwrap();
+        assert_eq!(decoded.files, manifest.files);
+        assert!(decoded.flushed_seqs.is_empty());
+
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let mut data = fs.read(&path).unwrap();
+        data[5] ^= 0xFF;
+        fs.create(&path).unwrap().write_all(&data).unwrap();
+        assert!(matches!(
+            Manifest::load(&fs, dir),
+            Err(EngineError::CorruptedManifest(_))
+        ));
+    }
+
+    #[test]
+    fn test_manifest_recovery_after_crash_at_every_point() {
+        let manifests: Vec<Manifest> = (1..=3)
+            .map(|count| Manifest {
+                next_file_id: count + 1,
+                files: (1..=count)
+                    .map(|file_id| ManifestFile {
+                        cf_id: 0,
+                        level: 6,
+                        file_id,
+                        global_seq: 0,
+                    })
+                    .collect(),
+                flushed_seqs: BTreeMap::new(),
+            })
+            .collect();
+        let dir = Path::new("/db");
+
+        // Power failures after each operation, then in the middle of each write
+        for torn_writes in [false, true] {
+            for point in 0.. {
+                let base = MemoryFileSystem::new();
+                base.create_dir_all(dir).unwrap();
+                let fs = FaultInjectionFs::new(Arc::new(base));
+                if torn_writes {
+                    fs.halt_after_bytes(point);
+                } else {
+                    fs.halt_after_ops(point);
+                }
+                let stored = manifests
+                    .iter()
+                    .take_while(|manifest| manifest.store(&fs, dir).is_ok())
+                    .count();
+                let halted = fs.is_halted();
+                fs.crash().unwrap();
+
+                // Either the last acknowledged manifest or the one being stored
+                let loaded = Manifest::load(&fs, dir).unwrap();
+                let acked = stored.checked_sub(1).map(|i| &manifests[i]);
+                assert!(
+                    loaded.as_ref() == acked || loaded.as_ref() == manifests.get(stored),
+                    "crash point {} (torn: {}): loaded {:?} after {} stores",
+                    point,
+                    torn_writes,
+                    loaded,
+                    stored
+                );
+                if !halted {
+                    assert_eq!(stored, manifests.len());
+                    break;
+                }
+            }
+        }
+    }
+}
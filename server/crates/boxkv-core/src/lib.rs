@@ -1 +1,11 @@
-pub mod wal;
+pub mod backup;
+pub mod blob;
+pub mod compaction;
+pub mod comparator;
+pub mod engine;
+pub mod memtable;
+pub mod merge;
+pub mod range_tombstone;
+pub mod sstable;
+pub mod wal;
+pub mod write_controller;
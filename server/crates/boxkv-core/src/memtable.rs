@@ -23,10 +23,16 @@
 //!
 //! # Design Principles
 //!
-//! - **Ordered Storage**: Uses `BTreeMap` for sorted key iteration (required for SSTable flush)
-//! - **Lock-Free Size Tracking**: `AtomicU64` for concurrent size checks without blocking
+//! - **Ordered Storage**: Uses `BTreeMap` for sorted key iteration (required for SSTable flush),
+//!   keys being ordered by a pluggable `Comparator`
+//! - **Arena Allocation**: Keys and values are copied into a bump arena, so memory usage is measured, not estimated
+//! - **Lock-Free Size Tracking**: Atomic counters for concurrent size checks without blocking
 //! - **MVCC Support**: Each entry stores a sequence number for multi-version concurrency control
 //! - **Tombstone Deletion**: Deletes are writes with a special marker (actual removal during compaction)
+//! - **Lazy Merges**: Merge operands are stored as-is, together with the older versions they
+//!   apply to, and only combined on read or during compaction
+//! - **Range Tombstones**: `delete_range()` records are kept in a separate fragmented list;
+//!   lookups return a point tombstone for keys they cover
 //!
 //! # Concurrency Model
 //!
@@ -36,21 +42,54 @@
 //!
 //! # Memory Management
 //!
-//! Memory usage is tracked approximately as:
+//! Keys and values live in arena chunks that are only released with the
+//! MemTable, so overwritten versions keep counting until the flush. Memory
+//! usage is reported as:
 //! ```text
-//! size = Σ(key_len + value_len + metadata_overhead)
+//! size = arena bytes reserved + entries × index slot size
 //! ```
 //!
-//! When `size` exceeds the configured threshold (typically 4MB), the Engine
-//! marks this MemTable as immutable and creates a new active one.
+//! When `size` reaches the configured threshold (typically 4MB), the Engine
+//! flushes this MemTable to an SSTable and creates a new active one.
+//!
+//! For multi-writer workloads, `SkipListMemTable` offers the same read semantics
+//! with lock-free reads and concurrent inserts through `&self`.
+//!
+//! # Representations
+//!
+//! All MemTable flavours implement the `MemTableRep` trait, and `new_rep()`
+//! builds the one selected for a column family (`ColumnFamilyOptions::memtable_rep`,
+//! defaulting to `StorageConfig::memtable_rep`): `MemTable` (BTreeMap),
+//! `SkipListMemTable` and `HashPrefixMemTable`.
+//!
+//! The `WriteBufferManager` caps the memory of all MemTables together.
+
+mod arena;
+mod hash_prefix;
+mod range_del;
+mod rep;
+mod skiplist;
+mod write_buffer;
 
+pub use hash_prefix::HashPrefixMemTable;
+pub use rep::{MemTableRep, new_rep};
+pub use skiplist::SkipListMemTable;
+pub use write_buffer::WriteBufferManager;
+
+use std::cmp::Ordering as CmpOrdering;
 use std::collections::BTreeMap;
 use std::mem::size_of;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use bytes::Bytes;
 use parking_lot::RwLock;
 
+use crate::comparator::{Comparator, default_comparator};
+use crate::range_tombstone::FragmentedRangeTombstones;
+use arena::BytesArena;
+use range_del::RangeTombstoneList;
+
 use boxkv_common::types::{Entry, ValueType};
 
 /// Internal entry metadata stored alongside each key-value pair.
@@ -58,12 +97,101 @@ use boxkv_common::types::{Entry, ValueType};
 /// Separated from the public `Entry` type to minimize memory overhead
 /// while maintaining MVCC semantics.
 struct EntryInfo {
-    /// Value data (Normal, Tombstone, or Expiring)
+    /// Value data (Normal, Tombstone, Expiring, or Merge)
     value: ValueType,
     /// Sequence number for MVCC ordering
     seq: u64,
+    /// Older versions the merge operand in `value` applies to, newest first.
+    /// Empty unless `value` is a merge operand; ends at the first version that
+    /// is not one.
+    merge_base: Vec<(u64, ValueType)>,
+}
+
+impl EntryInfo {
+    fn new(seq: u64, value: ValueType) -> Self {
+        Self {
+            value,
+            seq,
+            merge_base: Vec::new(),
+        }
+    }
+
+    /// Adds a version of the key, copying its value into `arena` if it is kept.
+    ///
+    /// A newer version replaces the stored one, unless it is a merge operand,
+    /// which keeps the previous version as its base. An older version is only
+    /// kept if merge operands above it still need it.
+    fn add_version(&mut self, seq: u64, value: &ValueType, arena: &BytesArena) {
+        if seq >= self.seq {
+            let previous_seq = std::mem::replace(&mut self.seq, seq);
+            let previous = std::mem::replace(&mut self.value, arena.copy_value(value));
+            if !self.value.is_merge() {
+                self.merge_base.clear();
+            } else if previous_seq != seq {
+                self.merge_base.insert(0, (previous_seq, previous));
+            }
+            return;
+        }
+
+        // Older version arriving late (e.g. during WAL replay)
+        if !self.value.is_merge() {
+            return;
+        }
+        let mut position = 0;
+        for (base_seq, base) in &self.merge_base {
+            if *base_seq == seq {
+                return;
+            }
+            if *base_seq < seq {
+                break;
+            }
+            if !base.is_merge() {
+                return;
+            }
+            position += 1;
+        }
+        self.merge_base
+            .insert(position, (seq, arena.copy_value(value)));
+        if !value.is_merge() {
+            self.merge_base.truncate(position + 1);
+        }
+    }
+
+    /// Returns the stored versions of `key`, newest first.
+    fn versions(&self, key: &Bytes) -> impl Iterator<Item = Entry> + '_ {
+        let key = key.clone();
+        std::iter::once((self.seq, &self.value))
+            .chain(self.merge_base.iter().map(|(seq, value)| (*seq, value)))
+            .map(move |(seq, value)| Entry::new(seq, key.clone(), value.clone()))
+    }
+}
+
+/// Map key ordered by the MemTable's comparator instead of raw bytes.
+struct OrderedKey {
+    key: Bytes,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.comparator.compare(&self.key, &other.key)
+    }
+}
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
 }
 
+impl PartialEq for OrderedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for OrderedKey {}
+
 /// In-memory write buffer storing sorted key-value pairs.
 ///
 /// This is the mutable part of the LSM-tree that receives all writes.
@@ -100,19 +228,31 @@ struct EntryInfo {
 pub struct MemTable {
     /// Ordered map of keys to entry metadata.
     /// BTreeMap ensures keys are sorted for efficient range scans and SSTable flush.
-    table: RwLock<BTreeMap<Bytes, EntryInfo>>,
+    table: RwLock<BTreeMap<OrderedKey, EntryInfo>>,
+
+    /// Orders the keys of `table`.
+    comparator: Arc<dyn Comparator>,
 
-    /// Approximate memory usage in bytes.
+    /// Backing storage for every key and value in `table`.
+    arena: BytesArena,
+
+    /// Memory taken by index slots, in bytes.
     /// Updated atomically to allow lock-free size checks.
-    size: AtomicU64,
+    index_size: AtomicU64,
+
+    /// Range deletions, kept apart from `table`.
+    range_tombstones: RangeTombstoneList,
 }
 
-/// Estimated overhead per entry for sequence number and internal bookkeeping.
-/// Used in size calculations to approximate total memory usage.
-const ENTRY_METADATA_SIZE: usize = size_of::<u64>() * 2; // seq + timestamp
+/// Upper bound of the index memory taken by one entry.
+///
+/// A slot holds the key handle and its `EntryInfo`. B-tree nodes are at least
+/// half full and hash tables at most double their capacity when growing, so an
+/// entry never takes more than two slots.
+const INDEX_ENTRY_SIZE: usize = 2 * size_of::<(OrderedKey, EntryInfo)>();
 
 impl MemTable {
-    /// Creates a new empty MemTable.
+    /// Creates a new empty MemTable ordered by `BytewiseComparator`.
     ///
     /// # Examples
     ///
@@ -121,53 +261,70 @@ impl MemTable {
     /// assert_eq!(memtable.size(), 0);
     /// ```
     pub fn new() -> Self {
+        Self::with_comparator(default_comparator())
+    }
+
+    /// Creates a new empty MemTable ordered by `comparator`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let memtable = MemTable::with_comparator(Arc::new(ReverseBytewiseComparator));
+    /// ```
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
         Self {
             table: RwLock::new(BTreeMap::new()),
-            size: AtomicU64::new(0),
+            range_tombstones: RangeTombstoneList::new(comparator.clone()),
+            comparator,
+            arena: BytesArena::new(),
+            index_size: AtomicU64::new(0),
+        }
+    }
+
+    /// Wraps a key for lookups in `table`.
+    fn lookup_key(&self, key: &Bytes) -> OrderedKey {
+        OrderedKey {
+            key: key.clone(),
+            comparator: self.comparator.clone(),
         }
     }
 
     /// Internal helper to update or insert an entry.
     ///
-    /// This method handles both insertions and updates, correctly adjusting
-    /// the size tracker based on the difference in serialized sizes.
+    /// Key and value are copied into the arena. Versions older than the stored
+    /// one are ignored, unless a merge operand applies to them.
     ///
     /// # Arguments
     ///
     /// * `seq` - Sequence number for MVCC (must be monotonically increasing globally)
     /// * `key` - Key bytes
-    /// * `value` - Value type (Normal, Tombstone, or Expiring)
+    /// * `value` - Value type (Normal, Tombstone, Expiring, Merge, or RangeTombstone,
+    ///   which goes to the range tombstone list)
     ///
     /// # Size Calculation
     ///
-    /// - **New Entry**: `size += key_len + value_len + metadata`
-    /// - **Update**: `size += (new_size - old_size)` (can be negative)
-    fn update(&mut self, seq: u64, key: Bytes, value: ValueType) {
+    /// - **New Entry**: key and value are copied into the arena, plus one index slot
+    /// - **Update**: only the new value is copied; the old one stays in the arena
+    fn update(&self, seq: u64, key: Bytes, value: ValueType) {
+        if let ValueType::RangeTombstone { end } = &value {
+            self.range_tombstones.add(seq, &key, end);
+            return;
+        }
+
         let mut writer = self.table.write();
 
-        match writer.get_mut(&key) {
+        match writer.get_mut(&self.lookup_key(&key)) {
             Some(entry_info) => {
-                // Key exists - update in place
-                let old_size = key.len() + entry_info.value.serialized_len() + ENTRY_METADATA_SIZE;
-
-                entry_info.value = value;
-                entry_info.seq = seq;
-
-                let new_size = key.len() + entry_info.value.serialized_len() + ENTRY_METADATA_SIZE;
-
-                // Adjust size atomically (can be positive or negative delta)
-                let diff = new_size as i64 - old_size as i64;
-                if diff > 0 {
-                    self.size.fetch_add(diff as u64, Ordering::SeqCst);
-                } else if diff < 0 {
-                    self.size.fetch_sub((-diff) as u64, Ordering::SeqCst);
-                }
+                // Key exists - update in place, reusing the stored key
+                entry_info.add_version(seq, &value, &self.arena);
             }
             None => {
-                // New key - insert and increase size
-                let size = key.len() + value.serialized_len() + ENTRY_METADATA_SIZE;
-                self.size.fetch_add(size as u64, Ordering::SeqCst);
-                writer.insert(key, EntryInfo { value, seq });
+                // New key - copy it into the arena and take an index slot
+                let key = self.lookup_key(&self.arena.copy(&key));
+                let value = self.arena.copy_value(&value);
+                self.index_size
+                    .fetch_add(INDEX_ENTRY_SIZE as u64, Ordering::SeqCst);
+                writer.insert(key, EntryInfo::new(seq, value));
             }
         }
     }
@@ -175,8 +332,9 @@ impl MemTable {
     /// Inserts or updates a key-value pair (PUT operation).
     ///
     /// If the key already exists, the old value is replaced and the size
-    /// is adjusted accordingly. The sequence number must be globally unique
-    /// and monotonically increasing.
+    /// is adjusted accordingly. The sequence number must be globally unique.
+    /// A version older than the stored one is ignored, like in every
+    /// `MemTableRep`, so the result doesn't depend on the order of writes.
     ///
     /// # Arguments
     ///
@@ -231,17 +389,79 @@ impl MemTable {
         self.update(seq, key, ValueType::Tombstone);
     }
 
+    /// Writes a merge operand for a key (MERGE operation).
+    ///
+    /// The operand is stored as-is on top of the current version, which is kept
+    /// until the operands are combined on read or during compaction.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut memtable = MemTable::new();
+    ///
+    /// memtable.put(1, Bytes::from("counter"), Bytes::from(1u64.to_be_bytes().to_vec()));
+    /// memtable.merge(2, Bytes::from("counter"), Bytes::from(5u64.to_be_bytes().to_vec()));
+    ///
+    /// let versions = memtable.get_versions(&Bytes::from("counter"));
+    /// assert_eq!(versions.len(), 2); // operand, then the value it applies to
+    /// ```
+    pub fn merge(&mut self, seq: u64, key: Bytes, operand: Bytes) {
+        self.update(seq, key, ValueType::Merge(operand));
+    }
+
+    /// Deletes every key in `[start, end)` with a single range tombstone
+    /// (DELETE_RANGE operation).
+    ///
+    /// Point entries are left in place: lookups see a tombstone for covered
+    /// keys, and compaction drops them.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut memtable = MemTable::new();
+    ///
+    /// memtable.put(1, Bytes::from("tenant1:a"), Bytes::from("x"));
+    /// memtable.delete_range(2, Bytes::from("tenant1:"), Bytes::from("tenant1;"));
+    ///
+    /// assert!(memtable.get(&Bytes::from("tenant1:a")).unwrap().is_tombstone());
+    /// ```
+    pub fn delete_range(&mut self, seq: u64, start: Bytes, end: Bytes) {
+        self.update(seq, start, ValueType::RangeTombstone { end });
+    }
+
+    /// Applies a recovered entry, keeping the version with the highest sequence number.
+    ///
+    /// Used by WAL replay: records are streamed in log order, which may differ
+    /// slightly from sequence order. Entries older than the stored version of the
+    /// same key are ignored, so the final state matches a replay in strict
+    /// sequence order.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut memtable = MemTable::new();
+    /// memtable.apply(Entry::new_normal(2, Bytes::from("k"), Bytes::from("new")));
+    /// memtable.apply(Entry::new_normal(1, Bytes::from("k"), Bytes::from("old")));
+    ///
+    /// assert_eq!(memtable.get(&Bytes::from("k")).unwrap().seq(), 2);
+    /// ```
+    pub fn apply(&mut self, entry: Entry) {
+        self.update(entry.seq(), entry.key().clone(), entry.val().clone());
+    }
+
     /// Retrieves an entry by key.
     ///
     /// # Returns
     ///
-    /// - `Some(Entry)` - Key exists (may be a tombstone)
+    /// - `Some(Entry)` - Key exists (may be a tombstone, including one standing
+    ///   for a range tombstone covering the key)
     /// - `None` - Key not found
     ///
     /// # MVCC Behavior
     ///
     /// Only the **latest version** (highest sequence number) is stored per key.
-    /// Older versions are overwritten during PUT operations.
+    /// Older versions are overwritten during PUT operations. If the latest
+    /// version is a merge operand, use `get_versions()` to resolve it.
     ///
     /// # Examples
     ///
@@ -258,17 +478,39 @@ impl MemTable {
     /// assert!(memtable.get(&Bytes::from("nonexistent")).is_none());
     /// ```
     pub fn get(&self, key: &Bytes) -> Option<Entry> {
-        let reader = self.table.read();
-        reader
-            .get(key)
-            .map(|entry_info| Entry::new(entry_info.seq, key.clone(), entry_info.value.clone()))
+        let latest = self
+            .table
+            .read()
+            .get(&self.lookup_key(key))
+            .map(|entry_info| Entry::new(entry_info.seq, key.clone(), entry_info.value.clone()));
+        self.range_tombstones.shadow(key, latest)
+    }
+
+    /// Retrieves the latest version of a key, followed by the older versions its
+    /// merge operands apply to, newest first.
+    ///
+    /// Returns a single entry unless the latest version is a merge operand, and
+    /// an empty vector if the key is not found.
+    pub fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        let versions = self
+            .table
+            .read()
+            .get(&self.lookup_key(key))
+            .map(|entry_info| entry_info.versions(key).collect())
+            .unwrap_or_default();
+        self.range_tombstones.shadow_versions(key, versions)
+    }
+
+    /// Returns the range tombstones of the MemTable.
+    ///
+    /// Flushed into the SSTable's range deletion meta block.
+    pub fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        self.range_tombstones.get()
     }
 
-    /// Returns the approximate memory usage in bytes.
+    /// Returns the memory usage in bytes.
     ///
-    /// This is a lock-free operation using atomic loads. The value is an
-    /// approximation because it doesn't account for BTreeMap's internal
-    /// node overhead.
+    /// This is a lock-free operation using atomic loads.
     ///
     /// # Usage
     ///
@@ -282,13 +524,16 @@ impl MemTable {
     ///
     /// # Accuracy
     ///
-    /// The size is approximate:
-    /// - **Included**: Key bytes + Value bytes + Metadata overhead
-    /// - **Not Included**: BTreeMap node pointers, allocator overhead
+    /// - **Exact**: Arena chunks holding keys and values, including overwritten
+    ///   versions and the unused tail of the current chunk
+    /// - **Upper bound**: Index slots (see `INDEX_ENTRY_SIZE`)
+    /// - **Estimate**: Range tombstones
     ///
-    /// Typical accuracy: 80-90% of actual memory usage.
+    /// The size never decreases, so it bounds the memory held by the MemTable.
     pub fn size(&self) -> u64 {
-        self.size.load(Ordering::SeqCst)
+        self.arena.allocated_bytes() as u64
+            + self.index_size.load(Ordering::SeqCst)
+            + self.range_tombstones.size()
     }
 
     /// Creates a consistent snapshot of all entries sorted by key.
@@ -310,7 +555,10 @@ impl MemTable {
     ///
     /// # Returns
     ///
-    /// A vector of entries sorted by key in ascending order.
+    /// A vector of entries sorted by key in ascending order. Keys whose latest
+    /// version is a merge operand also list the versions it applies to (see
+    /// `get_versions()`). Range tombstones are not applied (see
+    /// `range_tombstones()`).
     ///
     /// # Examples
     ///
@@ -331,9 +579,7 @@ impl MemTable {
         self.table
             .read()
             .iter()
-            .map(|(key, entry_info)| {
-                Entry::new(entry_info.seq, key.clone(), entry_info.value.clone())
-            })
+            .flat_map(|(key, entry_info)| entry_info.versions(&key.key))
             .collect()
     }
 }
@@ -433,45 +679,58 @@ mod tests {
         let mut memtable = MemTable::new();
         assert_eq!(memtable.size(), 0);
 
-        let key = Bytes::from("key1");
-        let value = Bytes::from("value1");
-        let expected_size = key.len() + value.len() + ENTRY_METADATA_SIZE;
+        // First entry reserves an arena chunk and an index slot
+        memtable.put(1, Bytes::from("key1"), Bytes::from("value1"));
+        assert_eq!(
+            memtable.size(),
+            (arena::BYTES_CHUNK_SIZE + INDEX_ENTRY_SIZE) as u64
+        );
 
-        memtable.put(1, key, value);
-        assert_eq!(memtable.size(), expected_size as u64);
+        // Next entry fits in the same chunk and only takes an index slot
+        memtable.put(2, Bytes::from("key2"), Bytes::from("value2"));
+        assert_eq!(
+            memtable.size(),
+            (arena::BYTES_CHUNK_SIZE + 2 * INDEX_ENTRY_SIZE) as u64
+        );
     }
 
     #[test]
     fn test_memtable_size_tracking_on_update() {
         let mut memtable = MemTable::new();
 
-        // First write: 4 + 6 + 16 = 26 bytes
         memtable.put(1, Bytes::from("key1"), Bytes::from("value1"));
         let size_after_first = memtable.size();
-        assert_eq!(size_after_first, 26);
 
-        // Update with longer value: 4 + 12 + 16 = 32 bytes
+        // Updates reuse the index slot; old values stay in the arena
         memtable.put(2, Bytes::from("key1"), Bytes::from("longer_value"));
-        let size_after_update = memtable.size();
-        assert_eq!(size_after_update, 32);
-
-        // Update with shorter value: 4 + 3 + 16 = 23 bytes
         memtable.put(3, Bytes::from("key1"), Bytes::from("abc"));
-        let size_after_shrink = memtable.size();
-        assert_eq!(size_after_shrink, 23);
+        assert_eq!(memtable.size(), size_after_first);
     }
 
     #[test]
     fn test_memtable_size_tracking_on_delete() {
         let mut memtable = MemTable::new();
 
-        // Put: 4 + 6 + 16 = 26 bytes
         memtable.put(1, Bytes::from("key1"), Bytes::from("value1"));
-        assert_eq!(memtable.size(), 26);
+        let size_after_put = memtable.size();
 
-        // Delete: Tombstone has no value data, so: 4 + 0 + 16 = 20 bytes
+        // Tombstones have no value data and never shrink the MemTable
         memtable.delete(2, Bytes::from("key1"));
-        assert_eq!(memtable.size(), 20);
+        assert_eq!(memtable.size(), size_after_put);
+    }
+
+    #[test]
+    fn test_memtable_size_counts_overwritten_versions() {
+        let mut memtable = MemTable::new();
+        let value = Bytes::from(vec![b'v'; 1024]);
+
+        // 1000 versions of 1KB each can't fit in the first chunk
+        for seq in 0..1000 {
+            memtable.put(seq, Bytes::from("key"), value.clone());
+        }
+
+        assert_eq!(memtable.snapshot().len(), 1);
+        assert!(memtable.size() >= 1000 * 1024);
     }
 
     #[test]
@@ -494,6 +753,22 @@ mod tests {
         assert_eq!(snapshot[3].key().as_ref(), b"zebra");
     }
 
+    #[test]
+    fn test_memtable_custom_comparator() {
+        let mut memtable =
+            MemTable::with_comparator(Arc::new(crate::comparator::ReverseBytewiseComparator));
+
+        memtable.put(1, Bytes::from("apple"), Bytes::from("a"));
+        memtable.put(2, Bytes::from("zebra"), Bytes::from("z"));
+        memtable.put(3, Bytes::from("mango"), Bytes::from("m"));
+        memtable.put(4, Bytes::from("apple"), Bytes::from("a2"));
+
+        let snapshot = memtable.snapshot();
+        let keys: Vec<&[u8]> = snapshot.iter().map(|e| e.key().as_ref()).collect();
+        assert_eq!(keys, vec![b"zebra".as_ref(), b"mango", b"apple"]);
+        assert_eq!(memtable.get(&Bytes::from("apple")).unwrap().seq(), 4);
+    }
+
     #[test]
     fn test_memtable_snapshot_includes_tombstones() {
         let mut memtable = MemTable::new();
@@ -546,8 +821,8 @@ mod tests {
             _ => panic!("Expected Normal value"),
         }
 
-        // Size should reflect the large entry
-        let expected_size = 1024 + 1024 * 1024 + ENTRY_METADATA_SIZE;
+        // Large value gets its own allocation, the key goes to a chunk
+        let expected_size = arena::BYTES_CHUNK_SIZE + 1024 * 1024 + INDEX_ENTRY_SIZE;
         assert_eq!(memtable.size(), expected_size as u64);
     }
 
@@ -665,45 +940,54 @@ mod tests {
     fn test_memtable_size_consistency_after_many_operations() {
         let mut memtable = MemTable::new();
 
-        // Track expected size manually
-        let mut expected_size = 0u64;
+        // Track bytes copied into the arena manually
+        let mut copied = 0;
 
         // Insert 100 entries
         for i in 0..100 {
             let key = Bytes::from(format!("key_{:03}", i));
             let value = Bytes::from(format!("value_{:03}", i));
-            let entry_size = key.len() + value.len() + ENTRY_METADATA_SIZE;
-            expected_size += entry_size as u64;
+            copied += key.len() + value.len();
 
             memtable.put(i, key, value);
         }
 
-        assert_eq!(memtable.size(), expected_size);
-
-        // Update 50 entries (size should change)
+        // Update 50 entries
         for i in 0..50 {
-            let key = Bytes::from(format!("key_{:03}", i));
-            let old_value_len = format!("value_{:03}", i).len();
             let new_value = Bytes::from("updated");
+            copied += new_value.len();
 
-            expected_size -= old_value_len as u64;
-            expected_size += new_value.len() as u64;
-
-            memtable.put(100 + i, key, new_value);
+            memtable.put(100 + i, Bytes::from(format!("key_{:03}", i)), new_value);
         }
 
-        assert_eq!(memtable.size(), expected_size);
-
         // Delete 25 entries (tombstones have no value data)
         for i in 50..75 {
-            let key = Bytes::from(format!("key_{:03}", i));
-            let old_value_len = format!("value_{:03}", i).len();
+            memtable.delete(150 + i, Bytes::from(format!("key_{:03}", i)));
+        }
+
+        // Everything fits in a single chunk
+        assert!(copied <= arena::BYTES_CHUNK_SIZE);
+        let expected_size = arena::BYTES_CHUNK_SIZE + 100 * INDEX_ENTRY_SIZE;
+        assert_eq!(memtable.size(), expected_size as u64);
+    }
+
+    #[test]
+    fn test_memtable_apply_keeps_newest_version() {
+        let mut memtable = MemTable::new();
 
-            expected_size -= old_value_len as u64;
+        memtable.apply(Entry::new_normal(5, Bytes::from("key1"), Bytes::from("v5")));
+        // Older entry arriving later must not overwrite the newer one
+        memtable.apply(Entry::new_normal(3, Bytes::from("key1"), Bytes::from("v3")));
+        memtable.apply(Entry::new_tombstone(4, Bytes::from("key1")));
 
-            memtable.delete(150 + i, key);
+        let entry = memtable.get(&Bytes::from("key1")).unwrap();
+        assert_eq!(entry.seq(), 5);
+        match entry.val() {
+            ValueType::Normal(data) => assert_eq!(data.as_ref(), b"v5"),
+            _ => panic!("Expected Normal value"),
         }
 
-        assert_eq!(memtable.size(), expected_size);
+        memtable.apply(Entry::new_tombstone(6, Bytes::from("key1")));
+        assert!(memtable.get(&Bytes::from("key1")).unwrap().is_tombstone());
     }
 }
@@ -0,0 +1,256 @@
+//! Bump allocators backing the MemTables.
+//!
+//! Memory is carved out of large chunks and only released when the arena is
+//! dropped, which matches the MemTable lifecycle (write, freeze, flush, drop).
+//! Because chunks are never reused, the bytes reserved by an arena are an exact
+//! measure of the memory held by the keys and values copied into it.
+//!
+//! - `Arena` hands out raw memory for the skiplist nodes.
+//! - `BytesArena` copies keys and values into shared chunks and hands them back
+//!   as `Bytes`, for the map-based MemTables.
+
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use bytes::{Bytes, BytesMut};
+use parking_lot::Mutex;
+
+use boxkv_common::types::ValueType;
+
+/// Size of a regular chunk.
+const CHUNK_SIZE: usize = 1024 * 1024; // 1MB
+
+/// Allocations larger than this get a dedicated chunk, so they don't waste the
+/// remainder of the current one.
+const LARGE_ALLOC_THRESHOLD: usize = CHUNK_SIZE / 4;
+
+/// All allocations are aligned to 8 bytes (enough for `u64` and pointers).
+const ALIGN: usize = size_of::<u64>();
+
+/// A contiguous block of arena memory.
+struct Chunk {
+    data: *mut u64,
+    /// Capacity in bytes (multiple of `ALIGN`).
+    cap: usize,
+    /// Bytes handed out so far. May exceed `cap` after a failed allocation.
+    used: AtomicUsize,
+}
+
+impl Chunk {
+    fn new(cap: usize) -> Box<Self> {
+        let words = vec![0u64; cap / ALIGN].into_boxed_slice();
+        Box::new(Self {
+            data: Box::into_raw(words) as *mut u64,
+            cap,
+            used: AtomicUsize::new(0),
+        })
+    }
+
+    /// Reserves `size` bytes, returning `None` if the chunk is full.
+    fn try_alloc(&self, size: usize) -> Option<*mut u8> {
+        let offset = self.used.fetch_add(size, Ordering::Relaxed);
+        if offset + size <= self.cap {
+            // SAFETY: `offset + size <= cap`, so the range lies within the chunk.
+            Some(unsafe { (self.data as *mut u8).add(offset) })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // SAFETY: `data` was created by `Box::into_raw` on a slice of `cap / ALIGN` words.
+        unsafe {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.data,
+                self.cap / ALIGN,
+            )));
+        }
+    }
+}
+
+/// Lock-free (on the fast path) bump allocator.
+///
+/// Threads allocate from the current chunk with a single `fetch_add`; a mutex
+/// is only taken to install a new chunk once the current one is exhausted.
+/// Returned memory is zeroed, 8-byte aligned and valid until the arena is dropped.
+pub(crate) struct Arena {
+    current: AtomicPtr<Chunk>,
+    /// Owns every chunk. Chunks are boxed so `current` stays valid when the Vec grows.
+    #[allow(clippy::vec_box)]
+    chunks: Mutex<Vec<Box<Chunk>>>,
+    /// Bytes handed out to callers (after alignment padding).
+    allocated: AtomicUsize,
+}
+
+// SAFETY: chunk memory is only handed out in disjoint ranges and chunks are
+// never freed before the arena itself, so sharing across threads is sound.
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    pub(crate) fn new() -> Self {
+        let mut chunk = Chunk::new(CHUNK_SIZE);
+        let current: *mut Chunk = &mut *chunk;
+
+        Self {
+            current: AtomicPtr::new(current),
+            chunks: Mutex::new(vec![chunk]),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocates `size` bytes of zeroed, 8-byte aligned memory.
+    pub(crate) fn alloc(&self, size: usize) -> *mut u8 {
+        let size = size.next_multiple_of(ALIGN).max(ALIGN);
+        self.allocated.fetch_add(size, Ordering::Relaxed);
+
+        if size > LARGE_ALLOC_THRESHOLD {
+            let chunk = Chunk::new(size);
+            let ptr = chunk.try_alloc(size).unwrap();
+            self.chunks.lock().push(chunk);
+            return ptr;
+        }
+
+        loop {
+            let current = self.current.load(Ordering::Acquire);
+            // SAFETY: `current` always points to a chunk owned by `self.chunks`.
+            if let Some(ptr) = unsafe { &*current }.try_alloc(size) {
+                return ptr;
+            }
+
+            let mut chunks = self.chunks.lock();
+            // Another thread may have installed a fresh chunk already.
+            if self.current.load(Ordering::Acquire) == current {
+                let mut chunk = Chunk::new(CHUNK_SIZE);
+                self.current.store(&mut *chunk, Ordering::Release);
+                chunks.push(chunk);
+            }
+        }
+    }
+
+    /// Returns the number of bytes handed out by `alloc` (including alignment padding).
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+/// Size of a `BytesArena` chunk. Kept small so the reported size of a MemTable
+/// holding few entries stays close to what it really uses.
+pub(super) const BYTES_CHUNK_SIZE: usize = 64 * 1024; // 64KB
+
+/// Buffers larger than this get their own allocation instead of a chunk slice.
+const BYTES_LARGE_THRESHOLD: usize = BYTES_CHUNK_SIZE / 4;
+
+/// Bump allocator producing `Bytes` slices of shared chunks.
+///
+/// Each chunk is a single `BytesMut` allocation; copies are appended to it and
+/// split off, so every returned `Bytes` shares the chunk's reference count.
+/// A chunk is freed once the arena and every `Bytes` pointing into it are gone,
+/// so entries handed out by `get()` or `snapshot()` stay valid after a flush.
+pub(crate) struct BytesArena {
+    current: Mutex<BytesMut>,
+    /// Bytes reserved from the allocator (chunk capacities and large buffers).
+    allocated: AtomicUsize,
+}
+
+impl BytesArena {
+    /// Creates an empty arena. The first chunk is allocated lazily.
+    pub(crate) fn new() -> Self {
+        Self {
+            current: Mutex::new(BytesMut::new()),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Copies `data` into the arena.
+    pub(crate) fn copy(&self, data: &[u8]) -> Bytes {
+        if data.is_empty() {
+            return Bytes::new();
+        }
+
+        if data.len() > BYTES_LARGE_THRESHOLD {
+            self.allocated.fetch_add(data.len(), Ordering::Relaxed);
+            return Bytes::copy_from_slice(data);
+        }
+
+        let mut current = self.current.lock();
+        // After a split, `capacity()` is the space left in the chunk.
+        if current.capacity() < data.len() {
+            *current = BytesMut::with_capacity(BYTES_CHUNK_SIZE);
+            self.allocated
+                .fetch_add(BYTES_CHUNK_SIZE, Ordering::Relaxed);
+        }
+        current.extend_from_slice(data);
+        current.split().freeze()
+    }
+
+    /// Copies the data of a value into the arena.
+    pub(crate) fn copy_value(&self, value: &ValueType) -> ValueType {
+        match value {
+            ValueType::Normal(data) => ValueType::Normal(self.copy(data)),
+            ValueType::Tombstone => ValueType::Tombstone,
+            ValueType::Expiring { data, expire_at } => ValueType::Expiring {
+                data: self.copy(data),
+                expire_at: *expire_at,
+            },
+            ValueType::Merge(operand) => ValueType::Merge(self.copy(operand)),
+            ValueType::RangeTombstone { end } => ValueType::RangeTombstone {
+                end: self.copy(end),
+            },
+            ValueType::BlobIndex(index) => ValueType::BlobIndex(*index),
+        }
+    }
+
+    /// Returns the number of bytes reserved by the arena.
+    ///
+    /// Includes the unused tail of the current chunk.
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_arena_shares_chunks() {
+        let arena = BytesArena::new();
+        assert_eq!(arena.allocated_bytes(), 0);
+
+        let a = arena.copy(b"hello");
+        let b = arena.copy(b"world");
+        assert_eq!(a.as_ref(), b"hello");
+        assert_eq!(b.as_ref(), b"world");
+        // Both copies come from the same chunk
+        assert_eq!(arena.allocated_bytes(), BYTES_CHUNK_SIZE);
+        assert_eq!(a.as_ptr().wrapping_add(a.len()), b.as_ptr());
+
+        // Empty data doesn't allocate
+        assert!(arena.copy(b"").is_empty());
+        assert_eq!(arena.allocated_bytes(), BYTES_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_bytes_arena_chunk_rollover_and_large_values() {
+        let arena = BytesArena::new();
+
+        let data = vec![b'x'; BYTES_LARGE_THRESHOLD];
+        let copies: Vec<Bytes> = (0..5).map(|_| arena.copy(&data)).collect();
+        // Four copies fill a chunk, the fifth starts a new one
+        assert_eq!(arena.allocated_bytes(), 2 * BYTES_CHUNK_SIZE);
+        assert!(copies.iter().all(|c| c.as_ref() == data.as_slice()));
+
+        // Large values are accounted at their exact size
+        let large = vec![b'y'; BYTES_CHUNK_SIZE];
+        assert_eq!(arena.copy(&large).len(), BYTES_CHUNK_SIZE);
+        assert_eq!(arena.allocated_bytes(), 3 * BYTES_CHUNK_SIZE);
+
+        // Copies outlive the arena
+        drop(arena);
+        assert_eq!(copies[4].as_ref(), data.as_slice());
+    }
+}
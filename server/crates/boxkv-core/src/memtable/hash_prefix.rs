@@ -0,0 +1,255 @@
+//! Hash-indexed MemTable bucketed by key prefix.
+//!
+//! # Overview
+//!
+//! `HashPrefixMemTable` targets workloads dominated by point lookups. Keys are
+//! hashed by their first `prefix_len` bytes into a fixed number of buckets, each
+//! holding a hash map guarded by its own lock:
+//!
+//! - **Point lookups** hash the key and probe a single bucket in O(1).
+//! - **Prefix scans** over a full prefix only touch the bucket owning it.
+//! - **Full ordered iteration** (SSTable flush) collects and sorts every bucket,
+//!   which is O(n log n) instead of the O(n) of ordered representations.
+//!
+//! Writers to different buckets never contend with each other.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::arena::BytesArena;
+use super::range_del::RangeTombstoneList;
+use super::{EntryInfo, INDEX_ENTRY_SIZE};
+use crate::comparator::{Comparator, compare_entries, default_comparator};
+use crate::range_tombstone::FragmentedRangeTombstones;
+use boxkv_common::types::{Entry, ValueType};
+
+/// Number of buckets (and locks) in the table.
+const BUCKET_COUNT: usize = 64;
+
+/// MemTable optimized for point lookups, bucketed by a fixed-length key prefix.
+///
+/// Keys shorter than `prefix_len` are bucketed by the whole key.
+///
+/// # Examples
+///
+/// ```ignore
+/// let memtable = HashPrefixMemTable::new(5);
+///
+/// memtable.insert(1, Bytes::from("user:1"), ValueType::Normal(Bytes::from("Alice")));
+/// memtable.insert(2, Bytes::from("user:2"), ValueType::Normal(Bytes::from("Bob")));
+///
+/// assert_eq!(memtable.get(&Bytes::from("user:1")).unwrap().seq(), 1);
+/// assert_eq!(memtable.scan_prefix(b"user:").len(), 2);
+/// ```
+pub struct HashPrefixMemTable {
+    prefix_len: usize,
+    hasher: RandomState,
+    buckets: Box<[RwLock<HashMap<Bytes, EntryInfo>>]>,
+
+    /// Orders entries returned by scans and snapshots.
+    comparator: Arc<dyn Comparator>,
+
+    /// Backing storage for every key and value, shared by all buckets.
+    arena: BytesArena,
+
+    /// Memory taken by index slots, in bytes.
+    index_size: AtomicU64,
+
+    /// Range deletions, kept apart from the buckets.
+    range_tombstones: RangeTombstoneList,
+}
+
+impl HashPrefixMemTable {
+    /// Creates a new empty table hashing keys by their first `prefix_len` bytes,
+    /// ordered by `BytewiseComparator`.
+    pub fn new(prefix_len: usize) -> Self {
+        Self::with_comparator(prefix_len, default_comparator())
+    }
+
+    /// Creates a new empty table whose scans are ordered by `comparator`.
+    ///
+    /// Prefixes are always matched on raw bytes.
+    pub fn with_comparator(prefix_len: usize, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            prefix_len,
+            range_tombstones: RangeTombstoneList::new(comparator.clone()),
+            comparator,
+            hasher: RandomState::new(),
+            buckets: (0..BUCKET_COUNT).map(|_| RwLock::default()).collect(),
+            arena: BytesArena::new(),
+            index_size: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the bucket owning `key`.
+    fn bucket(&self, key: &[u8]) -> &RwLock<HashMap<Bytes, EntryInfo>> {
+        let prefix = &key[..key.len().min(self.prefix_len)];
+        let index = self.hasher.hash_one(prefix) as usize % BUCKET_COUNT;
+        &self.buckets[index]
+    }
+
+    /// Inserts a version of `key`, unless a newer version is already stored.
+    ///
+    /// Key and value are copied into the arena, like in `MemTable`.
+    pub fn insert(&self, seq: u64, key: Bytes, value: ValueType) {
+        if let ValueType::RangeTombstone { end } = &value {
+            self.range_tombstones.add(seq, &key, end);
+            return;
+        }
+
+        let mut bucket = self.bucket(&key).write();
+
+        match bucket.get_mut(&key) {
+            Some(entry_info) => entry_info.add_version(seq, &value, &self.arena),
+            None => {
+                let key = self.arena.copy(&key);
+                let value = self.arena.copy_value(&value);
+                self.index_size
+                    .fetch_add(INDEX_ENTRY_SIZE as u64, Ordering::SeqCst);
+                bucket.insert(key, EntryInfo::new(seq, value));
+            }
+        }
+    }
+
+    /// Retrieves the latest version of a key (may be a tombstone).
+    pub fn get(&self, key: &Bytes) -> Option<Entry> {
+        let latest =
+            self.bucket(key).read().get(key).map(|entry_info| {
+                Entry::new(entry_info.seq, key.clone(), entry_info.value.clone())
+            });
+        self.range_tombstones.shadow(key, latest)
+    }
+
+    /// Retrieves the latest version of a key, followed by the older versions its
+    /// merge operands apply to (see `MemTable::get_versions()`).
+    pub fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        let versions = self
+            .bucket(key)
+            .read()
+            .get(key)
+            .map(|entry_info| entry_info.versions(key).collect())
+            .unwrap_or_default();
+        self.range_tombstones.shadow_versions(key, versions)
+    }
+
+    /// Returns the range tombstones of the table.
+    pub fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        self.range_tombstones.get()
+    }
+
+    /// Returns every entry whose key starts with `prefix`, sorted by key.
+    ///
+    /// Prefixes at least `prefix_len` bytes long are served from a single bucket;
+    /// shorter ones have to scan the whole table.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Vec<Entry> {
+        let matches = |key: &Bytes| key.starts_with(prefix);
+
+        let mut entries: Vec<Entry> = if prefix.len() >= self.prefix_len {
+            Self::collect(&self.bucket(prefix).read(), matches)
+        } else {
+            self.buckets
+                .iter()
+                .flat_map(|bucket| Self::collect(&bucket.read(), matches))
+                .collect()
+        };
+        entries.sort_unstable_by(|a, b| compare_entries(&*self.comparator, a, b));
+        entries
+    }
+
+    fn collect(bucket: &HashMap<Bytes, EntryInfo>, filter: impl Fn(&Bytes) -> bool) -> Vec<Entry> {
+        bucket
+            .iter()
+            .filter(|(key, _)| filter(key))
+            .flat_map(|(key, entry_info)| entry_info.versions(key))
+            .collect()
+    }
+
+    /// Returns the memory usage in bytes, measured like `MemTable::size()`.
+    pub fn size(&self) -> u64 {
+        self.arena.allocated_bytes() as u64
+            + self.index_size.load(Ordering::SeqCst)
+            + self.range_tombstones.size()
+    }
+
+    /// Creates a snapshot of all entries sorted by key.
+    ///
+    /// Buckets are locked one at a time, so writes racing with the snapshot may
+    /// or may not be included.
+    pub fn snapshot(&self) -> Vec<Entry> {
+        self.scan_prefix(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memtable::arena::BYTES_CHUNK_SIZE;
+
+    #[test]
+    fn test_hash_prefix_scan_prefix() {
+        let memtable = HashPrefixMemTable::new(5);
+
+        memtable.insert(
+            1,
+            Bytes::from("user:2"),
+            ValueType::Normal(Bytes::from("b")),
+        );
+        memtable.insert(
+            2,
+            Bytes::from("user:1"),
+            ValueType::Normal(Bytes::from("a")),
+        );
+        memtable.insert(
+            3,
+            Bytes::from("post:1"),
+            ValueType::Normal(Bytes::from("p")),
+        );
+        memtable.insert(4, Bytes::from("us"), ValueType::Tombstone);
+
+        let users = memtable.scan_prefix(b"user:");
+        let keys: Vec<&[u8]> = users.iter().map(|e| e.key().as_ref()).collect();
+        assert_eq!(keys, vec![b"user:1".as_ref(), b"user:2"]);
+
+        // Shorter prefix falls back to a full scan
+        let keys: Vec<Bytes> = memtable
+            .scan_prefix(b"us")
+            .into_iter()
+            .map(|e| e.key().clone())
+            .collect();
+        assert_eq!(keys, vec!["us", "user:1", "user:2"]);
+
+        assert_eq!(memtable.snapshot().len(), 4);
+    }
+
+    #[test]
+    fn test_hash_prefix_size_tracking() {
+        let memtable = HashPrefixMemTable::new(2);
+        assert_eq!(memtable.size(), 0);
+
+        memtable.insert(
+            1,
+            Bytes::from("key1"),
+            ValueType::Normal(Bytes::from("value1")),
+        );
+        let expected_size = (BYTES_CHUNK_SIZE + INDEX_ENTRY_SIZE) as u64;
+        assert_eq!(memtable.size(), expected_size);
+
+        // Updates and shadowed inserts reuse the index slot and the chunk
+        memtable.insert(2, Bytes::from("key1"), ValueType::Tombstone);
+        memtable.insert(
+            1,
+            Bytes::from("key1"),
+            ValueType::Normal(Bytes::from("value1")),
+        );
+        assert_eq!(memtable.size(), expected_size);
+
+        // Keys in other buckets share the same arena
+        memtable.insert(3, Bytes::from("zz"), ValueType::Tombstone);
+        assert_eq!(memtable.size(), expected_size + INDEX_ENTRY_SIZE as u64);
+    }
+}
@@ -0,0 +1,61 @@
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::comparator::Comparator;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use boxkv_common::types::Entry;
+
+/// Range tombstones of a MemTable, kept apart from its point entries.
+///
+/// Shared by every `MemTableRep`: range deletions are rare, so the list is
+/// simply re-fragmented under a write lock on every insert.
+pub(super) struct RangeTombstoneList {
+    tombstones: RwLock<FragmentedRangeTombstones>,
+    /// Bytes held by the inserted tombstones.
+    size: AtomicU64,
+}
+
+impl RangeTombstoneList {
+    pub(super) fn new(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            tombstones: RwLock::new(FragmentedRangeTombstones::new(comparator)),
+            size: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds the deletion of `[start, end)` at `seq`.
+    pub(super) fn add(&self, seq: u64, start: &[u8], end: &[u8]) {
+        let tombstone = RangeTombstone::new(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+            seq,
+        );
+        let size = start.len() + end.len() + size_of::<RangeTombstone>();
+        self.size.fetch_add(size as u64, Ordering::SeqCst);
+        self.tombstones.write().add(tombstone);
+    }
+
+    /// Returns a copy of the fragmented tombstones.
+    pub(super) fn get(&self) -> FragmentedRangeTombstones {
+        self.tombstones.read().clone()
+    }
+
+    /// See `FragmentedRangeTombstones::shadow`.
+    pub(super) fn shadow(&self, key: &Bytes, latest: Option<Entry>) -> Option<Entry> {
+        self.tombstones.read().shadow(key, latest)
+    }
+
+    /// See `FragmentedRangeTombstones::shadow_versions`.
+    pub(super) fn shadow_versions(&self, key: &Bytes, versions: Vec<Entry>) -> Vec<Entry> {
+        self.tombstones.read().shadow_versions(key, versions)
+    }
+
+    /// Returns the bytes held by the inserted tombstones (never decreasing).
+    pub(super) fn size(&self) -> u64 {
+        self.size.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,336 @@
+//! Pluggable MemTable representations.
+//!
+//! The Engine talks to the active MemTable through `MemTableRep`, so the data
+//! structure can be picked per configuration to fit the access pattern:
+//!
+//! | Representation       | Writes             | Point lookups | Ordered iteration  |
+//! |----------------------|--------------------|---------------|--------------------|
+//! | `MemTable`           | Exclusive lock     | O(log n)      | Snapshot, O(n)     |
+//! | `SkipListMemTable`   | Lock-free          | O(log n)      | Lazy, O(n)         |
+//! | `HashPrefixMemTable` | Per-bucket lock    | O(1)          | Sort, O(n log n)   |
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::{HashPrefixMemTable, MemTable, SkipListMemTable};
+use crate::comparator::Comparator;
+use crate::range_tombstone::FragmentedRangeTombstones;
+use boxkv_common::config::MemTableRepKind;
+use boxkv_common::types::{Entry, ValueType};
+
+/// Common interface of all MemTable data structures.
+///
+/// Every representation keeps MVCC semantics: readers only see the version of
+/// a key with the highest sequence number, whatever the insertion order. Merge
+/// operands are the exception: the older versions they apply to stay visible
+/// until a version that is not a merge operand.
+pub trait MemTableRep: Send + Sync {
+    /// Inserts a version of `key` (Normal, Tombstone, Expiring, or Merge).
+    ///
+    /// A version older than the one already stored is shadowed, so recovered
+    /// entries can be inserted in any order. A `RangeTombstone` (deleting
+    /// `[key, end)`) goes to the MemTable's range tombstone list.
+    fn insert(&self, seq: u64, key: Bytes, value: ValueType);
+
+    /// Retrieves the latest version of a key (may be a tombstone or a merge operand).
+    ///
+    /// Keys covered by a newer range tombstone are returned as a tombstone.
+    fn get(&self, key: &Bytes) -> Option<Entry>;
+
+    /// Retrieves the latest version of a key, followed by the older versions
+    /// its merge operands apply to, newest first.
+    ///
+    /// Feed the result to a `MergeContext` to resolve merge operands.
+    fn get_versions(&self, key: &Bytes) -> Vec<Entry>;
+
+    /// Returns the latest version of every key, sorted by the MemTable's comparator.
+    /// Merge operands are followed by the versions they apply to, newest first.
+    ///
+    /// Range tombstones are not applied. Used to flush the MemTable to an SSTable.
+    fn iter(&self) -> Box<dyn Iterator<Item = Entry> + '_>;
+
+    /// Returns the range tombstones, flushed into the SSTable's meta block.
+    fn range_tombstones(&self) -> FragmentedRangeTombstones;
+
+    /// Returns the memory held by the MemTable in bytes.
+    ///
+    /// Measured from arena allocations and never decreasing, so the Engine can
+    /// rely on it for flush decisions.
+    fn size(&self) -> u64;
+}
+
+/// Creates an empty MemTable of the representation `kind`, ordering keys
+/// with `comparator`.
+///
+/// `prefix_len` is the key prefix length of `HashPrefix` MemTables (see
+/// `StorageConfig::memtable_prefix_len`) and ignored by the others.
+///
+/// # Examples
+///
+/// ```ignore
+/// let memtable = memtable::new_rep(MemTableRepKind::SkipList, 8, default_comparator());
+/// memtable.insert(1, Bytes::from("key"), ValueType::Normal(Bytes::from("value")));
+/// ```
+pub fn new_rep(
+    kind: MemTableRepKind,
+    prefix_len: usize,
+    comparator: Arc<dyn Comparator>,
+) -> Box<dyn MemTableRep> {
+    match kind {
+        MemTableRepKind::BTree => Box::new(MemTable::with_comparator(comparator)),
+        MemTableRepKind::SkipList => Box::new(SkipListMemTable::with_comparator(comparator)),
+        MemTableRepKind::HashPrefix => {
+            Box::new(HashPrefixMemTable::with_comparator(prefix_len, comparator))
+        }
+    }
+}
+
+impl MemTableRep for MemTable {
+    fn insert(&self, seq: u64, key: Bytes, value: ValueType) {
+        self.update(seq, key, value);
+    }
+
+    fn get(&self, key: &Bytes) -> Option<Entry> {
+        MemTable::get(self, key)
+    }
+
+    fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        MemTable::get_versions(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Entry> + '_> {
+        Box::new(self.snapshot().into_iter())
+    }
+
+    fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        MemTable::range_tombstones(self)
+    }
+
+    fn size(&self) -> u64 {
+        MemTable::size(self)
+    }
+}
+
+impl MemTableRep for SkipListMemTable {
+    fn insert(&self, seq: u64, key: Bytes, value: ValueType) {
+        self.apply(Entry::new(seq, key, value));
+    }
+
+    fn get(&self, key: &Bytes) -> Option<Entry> {
+        SkipListMemTable::get(self, key)
+    }
+
+    fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        SkipListMemTable::get_versions(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Entry> + '_> {
+        Box::new(SkipListMemTable::iter(self))
+    }
+
+    fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        SkipListMemTable::range_tombstones(self)
+    }
+
+    fn size(&self) -> u64 {
+        SkipListMemTable::size(self)
+    }
+}
+
+impl MemTableRep for HashPrefixMemTable {
+    fn insert(&self, seq: u64, key: Bytes, value: ValueType) {
+        HashPrefixMemTable::insert(self, seq, key, value);
+    }
+
+    fn get(&self, key: &Bytes) -> Option<Entry> {
+        HashPrefixMemTable::get(self, key)
+    }
+
+    fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        HashPrefixMemTable::get_versions(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Entry> + '_> {
+        Box::new(self.snapshot().into_iter())
+    }
+
+    fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        HashPrefixMemTable::range_tombstones(self)
+    }
+
+    fn size(&self) -> u64 {
+        HashPrefixMemTable::size(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+
+    fn all_reps() -> Vec<(MemTableRepKind, Box<dyn MemTableRep>)> {
+        reps_with(default_comparator())
+    }
+
+    fn reps_with(comparator: Arc<dyn Comparator>) -> Vec<(MemTableRepKind, Box<dyn MemTableRep>)> {
+        [
+            MemTableRepKind::BTree,
+            MemTableRepKind::SkipList,
+            MemTableRepKind::HashPrefix,
+        ]
+        .into_iter()
+        .map(|kind| (kind, new_rep(kind, 2, comparator.clone())))
+        .collect()
+    }
+
+    #[test]
+    fn test_reps_point_lookups() {
+        for (kind, rep) in all_reps() {
+            rep.insert(
+                1,
+                Bytes::from("user:1"),
+                ValueType::Normal(Bytes::from("a")),
+            );
+            rep.insert(
+                2,
+                Bytes::from("user:2"),
+                ValueType::Normal(Bytes::from("b")),
+            );
+            rep.insert(3, Bytes::from("user:1"), ValueType::Tombstone);
+            // Older version arriving late is shadowed
+            rep.insert(
+                2,
+                Bytes::from("user:1"),
+                ValueType::Normal(Bytes::from("x")),
+            );
+
+            let entry = rep.get(&Bytes::from("user:1")).unwrap();
+            assert_eq!(entry.seq(), 3, "{:?}", kind);
+            assert!(entry.is_tombstone(), "{:?}", kind);
+            assert_eq!(rep.get(&Bytes::from("user:2")).unwrap().seq(), 2);
+            assert!(rep.get(&Bytes::from("user:3")).is_none(), "{:?}", kind);
+            assert!(rep.size() > 0, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_reps_iterate_in_key_order() {
+        for (kind, rep) in all_reps() {
+            let keys = ["zz", "a", "mango", "ab", "", "m"];
+            for (seq, key) in keys.iter().enumerate() {
+                rep.insert(
+                    seq as u64,
+                    Bytes::from(*key),
+                    ValueType::Normal(Bytes::from("v")),
+                );
+            }
+            rep.insert(10, Bytes::from("a"), ValueType::Normal(Bytes::from("v2")));
+
+            let entries: Vec<Entry> = rep.iter().collect();
+            let iterated: Vec<&[u8]> = entries.iter().map(|e| e.key().as_ref()).collect();
+            assert_eq!(
+                iterated,
+                vec![b"".as_ref(), b"a", b"ab", b"m", b"mango", b"zz"],
+                "{:?}",
+                kind
+            );
+            assert_eq!(entries[1].seq(), 10, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_reps_keep_merge_bases() {
+        let merge = |operand: &'static str| ValueType::Merge(Bytes::from(operand));
+
+        for (kind, rep) in all_reps() {
+            rep.insert(1, Bytes::from("k"), ValueType::Normal(Bytes::from("a")));
+            rep.insert(2, Bytes::from("k"), ValueType::Normal(Bytes::from("b")));
+            rep.insert(4, Bytes::from("k"), merge("d"));
+            // Operand arriving late still applies on top of "b"
+            rep.insert(3, Bytes::from("k"), merge("c"));
+            rep.insert(1, Bytes::from("other"), merge("x"));
+
+            let seqs: Vec<u64> = rep
+                .get_versions(&Bytes::from("k"))
+                .iter()
+                .map(|e| e.seq())
+                .collect();
+            assert_eq!(seqs, vec![4, 3, 2], "{:?}", kind);
+            assert_eq!(rep.get(&Bytes::from("k")).unwrap().seq(), 4, "{:?}", kind);
+
+            let flushed: Vec<(Bytes, u64)> =
+                rep.iter().map(|e| (e.key().clone(), e.seq())).collect();
+            assert_eq!(
+                flushed,
+                vec![
+                    (Bytes::from("k"), 4),
+                    (Bytes::from("k"), 3),
+                    (Bytes::from("k"), 2),
+                    (Bytes::from("other"), 1),
+                ],
+                "{:?}",
+                kind
+            );
+
+            // A newer full value drops the operands and their base
+            rep.insert(5, Bytes::from("k"), ValueType::Tombstone);
+            assert_eq!(rep.get_versions(&Bytes::from("k")).len(), 1, "{:?}", kind);
+            assert!(rep.get_versions(&Bytes::from("missing")).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_reps_range_tombstones() {
+        for (kind, rep) in all_reps() {
+            let put = |seq, key: &'static str| {
+                rep.insert(seq, Bytes::from(key), ValueType::Normal(Bytes::from("v")))
+            };
+            put(1, "t1:a");
+            put(2, "t1:b");
+            put(3, "t2:a");
+            let size = rep.size();
+
+            rep.insert(
+                4,
+                Bytes::from("t1:"),
+                ValueType::RangeTombstone {
+                    end: Bytes::from("t1;"),
+                },
+            );
+            put(5, "t1:b");
+            assert!(rep.size() > size, "{:?}", kind);
+
+            let deleted = rep.get(&Bytes::from("t1:a")).unwrap();
+            assert!(deleted.is_tombstone(), "{:?}", kind);
+            assert_eq!(deleted.seq(), 4, "{:?}", kind);
+            // Covered but never written: older sources must not be read
+            assert!(rep.get(&Bytes::from("t1:c")).unwrap().is_tombstone());
+            // Written after the deletion, or outside the range
+            assert_eq!(
+                rep.get(&Bytes::from("t1:b")).unwrap().seq(),
+                5,
+                "{:?}",
+                kind
+            );
+            assert!(!rep.get(&Bytes::from("t2:a")).unwrap().is_tombstone());
+
+            // Point entries are flushed untouched, tombstones separately
+            assert_eq!(rep.iter().count(), 3, "{:?}", kind);
+            assert_eq!(rep.range_tombstones().fragments().len(), 1, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_reps_use_comparator() {
+        for (kind, rep) in reps_with(Arc::new(ReverseBytewiseComparator)) {
+            for (seq, key) in ["b", "c", "a"].into_iter().enumerate() {
+                rep.insert(seq as u64, Bytes::from(key), ValueType::Tombstone);
+            }
+
+            let keys: Vec<Bytes> = rep.iter().map(|e| e.key().clone()).collect();
+            assert_eq!(keys, vec!["c", "b", "a"], "{:?}", kind);
+            assert!(rep.get(&Bytes::from("a")).is_some(), "{:?}", kind);
+        }
+    }
+}
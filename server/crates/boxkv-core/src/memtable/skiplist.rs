@@ -0,0 +1,696 @@
+//! Concurrent, arena-backed skiplist MemTable.
+//!
+//! # Overview
+//!
+//! `SkipListMemTable` accepts inserts through `&self` from many threads at once
+//! and serves reads without taking any lock, so the Engine doesn't need an outer
+//! lock around the active MemTable.
+//!
+//! # Design
+//!
+//! - **Insert-only**: Every write adds a node keyed by `(key ASC, seq DESC)`,
+//!   keys being ordered by the MemTable's `Comparator`. Updates and deletes never modify existing nodes, so readers can traverse
+//!   the list while writers link new nodes in with a single CAS per level.
+//! - **Arena allocation**: Nodes, keys and values are copied into an `Arena`
+//!   and freed all at once when the MemTable is dropped.
+//! - **Latest version wins**: `get()` and `snapshot()` only expose the version
+//!   with the highest sequence number per key, matching `MemTable`. Versions
+//!   below merge operands are kept visible until the first non-merge value.
+//!
+//! # Node Layout
+//!
+//! ```text
+//! +-------------+------------------------+----------+------------+
+//! | Node header | Tower (height x ptr)   | Key Data | Value Data |
+//! +-------------+------------------------+----------+------------+
+//! ```
+
+use std::cell::Cell;
+use std::cmp::Ordering as CmpOrdering;
+use std::hash::{BuildHasher, RandomState};
+use std::mem::size_of;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use bytes::Bytes;
+
+use super::arena::Arena;
+use super::range_del::RangeTombstoneList;
+use crate::comparator::{Comparator, default_comparator};
+use crate::range_tombstone::FragmentedRangeTombstones;
+use boxkv_common::types::{
+    BLOB_INDEX_VALUE_TYPE, BlobIndex, EXPIRING_VALUE_TYPE, Entry, MERGE_VALUE_TYPE,
+    NORMAL_VALUE_TYPE, TOMBSTONE_VALUE_TYPE, ValueType,
+};
+
+/// Maximum tower height. With a branching factor of 4 this comfortably indexes
+/// tens of millions of entries.
+const MAX_HEIGHT: usize = 12;
+
+/// Probability of growing a tower by one level is `1 / BRANCHING`.
+const BRANCHING: u64 = 4;
+
+/// Fixed-size node header, followed in memory by the tower, the key and the value.
+#[repr(C)]
+struct Node {
+    seq: u64,
+    /// Only meaningful for `EXPIRING_VALUE_TYPE`.
+    expire_at: u64,
+    key_len: u32,
+    val_len: u32,
+    value_tag: u8,
+    height: u8,
+}
+
+const NODE_HEADER_SIZE: usize = size_of::<Node>();
+const TOWER_SLOT_SIZE: usize = size_of::<AtomicPtr<Node>>();
+
+/// Returns the `level`-th next pointer of a node.
+///
+/// # Safety
+/// `node` must point to a live node and `level < node.height`.
+unsafe fn tower<'a>(node: *const Node, level: usize) -> &'a AtomicPtr<Node> {
+    unsafe {
+        &*((node as *const u8).add(NODE_HEADER_SIZE + level * TOWER_SLOT_SIZE)
+            as *const AtomicPtr<Node>)
+    }
+}
+
+/// Returns the key stored in a node.
+///
+/// # Safety
+/// `node` must point to a fully initialized node.
+unsafe fn node_key<'a>(node: *const Node) -> &'a [u8] {
+    unsafe {
+        let height = (*node).height as usize;
+        let data = (node as *const u8).add(NODE_HEADER_SIZE + height * TOWER_SLOT_SIZE);
+        std::slice::from_raw_parts(data, (*node).key_len as usize)
+    }
+}
+
+/// Returns the value bytes stored in a node.
+///
+/// # Safety
+/// `node` must point to a fully initialized node.
+unsafe fn node_value<'a>(node: *const Node) -> &'a [u8] {
+    unsafe {
+        let key = node_key(node);
+        std::slice::from_raw_parts(key.as_ptr().add(key.len()), (*node).val_len as usize)
+    }
+}
+
+/// Rebuilds a public `Entry` from a node (copying key and value out of the arena).
+///
+/// # Safety
+/// `node` must point to a fully initialized node.
+unsafe fn node_entry(node: *const Node) -> Entry {
+    unsafe {
+        let key = Bytes::copy_from_slice(node_key(node));
+        let data = Bytes::copy_from_slice(node_value(node));
+        let value = match (*node).value_tag {
+            NORMAL_VALUE_TYPE => ValueType::Normal(data),
+            TOMBSTONE_VALUE_TYPE => ValueType::Tombstone,
+            EXPIRING_VALUE_TYPE => ValueType::Expiring {
+                data,
+                expire_at: (*node).expire_at,
+            },
+            MERGE_VALUE_TYPE => ValueType::Merge(data),
+            BLOB_INDEX_VALUE_TYPE => {
+                ValueType::BlobIndex(BlobIndex::decode(&data).expect("blob index node"))
+            }
+            tag => unreachable!("invalid value tag {tag} in skiplist node"),
+        };
+        Entry::new((*node).seq, key, value)
+    }
+}
+
+/// Compares a node against a search target by `(key ASC, seq DESC)`.
+///
+/// # Safety
+/// `node` must point to a fully initialized node.
+unsafe fn compare(
+    comparator: &dyn Comparator,
+    node: *const Node,
+    key: &[u8],
+    seq: u64,
+) -> CmpOrdering {
+    unsafe {
+        comparator
+            .compare(node_key(node), key)
+            .then_with(|| seq.cmp(&(*node).seq))
+    }
+}
+
+thread_local! {
+    // Randomly seeded per thread; xorshift requires a non-zero state.
+    static RNG_STATE: Cell<u64> = Cell::new(RandomState::new().hash_one(0u64) | 1);
+}
+
+/// Picks a random tower height using a thread-local xorshift generator.
+fn random_height() -> usize {
+    RNG_STATE.with(|state| {
+        let mut height = 1;
+        let mut x = state.get();
+        while height < MAX_HEIGHT {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            if x % BRANCHING != 0 {
+                break;
+            }
+            height += 1;
+        }
+        state.set(x);
+        height
+    })
+}
+
+/// Lock-free, multi-writer MemTable backed by an arena skiplist.
+///
+/// # Thread Safety
+///
+/// - `put()`, `delete()` and `apply()` take `&self` and may run concurrently
+/// - Reads never block and observe every insert that completed before they started
+/// - Size tracking is lock-free
+///
+/// # Examples
+///
+/// ```ignore
+/// let memtable = Arc::new(SkipListMemTable::new());
+///
+/// std::thread::scope(|s| {
+///     for t in 0..4 {
+///         let memtable = &memtable;
+///         s.spawn(move || memtable.put(t, Bytes::from(format!("k{t}")), Bytes::from("v")));
+///     }
+/// });
+///
+/// assert_eq!(memtable.snapshot().len(), 4);
+/// ```
+pub struct SkipListMemTable {
+    arena: Arena,
+    head: *mut Node,
+    /// Arena bytes taken by the head node, excluded from `size()`.
+    head_size: usize,
+    /// Highest tower currently linked into the list.
+    max_height: AtomicUsize,
+    /// Orders keys in the list.
+    comparator: Arc<dyn Comparator>,
+    /// Range deletions, kept apart from the list.
+    range_tombstones: RangeTombstoneList,
+}
+
+// SAFETY: nodes are immutable once published (only tower pointers change, and
+// those are atomics), and the arena keeps every node alive until drop.
+unsafe impl Send for SkipListMemTable {}
+unsafe impl Sync for SkipListMemTable {}
+
+impl SkipListMemTable {
+    /// Creates a new empty skiplist MemTable ordered by `BytewiseComparator`.
+    pub fn new() -> Self {
+        Self::with_comparator(default_comparator())
+    }
+
+    /// Creates a new empty skiplist MemTable ordered by `comparator`.
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        let arena = Arena::new();
+        let head = Self::alloc_node(&arena, MAX_HEIGHT, 0, &[], &ValueType::Tombstone);
+        let head_size = arena.allocated_bytes();
+
+        Self {
+            arena,
+            head,
+            head_size,
+            max_height: AtomicUsize::new(1),
+            range_tombstones: RangeTombstoneList::new(comparator.clone()),
+            comparator,
+        }
+    }
+
+    /// Allocates and initializes a node (tower pointers are null).
+    fn alloc_node(
+        arena: &Arena,
+        height: usize,
+        seq: u64,
+        key: &[u8],
+        value: &ValueType,
+    ) -> *mut Node {
+        let encoded_index;
+        let (data, expire_at): (&[u8], u64) = match value {
+            ValueType::Normal(data) => (data, 0),
+            ValueType::Tombstone => (&[], 0),
+            ValueType::Expiring { data, expire_at } => (data, *expire_at),
+            ValueType::Merge(operand) => (operand, 0),
+            ValueType::BlobIndex(index) => {
+                encoded_index = index.encode();
+                (&encoded_index, 0)
+            }
+            ValueType::RangeTombstone { .. } => {
+                unreachable!("range tombstones are not stored in nodes")
+            }
+        };
+
+        let size = NODE_HEADER_SIZE + height * TOWER_SLOT_SIZE + key.len() + data.len();
+        let node = arena.alloc(size) as *mut Node;
+
+        // SAFETY: the arena returned `size` zeroed, aligned bytes owned by this node.
+        // Zeroed memory is a valid (null) `AtomicPtr` for every tower slot.
+        unsafe {
+            ptr::write(
+                node,
+                Node {
+                    seq,
+                    expire_at,
+                    key_len: key.len() as u32,
+                    val_len: data.len() as u32,
+                    value_tag: value.type_tag(),
+                    height: height as u8,
+                },
+            );
+            let key_dst = (node as *mut u8).add(NODE_HEADER_SIZE + height * TOWER_SLOT_SIZE);
+            ptr::copy_nonoverlapping(key.as_ptr(), key_dst, key.len());
+            ptr::copy_nonoverlapping(data.as_ptr(), key_dst.add(key.len()), data.len());
+        }
+
+        node
+    }
+
+    /// Inserts a new version of `key`. Concurrent callers are linearized per level by CAS.
+    ///
+    /// Range tombstones go to the separate range tombstone list.
+    fn insert(&self, seq: u64, key: Bytes, value: ValueType) {
+        if let ValueType::RangeTombstone { end } = &value {
+            self.range_tombstones.add(seq, &key, end);
+            return;
+        }
+
+        let height = random_height();
+        let node = Self::alloc_node(&self.arena, height, seq, &key, &value);
+
+        let mut max_height = self.max_height.load(Ordering::Relaxed);
+        while height > max_height {
+            match self.max_height.compare_exchange_weak(
+                max_height,
+                height,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => max_height = current,
+            }
+        }
+
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null_mut(); MAX_HEIGHT];
+
+        // SAFETY: every pointer reached from `head` is a published, initialized node,
+        // and `level < height` for every tower we touch.
+        unsafe {
+            let mut x = self.head;
+            for level in (0..MAX_HEIGHT).rev() {
+                (prev[level], next[level]) = self.find_splice(x, level, &key, seq);
+                x = prev[level];
+            }
+
+            for level in 0..height {
+                loop {
+                    tower(node, level).store(next[level], Ordering::Relaxed);
+                    if tower(prev[level], level)
+                        .compare_exchange(next[level], node, Ordering::Release, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        break;
+                    }
+                    // Lost a race with another writer; recompute the splice at this level.
+                    (prev[level], next[level]) = self.find_splice(prev[level], level, &key, seq);
+                }
+            }
+        }
+    }
+
+    /// Walks `level` starting at `start` and returns `(prev, next)` such that
+    /// `prev < (key, seq) <= next`.
+    ///
+    /// # Safety
+    /// `start` must be `head` or a published node with a tower above `level`.
+    unsafe fn find_splice(
+        &self,
+        start: *mut Node,
+        level: usize,
+        key: &[u8],
+        seq: u64,
+    ) -> (*mut Node, *mut Node) {
+        unsafe {
+            let mut x = start;
+            loop {
+                let next = tower(x, level).load(Ordering::Acquire);
+                if !next.is_null()
+                    && compare(&*self.comparator, next, key, seq) == CmpOrdering::Less
+                {
+                    x = next;
+                } else {
+                    return (x, next);
+                }
+            }
+        }
+    }
+
+    /// Returns the first node at or after `(key, seq)`, or null.
+    fn seek(&self, key: &[u8], seq: u64) -> *mut Node {
+        let top = self.max_height.load(Ordering::Relaxed);
+        let mut x = self.head;
+        let mut next = ptr::null_mut();
+        // SAFETY: see `insert`.
+        unsafe {
+            for level in (0..top).rev() {
+                (x, next) = self.find_splice(x, level, key, seq);
+            }
+        }
+        next
+    }
+
+    /// Inserts or updates a key-value pair (PUT operation).
+    ///
+    /// The previous version stays in the arena but is shadowed by the new one.
+    pub fn put(&self, seq: u64, key: Bytes, value: Bytes) {
+        self.insert(seq, key, ValueType::Normal(value));
+    }
+
+    /// Marks a key as deleted by writing a tombstone (DELETE operation).
+    pub fn delete(&self, seq: u64, key: Bytes) {
+        self.insert(seq, key, ValueType::Tombstone);
+    }
+
+    /// Deletes every key in `[start, end)` with a single range tombstone.
+    pub fn delete_range(&self, seq: u64, start: Bytes, end: Bytes) {
+        self.insert(seq, start, ValueType::RangeTombstone { end });
+    }
+
+    /// Applies a recovered entry.
+    ///
+    /// Versions are ordered by sequence number, so entries may be applied in any order.
+    pub fn apply(&self, entry: Entry) {
+        self.insert(entry.seq(), entry.key().clone(), entry.val().clone());
+    }
+
+    /// Retrieves the latest version of a key.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Entry)` - Key exists (may be a tombstone)
+    /// - `None` - Key not found
+    pub fn get(&self, key: &Bytes) -> Option<Entry> {
+        let node = self.seek(key, u64::MAX);
+        // SAFETY: `seek` only returns null or published nodes.
+        let latest = unsafe {
+            (!node.is_null() && self.comparator.compare(node_key(node), key) == CmpOrdering::Equal)
+                .then(|| node_entry(node))
+        };
+        self.range_tombstones.shadow(key, latest)
+    }
+
+    /// Retrieves the latest version of a key, followed by the older versions its
+    /// merge operands apply to, newest first.
+    ///
+    /// Stops after the first version that is not a merge operand.
+    pub fn get_versions(&self, key: &Bytes) -> Vec<Entry> {
+        let mut versions = Vec::new();
+        let mut node = self.seek(key, u64::MAX);
+        // SAFETY: `seek` and towers only lead to null or published nodes.
+        unsafe {
+            while !node.is_null()
+                && self.comparator.compare(node_key(node), key) == CmpOrdering::Equal
+            {
+                versions.push(node_entry(node));
+                if (*node).value_tag != MERGE_VALUE_TYPE {
+                    break;
+                }
+                node = tower(node, 0).load(Ordering::Acquire);
+            }
+        }
+        self.range_tombstones.shadow_versions(key, versions)
+    }
+
+    /// Returns the range tombstones of the MemTable.
+    pub fn range_tombstones(&self) -> FragmentedRangeTombstones {
+        self.range_tombstones.get()
+    }
+
+    /// Returns the number of bytes allocated from the arena.
+    ///
+    /// Includes node headers, towers and shadowed versions, plus range tombstones.
+    pub fn size(&self) -> u64 {
+        (self.arena.allocated_bytes() - self.head_size) as u64 + self.range_tombstones.size()
+    }
+
+    /// Returns an iterator over the latest version of every key, sorted by key.
+    ///
+    /// Merge operands are followed by the older versions they apply to (see
+    /// `get_versions`). Walks the bottom level lazily without blocking concurrent writers.
+    /// Inserts racing with the iteration may or may not be observed.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            // SAFETY: `head` has a full tower.
+            next: unsafe { tower(self.head, 0).load(Ordering::Acquire) },
+            memtable: self,
+        }
+    }
+
+    /// Returns the latest version of every key, sorted by key.
+    ///
+    /// Does not block concurrent writers. Inserts racing with the snapshot may
+    /// or may not be included.
+    pub fn snapshot(&self) -> Vec<Entry> {
+        self.iter().collect()
+    }
+}
+
+/// Ordered iterator over a `SkipListMemTable`, yielding the latest version per key
+/// (and the versions below merge operands).
+pub struct Iter<'a> {
+    next: *mut Node,
+    /// Keeps the arena (and therefore every node) alive.
+    memtable: &'a SkipListMemTable,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if self.next.is_null() {
+            return None;
+        }
+
+        // SAFETY: `next` is a published node kept alive by the borrowed memtable.
+        unsafe {
+            let node = self.next;
+            let key = node_key(node);
+
+            // Versions of a key are adjacent, newest first: skip the older ones,
+            // unless a merge operand still needs them.
+            let mut x = tower(node, 0).load(Ordering::Acquire);
+            let comparator = &*self.memtable.comparator;
+            while (*node).value_tag != MERGE_VALUE_TYPE
+                && !x.is_null()
+                && comparator.compare(node_key(x), key) == CmpOrdering::Equal
+            {
+                x = tower(x, 0).load(Ordering::Acquire);
+            }
+            self.next = x;
+
+            Some(node_entry(node))
+        }
+    }
+}
+
+impl Default for SkipListMemTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_skiplist_new_is_empty() {
+        let memtable = SkipListMemTable::new();
+        assert!(memtable.snapshot().is_empty());
+        assert!(memtable.get(&Bytes::from("key")).is_none());
+    }
+
+    #[test]
+    fn test_skiplist_put_and_get() {
+        let memtable = SkipListMemTable::new();
+
+        memtable.put(1, Bytes::from("key1"), Bytes::from("value1"));
+        memtable.put(2, Bytes::from("key2"), Bytes::from("value2"));
+
+        let entry = memtable.get(&Bytes::from("key1")).unwrap();
+        assert_eq!(entry.seq(), 1);
+        match entry.val() {
+            ValueType::Normal(data) => assert_eq!(data.as_ref(), b"value1"),
+            _ => panic!("Expected Normal value"),
+        }
+        assert_eq!(memtable.get(&Bytes::from("key2")).unwrap().seq(), 2);
+        assert!(memtable.get(&Bytes::from("key")).is_none());
+        assert!(memtable.get(&Bytes::from("key3")).is_none());
+    }
+
+    #[test]
+    fn test_skiplist_latest_version_wins() {
+        let memtable = SkipListMemTable::new();
+
+        memtable.put(1, Bytes::from("key1"), Bytes::from("v1"));
+        memtable.put(3, Bytes::from("key1"), Bytes::from("v3"));
+        // Out-of-order apply must not shadow the newer version
+        memtable.apply(Entry::new_normal(2, Bytes::from("key1"), Bytes::from("v2")));
+
+        let entry = memtable.get(&Bytes::from("key1")).unwrap();
+        assert_eq!(entry.seq(), 3);
+
+        memtable.delete(4, Bytes::from("key1"));
+        assert!(memtable.get(&Bytes::from("key1")).unwrap().is_tombstone());
+    }
+
+    #[test]
+    fn test_skiplist_value_types_roundtrip() {
+        let memtable = SkipListMemTable::new();
+
+        memtable.put(1, Bytes::from(""), Bytes::from(""));
+        memtable.apply(Entry::new_expiring(
+            2,
+            Bytes::from("exp"),
+            Bytes::from("v"),
+            42,
+        ));
+        memtable.delete(3, Bytes::from("gone"));
+
+        let snapshot = memtable.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot[0].key().len(), 0);
+        match snapshot[1].val() {
+            ValueType::Expiring { data, expire_at } => {
+                assert_eq!(data.as_ref(), b"v");
+                assert_eq!(*expire_at, 42);
+            }
+            _ => panic!("Expected Expiring value"),
+        }
+        assert!(snapshot[2].is_tombstone());
+    }
+
+    #[test]
+    fn test_skiplist_snapshot_sorted_latest_only() {
+        let memtable = SkipListMemTable::new();
+
+        memtable.put(1, Bytes::from("zebra"), Bytes::from("z"));
+        memtable.put(2, Bytes::from("apple"), Bytes::from("a"));
+        memtable.put(3, Bytes::from("mango"), Bytes::from("m"));
+        memtable.put(4, Bytes::from("apple"), Bytes::from("a2"));
+
+        let snapshot = memtable.snapshot();
+        let keys: Vec<&[u8]> = snapshot.iter().map(|e| e.key().as_ref()).collect();
+        assert_eq!(keys, vec![b"apple".as_ref(), b"mango", b"zebra"]);
+        assert_eq!(snapshot[0].seq(), 4);
+    }
+
+    #[test]
+    fn test_skiplist_size_grows_with_every_version() {
+        let memtable = SkipListMemTable::new();
+        assert_eq!(memtable.size(), 0);
+
+        memtable.put(1, Bytes::from("key1"), Bytes::from("value1"));
+        let after_first = memtable.size();
+        assert!(after_first >= (NODE_HEADER_SIZE + 10) as u64);
+
+        // Updates keep the old version in the arena
+        memtable.put(2, Bytes::from("key1"), Bytes::from("value1"));
+        assert!(memtable.size() > after_first);
+    }
+
+    #[test]
+    fn test_skiplist_large_values() {
+        let memtable = SkipListMemTable::new();
+        let large_value = vec![b'v'; 1024 * 1024];
+
+        memtable.put(1, Bytes::from("big"), Bytes::from(large_value.clone()));
+        memtable.put(2, Bytes::from("small"), Bytes::from("s"));
+
+        match memtable.get(&Bytes::from("big")).unwrap().val() {
+            ValueType::Normal(data) => assert_eq!(data.as_ref(), large_value.as_slice()),
+            _ => panic!("Expected Normal value"),
+        }
+        assert!(memtable.size() >= 1024 * 1024);
+    }
+
+    #[test]
+    fn test_skiplist_concurrent_inserts() {
+        let memtable = Arc::new(SkipListMemTable::new());
+        let threads = 8;
+        let per_thread = 2000u64;
+
+        thread::scope(|s| {
+            for t in 0..threads {
+                let memtable = &memtable;
+                s.spawn(move || {
+                    for i in 0..per_thread {
+                        let seq = t * per_thread + i + 1;
+                        // Half the keys are shared between threads
+                        let key = format!("key_{:05}", i % (per_thread / 2) + t * 10);
+                        memtable.put(seq, Bytes::from(key), Bytes::from(seq.to_string()));
+                    }
+                });
+            }
+        });
+
+        let snapshot = memtable.snapshot();
+        assert!(snapshot.windows(2).all(|w| w[0].key() < w[1].key()));
+
+        // Every key must resolve to its highest sequence number
+        for entry in &snapshot {
+            match entry.val() {
+                ValueType::Normal(data) => {
+                    assert_eq!(data.as_ref(), entry.seq().to_string().as_bytes())
+                }
+                _ => panic!("Expected Normal value"),
+            }
+        }
+        let expected = (0..threads)
+            .flat_map(|t| (0..per_thread).map(move |i| i % (per_thread / 2) + t * 10))
+            .collect::<std::collections::BTreeSet<_>>()
+            .len();
+        assert_eq!(snapshot.len(), expected);
+    }
+
+    #[test]
+    fn test_skiplist_concurrent_reads_during_writes() {
+        let memtable = Arc::new(SkipListMemTable::new());
+
+        thread::scope(|s| {
+            let writer = &memtable;
+            s.spawn(move || {
+                for seq in 1..=5000u64 {
+                    writer.put(seq, Bytes::from("hot"), Bytes::from(seq.to_string()));
+                }
+            });
+
+            let reader = &memtable;
+            s.spawn(move || {
+                let mut last_seen = 0;
+                for _ in 0..5000 {
+                    if let Some(entry) = reader.get(&Bytes::from("hot")) {
+                        // A reader never goes back in time
+                        assert!(entry.seq() >= last_seen);
+                        last_seen = entry.seq();
+                    }
+                }
+            });
+        });
+
+        assert_eq!(memtable.get(&Bytes::from("hot")).unwrap().seq(), 5000);
+    }
+}
@@ -0,0 +1,318 @@
+//! Global memory budget shared by every MemTable.
+//!
+//! # Overview
+//!
+//! `memtable_size_mb` bounds each MemTable on its own, but active and immutable
+//! MemTables of every keyspace add up. The `WriteBufferManager` tracks all of
+//! them against one budget (`write_buffer_size_mb`):
+//!
+//! ```text
+//! usage < 90% budget     → nothing to do
+//! usage ≥ 90% budget     → pick_flushes(): flush the largest MemTables early
+//! usage ≥ budget         → stall(): writers wait until a flush frees memory
+//! ```
+//!
+//! The Engine registers each MemTable when it is created and unregisters it
+//! once its flush completes and it is dropped, which is when memory is freed.
+//! It flushes the picked MemTables in the write that crossed the trigger, so
+//! none is left flushing for `stall()` to wait on.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+use tracing::{debug, info, warn};
+
+use super::MemTableRep;
+use boxkv_common::config::StorageConfig;
+
+/// Share of the budget (in percent) above which flushes are forced.
+const FLUSH_TRIGGER_PERCENT: u64 = 90;
+
+/// A MemTable tracked by the manager.
+struct Registered {
+    memtable: Arc<dyn MemTableRep>,
+    /// Already being flushed, so its memory will be released soon.
+    flushing: bool,
+}
+
+struct State {
+    next_id: u64,
+    memtables: HashMap<u64, Registered>,
+}
+
+/// Caps the memory of all MemTables (active and immutable) across keyspaces.
+///
+/// # Thread Safety
+///
+/// Shared behind an `Arc` by every keyspace. Registration and flush picking
+/// take a short lock; sizes are read from the MemTables' atomic counters.
+///
+/// # Examples
+///
+/// ```ignore
+/// let manager = WriteBufferManager::from_config(&config).unwrap();
+///
+/// let memtable: Arc<dyn MemTableRep> = Arc::from(memtable::new_rep(kind, prefix_len, comparator.clone()));
+/// let id = manager.register(memtable.clone());
+///
+/// // On the write path
+/// manager.stall();
+/// memtable.insert(seq, key, value);
+/// for (id, memtable) in manager.pick_flushes() {
+///     // freeze and schedule a flush...
+/// }
+///
+/// // Once the flush is done and the MemTable dropped
+/// manager.unregister(id);
+/// ```
+pub struct WriteBufferManager {
+    buffer_size: u64,
+    state: Mutex<State>,
+    /// Signalled whenever a MemTable is unregistered.
+    memory_freed: Condvar,
+}
+
+impl WriteBufferManager {
+    /// Creates a manager with a budget of `buffer_size` bytes.
+    pub fn new(buffer_size: u64) -> Self {
+        Self {
+            buffer_size,
+            state: Mutex::new(State {
+                next_id: 0,
+                memtables: HashMap::new(),
+            }),
+            memory_freed: Condvar::new(),
+        }
+    }
+
+    /// Builds the manager described by the storage configuration.
+    ///
+    /// Returns `None` when `write_buffer_size_mb` is 0 (no global limit).
+    pub fn from_config(config: &StorageConfig) -> Option<Arc<Self>> {
+        (config.write_buffer_size_mb > 0)
+            .then(|| Arc::new(Self::new(config.write_buffer_size_mb as u64 * 1024 * 1024)))
+    }
+
+    /// Returns the global budget in bytes.
+    pub fn buffer_size(&self) -> u64 {
+        self.buffer_size
+    }
+
+    /// Starts tracking a MemTable and returns its ID.
+    pub fn register(&self, memtable: Arc<dyn MemTableRep>) -> u64 {
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.memtables.insert(
+            id,
+            Registered {
+                memtable,
+                flushing: false,
+            },
+        );
+        id
+    }
+
+    /// Stops tracking a MemTable once it has been flushed, waking stalled writers.
+    pub fn unregister(&self, id: u64) {
+        if self.state.lock().memtables.remove(&id).is_some() {
+            self.memory_freed.notify_all();
+        }
+    }
+
+    /// Marks a MemTable as being flushed for another reason (e.g. it is full),
+    /// so `pick_flushes()` doesn't pick it again.
+    pub fn mark_flushing(&self, id: u64) {
+        if let Some(registered) = self.state.lock().memtables.get_mut(&id) {
+            registered.flushing = true;
+        }
+    }
+
+    /// Returns the memory held by all tracked MemTables, in bytes.
+    pub fn memory_usage(&self) -> u64 {
+        Self::usage(&self.state.lock())
+    }
+
+    fn usage(state: &State) -> u64 {
+        state.memtables.values().map(|r| r.memtable.size()).sum()
+    }
+
+    /// Picks the MemTables to flush early to get back under the flush trigger.
+    ///
+    /// Memory of MemTables already being flushed is considered freed. The
+    /// largest remaining ones are picked first and marked as flushing, so
+    /// calling this from several writers doesn't flush more than needed.
+    pub fn pick_flushes(&self) -> Vec<(u64, Arc<dyn MemTableRep>)> {
+        let trigger = self.buffer_size * FLUSH_TRIGGER_PERCENT / 100;
+        let mut state = self.state.lock();
+
+        let mut candidates: Vec<(u64, u64)> = state
+            .memtables
+            .iter()
+            .filter(|(_, r)| !r.flushing)
+            .map(|(&id, r)| (id, r.memtable.size()))
+            .collect();
+        let mut pending: u64 = candidates.iter().map(|&(_, size)| size).sum();
+        if pending < trigger {
+            return Vec::new();
+        }
+
+        candidates.sort_unstable_by_key(|&(_, size)| Reverse(size));
+
+        let mut picked = Vec::new();
+        for (id, size) in candidates {
+            if pending < trigger {
+                break;
+            }
+            pending -= size;
+
+            let registered = state.memtables.get_mut(&id).unwrap();
+            registered.flushing = true;
+            picked.push((id, registered.memtable.clone()));
+        }
+
+        info!(
+            budget = self.buffer_size,
+            usage = Self::usage(&state),
+            flushes = picked.len(),
+            "Write buffer near its limit, forcing flushes"
+        );
+
+        picked
+    }
+
+    /// Blocks the calling writer while the budget is exceeded.
+    ///
+    /// Returns immediately if memory is available, or if no flush is in
+    /// progress (waiting would never end; the caller should `pick_flushes()`).
+    /// Returns how long the writer was stalled.
+    pub fn stall(&self) -> Duration {
+        let start = Instant::now();
+        let mut state = self.state.lock();
+        let mut logged = false;
+
+        loop {
+            let usage = Self::usage(&state);
+            if usage < self.buffer_size {
+                break;
+            }
+            if !state.memtables.values().any(|r| r.flushing) {
+                debug!(
+                    budget = self.buffer_size,
+                    usage, "Write buffer full but no flush in progress, not stalling"
+                );
+                break;
+            }
+
+            if !logged {
+                warn!(
+                    budget = self.buffer_size,
+                    usage, "Write buffer full, stalling writes until a flush completes"
+                );
+                logged = true;
+            }
+            self.memory_freed.wait(&mut state);
+        }
+
+        start.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memtable::HashPrefixMemTable;
+    use boxkv_common::types::ValueType;
+    use bytes::Bytes;
+    use std::thread;
+
+    /// Creates a MemTable holding roughly `kb` kilobytes of values.
+    fn memtable_with(kb: usize) -> Arc<dyn MemTableRep> {
+        let memtable = Arc::new(HashPrefixMemTable::new(4));
+        for i in 0..kb {
+            memtable.insert(
+                i as u64,
+                Bytes::from(format!("key_{:05}", i)),
+                ValueType::Normal(Bytes::from(vec![b'v'; 1024])),
+            );
+        }
+        memtable
+    }
+
+    #[test]
+    fn test_write_buffer_tracks_usage() {
+        let manager = WriteBufferManager::new(1024 * 1024);
+        let small = memtable_with(10);
+        let large = memtable_with(200);
+
+        let small_id = manager.register(small.clone());
+        let large_id = manager.register(large.clone());
+        assert_eq!(manager.memory_usage(), small.size() + large.size());
+
+        manager.unregister(large_id);
+        assert_eq!(manager.memory_usage(), small.size());
+        manager.unregister(small_id);
+        assert_eq!(manager.memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_write_buffer_picks_largest_memtables() {
+        let small = memtable_with(10);
+        let medium = memtable_with(100);
+        let large = memtable_with(300);
+        // Flush trigger lies between `large + medium + small` and `medium + small`
+        let manager = WriteBufferManager::new(large.size() + medium.size());
+
+        manager.register(small);
+        let medium_id = manager.register(medium);
+        let large_id = manager.register(large);
+
+        let picked: Vec<u64> = manager
+            .pick_flushes()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(picked, vec![large_id]);
+
+        // Already flushing MemTables are not picked again
+        assert!(manager.pick_flushes().is_empty());
+
+        manager.mark_flushing(medium_id);
+        assert!(manager.pick_flushes().is_empty());
+    }
+
+    #[test]
+    fn test_write_buffer_below_trigger_picks_nothing() {
+        let manager = WriteBufferManager::new(64 * 1024 * 1024);
+        manager.register(memtable_with(100));
+
+        assert!(manager.pick_flushes().is_empty());
+        assert!(manager.stall() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_write_buffer_stall_until_flush_completes() {
+        let memtable = memtable_with(100);
+        let manager = WriteBufferManager::new(memtable.size());
+
+        let id = manager.register(memtable);
+        // Over budget, but nothing is flushing: stalling would never end
+        assert!(manager.stall() < Duration::from_secs(1));
+
+        assert_eq!(manager.pick_flushes().len(), 1);
+        thread::scope(|s| {
+            let manager = &manager;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                manager.unregister(id);
+            });
+
+            let stalled = manager.stall();
+            assert!(stalled >= Duration::from_millis(50));
+        });
+        assert_eq!(manager.memory_usage(), 0);
+    }
+}
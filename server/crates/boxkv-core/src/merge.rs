@@ -0,0 +1,425 @@
+//! Merge operators: read-modify-write without a read.
+//!
+//! # Overview
+//!
+//! A MERGE writes an operand (e.g. "+1") instead of a full value. Operands are
+//! stored as-is in the WAL, MemTables and SSTables, and are only combined with
+//! the older versions of the key by the keyspace's `MergeOperator`:
+//!
+//! - **On read**, the versions of a key are fed newest first to a
+//!   `MergeContext` until a full value (or tombstone) is found.
+//! - **During compaction**, operands are collapsed into a single value (or a
+//!   single operand when the base lives in an older level).
+//!
+//! ```text
+//! seq 7: Merge(+2)  ─┐
+//! seq 5: Merge(+3)  ─┼─► full_merge(existing = 10, operands = [+3, +2]) = 15
+//! seq 2: Normal(10) ─┘
+//! ```
+
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use thiserror::Error;
+
+use boxkv_common::types::{Entry, ValueType};
+
+#[derive(Debug, Error)]
+pub enum MergeError {
+    /// An operand or existing value could not be interpreted by the operator.
+    #[error("Merge operator {operator} failed: {reason}")]
+    InvalidOperand { operator: String, reason: String },
+
+    /// Operands were pushed on a base value still stored in a blob file;
+    /// the caller must read it and push it as a full value instead.
+    #[error("Merge operands found on a blob index; the blob value must be read first")]
+    UnresolvedBlobIndex,
+}
+
+/// Combines merge operands with the existing value of a key.
+///
+/// # Contract
+///
+/// - `full_merge` must be deterministic: it runs again on every read until the
+///   operands are compacted.
+/// - `name` identifies the operator; data written with one operator must be
+///   read with the same one.
+pub trait MergeOperator: Send + Sync {
+    /// Stable identifier of the operator.
+    fn name(&self) -> &str;
+
+    /// Applies `operands` (oldest first) to the existing value of `key`.
+    ///
+    /// `existing` is `None` if the key has no value (never written, or deleted).
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Result<Bytes, MergeError>;
+
+    /// Combines consecutive `operands` (oldest first) into a single operand,
+    /// without knowing the existing value.
+    ///
+    /// Used by compaction when the base value lives in an older level. Returns
+    /// `Ok(None)` if the operands can't be combined, which keeps them all.
+    fn partial_merge(&self, _key: &[u8], _operands: &[Bytes]) -> Result<Option<Bytes>, MergeError> {
+        Ok(None)
+    }
+}
+
+/// Adds unsigned 64-bit counters, encoded as 8 big-endian bytes.
+///
+/// A missing value counts as 0 and additions wrap around on overflow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    fn decode(&self, bytes: &[u8]) -> Result<u64, MergeError> {
+        let bytes: [u8; 8] = bytes.try_into().map_err(|_| MergeError::InvalidOperand {
+            operator: self.name().to_string(),
+            reason: format!("expected 8 bytes, got {}", bytes.len()),
+        })?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn sum(&self, initial: u64, operands: &[Bytes]) -> Result<Bytes, MergeError> {
+        let mut sum = initial;
+        for operand in operands {
+            sum = sum.wrapping_add(self.decode(operand)?);
+        }
+        Ok(Bytes::copy_from_slice(&sum.to_be_bytes()))
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "boxkv.U64AddOperator"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Result<Bytes, MergeError> {
+        let initial = existing.map(|v| self.decode(v)).transpose()?.unwrap_or(0);
+        self.sum(initial, operands)
+    }
+
+    fn partial_merge(&self, _key: &[u8], operands: &[Bytes]) -> Result<Option<Bytes>, MergeError> {
+        self.sum(0, operands).map(Some)
+    }
+}
+
+/// Appends operands to the existing value, separated by an optional delimiter.
+#[derive(Debug, Clone, Default)]
+pub struct BytesAppendOperator {
+    delimiter: Bytes,
+}
+
+impl BytesAppendOperator {
+    /// Creates an operator concatenating values without a delimiter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an operator inserting `delimiter` between appended values.
+    pub fn with_delimiter(delimiter: Bytes) -> Self {
+        Self { delimiter }
+    }
+
+    fn join<'a>(&self, parts: impl Iterator<Item = &'a [u8]>) -> Bytes {
+        let mut buf = BytesMut::new();
+        for (i, part) in parts.enumerate() {
+            if i > 0 {
+                buf.extend_from_slice(&self.delimiter);
+            }
+            buf.extend_from_slice(part);
+        }
+        buf.freeze()
+    }
+}
+
+impl MergeOperator for BytesAppendOperator {
+    fn name(&self) -> &str {
+        "boxkv.BytesAppendOperator"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Result<Bytes, MergeError> {
+        Ok(self.join(
+            existing
+                .into_iter()
+                .chain(operands.iter().map(|o| o.as_ref())),
+        ))
+    }
+
+    fn partial_merge(&self, _key: &[u8], operands: &[Bytes]) -> Result<Option<Bytes>, MergeError> {
+        Ok(Some(self.join(operands.iter().map(|o| o.as_ref()))))
+    }
+}
+
+/// Returns the built-in merge operator with the given name, if any.
+pub fn builtin_operator(name: &str) -> Option<Arc<dyn MergeOperator>> {
+    match name {
+        "boxkv.U64AddOperator" => Some(Arc::new(U64AddOperator)),
+        "boxkv.BytesAppendOperator" => Some(Arc::new(BytesAppendOperator::new())),
+        _ => None,
+    }
+}
+
+/// Resolves the value of one key from its versions, fed newest first.
+///
+/// Versions may come from several sources (active and immutable MemTables,
+/// then SSTables from the newest level down), so the context is fed until it
+/// reports a result or every source is exhausted.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut context = MergeContext::new(&*operator);
+/// for entry in memtable.get_versions(&key).into_iter().chain(sstable_versions) {
+///     if let Some(resolved) = context.push(entry)? {
+///         return Ok(Some(resolved));
+///     }
+/// }
+/// context.finish()
+/// ```
+pub struct MergeContext<'a> {
+    operator: &'a dyn MergeOperator,
+    /// Newest operand, whose key and sequence number the result takes.
+    newest: Option<Entry>,
+    /// Operand data, newest first.
+    operands: Vec<Bytes>,
+}
+
+impl<'a> MergeContext<'a> {
+    /// Creates an empty context resolving operands with `operator`.
+    pub fn new(operator: &'a dyn MergeOperator) -> Self {
+        Self {
+            operator,
+            newest: None,
+            operands: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if at least one merge operand was pushed.
+    pub fn has_operands(&self) -> bool {
+        !self.operands.is_empty()
+    }
+
+    /// Feeds the next older version of the key.
+    ///
+    /// Returns the resolved entry once a version that is not a merge operand is
+    /// reached; it is returned unchanged if no operand was pushed before it
+    /// (and may be a tombstone). Merged values keep the TTL of an expiring base.
+    ///
+    /// # Errors
+    /// Returns `MergeError::UnresolvedBlobIndex` if operands were pushed and
+    /// `entry` is a blob index.
+    pub fn push(&mut self, entry: Entry) -> Result<Option<Entry>, MergeError> {
+        let existing = match entry.val() {
+            ValueType::Merge(operand) => {
+                self.operands.push(operand.clone());
+                self.newest.get_or_insert(entry);
+                return Ok(None);
+            }
+            _ if self.operands.is_empty() => return Ok(Some(entry)),
+            ValueType::Normal(data) => Some(data),
+            ValueType::Expiring { data, .. } => Some(data),
+            ValueType::Tombstone | ValueType::RangeTombstone { .. } => None,
+            ValueType::BlobIndex(_) => return Err(MergeError::UnresolvedBlobIndex),
+        };
+
+        let merged = self.merge(existing.map(|d| d.as_ref()))?;
+        let value = match entry.val() {
+            ValueType::Expiring { expire_at, .. } => ValueType::Expiring {
+                data: merged,
+                expire_at: *expire_at,
+            },
+            _ => ValueType::Normal(merged),
+        };
+        Ok(Some(self.resolved(value)))
+    }
+
+    /// Resolves the pushed operands once every older version has been seen.
+    ///
+    /// Returns `None` if nothing was pushed.
+    pub fn finish(self) -> Result<Option<Entry>, MergeError> {
+        if self.operands.is_empty() {
+            return Ok(None);
+        }
+        let merged = self.merge(None)?;
+        Ok(Some(self.resolved(ValueType::Normal(merged))))
+    }
+
+    fn merge(&self, existing: Option<&[u8]>) -> Result<Bytes, MergeError> {
+        let newest = self.newest.as_ref().expect("operands were pushed");
+        let oldest_first: Vec<Bytes> = self.operands.iter().rev().cloned().collect();
+        self.operator
+            .full_merge(newest.key(), existing, &oldest_first)
+    }
+
+    fn resolved(&self, value: ValueType) -> Entry {
+        let newest = self.newest.as_ref().expect("operands were pushed");
+        Entry::new(newest.seq(), newest.key().clone(), value)
+    }
+}
+
+/// Resolves the value of a key from all of its versions, newest first.
+///
+/// Shortcut for a `MergeContext` fed from a single source.
+pub fn resolve(
+    operator: &dyn MergeOperator,
+    versions: impl IntoIterator<Item = Entry>,
+) -> Result<Option<Entry>, MergeError> {
+    let mut context = MergeContext::new(operator);
+    for entry in versions {
+        if let Some(resolved) = context.push(entry)? {
+            return Ok(Some(resolved));
+        }
+    }
+    context.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(value: u64) -> Bytes {
+        Bytes::copy_from_slice(&value.to_be_bytes())
+    }
+
+    fn key() -> Bytes {
+        Bytes::from("counter")
+    }
+
+    #[test]
+    fn test_u64_add_operator() {
+        let operator = U64AddOperator;
+        let merged = operator
+            .full_merge(b"k", Some(&counter(10)), &[counter(3), counter(2)])
+            .unwrap();
+        assert_eq!(merged, counter(15));
+
+        // Missing value counts as 0, overflow wraps
+        let merged = operator
+            .full_merge(b"k", None, &[counter(u64::MAX), counter(2)])
+            .unwrap();
+        assert_eq!(merged, counter(1));
+
+        assert_eq!(
+            operator
+                .partial_merge(b"k", &[counter(1), counter(4)])
+                .unwrap(),
+            Some(counter(5))
+        );
+        assert!(matches!(
+            operator.full_merge(b"k", None, &[Bytes::from("+1")]),
+            Err(MergeError::InvalidOperand { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bytes_append_operator() {
+        let operator = BytesAppendOperator::with_delimiter(Bytes::from(","));
+        let merged = operator
+            .full_merge(b"k", Some(b"a"), &[Bytes::from("b"), Bytes::from("c")])
+            .unwrap();
+        assert_eq!(merged, Bytes::from("a,b,c"));
+
+        let merged = operator
+            .full_merge(b"k", None, &[Bytes::from("b")])
+            .unwrap();
+        assert_eq!(merged, Bytes::from("b"));
+
+        let partial = BytesAppendOperator::new()
+            .partial_merge(b"k", &[Bytes::from("x"), Bytes::from("y")])
+            .unwrap();
+        assert_eq!(partial, Some(Bytes::from("xy")));
+
+        assert!(builtin_operator(operator.name()).is_some());
+        assert!(builtin_operator("unknown").is_none());
+    }
+
+    #[test]
+    fn test_resolve_operands_on_base_value() {
+        let operator = U64AddOperator;
+        let versions = vec![
+            Entry::new_merge(7, key(), counter(2)),
+            Entry::new_merge(5, key(), counter(3)),
+            Entry::new_normal(2, key(), counter(10)),
+            // Shadowed by the base, never read
+            Entry::new_merge(1, key(), counter(100)),
+        ];
+
+        let resolved = resolve(&operator, versions).unwrap().unwrap();
+        assert_eq!(resolved.seq(), 7);
+        assert_eq!(resolved.key(), &key());
+        assert!(matches!(resolved.val(), ValueType::Normal(v) if *v == counter(15)));
+    }
+
+    #[test]
+    fn test_resolve_without_base_value() {
+        let operator = U64AddOperator;
+
+        // Deleted base: operands apply to nothing
+        let resolved = resolve(
+            &operator,
+            vec![
+                Entry::new_merge(3, key(), counter(4)),
+                Entry::new_tombstone(2, key()),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+        assert!(matches!(resolved.val(), ValueType::Normal(v) if *v == counter(4)));
+
+        // No older version at all
+        let resolved = resolve(&operator, vec![Entry::new_merge(3, key(), counter(4))])
+            .unwrap()
+            .unwrap();
+        assert!(matches!(resolved.val(), ValueType::Normal(v) if *v == counter(4)));
+
+        // Plain values and tombstones pass through
+        let tombstone = resolve(&operator, vec![Entry::new_tombstone(2, key())])
+            .unwrap()
+            .unwrap();
+        assert!(tombstone.is_tombstone());
+        assert!(resolve(&operator, Vec::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_merge_context_across_sources_keeps_ttl() {
+        let operator = BytesAppendOperator::new();
+        let mut context = MergeContext::new(&operator);
+
+        // MemTable
+        assert!(
+            context
+                .push(Entry::new_merge(9, key(), Bytes::from("c")))
+                .unwrap()
+                .is_none()
+        );
+        assert!(context.has_operands());
+        // SSTable
+        let resolved = context
+            .push(Entry::new_expiring(4, key(), Bytes::from("ab"), 1000))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(resolved.seq(), 9);
+        match resolved.val() {
+            ValueType::Expiring { data, expire_at } => {
+                assert_eq!(data, &Bytes::from("abc"));
+                assert_eq!(*expire_at, 1000);
+            }
+            other => panic!("Expected Expiring value, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,473 @@
+//! Range tombstones: deleting a whole key range with a single record.
+//!
+//! # Overview
+//!
+//! `delete_range(start, end)` writes one `RangeTombstone` instead of a point
+//! tombstone per key. It deletes every version of every key in `[start, end)`
+//! with a sequence number below its own.
+//!
+//! Range tombstones are kept apart from point entries, in each MemTable and in
+//! a meta block of each SSTable, as a `FragmentedRangeTombstones` list:
+//! overlapping tombstones are split into non-overlapping fragments carrying the
+//! newest covering sequence number, so a lookup is a single binary search.
+//!
+//! ```text
+//! [a ─────── f) seq 5        fragments: [a, c) seq 5
+//!      [c ─────────── k) seq 9            [c, f) seq 9
+//!                                         [f, k) seq 9  (coalesced with [c, f))
+//! ```
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::comparator::Comparator;
+use boxkv_common::types::{Entry, ValueType};
+
+/// Deletion of every key in `[start, end)` older than `seq`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    /// First deleted key (inclusive).
+    pub start: Bytes,
+    /// End of the range (exclusive).
+    pub end: Bytes,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, seq: u64) -> Self {
+        Self { start, end, seq }
+    }
+
+    /// Extracts the range tombstone carried by a WAL entry, if any.
+    pub fn from_entry(entry: &Entry) -> Option<Self> {
+        match entry.val() {
+            ValueType::RangeTombstone { end } => {
+                Some(Self::new(entry.key().clone(), end.clone(), entry.seq()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts the tombstone into its WAL entry.
+    pub fn to_entry(&self) -> Entry {
+        Entry::new_range_tombstone(self.seq, self.start.clone(), self.end.clone())
+    }
+}
+
+/// Non-overlapping, sorted fragments of a set of range tombstones.
+///
+/// Each fragment keeps the newest sequence number of the tombstones covering
+/// it; older ones can't delete anything the newest doesn't.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut tombstones = FragmentedRangeTombstones::new(comparator);
+/// tombstones.add(RangeTombstone::new(Bytes::from("a"), Bytes::from("m"), 10));
+///
+/// assert_eq!(tombstones.covering_seq(b"c"), Some(10));
+/// assert!(tombstones.shadows(b"c", 7));
+/// assert!(!tombstones.shadows(b"c", 12)); // Written after the deletion
+/// ```
+#[derive(Clone)]
+pub struct FragmentedRangeTombstones {
+    fragments: Vec<RangeTombstone>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl FragmentedRangeTombstones {
+    /// Creates an empty list ordered by `comparator`.
+    pub fn new(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            fragments: Vec::new(),
+            comparator,
+        }
+    }
+
+    /// Fragments a set of (possibly overlapping) tombstones.
+    pub fn from_tombstones(
+        tombstones: impl IntoIterator<Item = RangeTombstone>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Self {
+        let tombstones: Vec<RangeTombstone> = tombstones.into_iter().collect();
+        let fragments = Self::fragment(&tombstones, &*comparator);
+        Self {
+            fragments,
+            comparator,
+        }
+    }
+
+    /// Adds a tombstone, splitting the fragments it overlaps.
+    ///
+    /// Only those fragments and their two neighbours are rebuilt: finding them
+    /// is a binary search, and the fragments after them are shifted in place.
+    /// Empty ranges (`start >= end`) are ignored.
+    pub fn add(&mut self, tombstone: RangeTombstone) {
+        let comparator = &*self.comparator;
+        if comparator.compare(&tombstone.start, &tombstone.end).is_ge() {
+            return;
+        }
+        let first = self
+            .fragments
+            .partition_point(|f| comparator.compare(&f.end, &tombstone.start).is_le());
+        let last = self
+            .fragments
+            .partition_point(|f| comparator.compare(&f.start, &tombstone.end).is_lt());
+
+        // The overlapped fragments, cut at the tombstone's bounds, and the gaps
+        // between them
+        let mut pieces = Vec::with_capacity(2 * (last - first) + 1);
+        let mut cursor = tombstone.start.clone();
+        for fragment in &self.fragments[first..last] {
+            if comparator
+                .compare(&fragment.start, &tombstone.start)
+                .is_lt()
+            {
+                pieces.push(RangeTombstone::new(
+                    fragment.start.clone(),
+                    tombstone.start.clone(),
+                    fragment.seq,
+                ));
+            } else if comparator.compare(&cursor, &fragment.start).is_lt() {
+                pieces.push(RangeTombstone::new(
+                    cursor,
+                    fragment.start.clone(),
+                    tombstone.seq,
+                ));
+            }
+            let start = if comparator
+                .compare(&fragment.start, &tombstone.start)
+                .is_lt()
+            {
+                &tombstone.start
+            } else {
+                &fragment.start
+            };
+            let end = if comparator.compare(&tombstone.end, &fragment.end).is_lt() {
+                &tombstone.end
+            } else {
+                &fragment.end
+            };
+            pieces.push(RangeTombstone::new(
+                start.clone(),
+                end.clone(),
+                fragment.seq.max(tombstone.seq),
+            ));
+            if comparator.compare(&tombstone.end, &fragment.end).is_lt() {
+                pieces.push(RangeTombstone::new(
+                    tombstone.end.clone(),
+                    fragment.end.clone(),
+                    fragment.seq,
+                ));
+            }
+            cursor = end.clone();
+        }
+        if comparator.compare(&cursor, &tombstone.end).is_lt() {
+            pieces.push(RangeTombstone::new(cursor, tombstone.end, tombstone.seq));
+        }
+
+        let from = first.saturating_sub(1);
+        let to = (last + 1).min(self.fragments.len());
+        let mut rebuilt = Vec::with_capacity(pieces.len() + 2);
+        for piece in self.fragments[from..first]
+            .iter()
+            .cloned()
+            .chain(pieces)
+            .chain(self.fragments[last..to].iter().cloned())
+        {
+            push_coalesced(&mut rebuilt, piece, comparator);
+        }
+        self.fragments.splice(from..to, rebuilt);
+    }
+
+    fn fragment(tombstones: &[RangeTombstone], comparator: &dyn Comparator) -> Vec<RangeTombstone> {
+        let tombstones: Vec<&RangeTombstone> = tombstones
+            .iter()
+            .filter(|t| comparator.compare(&t.start, &t.end) == Ordering::Less)
+            .collect();
+
+        let mut bounds: Vec<&Bytes> = tombstones.iter().flat_map(|t| [&t.start, &t.end]).collect();
+        bounds.sort_unstable_by(|a, b| comparator.compare(a, b));
+        bounds.dedup_by(|a, b| comparator.compare(a, b) == Ordering::Equal);
+
+        let mut fragments: Vec<RangeTombstone> = Vec::new();
+        for pair in bounds.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            let Some(seq) = tombstones
+                .iter()
+                .filter(|t| {
+                    comparator.compare(&t.start, start).is_le()
+                        && comparator.compare(end, &t.end).is_le()
+                })
+                .map(|t| t.seq)
+                .max()
+            else {
+                continue;
+            };
+
+            push_coalesced(
+                &mut fragments,
+                RangeTombstone::new(start.clone(), end.clone(), seq),
+                comparator,
+            );
+        }
+        fragments
+    }
+
+    /// Returns `true` if there are no range tombstones.
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Returns the fragments, sorted and non-overlapping.
+    ///
+    /// Used to persist the list (e.g. into an SSTable meta block).
+    pub fn fragments(&self) -> &[RangeTombstone] {
+        &self.fragments
+    }
+
+    /// Returns the sequence number of the newest tombstone covering `key`.
+    pub fn covering_seq(&self, key: &[u8]) -> Option<u64> {
+        let index = self
+            .fragments
+            .partition_point(|f| self.comparator.compare(&f.end, key).is_le());
+        self.fragments
+            .get(index)
+            .filter(|f| self.comparator.compare(&f.start, key).is_le())
+            .map(|f| f.seq)
+    }
+
+    /// Returns `true` if the version of `key` written at `seq` is deleted.
+    pub fn shadows(&self, key: &[u8], seq: u64) -> bool {
+        self.covering_seq(key)
+            .is_some_and(|covering| seq < covering)
+    }
+
+    /// Applies the tombstones to the latest version of `key` found next to them.
+    ///
+    /// Returns a point tombstone (at the range tombstone's sequence number) if
+    /// the key is deleted, so that readers stop looking in older sources.
+    pub fn shadow(&self, key: &Bytes, latest: Option<Entry>) -> Option<Entry> {
+        match self.covering_seq(key) {
+            Some(covering) if latest.as_ref().is_none_or(|e| e.seq() < covering) => {
+                Some(Entry::new_tombstone(covering, key.clone()))
+            }
+            _ => latest,
+        }
+    }
+
+    /// Applies the tombstones to versions of `key` listed newest first (see
+    /// `MemTableRep::get_versions`).
+    ///
+    /// Deleted versions are replaced by a point tombstone, which ends the
+    /// merge operands above it.
+    pub fn shadow_versions(&self, key: &Bytes, versions: Vec<Entry>) -> Vec<Entry> {
+        let Some(covering) = self.covering_seq(key) else {
+            return versions;
+        };
+
+        let mut visible: Vec<Entry> = versions
+            .into_iter()
+            .take_while(|e| e.seq() >= covering)
+            .collect();
+        if visible.last().is_none_or(|e| e.is_merge()) {
+            visible.push(Entry::new_tombstone(covering, key.clone()));
+        }
+        visible
+    }
+
+    /// Returns `true` if every key in `[smallest, largest]` is deleted for all
+    /// versions up to `largest_seq`.
+    ///
+    /// Used to drop SSTables without reading them.
+    pub fn covers(&self, smallest: &[u8], largest: &[u8], largest_seq: u64) -> bool {
+        let mut index = self
+            .fragments
+            .partition_point(|f| self.comparator.compare(&f.end, smallest).is_le());
+        let mut cursor = smallest;
+
+        while let Some(fragment) = self.fragments.get(index) {
+            // A gap before this fragment, or a version newer than the deletion
+            if self.comparator.compare(&fragment.start, cursor).is_gt()
+                || fragment.seq <= largest_seq
+            {
+                return false;
+            }
+            if self.comparator.compare(&fragment.end, largest).is_gt() {
+                return true;
+            }
+            cursor = &fragment.end;
+            index += 1;
+        }
+        false
+    }
+}
+
+/// Appends `fragment` to the sorted `fragments`, extending the last one
+/// instead when contiguous with the same sequence number.
+fn push_coalesced(
+    fragments: &mut Vec<RangeTombstone>,
+    fragment: RangeTombstone,
+    comparator: &dyn Comparator,
+) {
+    if let Some(last) = fragments.last_mut()
+        && last.seq == fragment.seq
+        && comparator.compare(&last.end, &fragment.start) == Ordering::Equal
+    {
+        last.end = fragment.end;
+        return;
+    }
+    fragments.push(fragment);
+}
+
+impl std::fmt::Debug for FragmentedRangeTombstones {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FragmentedRangeTombstones")
+            .field("fragments", &self.fragments)
+            .field("comparator", &self.comparator.name())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+
+    fn tombstone(start: &'static str, end: &'static str, seq: u64) -> RangeTombstone {
+        RangeTombstone::new(Bytes::from(start), Bytes::from(end), seq)
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let tombstones = FragmentedRangeTombstones::from_tombstones(
+            vec![
+                tombstone("a", "f", 5),
+                tombstone("c", "k", 9),
+                tombstone("d", "e", 3),
+                tombstone("x", "x", 20), // Empty range
+            ],
+            default_comparator(),
+        );
+
+        assert_eq!(
+            tombstones.fragments(),
+            &[tombstone("a", "c", 5), tombstone("c", "k", 9)]
+        );
+        assert_eq!(tombstones.covering_seq(b"a"), Some(5));
+        assert_eq!(tombstones.covering_seq(b"d"), Some(9));
+        assert_eq!(tombstones.covering_seq(b"k"), None);
+        assert_eq!(tombstones.covering_seq(b"0"), None);
+
+        assert!(tombstones.shadows(b"b", 4));
+        assert!(!tombstones.shadows(b"b", 5));
+    }
+
+    #[test]
+    fn test_add_refragments() {
+        let mut tombstones = FragmentedRangeTombstones::new(default_comparator());
+        assert!(tombstones.is_empty());
+
+        tombstones.add(tombstone("c", "k", 9));
+        tombstones.add(tombstone("a", "f", 12));
+        assert_eq!(
+            tombstones.fragments(),
+            &[tombstone("a", "f", 12), tombstone("f", "k", 9)]
+        );
+    }
+
+    #[test]
+    fn test_add_matches_fragmenting_at_once() {
+        // Small seeded LCG: ranges over few keys overlap in every way
+        let mut state: u64 = 7;
+        let mut next = |bound: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        let key = |k: u64| Bytes::from(vec![b'a' + k as u8]);
+
+        for _ in 0..200 {
+            let tombstones: Vec<RangeTombstone> = (0..next(8))
+                .map(|_| RangeTombstone::new(key(next(10)), key(next(10)), next(5) + 1))
+                .collect();
+            let mut added = FragmentedRangeTombstones::new(default_comparator());
+            for tombstone in tombstones.clone() {
+                added.add(tombstone);
+            }
+            let at_once = FragmentedRangeTombstones::from_tombstones(
+                tombstones.clone(),
+                default_comparator(),
+            );
+            assert_eq!(added.fragments(), at_once.fragments(), "{:?}", tombstones);
+        }
+    }
+
+    #[test]
+    fn test_shadow_point_lookups() {
+        let mut tombstones = FragmentedRangeTombstones::new(default_comparator());
+        tombstones.add(tombstone("a", "m", 10));
+        let key = Bytes::from("c");
+
+        let old = Entry::new_normal(4, key.clone(), Bytes::from("v"));
+        let shadowed = tombstones.shadow(&key, Some(old)).unwrap();
+        assert!(shadowed.is_tombstone());
+        assert_eq!(shadowed.seq(), 10);
+
+        // Nothing in this source, but older sources must not be read either
+        assert!(tombstones.shadow(&key, None).unwrap().is_tombstone());
+
+        let new = Entry::new_normal(11, key.clone(), Bytes::from("v"));
+        assert_eq!(tombstones.shadow(&key, Some(new)).unwrap().seq(), 11);
+        assert!(tombstones.shadow(&Bytes::from("z"), None).is_none());
+
+        // Merge operands above the deletion apply to nothing
+        let versions = vec![
+            Entry::new_merge(12, key.clone(), Bytes::from("x")),
+            Entry::new_normal(4, key.clone(), Bytes::from("v")),
+        ];
+        let seqs: Vec<u64> = tombstones
+            .shadow_versions(&key, versions)
+            .iter()
+            .map(|e| e.seq())
+            .collect();
+        assert_eq!(seqs, vec![12, 10]);
+    }
+
+    #[test]
+    fn test_covers_file_range() {
+        let tombstones = FragmentedRangeTombstones::from_tombstones(
+            vec![tombstone("a", "f", 10), tombstone("f", "k", 20)],
+            default_comparator(),
+        );
+
+        assert!(tombstones.covers(b"b", b"j", 9));
+        // `k` is excluded from the range
+        assert!(!tombstones.covers(b"b", b"k", 9));
+        // A version in the file is newer than the first fragment
+        assert!(!tombstones.covers(b"b", b"j", 10));
+        assert!(tombstones.covers(b"g", b"j", 15));
+
+        let gap = FragmentedRangeTombstones::from_tombstones(
+            vec![tombstone("a", "c", 10), tombstone("d", "k", 10)],
+            default_comparator(),
+        );
+        assert!(!gap.covers(b"b", b"e", 5));
+    }
+
+    #[test]
+    fn test_fragments_with_custom_comparator() {
+        // Ranges run from the larger key down under the reverse ordering
+        let tombstones = FragmentedRangeTombstones::from_tombstones(
+            vec![tombstone("m", "a", 7)],
+            Arc::new(ReverseBytewiseComparator),
+        );
+
+        assert_eq!(tombstones.covering_seq(b"c"), Some(7));
+        assert_eq!(tombstones.covering_seq(b"a"), None);
+        assert_eq!(tombstones.covering_seq(b"z"), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! Sorted String Table (SSTable) on-disk format.
+//!
+//! # File Layout
+//!
+//! ```text
+//! +--------------+-----+--------------+-------------+------------------+-------------+--------+
+//! | Data Block 1 | ... | Data Block N | Meta Blocks | Meta Index Block | Index Block | Footer |
+//! +--------------+-----+--------------+-------------+------------------+-------------+--------+
+//! ```
+//!
+//! - **Data Block**: entries sorted by `(key ASC, seq DESC)`, every value type
+//!   (including merge operands) tagged as in the WAL (see `BlockBuilder`).
+//! - **Meta Index Block**: locates named meta blocks, such as the table's range
+//!   tombstones (`RANGE_DEL_BLOCK_NAME`, see `RangeDelBlock`). Readers load
+//!   them into a `FragmentedRangeTombstones` and apply them to point lookups.
+//! - **Index Block**: one entry per data block, keyed by the block's last key and
+//!   searched with the table's `Comparator`.
+//! - **Footer**: fixed-size trailer locating the index blocks (see `Footer`).
+//!
+//! # Writing and Reading
+//!
+//! `SstFileWriter` builds a table from keys added in comparator order, e.g. by
+//! a bulk load outside the engine; it also records the comparator's name in a
+//! meta block (`COMPARATOR_BLOCK_NAME`). `Table` reads a table back, loading its
+//! index and range tombstones on open and its data blocks on demand.
+
+mod block;
+mod format;
+mod index;
+mod meta;
+mod reader;
+mod writer;
+
+pub use block::{BlockBuilder, BlockIter};
+pub use format::{BlockHandle, Footer, varint};
+pub use index::IndexBlock;
+pub use meta::{COMPARATOR_BLOCK_NAME, MetaIndexBlock, RANGE_DEL_BLOCK_NAME, RangeDelBlock};
+pub use reader::{Table, TableIter};
+pub use writer::{SstFileInfo, SstFileWriter};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+/// Size of the encoded `Footer`, always the last bytes of an SSTable file.
+pub const FOOTER_SIZE: usize = 48;
+
+/// Size of the magic number at the end of the footer.
+pub const MAGIC_SIZE: usize = 8;
+
+/// Magic number identifying BoxKV SSTable files ("boxkvsst").
+pub const MAGIC: u64 = 0x626f_786b_7673_7374;
+
+/// Target size of a data block; a block is cut once it reaches it.
+pub const BLOCK_SIZE: usize = 4096;
+
+#[derive(Debug, Error)]
+pub enum SSTableError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Malformed encoding (truncated or invalid varints, lengths, ...).
+    #[error("Decode error: {0}")]
+    Decode(String),
+
+    /// Structurally valid data with inconsistent content.
+    #[error("Corrupted SSTable: {0}")]
+    Corrupted(String),
+
+    /// The table was written with another comparator than the reader's.
+    #[error("SSTable written with comparator {found:?}, expected {expected:?}")]
+    ComparatorMismatch { expected: String, found: String },
+
+    /// Misuse of `SstFileWriter` (keys out of order, empty table, ...).
+    #[error("Invalid SSTable input: {0}")]
+    InvalidInput(String),
+}
+
+pub type Result<T> = std::result::Result<T, SSTableError>;
+
+/// Summary of an SSTable file, as tracked by the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub file_id: u64,
+    pub level: usize,
+    pub file_size: u64,
+    /// Smallest and largest point keys, in comparator order.
+    pub smallest_key: Bytes,
+    pub largest_key: Bytes,
+    /// Sequence number range of every record, range tombstones included.
+    pub smallest_seq: u64,
+    pub largest_seq: u64,
+}
@@ -0,0 +1,241 @@
+use bytes::Bytes;
+
+use crate::sstable::{Result, SSTableError, varint};
+use boxkv_common::types::{
+    BLOB_INDEX_VALUE_TYPE, BlobIndex, EXPIRING_VALUE_TYPE, Entry, MERGE_VALUE_TYPE,
+    NORMAL_VALUE_TYPE, RANGE_TOMBSTONE_VALUE_TYPE, TOMBSTONE_VALUE_TYPE, ValueType,
+};
+
+/// Size of the expiration timestamp in an Expiring value section.
+const EXPIRE_AT_SIZE: usize = 8;
+
+/// Builds a Data Block from entries added in `(key ASC, seq DESC)` order.
+///
+/// # Encoding Format
+/// ```text
+/// +--------------------+-----+--------------------+
+/// | Entry 1            | ... | Entry N            |
+/// +--------------------+-----+--------------------+
+///
+/// Entry: KeyLen (varint) | Key | Seq (varint) | ValueTag (1B) | ValueLen (varint) | Value Section
+/// ```
+///
+/// The Value Section uses the same layout as the WAL for every `ValueTag`
+/// (Expiring values start with an 8-byte big-endian `expire_at`). Range
+/// tombstones normally live in their own meta block (see `RangeDelBlock`).
+///
+/// # Examples
+/// ```ignore
+/// let mut builder = BlockBuilder::new();
+/// builder.add(&Entry::new_normal(1, Bytes::from("a"), Bytes::from("v")));
+/// builder.add(&Entry::new_merge(2, Bytes::from("b"), Bytes::from("+1")));
+///
+/// let entries: Vec<Entry> = BlockIter::new(Bytes::from(builder.finish()))
+///     .collect::<Result<_>>()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct BlockBuilder {
+    buf: Vec<u8>,
+    count: usize,
+}
+
+impl BlockBuilder {
+    /// Creates an empty block builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an entry to the block.
+    pub fn add(&mut self, entry: &Entry) {
+        varint::encode(entry.key().len() as u64, &mut self.buf);
+        self.buf.extend_from_slice(entry.key());
+        varint::encode(entry.seq(), &mut self.buf);
+        self.buf.push(entry.val().type_tag());
+        varint::encode(entry.val().serialized_len() as u64, &mut self.buf);
+
+        match entry.val() {
+            ValueType::Normal(data)
+            | ValueType::Merge(data)
+            | ValueType::RangeTombstone { end: data } => self.buf.extend_from_slice(data),
+            ValueType::Tombstone => {}
+            ValueType::Expiring { data, expire_at } => {
+                self.buf.extend_from_slice(&expire_at.to_be_bytes());
+                self.buf.extend_from_slice(data);
+            }
+            ValueType::BlobIndex(index) => self.buf.extend_from_slice(&index.encode()),
+        }
+        self.count += 1;
+    }
+
+    /// Returns the number of entries added so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns `true` if no entry was added.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the current size of the encoded block in bytes.
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the encoded block.
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Iterator decoding the entries of a Data Block (see `BlockBuilder`).
+///
+/// Stops after the first decoding error.
+pub struct BlockIter {
+    data: Bytes,
+    offset: usize,
+}
+
+impl BlockIter {
+    /// Creates an iterator over an encoded block.
+    pub fn new(data: Bytes) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let (value, len) = varint::decode(&self.data[self.offset..])?;
+        self.offset += len;
+        Ok(value)
+    }
+
+    fn read_bytes(&mut self, len: u64) -> Result<Bytes> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| {
+                SSTableError::Decode(format!(
+                    "Truncated entry: need {} bytes at offset {}, block has {}",
+                    len,
+                    self.offset,
+                    self.data.len()
+                ))
+            })?;
+        let bytes = self.data.slice(self.offset..end);
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_entry(&mut self) -> Result<Entry> {
+        let key_len = self.read_varint()?;
+        let key = self.read_bytes(key_len)?;
+        let seq = self.read_varint()?;
+        let tag = *self.read_bytes(1)?.first().unwrap();
+        let val_len = self.read_varint()?;
+        let val = self.read_bytes(val_len)?;
+
+        let value = match tag {
+            NORMAL_VALUE_TYPE => ValueType::Normal(val),
+            TOMBSTONE_VALUE_TYPE => ValueType::Tombstone,
+            EXPIRING_VALUE_TYPE => {
+                if val.len() < EXPIRE_AT_SIZE {
+                    return Err(SSTableError::Corrupted(format!(
+                        "Expiring value of {} bytes is shorter than its timestamp",
+                        val.len()
+                    )));
+                }
+                ValueType::Expiring {
+                    expire_at: u64::from_be_bytes(val[..EXPIRE_AT_SIZE].try_into().unwrap()),
+                    data: val.slice(EXPIRE_AT_SIZE..),
+                }
+            }
+            MERGE_VALUE_TYPE => ValueType::Merge(val),
+            RANGE_TOMBSTONE_VALUE_TYPE => ValueType::RangeTombstone { end: val },
+            BLOB_INDEX_VALUE_TYPE => {
+                ValueType::BlobIndex(BlobIndex::decode(&val).ok_or_else(|| {
+                    SSTableError::Corrupted(format!("Blob index of {} bytes", val.len()))
+                })?)
+            }
+            tag => {
+                return Err(SSTableError::Corrupted(format!(
+                    "Invalid value tag {}",
+                    tag
+                )));
+            }
+        };
+        Ok(Entry::new(seq, key, value))
+    }
+}
+
+impl Iterator for BlockIter {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        let entry = self.read_entry();
+        if entry.is_err() {
+            self.offset = self.data.len();
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(block: Vec<u8>) -> Result<Vec<Entry>> {
+        BlockIter::new(Bytes::from(block)).collect()
+    }
+
+    #[test]
+    fn test_block_roundtrip_all_value_types() {
+        let entries = vec![
+            Entry::new_normal(1, Bytes::from("a"), Bytes::from("value")),
+            Entry::new_tombstone(300, Bytes::from("b")),
+            Entry::new_expiring(7, Bytes::from("c"), Bytes::from("ttl"), 1_700_000_000),
+            Entry::new_merge(u64::MAX, Bytes::from("d"), Bytes::from("+1")),
+            Entry::new_merge(5, Bytes::from("d"), Bytes::new()),
+        ];
+
+        let mut builder = BlockBuilder::new();
+        for entry in &entries {
+            builder.add(entry);
+        }
+        assert_eq!(builder.len(), 5);
+
+        let decoded = decode(builder.finish()).unwrap();
+        assert_eq!(decoded, entries);
+        for (decoded, expected) in decoded.iter().zip(&entries) {
+            assert_eq!(decoded.val(), expected.val());
+        }
+    }
+
+    #[test]
+    fn test_block_empty() {
+        let builder = BlockBuilder::new();
+        assert!(builder.is_empty());
+        assert!(decode(builder.finish()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_block_truncated_or_corrupted() {
+        let mut builder = BlockBuilder::new();
+        builder.add(&Entry::new_normal(
+            1,
+            Bytes::from("key"),
+            Bytes::from("value"),
+        ));
+        let block = builder.finish();
+
+        let truncated = block[..block.len() - 1].to_vec();
+        assert!(matches!(decode(truncated), Err(SSTableError::Decode(_))));
+
+        // Value tag follows KeyLen (1) + Key (3) + Seq (1)
+        let mut corrupted = block.clone();
+        corrupted[5] = 42;
+        assert!(matches!(decode(corrupted), Err(SSTableError::Corrupted(_))));
+    }
+}
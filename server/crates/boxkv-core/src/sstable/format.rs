@@ -225,7 +225,7 @@ impl Footer {
     ///
     /// # Arguments
     /// * `data` - A fixed-size array of exactly `FOOTER_SIZE` (48) bytes containing
-    ///            the encoded footer
+    ///   the encoded footer
     ///
     /// # Returns
     /// A decoded `Footer` structure containing the block handles and magic number.
@@ -425,7 +425,7 @@ pub mod varint {
     /// ```
     pub fn encoded_size(value: u64) -> usize {
         let bit_len = 64 - value.leading_zeros() as usize;
-        if bit_len == 0 { 1 } else { (bit_len + 6) / 7 }
+        if bit_len == 0 { 1 } else { bit_len.div_ceil(7) }
     }
 }
 
@@ -729,8 +729,8 @@ mod tests {
         let padding_end = FOOTER_SIZE - MAGIC_SIZE;
 
         // Verify padding is all zeros
-        for i in padding_start..padding_end {
-            assert_eq!(buf[i], 0, "Padding byte at index {} should be zero", i);
+        for (i, byte) in buf.iter().enumerate().take(padding_end).skip(padding_start) {
+            assert_eq!(*byte, 0, "Padding byte at index {} should be zero", i);
         }
     }
 
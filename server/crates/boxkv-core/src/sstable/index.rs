@@ -0,0 +1,202 @@
+use bytes::Bytes;
+
+use crate::comparator::Comparator;
+use crate::sstable::{BlockHandle, Result, SSTableError, varint};
+
+/// Index Block mapping the last key of each data block to its location.
+///
+/// Entries are sorted by the table's comparator, which is also used to search
+/// them, so lookups find the right block whatever the key ordering.
+///
+/// # Encoding Format
+/// ```text
+/// +--------------------+-----+--------------------+
+/// | Entry 1            | ... | Entry N            |
+/// +--------------------+-----+--------------------+
+///
+/// Entry: KeyLen (varint) | Key | BlockHandle (varint offset, varint size)
+/// ```
+///
+/// # Examples
+/// ```ignore
+/// let mut index = IndexBlock::new();
+/// index.add(Bytes::from("m"), BlockHandle::new(0, 4096));
+/// index.add(Bytes::from("z"), BlockHandle::new(4096, 4096));
+///
+/// // "c" <= "m", so it can only be in the first block
+/// assert_eq!(index.find(b"c", &BytewiseComparator), Some(BlockHandle::new(0, 4096)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexBlock {
+    entries: Vec<(Bytes, BlockHandle)>,
+}
+
+impl IndexBlock {
+    /// Creates an empty index block.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the entry of the next data block.
+    ///
+    /// Blocks must be added in comparator order.
+    pub fn add(&mut self, last_key: Bytes, handle: BlockHandle) {
+        self.entries.push((last_key, handle));
+    }
+
+    /// Returns the number of indexed data blocks.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no data block is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the data block that may contain `key`: the first one whose last
+    /// key is not before `key`, or `None` if `key` is past every block.
+    pub fn find(&self, key: &[u8], comparator: &dyn Comparator) -> Option<BlockHandle> {
+        self.blocks_from(key, comparator)
+            .next()
+            .map(|&(_, handle)| handle)
+    }
+
+    /// Returns the entries of the blocks that may contain `key` or later keys:
+    /// `find()`'s block first, then the following ones.
+    ///
+    /// The versions of a key may span several consecutive blocks.
+    pub fn blocks_from(
+        &self,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> impl Iterator<Item = &(Bytes, BlockHandle)> {
+        self.entries[self.position(key, comparator)..].iter()
+    }
+
+    /// Returns the position of `find()`'s block, `len()` if there is none.
+    pub fn position(&self, key: &[u8], comparator: &dyn Comparator) -> usize {
+        self.entries
+            .partition_point(|(last_key, _)| comparator.compare(last_key, key).is_lt())
+    }
+
+    /// Returns the last key of every block with its location, in order.
+    pub fn entries(&self) -> &[(Bytes, BlockHandle)] {
+        &self.entries
+    }
+
+    /// Encodes the index block.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (key, handle) in &self.entries {
+            varint::encode(key.len() as u64, &mut buf);
+            buf.extend_from_slice(key);
+            buf.extend_from_slice(&handle.encode());
+        }
+        buf
+    }
+
+    /// Decodes an index block.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::Decode` if an entry is truncated or malformed.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let (key_len, read) = varint::decode(&data[pos..])?;
+            pos += read;
+
+            let key_end = pos
+                .checked_add(key_len as usize)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(|| {
+                    SSTableError::Decode(format!(
+                        "index entry key truncated: need {} bytes at offset {}, have {}",
+                        key_len,
+                        pos,
+                        data.len() - pos
+                    ))
+                })?;
+            let key = Bytes::copy_from_slice(&data[pos..key_end]);
+            pos = key_end;
+
+            let (handle, read) = BlockHandle::decode(&data[pos..])?;
+            pos += read;
+
+            entries.push((key, handle));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{BytewiseComparator, ReverseBytewiseComparator};
+
+    #[test]
+    fn test_index_block_roundtrip() {
+        let mut index = IndexBlock::new();
+        index.add(Bytes::from("apple"), BlockHandle::new(0, 4096));
+        index.add(Bytes::from(""), BlockHandle::new(4096, 100));
+        index.add(
+            Bytes::from(vec![b'k'; 300]),
+            BlockHandle::new(4196, 1 << 20),
+        );
+
+        let decoded = IndexBlock::decode(&index.encode()).unwrap();
+        assert_eq!(decoded, index);
+        assert_eq!(decoded.len(), 3);
+
+        assert!(IndexBlock::decode(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_block_decode_truncated() {
+        let mut index = IndexBlock::new();
+        index.add(Bytes::from("apple"), BlockHandle::new(0, 4096));
+        let encoded = index.encode();
+
+        for len in 1..encoded.len() {
+            assert!(
+                matches!(
+                    IndexBlock::decode(&encoded[..len]),
+                    Err(SSTableError::Decode(_))
+                ),
+                "Truncation at {} bytes not detected",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_block_find_bytewise() {
+        let mut index = IndexBlock::new();
+        index.add(Bytes::from("f"), BlockHandle::new(0, 10));
+        index.add(Bytes::from("m"), BlockHandle::new(10, 10));
+        index.add(Bytes::from("t"), BlockHandle::new(20, 10));
+
+        let cmp = BytewiseComparator;
+        assert_eq!(index.find(b"a", &cmp), Some(BlockHandle::new(0, 10)));
+        assert_eq!(index.find(b"f", &cmp), Some(BlockHandle::new(0, 10)));
+        assert_eq!(index.find(b"g", &cmp), Some(BlockHandle::new(10, 10)));
+        assert_eq!(index.find(b"t", &cmp), Some(BlockHandle::new(20, 10)));
+        assert_eq!(index.find(b"u", &cmp), None);
+    }
+
+    #[test]
+    fn test_index_block_find_custom_comparator() {
+        // Blocks written in reverse order: last keys descend
+        let mut index = IndexBlock::new();
+        index.add(Bytes::from("t"), BlockHandle::new(0, 10));
+        index.add(Bytes::from("f"), BlockHandle::new(10, 10));
+
+        let cmp = ReverseBytewiseComparator;
+        assert_eq!(index.find(b"z", &cmp), Some(BlockHandle::new(0, 10)));
+        assert_eq!(index.find(b"m", &cmp), Some(BlockHandle::new(10, 10)));
+        assert_eq!(index.find(b"a", &cmp), None);
+    }
+}
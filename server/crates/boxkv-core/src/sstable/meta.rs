@@ -0,0 +1,193 @@
+use bytes::Bytes;
+
+use crate::range_tombstone::RangeTombstone;
+use crate::sstable::{BlockHandle, Result, SSTableError, varint};
+
+/// Name of the meta block holding the table's range tombstones.
+pub const RANGE_DEL_BLOCK_NAME: &str = "boxkv.range_del";
+
+/// Name of the meta block holding the name of the table's comparator.
+pub const COMPARATOR_BLOCK_NAME: &str = "boxkv.comparator";
+
+/// Reads a varint-length-prefixed byte string at `*pos`.
+fn read_bytes(data: &[u8], pos: &mut usize, what: &str) -> Result<Bytes> {
+    let (len, read) = varint::decode(&data[*pos..])?;
+    *pos += read;
+
+    let end = pos
+        .checked_add(len as usize)
+        .filter(|&end| end <= data.len())
+        .ok_or_else(|| {
+            SSTableError::Decode(format!(
+                "{} truncated: need {} bytes at offset {}, have {}",
+                what,
+                len,
+                pos,
+                data.len() - *pos
+            ))
+        })?;
+    let bytes = Bytes::copy_from_slice(&data[*pos..end]);
+    *pos = end;
+    Ok(bytes)
+}
+
+fn write_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    varint::encode(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Meta Index Block mapping meta block names to their location.
+///
+/// # Encoding Format
+/// ```text
+/// Entry: NameLen (varint) | Name | BlockHandle (varint offset, varint size)
+/// ```
+///
+/// # Examples
+/// ```ignore
+/// let mut meta_index = MetaIndexBlock::new();
+/// meta_index.add(RANGE_DEL_BLOCK_NAME, handle);
+///
+/// let footer = Footer::new(meta_index_handle, index_handle);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetaIndexBlock {
+    entries: Vec<(String, BlockHandle)>,
+}
+
+impl MetaIndexBlock {
+    /// Creates an empty meta index block.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the location of a meta block.
+    pub fn add(&mut self, name: &str, handle: BlockHandle) {
+        self.entries.push((name.to_string(), handle));
+    }
+
+    /// Returns the location of the meta block called `name`, if present.
+    pub fn get(&self, name: &str) -> Option<BlockHandle> {
+        self.entries
+            .iter()
+            .find(|(entry_name, _)| entry_name == name)
+            .map(|&(_, handle)| handle)
+    }
+
+    /// Encodes the meta index block.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for (name, handle) in &self.entries {
+            write_bytes(name.as_bytes(), &mut buf);
+            buf.extend_from_slice(&handle.encode());
+        }
+        buf
+    }
+
+    /// Decodes a meta index block.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::Decode` if an entry is truncated or a name is not UTF-8.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let name = read_bytes(data, &mut pos, "meta index name")?;
+            let name = String::from_utf8(name.to_vec())
+                .map_err(|e| SSTableError::Decode(format!("meta index name: {}", e)))?;
+
+            let (handle, read) = BlockHandle::decode(&data[pos..])?;
+            pos += read;
+
+            entries.push((name, handle));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// Meta block storing the range tombstones of an SSTable.
+///
+/// Holds the fragments of the table's `FragmentedRangeTombstones`, sorted and
+/// non-overlapping.
+///
+/// # Encoding Format
+/// ```text
+/// Entry: StartLen (varint) | Start | EndLen (varint) | End | Seq (varint)
+/// ```
+pub struct RangeDelBlock;
+
+impl RangeDelBlock {
+    /// Encodes range tombstones.
+    pub fn encode(tombstones: &[RangeTombstone]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for tombstone in tombstones {
+            write_bytes(&tombstone.start, &mut buf);
+            write_bytes(&tombstone.end, &mut buf);
+            varint::encode(tombstone.seq, &mut buf);
+        }
+        buf
+    }
+
+    /// Decodes range tombstones.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::Decode` if an entry is truncated or malformed.
+    pub fn decode(data: &[u8]) -> Result<Vec<RangeTombstone>> {
+        let mut tombstones = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let start = read_bytes(data, &mut pos, "range tombstone start")?;
+            let end = read_bytes(data, &mut pos, "range tombstone end")?;
+            let (seq, read) = varint::decode(&data[pos..])?;
+            pos += read;
+
+            tombstones.push(RangeTombstone::new(start, end, seq));
+        }
+
+        Ok(tombstones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_index_block_roundtrip() {
+        let mut meta_index = MetaIndexBlock::new();
+        meta_index.add(RANGE_DEL_BLOCK_NAME, BlockHandle::new(4096, 120));
+        meta_index.add("boxkv.filter", BlockHandle::new(4216, 64));
+
+        let decoded = MetaIndexBlock::decode(&meta_index.encode()).unwrap();
+        assert_eq!(decoded, meta_index);
+        assert_eq!(
+            decoded.get(RANGE_DEL_BLOCK_NAME),
+            Some(BlockHandle::new(4096, 120))
+        );
+        assert_eq!(decoded.get("missing"), None);
+    }
+
+    #[test]
+    fn test_range_del_block_roundtrip() {
+        let tombstones = vec![
+            RangeTombstone::new(Bytes::from("a"), Bytes::from("c"), 5),
+            RangeTombstone::new(Bytes::from("c"), Bytes::from(vec![b'k'; 200]), u64::MAX),
+        ];
+
+        let encoded = RangeDelBlock::encode(&tombstones);
+        assert_eq!(RangeDelBlock::decode(&encoded).unwrap(), tombstones);
+
+        // Cuts inside the last entry (a cut on an entry boundary is a valid block)
+        let first_len = RangeDelBlock::encode(&tombstones[..1]).len();
+        for len in first_len + 1..encoded.len() {
+            assert!(
+                RangeDelBlock::decode(&encoded[..len]).is_err(),
+                "Truncation at {} bytes not detected",
+                len
+            );
+        }
+    }
+}
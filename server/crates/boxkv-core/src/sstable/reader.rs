@@ -0,0 +1,328 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::comparator::Comparator;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::sstable::{
+    BlockHandle, BlockIter, COMPARATOR_BLOCK_NAME, FOOTER_SIZE, Footer, IndexBlock, MetaIndexBlock,
+    RANGE_DEL_BLOCK_NAME, RangeDelBlock, Result, SSTableError,
+};
+use boxkv_common::env::{FileSystem, RandomAccessFile};
+use boxkv_common::types::Entry;
+
+/// Read access to an SSTable file.
+///
+/// The index and range tombstones are loaded on open; data blocks are read
+/// from the file on each lookup.
+///
+/// A table ingested into an engine carries a global sequence number that
+/// replaces the sequence number of all of its records (see
+/// `with_global_seq()`).
+///
+/// # Examples
+/// ```ignore
+/// let table = Table::open(&*fs, path, default_comparator())?.with_global_seq(42);
+/// for entry in table.get_versions(&key)? {
+///     // ...
+/// }
+/// ```
+pub struct Table {
+    path: PathBuf,
+    file: Box<dyn RandomAccessFile>,
+    file_size: u64,
+    comparator: Arc<dyn Comparator>,
+    index: IndexBlock,
+    range_dels: FragmentedRangeTombstones,
+    global_seq: Option<u64>,
+}
+
+impl Table {
+    /// Opens the table at `path` on `fs`, ordered by `comparator`.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::ComparatorMismatch` if the table records another
+    /// comparator, and `SSTableError::Corrupted` or `SSTableError::Decode` if
+    /// its footer or index blocks are invalid.
+    pub fn open(
+        fs: &dyn FileSystem,
+        path: impl Into<PathBuf>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let path = path.into();
+        let file = fs.open_random_access(&path)?;
+        let file_size = file.size()?;
+        if file_size < FOOTER_SIZE as u64 {
+            return Err(SSTableError::Corrupted(format!(
+                "{:?} is {} bytes, shorter than a footer",
+                path, file_size
+            )));
+        }
+
+        let mut buf = [0u8; FOOTER_SIZE];
+        file.read_exact_at(&mut buf, file_size - FOOTER_SIZE as u64)?;
+        let footer = Footer::decode(&buf)?;
+        if !footer.validate_magic() {
+            return Err(SSTableError::Corrupted(format!(
+                "{:?} has an invalid magic number",
+                path
+            )));
+        }
+
+        let meta_index =
+            MetaIndexBlock::decode(&read_block(&*file, footer.meta_index_handle, file_size)?)?;
+        if let Some(handle) = meta_index.get(COMPARATOR_BLOCK_NAME) {
+            let name = read_block(&*file, handle, file_size)?;
+            if name != comparator.name().as_bytes() {
+                return Err(SSTableError::ComparatorMismatch {
+                    expected: comparator.name().to_string(),
+                    found: String::from_utf8_lossy(&name).into_owned(),
+                });
+            }
+        }
+        let tombstones = match meta_index.get(RANGE_DEL_BLOCK_NAME) {
+            Some(handle) => RangeDelBlock::decode(&read_block(&*file, handle, file_size)?)?,
+            None => Vec::new(),
+        };
+        let index = IndexBlock::decode(&read_block(&*file, footer.index_handle, file_size)?)?;
+
+        Ok(Self {
+            path,
+            file,
+            file_size,
+            range_dels: FragmentedRangeTombstones::from_tombstones(tombstones, comparator.clone()),
+            comparator,
+            index,
+            global_seq: None,
+        })
+    }
+
+    /// Reports every record of the table at sequence number `seq`.
+    pub fn with_global_seq(mut self, seq: u64) -> Self {
+        let tombstones = self
+            .range_dels
+            .fragments()
+            .iter()
+            .map(|t| RangeTombstone::new(t.start.clone(), t.end.clone(), seq));
+        self.range_dels =
+            FragmentedRangeTombstones::from_tombstones(tombstones, self.comparator.clone());
+        self.global_seq = Some(seq);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn global_seq(&self) -> Option<u64> {
+        self.global_seq
+    }
+
+    /// Returns the range tombstones of the table.
+    pub fn range_tombstones(&self) -> &FragmentedRangeTombstones {
+        &self.range_dels
+    }
+
+    /// Returns the largest point key, `None` if the table has no point entry.
+    pub fn largest_key(&self) -> Option<&Bytes> {
+        self.index.entries().last().map(|(key, _)| key)
+    }
+
+    /// Returns the smallest point key, `None` if the table has no point entry.
+    pub fn smallest_key(&self) -> Result<Option<Bytes>> {
+        self.iter()
+            .next()
+            .transpose()
+            .map(|e| e.map(|e| e.key().clone()))
+    }
+
+    fn read_block(&self, handle: BlockHandle) -> Result<Bytes> {
+        read_block(&*self.file, handle, self.file_size).map(Bytes::from)
+    }
+
+    fn with_seq(&self, entry: Entry) -> Entry {
+        match self.global_seq {
+            Some(seq) => Entry::new(seq, entry.key().clone(), entry.val().clone()),
+            None => entry,
+        }
+    }
+
+    /// Returns the latest version of `key` in the table, followed by the older
+    /// versions its merge operands apply to, newest first.
+    ///
+    /// The table's range tombstones are applied as in
+    /// `FragmentedRangeTombstones::shadow_versions()`, so a deleted key yields
+    /// a point tombstone.
+    pub fn get_versions(&self, key: &Bytes) -> Result<Vec<Entry>> {
+        let mut versions = Vec::new();
+        'blocks: for (last_key, handle) in self.index.blocks_from(key, &*self.comparator) {
+            for entry in BlockIter::new(self.read_block(*handle)?) {
+                let entry = entry?;
+                match self.comparator.compare(entry.key(), key) {
+                    std::cmp::Ordering::Less => continue,
+                    std::cmp::Ordering::Greater => break 'blocks,
+                    std::cmp::Ordering::Equal => {}
+                }
+                let is_merge = entry.is_merge();
+                versions.push(self.with_seq(entry));
+                if !is_merge {
+                    break 'blocks;
+                }
+            }
+            if self.comparator.compare(last_key, key).is_gt() {
+                break;
+            }
+        }
+        Ok(self.range_dels.shadow_versions(key, versions))
+    }
+
+    /// Iterates over every point entry, in `(key ASC, seq DESC)` order.
+    pub fn iter(&self) -> TableIter<'_> {
+        TableIter {
+            table: self,
+            next_block: 0,
+            block: None,
+            failed: false,
+        }
+    }
+
+    /// Iterates over the point entries from the block that may contain `key`,
+    /// skipping the blocks before it.
+    ///
+    /// The first block may still yield entries before `key`.
+    pub fn iter_from(&self, key: &[u8]) -> TableIter<'_> {
+        TableIter {
+            next_block: self.index.position(key, &*self.comparator),
+            ..self.iter()
+        }
+    }
+}
+
+/// Iterator over the point entries of a `Table` (see `Table::iter()`).
+///
+/// Stops after the first error.
+pub struct TableIter<'a> {
+    table: &'a Table,
+    next_block: usize,
+    block: Option<BlockIter>,
+    failed: bool,
+}
+
+impl Iterator for TableIter<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if let Some(block) = &mut self.block
+                && let Some(entry) = block.next()
+            {
+                self.failed = entry.is_err();
+                return Some(entry.map(|e| self.table.with_seq(e)));
+            }
+            let &(_, handle) = self.table.index.entries().get(self.next_block)?;
+            self.next_block += 1;
+            match self.table.read_block(handle) {
+                Ok(data) => self.block = Some(BlockIter::new(data)),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the block at `handle`, checking it lies before the footer.
+fn read_block(file: &dyn RandomAccessFile, handle: BlockHandle, file_size: u64) -> Result<Vec<u8>> {
+    if handle
+        .offset
+        .checked_add(handle.size)
+        .is_none_or(|end| end > file_size - FOOTER_SIZE as u64)
+    {
+        return Err(SSTableError::Corrupted(format!(
+            "block at offset {} of {} bytes is past the end of the data ({} bytes)",
+            handle.offset,
+            handle.size,
+            file_size - FOOTER_SIZE as u64
+        )));
+    }
+    let mut buf = vec![0u8; handle.size as usize];
+    file.read_exact_at(&mut buf, handle.offset)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{ReverseBytewiseComparator, default_comparator};
+    use crate::sstable::SstFileWriter;
+    use boxkv_common::env::MemoryFileSystem;
+    use boxkv_common::types::ValueType;
+
+    #[test]
+    fn test_written_table_reads_back() {
+        let fs = MemoryFileSystem::new();
+        let path = PathBuf::from("bulk.sst");
+        let mut writer = SstFileWriter::create(&fs, &path, default_comparator()).unwrap();
+        // Enough keys for several data blocks
+        for i in 0..2000u32 {
+            let key = Bytes::from(format!("key{:05}", i));
+            if i % 100 == 7 {
+                writer.delete(key).unwrap();
+            } else {
+                writer.put(key, Bytes::from(format!("value{}", i))).unwrap();
+            }
+        }
+        writer
+            .delete_range(Bytes::from("a"), Bytes::from("b"))
+            .unwrap();
+        let info = writer.finish().unwrap();
+        assert_eq!(info.num_entries, 2000);
+        assert_eq!(info.smallest_key, Some(Bytes::from("key00000")));
+        assert_eq!(info.largest_key, Some(Bytes::from("key01999")));
+
+        let table = Table::open(&fs, &path, default_comparator())
+            .unwrap()
+            .with_global_seq(9);
+        assert!(table.index.len() > 1);
+        assert_eq!(table.file_size(), info.file_size);
+        assert_eq!(table.smallest_key().unwrap(), info.smallest_key);
+        assert_eq!(table.largest_key(), info.largest_key.as_ref());
+        assert_eq!(table.iter().count(), 2000);
+        assert!(table.iter().all(|e| e.unwrap().seq() == 9));
+        let from: Vec<Bytes> = table
+            .iter_from(b"key01500")
+            .map(|e| e.unwrap().key().clone())
+            .collect();
+        assert!(from.len() < 2000);
+        assert!(from.contains(&Bytes::from("key01500")));
+        assert_eq!(from.last(), Some(&Bytes::from("key01999")));
+
+        let versions = table.get_versions(&Bytes::from("key01234")).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].seq(), 9);
+        assert_eq!(
+            versions[0].val(),
+            &ValueType::Normal(Bytes::from("value1234"))
+        );
+        assert!(table.get_versions(&Bytes::from("key01207")).unwrap()[0].is_tombstone());
+        assert!(table.get_versions(&Bytes::from("key2")).unwrap().is_empty());
+        // Deleted by the range deletion, at the table's sequence number
+        let deleted = table.get_versions(&Bytes::from("abc")).unwrap();
+        assert!(deleted[0].is_tombstone());
+        assert_eq!(deleted[0].seq(), 9);
+
+        assert!(matches!(
+            Table::open(&fs, &path, Arc::new(ReverseBytewiseComparator)),
+            Err(SSTableError::ComparatorMismatch { .. })
+        ));
+    }
+}
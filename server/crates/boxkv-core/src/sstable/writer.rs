@@ -0,0 +1,265 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::comparator::Comparator;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::sstable::{
+    BLOCK_SIZE, BlockBuilder, BlockHandle, COMPARATOR_BLOCK_NAME, FOOTER_SIZE, Footer, IndexBlock,
+    MetaIndexBlock, RANGE_DEL_BLOCK_NAME, RangeDelBlock, Result, SSTableError,
+};
+use boxkv_common::env::{FileSystem, WritableFile};
+use boxkv_common::types::{Entry, ValueType};
+
+/// Summary of a table written by `SstFileWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SstFileInfo {
+    pub path: PathBuf,
+    /// Smallest and largest point keys, `None` if the table only holds range
+    /// deletions.
+    pub smallest_key: Option<Bytes>,
+    pub largest_key: Option<Bytes>,
+    pub num_entries: u64,
+    pub num_range_deletions: u64,
+    pub file_size: u64,
+}
+
+/// Writes an SSTable outside of any engine, from keys added in strictly
+/// increasing comparator order.
+///
+/// Entries are written with sequence number 0: the engine assigns the whole
+/// table a single sequence number when it ingests it (see
+/// `Engine::ingest_external_files()`). Range deletions therefore only delete
+/// older data, never keys of the same table.
+///
+/// # Examples
+/// ```ignore
+/// let mut writer = SstFileWriter::create(&*fs, path, default_comparator())?;
+/// for (key, value) in sorted_rows {
+///     writer.put(key, value)?;
+/// }
+/// let info = writer.finish()?;
+/// engine.ingest_external_files(&[info.path])?;
+/// ```
+pub struct SstFileWriter {
+    path: PathBuf,
+    writer: BufWriter<Box<dyn WritableFile>>,
+    comparator: Arc<dyn Comparator>,
+    block: BlockBuilder,
+    index: IndexBlock,
+    range_dels: Vec<RangeTombstone>,
+    /// Bytes written so far.
+    offset: u64,
+    smallest_key: Option<Bytes>,
+    last_key: Option<Bytes>,
+    /// Sequence number of the last entry added.
+    last_seq: u64,
+    num_entries: u64,
+}
+
+impl SstFileWriter {
+    /// Creates the table file at `path` on `fs`, replacing any existing file.
+    ///
+    /// The table must be written on the `FileSystem` of the engine that will
+    /// ingest it.
+    pub fn create(
+        fs: &dyn FileSystem,
+        path: impl Into<PathBuf>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let path = path.into();
+        let writer = BufWriter::new(fs.create(&path)?);
+        Ok(Self {
+            path,
+            writer,
+            comparator,
+            block: BlockBuilder::new(),
+            index: IndexBlock::new(),
+            range_dels: Vec::new(),
+            offset: 0,
+            smallest_key: None,
+            last_key: None,
+            last_seq: 0,
+            num_entries: 0,
+        })
+    }
+
+    /// Returns the path of the table being written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds a value for `key`.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::InvalidInput` if `key` is not after the previous key.
+    pub fn put(&mut self, key: Bytes, value: Bytes) -> Result<()> {
+        self.add(key, ValueType::Normal(value))
+    }
+
+    /// Adds a point deletion of `key`, hiding older versions of it.
+    pub fn delete(&mut self, key: Bytes) -> Result<()> {
+        self.add(key, ValueType::Tombstone)
+    }
+
+    /// Adds a merge operand for `key`, applied on top of older versions of it.
+    pub fn merge(&mut self, key: Bytes, operand: Bytes) -> Result<()> {
+        self.add(key, ValueType::Merge(operand))
+    }
+
+    /// Adds a deletion of the older versions of every key in `[start, end)`.
+    ///
+    /// Unlike point entries, range deletions may be added in any order.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::InvalidInput` if the range is empty.
+    pub fn delete_range(&mut self, start: Bytes, end: Bytes) -> Result<()> {
+        if self.comparator.compare(&start, &end).is_ge() {
+            return Err(SSTableError::InvalidInput(format!(
+                "empty range [{:?}, {:?})",
+                start, end
+            )));
+        }
+        self.range_dels.push(RangeTombstone::new(start, end, 0));
+        Ok(())
+    }
+
+    fn add(&mut self, key: Bytes, value: ValueType) -> Result<()> {
+        self.add_entry(&Entry::new(0, key, value))
+    }
+
+    /// Adds `entry` with its own sequence number, for the tables the engine
+    /// writes itself.
+    ///
+    /// Entries must be added in `(key ASC, seq DESC)` order, so several
+    /// versions of a key may follow each other.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::InvalidInput` if `entry` is not after the
+    /// previous entry.
+    pub(crate) fn add_entry(&mut self, entry: &Entry) -> Result<()> {
+        let key = entry.key();
+        if let Some(last) = &self.last_key {
+            let order = self.comparator.compare(last, key);
+            if order.is_gt() || (order.is_eq() && entry.seq() >= self.last_seq) {
+                return Err(SSTableError::InvalidInput(format!(
+                    "key {:?} at seq {} added after {:?} at seq {}",
+                    key,
+                    entry.seq(),
+                    last,
+                    self.last_seq
+                )));
+            }
+        }
+
+        self.block.add(entry);
+        self.smallest_key.get_or_insert_with(|| key.clone());
+        self.last_key = Some(key.clone());
+        self.last_seq = entry.seq();
+        self.num_entries += 1;
+        if self.block.size() >= BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    /// Adds a range tombstone with its own sequence number (see `add_entry()`).
+    pub(crate) fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_dels.push(tombstone);
+    }
+
+    /// Writes the current data block and indexes it under its last key.
+    fn flush_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::take(&mut self.block).finish();
+        let handle = self.write_block(&block)?;
+        let last_key = self.last_key.clone().expect("block has entries");
+        self.index.add(last_key, handle);
+        Ok(())
+    }
+
+    fn write_block(&mut self, data: &[u8]) -> Result<BlockHandle> {
+        self.writer.write_all(data)?;
+        let handle = BlockHandle::new(self.offset, data.len() as u64);
+        self.offset += data.len() as u64;
+        Ok(handle)
+    }
+
+    /// Writes the meta blocks, index and footer, then syncs the file.
+    ///
+    /// # Errors
+    /// Returns `SSTableError::InvalidInput` if nothing was added.
+    pub fn finish(mut self) -> Result<SstFileInfo> {
+        if self.num_entries == 0 && self.range_dels.is_empty() {
+            return Err(SSTableError::InvalidInput("empty table".to_string()));
+        }
+        self.flush_block()?;
+
+        let mut meta_index = MetaIndexBlock::new();
+        let num_range_deletions = self.range_dels.len() as u64;
+        if !self.range_dels.is_empty() {
+            let fragmented = FragmentedRangeTombstones::from_tombstones(
+                std::mem::take(&mut self.range_dels),
+                self.comparator.clone(),
+            );
+            let handle = self.write_block(&RangeDelBlock::encode(fragmented.fragments()))?;
+            meta_index.add(RANGE_DEL_BLOCK_NAME, handle);
+        }
+        let name = self.comparator.name().as_bytes().to_vec();
+        let handle = self.write_block(&name)?;
+        meta_index.add(COMPARATOR_BLOCK_NAME, handle);
+
+        let meta_index_handle = self.write_block(&meta_index.encode())?;
+        let index_handle = self.write_block(&self.index.encode())?;
+        let mut footer = [0u8; FOOTER_SIZE];
+        Footer::new(meta_index_handle, index_handle).encode(&mut footer);
+        self.writer.write_all(&footer)?;
+        self.offset += FOOTER_SIZE as u64;
+
+        let mut file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync()?;
+
+        Ok(SstFileInfo {
+            path: self.path,
+            smallest_key: self.smallest_key,
+            largest_key: self.last_key,
+            num_entries: self.num_entries,
+            num_range_deletions,
+            file_size: self.offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::default_comparator;
+    use boxkv_common::env::MemoryFileSystem;
+
+    #[test]
+    fn test_writer_rejects_unordered_and_empty_input() {
+        let fs = MemoryFileSystem::new();
+        let mut writer = SstFileWriter::create(&fs, "a.sst", default_comparator()).unwrap();
+        writer.put(Bytes::from("b"), Bytes::from("1")).unwrap();
+        for key in ["a", "b"] {
+            assert!(matches!(
+                writer.put(Bytes::from(key), Bytes::from("2")),
+                Err(SSTableError::InvalidInput(_))
+            ));
+        }
+        assert!(matches!(
+            writer.delete_range(Bytes::from("z"), Bytes::from("a")),
+            Err(SSTableError::InvalidInput(_))
+        ));
+
+        let writer = SstFileWriter::create(&fs, "b.sst", default_comparator()).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(SSTableError::InvalidInput(_))
+        ));
+    }
+}
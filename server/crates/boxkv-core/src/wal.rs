@@ -1,18 +1,27 @@
+mod archive;
+mod changes;
 mod reader;
+mod replay;
 mod writer;
 
-use crate::wal::reader::{ReadError, WalIterator};
+pub use crate::wal::archive::{ArchiveRetention, RecoveryTarget, WalArchive};
+pub use crate::wal::changes::ChangeStream;
+pub use crate::wal::replay::{ReplayProgress, WalReplay};
+
+use crate::memtable::MemTable;
+use crate::wal::reader::ReadError;
 use crate::wal::writer::{WalWriter, WriteError};
 
-use std::fs;
-use std::fs::File;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use bytes::Bytes;
 use thiserror::Error;
-use tracing::{debug, info, trace, warn};
+use tracing::{debug, info, trace};
 
-use boxkv_common::types::Entry;
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, DEFAULT_COLUMN_FAMILY_ID, Entry};
 
 #[derive(Debug, Error)]
 pub enum WalError {
@@ -29,6 +38,17 @@ pub enum WalError {
         #[source]
         source: WriteError,
     },
+
+    /// A replayed record belongs to a column family the caller has no MemTable for.
+    #[error("Record {seq} belongs to unknown column family {cf_id}")]
+    UnknownColumnFamily { cf_id: ColumnFamilyId, seq: u64 },
+
+    /// The requested changes were already removed from both the live WAL and the archive.
+    #[error("Sequence {requested} already purged, oldest available is {oldest_available}")]
+    SequencePurged {
+        requested: u64,
+        oldest_available: u64,
+    },
 }
 
 /// Private trait to add file path context to Results.
@@ -66,13 +86,28 @@ impl<T> WalContext<T, std::io::Error> for Result<T, std::io::Error> {
 
 /// WAL Binary Format Specification
 ///
-/// ## Header (21 bytes, fixed):
+/// ## File Header (12 bytes, once per file):
+/// ```text
+/// +---------------------------+--------------+
+/// | Magic "boxkvwal" (8B)     | Version (4B) |
+/// +---------------------------+--------------+
+/// ```
+///
+/// Readers reject files with another magic number (including files written
+/// before the header existed) or an unknown version. The header is followed
+/// by records.
+///
+/// ## Header (33 bytes, fixed):
 /// ```text
-/// +----------+----------------+--------------+----------------+
-/// | CRC (4B) | PayloadLen (8B)| ValueTag(1B) | Seq (8B)       |
-/// +----------+----------------+--------------+----------------+
+/// +----------+----------------+--------------+----------+-----------+----------------+
+/// | CRC (4B) | PayloadLen (8B)| ValueTag(1B) | Seq (8B) | CfId (4B) | Timestamp (8B) |
+/// +----------+----------------+--------------+----------+-----------+----------------+
 /// ```
 ///
+/// `CfId` is the column family the entry belongs to (0 for the default one).
+/// `Timestamp` is the wall-clock time the record was logged at, in
+/// milliseconds since the Unix epoch; point-in-time recovery filters on it.
+///
 /// ## Payload (variable length):
 /// ```text
 /// +-------------+----------+----------------------+
@@ -101,11 +136,65 @@ impl<T> WalContext<T, std::io::Error> for Result<T, std::io::Error> {
 /// +-------------+------------+
 /// ```
 ///
+/// **[ValueTag = 3] Merge:**
+/// ```text
+/// +---------------+
+/// | Operand Data  |
+/// +---------------+
+/// ```
+///
+/// **[ValueTag = 4] RangeTombstone:** (Key Data is the inclusive start key)
+/// ```text
+/// +------------------------+
+/// | End Key (exclusive)    |
+/// +------------------------+
+/// ```
+///
+/// **[ValueTag = 5] BlobIndex:** (the value itself lives in a blob file)
+/// ```text
+/// +-------------+-------------+-----------+
+/// | FileId (8B) | Offset (8B) | Size (8B) |
+/// +-------------+-------------+-----------+
+/// ```
+///
+/// ## Batch Records
+///
+/// A group of writes that must be recovered atomically is logged as a single
+/// record with `ValueTag = 0x80`. `Seq` is the first entry's sequence number
+/// (the others follow consecutively), `CfId` is 0, KeyLen is 0 and the Value
+/// Section is:
+/// ```text
+/// +------------+---------+-----+---------+
+/// | Count (4B) | Entry 1 | ... | Entry N |
+/// +------------+---------+-----+---------+
+///
+/// Entry: CfId (4B) | ValueTag (1B) | KeyLen (8B) | Key | ValueLen (8B) | Value Section
+/// ```
+///
+/// Entries of a batch may belong to different column families.
+///
+/// ## Relocation Records
+///
+/// Values moved to another blob file by blob garbage collection are logged as
+/// one record with `ValueTag = 0x81` and the batch layout. Recovery applies
+/// them like a batch; the change feed skips them, as the values of their keys
+/// didn't change.
+///
+/// ## Ingestion Records
+///
+/// An external SSTable ingested into a column family is logged as a record
+/// with `ValueTag = 0x82`, the sequence number its records got and its column
+/// family, KeyLen 0 and an empty Value Section. Its records are only in the
+/// manifest: recovery and the change feed just account for the sequence
+/// number.
+///
 /// ## CRC Checksum Coverage:
 /// The CRC32 checksum covers all fields except itself:
 /// - PayloadLen (8 bytes)
 /// - ValueTag (1 byte)
 /// - Seq (8 bytes)
+/// - CfId (4 bytes)
+/// - Timestamp (8 bytes)
 /// - KeyLen (8 bytes)
 /// - Key Data (variable)
 /// - Value Section (variable)
@@ -113,39 +202,70 @@ const WAL_CRC_SIZE: usize = 4;
 const WAL_PAYLOAD_LEN_SIZE: usize = 8;
 const WAL_TYPE_SIZE: usize = 1;
 const WAL_SEQ_SIZE: usize = 8;
-const WAL_HEADER_SIZE: usize = WAL_CRC_SIZE + WAL_PAYLOAD_LEN_SIZE + WAL_TYPE_SIZE + WAL_SEQ_SIZE;
+const WAL_CF_ID_SIZE: usize = 4;
+const WAL_TIMESTAMP_SIZE: usize = 8;
+const WAL_HEADER_SIZE: usize = WAL_CRC_SIZE
+    + WAL_PAYLOAD_LEN_SIZE
+    + WAL_TYPE_SIZE
+    + WAL_SEQ_SIZE
+    + WAL_CF_ID_SIZE
+    + WAL_TIMESTAMP_SIZE;
 
 const WAL_KEY_LEN_SIZE: usize = 8;
 const WAL_EXPIRE_LEN_SIZE: usize = 8;
 
+/// Record type of batch records, outside the range of value tags.
+const WAL_BATCH_TYPE: u8 = 0x80;
+const WAL_BATCH_COUNT_SIZE: usize = 4;
+/// Record type of relocation records, laid out like batch records.
+const WAL_RELOCATION_TYPE: u8 = 0x81;
+/// Record type of ingestion records, which have no key or value.
+const WAL_INGESTION_TYPE: u8 = 0x82;
+
+/// Identifies WAL files ("boxkvwal").
+const WAL_MAGIC: u64 = 0x626f_786b_7677_616c;
+/// Version of the file and record layout described above.
+const WAL_FORMAT_VERSION: u32 = 1;
+const WAL_FILE_HEADER_SIZE: usize = 12;
+
 /// Manages the Write-Ahead Log (WAL) for data persistence and crash recovery.
 ///
 /// This struct represents the *active* WAL file being written to.
 pub struct Wal {
     writer: WalWriter,
     path: PathBuf,
+    file_id: u64,
 }
 
 impl Wal {
     /// Creates a new active WAL file for writing.
     ///
     /// # Arguments
+    /// * `fs` - File system holding the WAL directory
     /// * `dir` - Directory path where the WAL file will be created
     /// * `file_id` - Unique file identifier (formatted as 9-digit zero-padded filename)
     ///
     /// # File Naming
     /// Files are named as `{:09}.wal`, e.g., `000000001.wal`, `000000042.wal`
     ///
+    /// The directory is synced, so the file survives a crash once synced.
+    ///
     /// # Errors
     /// Returns `WalError::Write` if file creation fails.
-    pub fn create(dir: PathBuf, file_id: u64) -> Result<Self, WalError> {
-        let path = dir.join(format!("{:09}.wal", file_id));
+    pub fn create(fs: &dyn FileSystem, dir: PathBuf, file_id: u64) -> Result<Self, WalError> {
+        let path = Self::file_path(&dir, file_id);
 
         info!(file_id, ?path, "Creating WAL file");
 
+        let writer = WalWriter::new(fs, &path).with_context(&path)?;
+        // Synced records must not be lost with the directory entry
+        fs.sync_dir(&dir)
+            .map_err(WriteError::from)
+            .with_context(&path)?;
         Ok(Self {
-            writer: WalWriter::new(path.clone()).with_context(&path)?,
+            writer,
             path,
+            file_id,
         })
     }
 
@@ -158,7 +278,11 @@ impl Wal {
     /// 4. Filtering out entries with `seq < min_seq` (already persisted to SSTable)
     /// 5. Sorting all recovered entries by sequence number
     ///
+    /// All entries are materialized in memory. Prefer `Wal::replay_into` for
+    /// recovery of large logs.
+    ///
     /// # Arguments
+    /// * `fs` - File system holding the WAL directory
     /// * `dir` - Directory containing WAL files
     /// * `min_seq` - Minimum sequence number to recover (entries below this are skipped)
     ///
@@ -171,20 +295,137 @@ impl Wal {
     /// - Truncated WAL files (partial last record) are handled gracefully with a warning
     /// - CRC mismatches result in an error
     /// - I/O errors are propagated
-    pub fn read_all_entries(dir: PathBuf, min_seq: u64) -> Result<(Vec<Entry>, u64), WalError> {
+    pub fn read_all_entries(
+        fs: &Arc<dyn FileSystem>,
+        dir: PathBuf,
+        min_seq: u64,
+    ) -> Result<(Vec<Entry>, u64), WalError> {
         info!(min_seq, ?dir, "Starting WAL recovery");
         let start = std::time::Instant::now();
 
-        let read_dir = fs::read_dir(&dir).with_context(&dir)?;
+        let mut replay = Self::replay(fs, dir, min_seq)?;
+        let mut all_entries = Vec::new();
+        for record in replay.by_ref() {
+            all_entries.push(record?.1);
+        }
+        let max_seq = replay.progress().max_seq;
 
-        let mut wal_files: Vec<(u64, PathBuf)> = Vec::new();
+        // Final sort by sequence number
+        // This handles potential out-of-order writes if multiple threads allocated Seqs
+        // but wrote to the WAL in a slightly different physical order.
+        all_entries.sort_by_key(|r| r.seq());
+
+        let elapsed = start.elapsed();
+        info!(
+            record_count = all_entries.len(),
+            max_seq,
+            elapsed_ms = elapsed.as_millis(),
+            "WAL recovery completed"
+        );
+
+        Ok((all_entries, max_seq))
+    }
+
+    /// Opens a streaming replay over all WAL files in the specified directory.
+    ///
+    /// Unlike `read_all_entries`, entries are read lazily one record at a time,
+    /// so memory usage does not grow with the size of the log. Entries are
+    /// yielded in log order (see `WalReplay` for ordering guarantees).
+    ///
+    /// # Arguments
+    /// * `fs` - File system holding the WAL directory
+    /// * `dir` - Directory containing WAL files
+    /// * `min_seq` - Minimum sequence number to recover (entries below this are skipped)
+    ///
+    /// # Errors
+    /// Returns `WalError::Read` if the directory or file metadata cannot be read.
+    pub fn replay(
+        fs: &Arc<dyn FileSystem>,
+        dir: PathBuf,
+        min_seq: u64,
+    ) -> Result<WalReplay, WalError> {
+        let wal_files = Self::list_files(&**fs, &dir)?;
+
+        debug!(file_count = wal_files.len(), "Scanned WAL files");
+
+        WalReplay::new(fs, wal_files, min_seq)
+    }
+
+    /// Replays all WAL files in the directory straight into the MemTables of
+    /// their column families.
+    ///
+    /// Entries are applied with `MemTable::apply`, which keeps the version with the
+    /// highest sequence number per key. The result is identical to applying the
+    /// entries in sequence order, without buffering or sorting the whole log.
+    ///
+    /// Progress is logged periodically for large recoveries.
+    ///
+    /// # Returns
+    /// The final `ReplayProgress`; `max_seq` is used to resume sequence allocation.
+    ///
+    /// # Errors
+    /// Returns `WalError::UnknownColumnFamily` for a record whose column
+    /// family has no MemTable in `memtables`, instead of dropping it.
+    pub fn replay_into(
+        fs: &Arc<dyn FileSystem>,
+        dir: PathBuf,
+        min_seq: u64,
+        memtables: &mut HashMap<ColumnFamilyId, MemTable>,
+    ) -> Result<ReplayProgress, WalError> {
+        info!(min_seq, ?dir, "Starting streaming WAL recovery");
+        let start = std::time::Instant::now();
+
+        let mut replay = Self::replay(fs, dir, min_seq)?;
+        for record in replay.by_ref() {
+            let (cf_id, entry) = record?;
+            apply_to_column_family(memtables, cf_id, entry)?;
+        }
+        let progress = replay.progress().clone();
+
+        info!(
+            record_count = progress.entries_replayed,
+            bytes = progress.bytes_replayed,
+            max_seq = progress.max_seq,
+            elapsed_ms = start.elapsed().as_millis(),
+            "WAL recovery completed"
+        );
+
+        Ok(progress)
+    }
+
+    /// Opens a change-data-capture stream of all mutations after `seq`.
+    ///
+    /// Records are read from the archive (if configured) and the live WAL
+    /// directory, and the stream keeps following the active file as new records
+    /// are appended. See `ChangeStream` for details.
+    ///
+    /// # Arguments
+    /// * `fs` - File system holding the live WAL directory and the archive
+    /// * `dir` - Live WAL directory
+    /// * `archive` - Optional archive holding retired WAL files
+    /// * `seq` - Last sequence number already seen by the caller (0 for everything)
+    ///
+    /// # Errors
+    /// Returns `WalError::SequencePurged` if records following `seq` were already
+    /// removed from both the live directory and the archive.
+    pub fn changes_since(
+        fs: &Arc<dyn FileSystem>,
+        dir: PathBuf,
+        archive: Option<&WalArchive>,
+        seq: u64,
+    ) -> Result<ChangeStream, WalError> {
+        ChangeStream::open(fs, dir, archive.map(|a| a.dir().to_path_buf()), seq)
+    }
 
-        // 1. Scan directory for WAL files
-        for entry in read_dir {
-            let entry = entry.with_context(&dir)?;
-            let path = entry.path();
+    /// Lists all `.wal` files in the directory, sorted by file ID.
+    pub(crate) fn list_files(
+        fs: &dyn FileSystem,
+        dir: &Path,
+    ) -> Result<Vec<(u64, PathBuf)>, WalError> {
+        let mut wal_files: Vec<(u64, PathBuf)> = Vec::new();
 
-            if !path.is_file() || path.extension().and_then(|s| s.to_str()) != Some("wal") {
+        for path in fs.list_dir(dir).with_context(dir)? {
+            if fs.is_dir(&path) || path.extension().and_then(|s| s.to_str()) != Some("wal") {
                 continue;
             }
 
@@ -196,66 +437,67 @@ impl Wal {
             }
         }
 
-        // 2. Sort files by ID to ensure chronological order
+        // Sort files by ID to ensure chronological order
         wal_files.sort_unstable_by_key(|&(id, _)| id);
 
-        debug!(file_count = wal_files.len(), "Scanned WAL files");
+        Ok(wal_files)
+    }
 
-        let mut max_seq = u64::MIN;
-        let mut all_entries = Vec::new();
+    /// Appends an entry of any value type to the WAL, for column family `cf_id`.
+    pub fn append(&mut self, cf_id: ColumnFamilyId, entry: &Entry) -> Result<(), WalError> {
+        trace!(
+            cf_id,
+            seq = entry.seq(),
+            key_len = entry.key().len(),
+            tag = entry.val().type_tag(),
+            "Appending entry to WAL"
+        );
 
-        // 3. Iterate through each file and read records
-        for (file_id, path) in &wal_files {
-            let file = File::open(path).with_context(path)?;
-            let read_it = WalIterator::new(file);
-
-            let mut entry_count = 0;
-            for res in read_it {
-                match res {
-                    Ok(entry) => {
-                        if entry.seq() >= min_seq {
-                            max_seq = max_seq.max(entry.seq());
-                            all_entries.push(entry);
-                            entry_count += 1;
-                        }
-                    }
-                    Err(ReadError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                        // Warn: WAL file truncated at the end.
-                        // This is expected if the system crashed while writing the last record.
-                        // We ignore the partial record and stop reading this file.
-                        warn!(
-                            file_id,
-                            ?path,
-                            "WAL file truncated, skipping partial record"
-                        );
-                        break;
-                    }
-                    Err(e) => {
-                        return Err(WalError::Read {
-                            path: path.clone(),
-                            source: e,
-                        });
-                    }
-                }
-            }
+        self.writer.append(cf_id, entry).with_context(&self.path)
+    }
 
-            debug!(file_id, entry_count, ?path, "Completed reading WAL file");
-        }
+    /// Appends a group of entries as one batch record.
+    ///
+    /// Recovery sees either every entry of the batch or none of them. The
+    /// entries must have consecutive sequence numbers, and may belong to
+    /// different column families.
+    pub fn append_batch(&mut self, entries: &[(ColumnFamilyId, Entry)]) -> Result<(), WalError> {
+        trace!(
+            first_seq = entries.first().map(|(_, e)| e.seq()),
+            count = entries.len(),
+            "Appending batch to WAL"
+        );
 
-        // 4. Final sort by sequence number
-        // This handles potential out-of-order writes if multiple threads allocated Seqs
-        // but wrote to the WAL in a slightly different physical order.
-        all_entries.sort_by_key(|r| r.seq());
+        self.writer.append_batch(entries).with_context(&self.path)
+    }
 
-        let elapsed = start.elapsed();
-        info!(
-            record_count = all_entries.len(),
-            max_seq,
-            elapsed_ms = elapsed.as_millis(),
-            "WAL recovery completed"
+    /// Appends the values moved by blob garbage collection as one relocation
+    /// record, atomic like a batch.
+    ///
+    /// Recovery applies them as writes, but the change feed doesn't report
+    /// them: the values of their keys are unchanged.
+    pub fn append_relocations(
+        &mut self,
+        entries: &[(ColumnFamilyId, Entry)],
+    ) -> Result<(), WalError> {
+        trace!(
+            first_seq = entries.first().map(|(_, e)| e.seq()),
+            count = entries.len(),
+            "Appending blob relocations to WAL"
         );
 
-        Ok((all_entries, max_seq))
+        self.writer
+            .append_relocations(entries)
+            .with_context(&self.path)
+    }
+
+    /// Appends the marker of an SSTable ingested into `cf_id` at `seq`.
+    pub fn append_ingestion(&mut self, cf_id: ColumnFamilyId, seq: u64) -> Result<(), WalError> {
+        trace!(cf_id, seq, "Appending ingestion to WAL");
+
+        self.writer
+            .append_ingestion(cf_id, seq)
+            .with_context(&self.path)
     }
 
     /// Appends a PUT operation to the WAL.
@@ -273,7 +515,7 @@ impl Wal {
         );
 
         self.writer
-            .append(&Entry::new_normal(seq, key, val))
+            .append(DEFAULT_COLUMN_FAMILY_ID, &Entry::new_normal(seq, key, val))
             .with_context(&self.path)?;
 
         Ok(())
@@ -288,7 +530,7 @@ impl Wal {
         trace!(seq, key_len = key.len(), "Appending DELETE to WAL");
 
         self.writer
-            .append(&Entry::new_tombstone(seq, key))
+            .append(DEFAULT_COLUMN_FAMILY_ID, &Entry::new_tombstone(seq, key))
             .with_context(&self.path)?;
 
         Ok(())
@@ -311,7 +553,63 @@ impl Wal {
         trace!(seq, key_len = key.len(), "Appending EXPIRE to WAL");
 
         self.writer
-            .append(&Entry::new_expiring(seq, key, val, expire_at))
+            .append(
+                DEFAULT_COLUMN_FAMILY_ID,
+                &Entry::new_expiring(seq, key, val, expire_at),
+            )
+            .with_context(&self.path)?;
+
+        Ok(())
+    }
+
+    /// Appends a MERGE operand to the WAL.
+    ///
+    /// # Arguments
+    /// * `seq` - Sequence number for MVCC
+    /// * `key` - Key bytes
+    /// * `operand` - Operand passed to the keyspace's merge operator
+    pub fn append_merge(&mut self, seq: u64, key: Bytes, operand: Bytes) -> Result<(), WalError> {
+        trace!(
+            seq,
+            key_len = key.len(),
+            operand_len = operand.len(),
+            "Appending MERGE to WAL"
+        );
+
+        self.writer
+            .append(
+                DEFAULT_COLUMN_FAMILY_ID,
+                &Entry::new_merge(seq, key, operand),
+            )
+            .with_context(&self.path)?;
+
+        Ok(())
+    }
+
+    /// Appends a DELETE_RANGE operation (range tombstone) to the WAL.
+    ///
+    /// # Arguments
+    /// * `seq` - Sequence number for MVCC
+    /// * `start` - First key of the deleted range (inclusive)
+    /// * `end` - End of the deleted range (exclusive)
+    pub fn append_range_tombstone(
+        &mut self,
+        seq: u64,
+        start: Bytes,
+        end: Bytes,
+    ) -> Result<(), WalError> {
+        trace!(
+            seq,
+            start_len = start.len(),
+            end_len = end.len(),
+            "Appending DELETE_RANGE to WAL"
+        );
+
+        self.writer
+            .append(
+                DEFAULT_COLUMN_FAMILY_ID,
+                &Entry::new_range_tombstone(seq, start, end),
+            )
             .with_context(&self.path)?;
 
         Ok(())
@@ -324,14 +622,42 @@ impl Wal {
     /// are no longer needed for recovery.
     ///
     /// # Arguments
+    /// * `fs` - File system holding the WAL directory
     /// * `dir` - Directory containing the WAL file
     /// * `file_id` - File identifier to delete
-    pub fn delete(dir: PathBuf, file_id: u64) -> Result<(), WalError> {
-        let path = dir.join(format!("{:09}.wal", file_id));
+    pub fn delete(fs: &dyn FileSystem, dir: PathBuf, file_id: u64) -> Result<(), WalError> {
+        let path = Self::file_path(&dir, file_id);
 
         info!(file_id, ?path, "Deleting WAL file");
 
-        fs::remove_file(&path).with_context(&path)
+        fs.remove_file(&path).with_context(&path)
+    }
+
+    /// Retires an obsolete WAL file after its Memtable has been flushed.
+    ///
+    /// If an archive is configured, the file is moved into it (for point-in-time
+    /// recovery) instead of being deleted.
+    ///
+    /// # Arguments
+    /// * `fs` - File system holding the WAL directory
+    /// * `dir` - Directory containing the WAL file
+    /// * `file_id` - File identifier to retire
+    /// * `archive` - Optional archive receiving the file
+    pub fn retire(
+        fs: &dyn FileSystem,
+        dir: PathBuf,
+        file_id: u64,
+        archive: Option<&WalArchive>,
+    ) -> Result<(), WalError> {
+        match archive {
+            Some(archive) => archive.archive(&dir, file_id),
+            None => Self::delete(fs, dir, file_id),
+        }
+    }
+
+    /// Returns the path of the WAL file with the given ID (`{:09}.wal`).
+    fn file_path(dir: &Path, file_id: u64) -> PathBuf {
+        dir.join(format!("{:09}.wal", file_id))
     }
 
     /// Syncs all pending writes to physical disk (fsync).
@@ -347,36 +673,78 @@ impl Wal {
         self.writer.sync().with_context(&self.path)?;
         Ok(())
     }
+
+    /// Returns the path of the active WAL file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the ID of the active WAL file.
+    pub fn file_id(&self) -> u64 {
+        self.file_id
+    }
+}
+
+/// Applies a replayed entry to the MemTable of column family `cf_id`.
+fn apply_to_column_family(
+    memtables: &mut HashMap<ColumnFamilyId, MemTable>,
+    cf_id: ColumnFamilyId,
+    entry: Entry,
+) -> Result<(), WalError> {
+    let memtable = memtables
+        .get_mut(&cf_id)
+        .ok_or(WalError::UnknownColumnFamily {
+            cf_id,
+            seq: entry.seq(),
+        })?;
+    memtable.apply(entry);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use boxkv_common::types::ValueType;
-    use tempfile::TempDir;
+    use boxkv_common::env::{FaultInjectionFs, MemoryFileSystem};
+    use boxkv_common::types::{DEFAULT_COLUMN_FAMILY_ID, ValueType};
+    use std::io::Write;
+
+    /// Returns an in-memory file system holding an empty WAL directory.
+    fn mem_dir() -> (Arc<dyn FileSystem>, PathBuf) {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let dir = PathBuf::from("/db");
+        fs.create_dir_all(&dir).unwrap();
+        (fs, dir)
+    }
+
+    /// Chops the file at `path` down to its first `len` bytes.
+    fn truncate(fs: &dyn FileSystem, path: &Path, len: u64) {
+        let data = fs.read(path).unwrap();
+        fs.create(path)
+            .unwrap()
+            .write_all(&data[..len as usize])
+            .unwrap();
+    }
 
     #[test]
     fn test_wal_create_and_file_naming() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
-        let _wal = Wal::create(dir_path.clone(), 1).unwrap();
-        assert!(dir_path.join("000000001.wal").exists());
+        let _wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+        assert!(fs.exists(&dir_path.join("000000001.wal")));
 
-        let _wal2 = Wal::create(dir_path.clone(), 42).unwrap();
-        assert!(dir_path.join("000000042.wal").exists());
+        let _wal2 = Wal::create(&*fs, dir_path.clone(), 42).unwrap();
+        assert!(fs.exists(&dir_path.join("000000042.wal")));
 
-        let _wal3 = Wal::create(dir_path.clone(), 123456789).unwrap();
-        assert!(dir_path.join("123456789.wal").exists());
+        let _wal3 = Wal::create(&*fs, dir_path.clone(), 123456789).unwrap();
+        assert!(fs.exists(&dir_path.join("123456789.wal")));
     }
 
     #[test]
     fn test_wal_append_normal_value() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(100, Bytes::from("key1"), Bytes::from("value1"))
                 .unwrap();
             wal.append_normal(101, Bytes::from("key2"), Bytes::from("value2"))
@@ -384,7 +752,7 @@ mod tests {
             wal.sync().unwrap();
         }
 
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(max_seq, 101);
         assert_eq!(entries.len(), 2);
 
@@ -405,17 +773,16 @@ mod tests {
 
     #[test]
     fn test_wal_append_tombstone() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_tombstone(200, Bytes::from("deleted_key"))
                 .unwrap();
             wal.sync().unwrap();
         }
 
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(max_seq, 200);
         assert_eq!(entries.len(), 1);
 
@@ -427,13 +794,12 @@ mod tests {
 
     #[test]
     fn test_wal_append_expiring_value() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         let expire_at = 1234567890u64;
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_expire(
                 300,
                 Bytes::from("expire_key"),
@@ -444,7 +810,7 @@ mod tests {
             wal.sync().unwrap();
         }
 
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(max_seq, 300);
         assert_eq!(entries.len(), 1);
 
@@ -464,11 +830,10 @@ mod tests {
 
     #[test]
     fn test_wal_mixed_value_types() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(1, Bytes::from("k1"), Bytes::from("v1"))
                 .unwrap();
             wal.append_tombstone(2, Bytes::from("k2")).unwrap();
@@ -476,27 +841,39 @@ mod tests {
                 .unwrap();
             wal.append_normal(4, Bytes::from("k4"), Bytes::from("v4"))
                 .unwrap();
+            wal.append_merge(5, Bytes::from("k4"), Bytes::from("+1"))
+                .unwrap();
+            wal.append_range_tombstone(6, Bytes::from("k1"), Bytes::from("k3"))
+                .unwrap();
             wal.sync().unwrap();
         }
 
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
-        assert_eq!(max_seq, 4);
-        assert_eq!(entries.len(), 4);
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
+        assert_eq!(max_seq, 6);
+        assert_eq!(entries.len(), 6);
 
         assert!(matches!(entries[0].val(), ValueType::Normal(_)));
         assert!(matches!(entries[1].val(), ValueType::Tombstone));
         assert!(matches!(entries[2].val(), ValueType::Expiring { .. }));
         assert!(matches!(entries[3].val(), ValueType::Normal(_)));
+        match entries[4].val() {
+            ValueType::Merge(operand) => assert_eq!(operand.as_ref(), b"+1"),
+            _ => panic!("Expected Merge operand"),
+        }
+        assert_eq!(entries[5].key().as_ref(), b"k1");
+        match entries[5].val() {
+            ValueType::RangeTombstone { end } => assert_eq!(end.as_ref(), b"k3"),
+            _ => panic!("Expected RangeTombstone"),
+        }
     }
 
     #[test]
     fn test_wal_multiple_files_recovery() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         // Create WAL file 1
         {
-            let mut wal1 = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal1 = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal1.append_normal(10, Bytes::from("k1"), Bytes::from("v1"))
                 .unwrap();
             wal1.append_normal(20, Bytes::from("k2"), Bytes::from("v2"))
@@ -506,7 +883,7 @@ mod tests {
 
         // Create WAL file 2
         {
-            let mut wal2 = Wal::create(dir_path.clone(), 2).unwrap();
+            let mut wal2 = Wal::create(&*fs, dir_path.clone(), 2).unwrap();
             wal2.append_normal(30, Bytes::from("k3"), Bytes::from("v3"))
                 .unwrap();
             wal2.append_tombstone(40, Bytes::from("k1")).unwrap();
@@ -515,14 +892,14 @@ mod tests {
 
         // Create WAL file 3
         {
-            let mut wal3 = Wal::create(dir_path.clone(), 3).unwrap();
+            let mut wal3 = Wal::create(&*fs, dir_path.clone(), 3).unwrap();
             wal3.append_expire(50, Bytes::from("k4"), Bytes::from("v4"), 8888)
                 .unwrap();
             wal3.sync().unwrap();
         }
 
         // Recover all
-        let (entries, max_seq) = Wal::read_all_entries(dir_path.clone(), 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path.clone(), 0).unwrap();
         assert_eq!(max_seq, 50);
         assert_eq!(entries.len(), 5);
 
@@ -536,11 +913,10 @@ mod tests {
 
     #[test]
     fn test_wal_min_seq_filtering() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(10, Bytes::from("k1"), Bytes::from("v1"))
                 .unwrap();
             wal.append_normal(20, Bytes::from("k2"), Bytes::from("v2"))
@@ -553,60 +929,57 @@ mod tests {
         }
 
         // Filter seq < 25 (should get seq 30 and 40)
-        let (entries, max_seq) = Wal::read_all_entries(dir_path.clone(), 25).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path.clone(), 25).unwrap();
         assert_eq!(max_seq, 40);
         assert_eq!(entries.len(), 2);
         assert_eq!(entries[0].seq(), 30);
         assert_eq!(entries[1].seq(), 40);
 
         // Filter seq < 40 (should get only seq 40)
-        let (entries, max_seq) = Wal::read_all_entries(dir_path.clone(), 40).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path.clone(), 40).unwrap();
         assert_eq!(max_seq, 40);
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].seq(), 40);
 
         // Filter seq < 100 (should get nothing)
-        let (entries, _) = Wal::read_all_entries(dir_path, 100).unwrap();
+        let (entries, _) = Wal::read_all_entries(&fs, dir_path, 100).unwrap();
         assert_eq!(entries.len(), 0);
     }
 
     #[test]
     fn test_wal_delete_file() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
-        let _wal = Wal::create(dir_path.clone(), 1).unwrap();
+        let _wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
         let file_path = dir_path.join("000000001.wal");
-        assert!(file_path.exists());
+        assert!(fs.exists(&file_path));
 
-        Wal::delete(dir_path.clone(), 1).unwrap();
-        assert!(!file_path.exists());
+        Wal::delete(&*fs, dir_path.clone(), 1).unwrap();
+        assert!(!fs.exists(&file_path));
 
         // Deleting non-existent file should return error
-        assert!(Wal::delete(dir_path, 999).is_err());
+        assert!(Wal::delete(&*fs, dir_path, 999).is_err());
     }
 
     #[test]
     fn test_wal_empty_recovery() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         // No WAL files exist
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(entries.len(), 0);
         assert_eq!(max_seq, u64::MIN);
     }
 
     #[test]
     fn test_wal_large_values() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         let large_key = vec![b'k'; 1024]; // 1KB key
         let large_value = vec![b'v'; 1024 * 1024]; // 1MB value
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(
                 1,
                 Bytes::from(large_key.clone()),
@@ -616,7 +989,7 @@ mod tests {
             wal.sync().unwrap();
         }
 
-        let (entries, _) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, _) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].key().len(), 1024);
         match entries[0].val() {
@@ -627,18 +1000,17 @@ mod tests {
 
     #[test]
     fn test_wal_empty_key_and_value() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(1, Bytes::from(""), Bytes::from(""))
                 .unwrap();
             wal.append_tombstone(2, Bytes::from("")).unwrap();
             wal.sync().unwrap();
         }
 
-        let (entries, _) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, _) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(entries.len(), 2);
         assert_eq!(entries[0].key().len(), 0);
         assert_eq!(entries[1].key().len(), 0);
@@ -646,15 +1018,14 @@ mod tests {
 
     #[test]
     fn test_wal_binary_key_and_value() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         // Binary data with all byte values
         let binary_key: Vec<u8> = (0..=255).collect();
         let binary_value: Vec<u8> = (0..=255).rev().collect();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(
                 1,
                 Bytes::from(binary_key.clone()),
@@ -664,7 +1035,7 @@ mod tests {
             wal.sync().unwrap();
         }
 
-        let (entries, _) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, _) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].key().as_ref(), binary_key.as_slice());
         match entries[0].val() {
@@ -675,11 +1046,10 @@ mod tests {
 
     #[test]
     fn test_wal_sequence_number_ordering() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             // Write in non-sequential order
             wal.append_normal(100, Bytes::from("k100"), Bytes::from("v100"))
                 .unwrap();
@@ -692,7 +1062,7 @@ mod tests {
             wal.sync().unwrap();
         }
 
-        let (entries, max_seq) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, max_seq) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(max_seq, 200);
         assert_eq!(entries.len(), 4);
 
@@ -705,11 +1075,10 @@ mod tests {
 
     #[test]
     fn test_wal_sync_durability() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_path_buf();
+        let (fs, dir_path) = mem_dir();
 
         {
-            let mut wal = Wal::create(dir_path.clone(), 1).unwrap();
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
             wal.append_normal(1, Bytes::from("k1"), Bytes::from("v1"))
                 .unwrap();
             // sync() ensures data is on disk
@@ -717,7 +1086,266 @@ mod tests {
         }
 
         // Drop wal without explicit sync should still work because we called sync()
-        let (entries, _) = Wal::read_all_entries(dir_path, 0).unwrap();
+        let (entries, _) = Wal::read_all_entries(&fs, dir_path, 0).unwrap();
         assert_eq!(entries.len(), 1);
     }
+
+    #[test]
+    fn test_wal_replay_streams_in_log_order() {
+        let (fs, dir_path) = mem_dir();
+
+        {
+            let mut wal1 = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+            wal1.append_normal(20, Bytes::from("k2"), Bytes::from("v2"))
+                .unwrap();
+            wal1.append_normal(10, Bytes::from("k1"), Bytes::from("v1"))
+                .unwrap();
+            wal1.sync().unwrap();
+
+            let mut wal2 = Wal::create(&*fs, dir_path.clone(), 2).unwrap();
+            wal2.append_tombstone(30, Bytes::from("k1")).unwrap();
+            wal2.sync().unwrap();
+        }
+
+        let mut replay = Wal::replay(&fs, dir_path, 15).unwrap();
+        assert_eq!(replay.progress().files_total, 2);
+
+        let seqs: Vec<u64> = replay.by_ref().map(|r| r.unwrap().1.seq()).collect();
+        // Log order is preserved, entries below min_seq are skipped
+        assert_eq!(seqs, vec![20, 30]);
+
+        let progress = replay.progress();
+        assert_eq!(progress.files_done, 2);
+        assert_eq!(progress.entries_replayed, 2);
+        assert_eq!(progress.max_seq, 30);
+        assert_eq!(progress.bytes_replayed, progress.bytes_total);
+    }
+
+    #[test]
+    fn test_wal_replay_into_memtable() {
+        let (fs, dir_path) = mem_dir();
+
+        {
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+            // Physical order differs from sequence order for "k1"
+            wal.append_normal(2, Bytes::from("k1"), Bytes::from("new"))
+                .unwrap();
+            wal.append_normal(1, Bytes::from("k1"), Bytes::from("old"))
+                .unwrap();
+            wal.append_normal(3, Bytes::from("k2"), Bytes::from("v2"))
+                .unwrap();
+            wal.append_tombstone(4, Bytes::from("k2")).unwrap();
+            wal.append(
+                7,
+                &Entry::new_normal(5, Bytes::from("k1"), Bytes::from("cf7")),
+            )
+            .unwrap();
+            wal.sync().unwrap();
+        }
+
+        let mut memtables: HashMap<ColumnFamilyId, MemTable> = [
+            (DEFAULT_COLUMN_FAMILY_ID, MemTable::new()),
+            (7, MemTable::new()),
+        ]
+        .into();
+        let progress = Wal::replay_into(&fs, dir_path.clone(), 0, &mut memtables).unwrap();
+        assert_eq!(progress.entries_replayed, 5);
+        assert_eq!(progress.max_seq, 5);
+
+        let memtable = &memtables[&DEFAULT_COLUMN_FAMILY_ID];
+        let k1 = memtable.get(&Bytes::from("k1")).unwrap();
+        assert_eq!(k1.seq(), 2);
+        match k1.val() {
+            ValueType::Normal(data) => assert_eq!(data.as_ref(), b"new"),
+            _ => panic!("Expected Normal value"),
+        }
+        assert!(memtable.get(&Bytes::from("k2")).unwrap().is_tombstone());
+        assert_eq!(memtables[&7].get(&Bytes::from("k1")).unwrap().seq(), 5);
+
+        // Records of a column family without a MemTable are not dropped silently
+        memtables.remove(&7);
+        assert!(matches!(
+            Wal::replay_into(&fs, dir_path, 0, &mut memtables),
+            Err(WalError::UnknownColumnFamily { cf_id: 7, seq: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_wal_replay_truncated_file() {
+        let (fs, dir_path) = mem_dir();
+
+        {
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+            wal.append_normal(1, Bytes::from("k1"), Bytes::from("v1"))
+                .unwrap();
+            wal.append_normal(2, Bytes::from("k2"), Bytes::from("v2"))
+                .unwrap();
+            wal.sync().unwrap();
+        }
+
+        // Chop off the tail of the last record
+        let path = dir_path.join("000000001.wal");
+        let len = fs.file_size(&path).unwrap();
+        truncate(&*fs, &path, len - 3);
+
+        let mut replay = Wal::replay(&fs, dir_path, 0).unwrap();
+        let entries: Vec<Entry> = replay.by_ref().map(|r| r.unwrap().1).collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].seq(), 1);
+        assert_eq!(replay.progress().files_done, 1);
+    }
+
+    #[test]
+    fn test_wal_batch_is_atomic() {
+        let (fs, dir_path) = mem_dir();
+
+        let batch = vec![
+            (0, Entry::new_normal(2, Bytes::from("a"), Bytes::from("1"))),
+            (7, Entry::new_tombstone(3, Bytes::from("b"))),
+            (
+                0,
+                Entry::new_expiring(4, Bytes::from("c"), Bytes::from("3"), 9999),
+            ),
+            (7, Entry::new_merge(5, Bytes::from("d"), Bytes::from("+1"))),
+        ];
+        {
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+            wal.append_normal(1, Bytes::from("k"), Bytes::from("v"))
+                .unwrap();
+            wal.append_batch(&batch).unwrap();
+            wal.append(
+                3,
+                &Entry::new_normal(6, Bytes::from("k"), Bytes::from("v2")),
+            )
+            .unwrap();
+            wal.sync().unwrap();
+        }
+
+        let mut replay = Wal::replay(&fs, dir_path.clone(), 0).unwrap();
+        let records: Vec<_> = replay.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(replay.progress().max_seq, 6);
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].0, DEFAULT_COLUMN_FAMILY_ID);
+        assert_eq!(&records[1..5], &batch[..]);
+        for ((_, decoded), (_, expected)) in records[1..5].iter().zip(&batch) {
+            assert_eq!(decoded.val(), expected.val());
+        }
+        assert_eq!(records[5].0, 3);
+
+        // A torn batch is dropped as a whole
+        let path = dir_path.join("000000001.wal");
+        let first_record = (WAL_FILE_HEADER_SIZE + WAL_HEADER_SIZE + WAL_KEY_LEN_SIZE + 2) as u64;
+        truncate(&*fs, &path, first_record + 30);
+
+        let seqs: Vec<u64> = Wal::replay(&fs, dir_path, 0)
+            .unwrap()
+            .map(|r| r.unwrap().1.seq())
+            .collect();
+        assert_eq!(seqs, vec![1]);
+    }
+
+    #[test]
+    fn test_wal_rejects_files_without_current_header() {
+        let (fs, dir_path) = mem_dir();
+        {
+            let mut wal = Wal::create(&*fs, dir_path.clone(), 1).unwrap();
+            wal.append_normal(1, Bytes::from("k"), Bytes::from("v"))
+                .unwrap();
+            wal.sync().unwrap();
+        }
+        let path = dir_path.join("000000001.wal");
+        let data = fs.read(&path).unwrap();
+
+        // A file from before the header existed starts with a record
+        fs.create(&path)
+            .unwrap()
+            .write_all(&data[WAL_FILE_HEADER_SIZE..])
+            .unwrap();
+        match Wal::read_all_entries(&fs, dir_path.clone(), 0) {
+            Err(WalError::Read {
+                source: ReadError::BadMagic(_),
+                ..
+            }) => {}
+            other => panic!("Expected BadMagic, got {:?}", other.map(|_| ())),
+        }
+
+        let mut newer = data.clone();
+        newer[WAL_FILE_HEADER_SIZE - 1] = 2;
+        fs.create(&path).unwrap().write_all(&newer).unwrap();
+        match Wal::read_all_entries(&fs, dir_path, 0) {
+            Err(WalError::Read {
+                source: ReadError::UnsupportedVersion { found: 2, .. },
+                ..
+            }) => {}
+            other => panic!("Expected UnsupportedVersion, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    /// Logs `entries` over several WAL files, syncing each one, until an
+    /// operation fails. Returns the number of entries acknowledged.
+    fn log_until_failure(fs: &dyn FileSystem, dir: &Path, entries: &[Entry]) -> usize {
+        let mut acked = 0;
+        let _ = (|| -> Result<(), WalError> {
+            for (file_id, chunk) in (1..).zip(entries.chunks(3)) {
+                let mut wal = Wal::create(fs, dir.to_path_buf(), file_id)?;
+                for entry in chunk {
+                    wal.append(DEFAULT_COLUMN_FAMILY_ID, entry)?;
+                    wal.sync()?;
+                    acked += 1;
+                }
+            }
+            Ok(())
+        })();
+        acked
+    }
+
+    #[test]
+    fn test_recovery_after_crash_at_every_point() {
+        let entries: Vec<Entry> = (1..=7)
+            .map(|seq| {
+                Entry::new_normal(
+                    seq,
+                    Bytes::from(format!("key{}", seq)),
+                    Bytes::from(vec![seq as u8; 20]),
+                )
+            })
+            .collect();
+        let dir = Path::new("/db");
+
+        // Power failures after each operation, then in the middle of each write
+        for torn_writes in [false, true] {
+            for point in 0.. {
+                let base = MemoryFileSystem::new();
+                base.create_dir_all(dir).unwrap();
+                let fault = Arc::new(FaultInjectionFs::new(Arc::new(base)));
+                if torn_writes {
+                    fault.halt_after_bytes(point);
+                } else {
+                    fault.halt_after_ops(point);
+                }
+                let acked = log_until_failure(&*fault, dir, &entries);
+                let halted = fault.is_halted();
+                fault.crash().unwrap();
+
+                let fs: Arc<dyn FileSystem> = fault;
+                let (recovered, _) = Wal::read_all_entries(&fs, dir.to_path_buf(), 0).unwrap();
+                assert!(
+                    recovered.len() >= acked,
+                    "crash point {} (torn: {}): {} of {} acknowledged entries recovered",
+                    point,
+                    torn_writes,
+                    recovered.len(),
+                    acked
+                );
+                let keys = |entries: &[Entry]| -> Vec<(u64, Bytes)> {
+                    entries.iter().map(|e| (e.seq(), e.key().clone())).collect()
+                };
+                assert_eq!(keys(&recovered), keys(&entries[..recovered.len()]));
+                if !halted {
+                    assert_eq!(acked, entries.len());
+                    break;
+                }
+            }
+        }
+    }
 }
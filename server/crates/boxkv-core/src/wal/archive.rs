@@ -0,0 +1,514 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, info, warn};
+
+use super::replay::{ReplayProgress, WalReplay};
+use super::{Wal, WalContext, WalError, apply_to_column_family};
+use crate::memtable::MemTable;
+
+use boxkv_common::config::StorageConfig;
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::ColumnFamilyId;
+
+/// Retention policy for archived WAL files.
+///
+/// Both limits are optional; a file is purged as soon as it violates either one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveRetention {
+    /// Archived files last modified longer ago than this are removed.
+    pub max_age: Option<Duration>,
+    /// Oldest files are removed until the archive fits within this many bytes.
+    pub max_size: Option<u64>,
+}
+
+/// Point up to which archived WAL records are replayed during a restore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Replay every archived record.
+    Latest,
+    /// Replay records with `seq <= target` only.
+    Seq(u64),
+    /// Replay records logged at or before this time.
+    ///
+    /// Each record carries the time it was logged at, with millisecond
+    /// precision.
+    Time(SystemTime),
+}
+
+/// Archive directory receiving obsolete WAL files instead of deleting them.
+///
+/// Archived files keep their name (`{:09}.wal`) and modification time, so the
+/// archive can be replayed like a regular WAL directory for point-in-time
+/// recovery. Modification times only drive age-based retention.
+pub struct WalArchive {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    retention: ArchiveRetention,
+}
+
+impl WalArchive {
+    /// Opens (and creates if needed) an archive directory on `fs`.
+    ///
+    /// The archive must be on the same `FileSystem` as the WAL files it
+    /// receives.
+    ///
+    /// # Errors
+    /// Returns `WalError::Read` if the directory cannot be created.
+    pub fn new(
+        fs: &Arc<dyn FileSystem>,
+        dir: PathBuf,
+        retention: ArchiveRetention,
+    ) -> Result<Self, WalError> {
+        fs.create_dir_all(&dir).with_context(&dir)?;
+
+        Ok(Self {
+            fs: fs.clone(),
+            dir,
+            retention,
+        })
+    }
+
+    /// Builds the archive described by the storage configuration.
+    ///
+    /// Returns `Ok(None)` when `wal_archive_dir` is not set.
+    pub fn from_config(
+        fs: &Arc<dyn FileSystem>,
+        config: &StorageConfig,
+    ) -> Result<Option<Self>, WalError> {
+        let Some(dir) = &config.wal_archive_dir else {
+            return Ok(None);
+        };
+
+        let retention = ArchiveRetention {
+            max_age: (config.wal_archive_ttl_secs > 0)
+                .then(|| Duration::from_secs(config.wal_archive_ttl_secs)),
+            max_size: (config.wal_archive_size_limit_mb > 0)
+                .then(|| config.wal_archive_size_limit_mb * 1024 * 1024),
+        };
+
+        Self::new(fs, dir.clone(), retention).map(Some)
+    }
+
+    /// Returns the archive directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves a WAL file from `wal_dir` into the archive, then applies retention.
+    ///
+    /// Falls back to copy + delete when the archive is on another filesystem.
+    pub fn archive(&self, wal_dir: &Path, file_id: u64) -> Result<(), WalError> {
+        let src = Wal::file_path(wal_dir, file_id);
+        let dst = Wal::file_path(&self.dir, file_id);
+
+        info!(file_id, ?src, ?dst, "Archiving WAL file");
+
+        if let Err(e) = self.fs.rename(&src, &dst) {
+            debug!(error = %e, "Rename failed, copying WAL file into archive");
+
+            let modified = self.fs.modified(&src).with_context(&src)?;
+            self.fs.copy(&src, &dst).with_context(&dst)?;
+            // Preserve the last write time, it drives age-based retention.
+            self.fs.set_modified(&dst, modified).with_context(&dst)?;
+            self.fs.remove_file(&src).with_context(&src)?;
+        }
+
+        self.purge()?;
+        Ok(())
+    }
+
+    /// Removes archived files that exceed the retention policy.
+    ///
+    /// Age is checked first, then the oldest remaining files are removed until
+    /// the total size fits. Returns the number of removed files.
+    pub fn purge(&self) -> Result<usize, WalError> {
+        let now = SystemTime::now();
+
+        let mut files = Vec::new();
+        for (file_id, path) in Wal::list_files(&*self.fs, &self.dir)? {
+            let len = self.fs.file_size(&path).with_context(&path)?;
+            let modified = self.fs.modified(&path).with_context(&path)?;
+            files.push((file_id, path, len, modified));
+        }
+
+        let mut total: u64 = files.iter().map(|(_, _, len, _)| len).sum();
+        let mut removed = 0;
+
+        for (file_id, path, len, modified) in files {
+            let expired = self
+                .retention
+                .max_age
+                .is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age);
+            let oversized = self.retention.max_size.is_some_and(|max| total > max);
+
+            if !expired && !oversized {
+                continue;
+            }
+
+            debug!(
+                file_id,
+                ?path,
+                expired,
+                oversized,
+                "Purging archived WAL file"
+            );
+            self.fs.remove_file(&path).with_context(&path)?;
+            total -= len;
+            removed += 1;
+        }
+
+        if removed > 0 {
+            info!(
+                removed,
+                remaining_bytes = total,
+                "Purged archived WAL files"
+            );
+        }
+
+        Ok(removed)
+    }
+
+    /// Restores MemTables from archived and live WAL files on top of a checkpoint.
+    ///
+    /// Files from the archive and from `live_dir` are replayed in file ID order.
+    /// Records newer than the flushed sequence number of their column family
+    /// in the checkpoint, and within `target`, are applied to the MemTable of
+    /// their column family. Records already in an SSTable of the checkpoint
+    /// are never applied twice.
+    ///
+    /// Ingested SSTables are not in the WAL: only their sequence numbers are
+    /// replayed.
+    ///
+    /// # Arguments
+    /// * `live_dir` - Active WAL directory (files not yet archived)
+    /// * `flushed_seqs` - Sequence number each column family of the checkpoint
+    ///   is flushed up to (0 if missing)
+    /// * `target` - Point in time up to which records are applied
+    /// * `memtables` - MemTable receiving the records of each column family
+    ///
+    /// # Returns
+    /// The replay progress, where `entries_replayed` and `max_seq` only
+    /// account for the records applied.
+    ///
+    /// # Errors
+    /// Returns `WalError::UnknownColumnFamily` for a record to apply whose
+    /// column family has no MemTable in `memtables`.
+    pub fn restore(
+        &self,
+        live_dir: &Path,
+        flushed_seqs: &BTreeMap<ColumnFamilyId, u64>,
+        target: RecoveryTarget,
+        memtables: &mut HashMap<ColumnFamilyId, MemTable>,
+    ) -> Result<ReplayProgress, WalError> {
+        let flushed_seq = |cf_id| flushed_seqs.get(&cf_id).copied().unwrap_or(0);
+        // Older records are in the checkpoint for every column family
+        let min_seq = memtables
+            .keys()
+            .map(|&cf_id| flushed_seq(cf_id))
+            .min()
+            .unwrap_or(0)
+            + 1;
+        info!(min_seq, ?target, archive = ?self.dir, "Starting point-in-time restore");
+
+        let mut files = Wal::list_files(&*self.fs, &self.dir)?;
+        for (file_id, path) in Wal::list_files(&*self.fs, live_dir)? {
+            if files.iter().any(|(id, _)| *id == file_id) {
+                warn!(
+                    file_id,
+                    ?path,
+                    "WAL file present in both archive and live dir"
+                );
+                continue;
+            }
+            files.push((file_id, path));
+        }
+        files.sort_unstable_by_key(|&(id, _)| id);
+
+        let mut replay = WalReplay::new(&self.fs, files, min_seq)?;
+        let mut applied = 0;
+        let mut max_seq = 0;
+        while let Some(record) = replay.next() {
+            let (cf_id, entry) = record?;
+            if entry.seq() <= flushed_seq(cf_id) {
+                continue;
+            }
+            let within_target = match target {
+                RecoveryTarget::Latest => true,
+                RecoveryTarget::Seq(seq) => entry.seq() <= seq,
+                RecoveryTarget::Time(time) => replay.logged_at() <= time,
+            };
+            if within_target {
+                applied += 1;
+                max_seq = max_seq.max(entry.seq());
+                apply_to_column_family(memtables, cf_id, entry)?;
+            }
+        }
+        // Only count what was restored, not what was read past the target
+        let progress = ReplayProgress {
+            entries_replayed: applied,
+            max_seq,
+            ..replay.progress().clone()
+        };
+
+        info!(
+            record_count = progress.entries_replayed,
+            max_seq = progress.max_seq,
+            "Point-in-time restore completed"
+        );
+
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::env::MemoryFileSystem;
+    use boxkv_common::types::{DEFAULT_COLUMN_FAMILY_ID, Entry};
+    use bytes::Bytes;
+
+    fn default_memtables() -> HashMap<ColumnFamilyId, MemTable> {
+        [(DEFAULT_COLUMN_FAMILY_ID, MemTable::new())].into()
+    }
+
+    fn setup() -> (Arc<dyn FileSystem>, PathBuf) {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let wal_dir = PathBuf::from("/db/wal");
+        fs.create_dir_all(&wal_dir).unwrap();
+        (fs, wal_dir)
+    }
+
+    fn write_wal(fs: &Arc<dyn FileSystem>, dir: &Path, file_id: u64, first_seq: u64, count: u64) {
+        let mut wal = Wal::create(&**fs, dir.to_path_buf(), file_id).unwrap();
+        for seq in first_seq..first_seq + count {
+            wal.append_normal(seq, Bytes::from(format!("k{:04}", seq)), Bytes::from("v"))
+                .unwrap();
+        }
+        wal.sync().unwrap();
+    }
+
+    #[test]
+    fn test_retire_moves_file_into_archive() {
+        let (fs, wal_dir) = setup();
+
+        let archive = WalArchive::new(
+            &fs,
+            PathBuf::from("/db/archive"),
+            ArchiveRetention::default(),
+        )
+        .unwrap();
+        write_wal(&fs, &wal_dir, 1, 1, 3);
+
+        Wal::retire(&*fs, wal_dir.clone(), 1, Some(&archive)).unwrap();
+        assert!(!fs.exists(&wal_dir.join("000000001.wal")));
+        assert!(fs.exists(&archive.dir().join("000000001.wal")));
+
+        // Without an archive the file is deleted
+        write_wal(&fs, &wal_dir, 2, 4, 1);
+        Wal::retire(&*fs, wal_dir.clone(), 2, None).unwrap();
+        assert!(!fs.exists(&wal_dir.join("000000002.wal")));
+        assert!(!fs.exists(&archive.dir().join("000000002.wal")));
+    }
+
+    #[test]
+    fn test_purge_by_size_removes_oldest_first() {
+        let (fs, wal_dir) = setup();
+
+        write_wal(&fs, &wal_dir, 1, 1, 10);
+        let file_size = fs.file_size(&wal_dir.join("000000001.wal")).unwrap();
+
+        let retention = ArchiveRetention {
+            max_age: None,
+            max_size: Some(file_size * 2),
+        };
+        let archive = WalArchive::new(&fs, PathBuf::from("/db/archive"), retention).unwrap();
+
+        archive.archive(&wal_dir, 1).unwrap();
+        write_wal(&fs, &wal_dir, 2, 11, 10);
+        archive.archive(&wal_dir, 2).unwrap();
+        write_wal(&fs, &wal_dir, 3, 21, 10);
+        archive.archive(&wal_dir, 3).unwrap();
+
+        assert!(!fs.exists(&archive.dir().join("000000001.wal")));
+        assert!(fs.exists(&archive.dir().join("000000002.wal")));
+        assert!(fs.exists(&archive.dir().join("000000003.wal")));
+    }
+
+    #[test]
+    fn test_purge_by_age() {
+        let (fs, wal_dir) = setup();
+
+        let archive_dir = PathBuf::from("/db/archive");
+        let archive =
+            WalArchive::new(&fs, archive_dir.clone(), ArchiveRetention::default()).unwrap();
+        write_wal(&fs, &wal_dir, 1, 1, 1);
+        archive.archive(&wal_dir, 1).unwrap();
+
+        // Pretend the file was written two hours ago
+        let path = archive_dir.join("000000001.wal");
+        fs.set_modified(&path, SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+
+        let retention = ArchiveRetention {
+            max_age: Some(Duration::from_secs(3600)),
+            max_size: None,
+        };
+        let archive = WalArchive::new(&fs, archive_dir, retention).unwrap();
+        assert_eq!(archive.purge().unwrap(), 1);
+        assert!(!fs.exists(&path));
+    }
+
+    #[test]
+    fn test_restore_up_to_sequence() {
+        let (fs, wal_dir) = setup();
+
+        let archive = WalArchive::new(
+            &fs,
+            PathBuf::from("/db/archive"),
+            ArchiveRetention::default(),
+        )
+        .unwrap();
+
+        write_wal(&fs, &wal_dir, 1, 1, 5); // seq 1..=5, archived
+        archive.archive(&wal_dir, 1).unwrap();
+        write_wal(&fs, &wal_dir, 2, 6, 5); // seq 6..=10, still live
+
+        // Checkpoint covers seq < 3, restore up to seq 7
+        let mut memtables = default_memtables();
+        let progress = archive
+            .restore(
+                &wal_dir,
+                &[(DEFAULT_COLUMN_FAMILY_ID, 2)].into(),
+                RecoveryTarget::Seq(7),
+                &mut memtables,
+            )
+            .unwrap();
+
+        let seqs: Vec<u64> = memtables[&DEFAULT_COLUMN_FAMILY_ID]
+            .snapshot()
+            .iter()
+            .map(|e| e.seq())
+            .collect();
+        assert_eq!(seqs, vec![3, 4, 5, 6, 7]);
+        assert_eq!(progress.entries_replayed, 5);
+        assert_eq!(progress.max_seq, 7);
+    }
+
+    #[test]
+    fn test_restore_up_to_time() {
+        let (fs, wal_dir) = setup();
+
+        let archive = WalArchive::new(
+            &fs,
+            PathBuf::from("/db/archive"),
+            ArchiveRetention::default(),
+        )
+        .unwrap();
+
+        let append = |wal: &mut Wal, seq: u64| {
+            wal.append_normal(seq, Bytes::from(format!("k{:04}", seq)), Bytes::from("v"))
+                .unwrap();
+            wal.sync().unwrap();
+        };
+        let pause = || std::thread::sleep(Duration::from_millis(5));
+
+        write_wal(&fs, &wal_dir, 1, 1, 2);
+        archive.archive(&wal_dir, 1).unwrap();
+        // The target falls in the middle of the live file
+        let mut wal = Wal::create(&*fs, wal_dir.clone(), 2).unwrap();
+        append(&mut wal, 3);
+        pause();
+        let target = SystemTime::now();
+        pause();
+        append(&mut wal, 4);
+
+        let mut memtables = default_memtables();
+        let progress = archive
+            .restore(
+                &wal_dir,
+                &BTreeMap::new(),
+                RecoveryTarget::Time(target),
+                &mut memtables,
+            )
+            .unwrap();
+
+        let seqs: Vec<u64> = memtables[&DEFAULT_COLUMN_FAMILY_ID]
+            .snapshot()
+            .iter()
+            .map(|e| e.seq())
+            .collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(progress.max_seq, 3);
+    }
+
+    #[test]
+    fn test_restore_applies_every_column_family() {
+        let (fs, wal_dir) = setup();
+
+        let archive = WalArchive::new(
+            &fs,
+            PathBuf::from("/db/archive"),
+            ArchiveRetention::default(),
+        )
+        .unwrap();
+        let mut wal = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        wal.append_batch(&[
+            (0, Entry::new_normal(1, Bytes::from("a"), Bytes::from("1"))),
+            (3, Entry::new_normal(2, Bytes::from("b"), Bytes::from("2"))),
+        ])
+        .unwrap();
+        wal.sync().unwrap();
+        archive.archive(&wal_dir, 1).unwrap();
+
+        let mut memtables = default_memtables();
+        memtables.insert(3, MemTable::new());
+        archive
+            .restore(
+                &wal_dir,
+                &BTreeMap::new(),
+                RecoveryTarget::Latest,
+                &mut memtables,
+            )
+            .unwrap();
+        assert!(memtables[&0].get(&Bytes::from("a")).is_some());
+        assert!(memtables[&3].get(&Bytes::from("b")).is_some());
+
+        // Each column family resumes after its own flushed sequence number
+        let mut memtables = default_memtables();
+        memtables.insert(3, MemTable::new());
+        let progress = archive
+            .restore(
+                &wal_dir,
+                &[(0, 0), (3, 2)].into(),
+                RecoveryTarget::Latest,
+                &mut memtables,
+            )
+            .unwrap();
+        assert_eq!(progress.entries_replayed, 1);
+        assert!(memtables[&0].get(&Bytes::from("a")).is_some());
+        assert!(memtables[&3].get(&Bytes::from("b")).is_none());
+
+        let mut memtables = default_memtables();
+        assert!(matches!(
+            archive.restore(
+                &wal_dir,
+                &BTreeMap::new(),
+                RecoveryTarget::Latest,
+                &mut memtables
+            ),
+            Err(WalError::UnknownColumnFamily { cf_id: 3, seq: 2 })
+        ));
+        // Records past the target don't need a MemTable
+        archive
+            .restore(
+                &wal_dir,
+                &BTreeMap::new(),
+                RecoveryTarget::Seq(1),
+                &mut memtables,
+            )
+            .unwrap();
+    }
+}
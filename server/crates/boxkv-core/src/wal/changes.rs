@@ -0,0 +1,430 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tracing::{debug, info, warn};
+
+use super::reader::{ReadError, WalIterator, WalRecord};
+use super::{Wal, WalContext, WalError};
+
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, Entry};
+
+/// Change-data-capture stream over live and archived WAL files.
+///
+/// Yields every mutation with a sequence number greater than the requested one,
+/// along with its column family, in log order, starting from the archive (if
+/// any) and continuing into the live WAL directory. The stream follows the
+/// active file: when it is caught up, `next()` returns `None`, and calling it
+/// again later yields records appended in the meantime. It is therefore
+/// **not** a fused iterator.
+///
+/// Values moved by blob garbage collection (relocation records) are not
+/// mutations and are skipped. So are the markers of ingested SSTables: their
+/// records are not in the WAL, but their sequence numbers are accounted for.
+///
+/// Sequence numbers are assumed to increase across WAL files (a new file is
+/// only created after the previous one stopped receiving writes).
+pub struct ChangeStream {
+    fs: Arc<dyn FileSystem>,
+    live_dir: PathBuf,
+    archive_dir: Option<PathBuf>,
+    /// Only entries with `seq > since` are yielded.
+    since: u64,
+    /// Highest sequence number read so far.
+    last_seq: u64,
+    current: Option<Segment>,
+}
+
+/// WAL file currently being read by a `ChangeStream`.
+struct Segment {
+    file_id: u64,
+    path: PathBuf,
+    iter: WalIterator,
+    /// A newer file exists, so no more records will be appended to this one.
+    sealed: bool,
+}
+
+impl ChangeStream {
+    pub(super) fn open(
+        fs: &Arc<dyn FileSystem>,
+        live_dir: PathBuf,
+        archive_dir: Option<PathBuf>,
+        since: u64,
+    ) -> Result<Self, WalError> {
+        let mut stream = Self {
+            fs: fs.clone(),
+            live_dir,
+            archive_dir,
+            since,
+            last_seq: since,
+            current: None,
+        };
+
+        let files = stream.list_files()?;
+
+        // Start at the last file beginning at or before the requested position,
+        // earlier files only contain older records.
+        let mut start = None;
+        for (file_id, path) in &files {
+            let Some(first_seq) = stream.first_seq(path)? else {
+                continue;
+            };
+
+            if first_seq > since.saturating_add(1) {
+                if start.is_none() {
+                    return Err(WalError::SequencePurged {
+                        requested: since.saturating_add(1),
+                        oldest_available: first_seq,
+                    });
+                }
+                break;
+            }
+            start = Some(*file_id);
+        }
+
+        info!(since, start_file = ?start, "Opening WAL change stream");
+
+        if let Some(file_id) = start {
+            stream.open_segment(file_id)?;
+        }
+
+        Ok(stream)
+    }
+
+    /// Returns the highest sequence number read so far, skipped records
+    /// included.
+    ///
+    /// Subscribers persist this value to resume with `Wal::changes_since`.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Lists live and archived WAL files, sorted by file ID.
+    fn list_files(&self) -> Result<Vec<(u64, PathBuf)>, WalError> {
+        let mut files = Wal::list_files(&*self.fs, &self.live_dir)?;
+        if let Some(archive_dir) = &self.archive_dir {
+            for (file_id, path) in Wal::list_files(&*self.fs, archive_dir)? {
+                if !files.iter().any(|(id, _)| *id == file_id) {
+                    files.push((file_id, path));
+                }
+            }
+        }
+        files.sort_unstable_by_key(|&(id, _)| id);
+
+        Ok(files)
+    }
+
+    /// Reads the sequence number of the first record in a file.
+    fn first_seq(&self, path: &Path) -> Result<Option<u64>, WalError> {
+        let file = self.fs.open_sequential(path).with_context(path)?;
+        match WalIterator::new(file).next_record() {
+            Some(Ok(record)) => Ok(Some(record.seq())),
+            Some(Err(ReadError::Io(e))) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Some(Err(e)) => Err(WalError::Read {
+                path: path.to_path_buf(),
+                source: e,
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens a file by ID, looking in the live directory first, then the archive.
+    ///
+    /// A file can be archived between listing and opening, so both locations are
+    /// checked. If it is in neither, its records were purged.
+    fn open_segment(&mut self, file_id: u64) -> Result<(), WalError> {
+        let candidates =
+            std::iter::once(self.live_dir.as_path()).chain(self.archive_dir.as_deref());
+        for dir in candidates {
+            let path = Wal::file_path(dir, file_id);
+            match self.fs.open_sequential(&path) {
+                Ok(file) => {
+                    debug!(file_id, ?path, "Change stream switched to WAL file");
+                    self.current = Some(Segment {
+                        file_id,
+                        path,
+                        iter: WalIterator::new(file),
+                        sealed: false,
+                    });
+                    return Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(&path),
+            }
+        }
+
+        Err(WalError::SequencePurged {
+            requested: self.last_seq.saturating_add(1),
+            oldest_available: self.oldest_available()?,
+        })
+    }
+
+    /// Returns the first sequence number still available, or 0 if there is none.
+    fn oldest_available(&self) -> Result<u64, WalError> {
+        for (_, path) in self.list_files()? {
+            if let Some(seq) = self.first_seq(&path)? {
+                return Ok(seq);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Returns the ID of the oldest file newer than `file_id`, if any.
+    fn next_file_after(&self, file_id: u64) -> Result<Option<u64>, WalError> {
+        Ok(self
+            .list_files()?
+            .into_iter()
+            .map(|(id, _)| id)
+            .find(|&id| id > file_id))
+    }
+
+    fn next_change(&mut self) -> Result<Option<(ColumnFamilyId, Entry)>, WalError> {
+        loop {
+            if self.current.is_none() {
+                // Nothing was written yet when the stream was opened.
+                match self.list_files()?.first() {
+                    Some(&(file_id, _)) => self.open_segment(file_id)?,
+                    None => return Ok(None),
+                }
+            }
+
+            let segment = self.current.as_mut().unwrap();
+            match segment.iter.next_record() {
+                Some(Ok(record)) => {
+                    if record.seq() <= self.since {
+                        continue;
+                    }
+                    self.last_seq = self.last_seq.max(record.seq());
+                    match record {
+                        WalRecord::Write(cf_id, entry) => return Ok(Some((cf_id, entry))),
+                        WalRecord::Relocation(..) | WalRecord::Ingestion(..) => continue,
+                    }
+                }
+                Some(Err(ReadError::Io(e))) if e.kind() == ErrorKind::UnexpectedEof => {
+                    if segment.sealed {
+                        // The writer moved on, so this is a torn record from a crash.
+                        warn!(
+                            file_id = segment.file_id,
+                            path = ?segment.path,
+                            "WAL file truncated, skipping partial record"
+                        );
+                    } else {
+                        // Record still being appended, retry from its start later.
+                        segment.iter.rewind_partial().with_context(&segment.path)?;
+                        let file_id = segment.file_id;
+                        if self.next_file_after(file_id)?.is_none() {
+                            return Ok(None);
+                        }
+                        // The writer moved on, so the record will never complete
+                        // (e.g. the process crashed and restarted on a new file).
+                        // Read it once more in case it was finished before the
+                        // switch, and treat it as the end of the file otherwise.
+                        self.current.as_mut().unwrap().sealed = true;
+                        continue;
+                    }
+                }
+                Some(Err(e)) => {
+                    return Err(WalError::Read {
+                        path: segment.path.clone(),
+                        source: e,
+                    });
+                }
+                None => {}
+            }
+
+            let (file_id, sealed) = (segment.file_id, segment.sealed);
+            match self.next_file_after(file_id)? {
+                Some(next_id) if sealed => self.open_segment(next_id)?,
+                Some(_) => {
+                    // The writer switched files: drain whatever was appended to this
+                    // one before the switch, then move on.
+                    let segment = self.current.as_mut().unwrap();
+                    segment.sealed = true;
+                    segment.iter.rewind_partial().with_context(&segment.path)?;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Iterator for ChangeStream {
+    type Item = Result<(ColumnFamilyId, Entry), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_change().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{ArchiveRetention, WalArchive};
+    use boxkv_common::env::MemoryFileSystem;
+    use bytes::Bytes;
+    use std::io::Write;
+
+    fn mem_dir() -> (Arc<dyn FileSystem>, PathBuf) {
+        let fs: Arc<dyn FileSystem> = Arc::new(MemoryFileSystem::new());
+        let wal_dir = PathBuf::from("/db/wal");
+        fs.create_dir_all(&wal_dir).unwrap();
+        (fs, wal_dir)
+    }
+
+    fn append(wal: &mut Wal, seq: u64) {
+        wal.append_normal(seq, Bytes::from(format!("k{:04}", seq)), Bytes::from("v"))
+            .unwrap();
+        wal.sync().unwrap();
+    }
+
+    fn drain(stream: &mut ChangeStream) -> Vec<u64> {
+        stream.by_ref().map(|r| r.unwrap().1.seq()).collect()
+    }
+
+    #[test]
+    fn test_changes_since_reads_archive_then_live() {
+        let (fs, wal_dir) = mem_dir();
+        let archive = WalArchive::new(
+            &fs,
+            PathBuf::from("/db/archive"),
+            ArchiveRetention::default(),
+        )
+        .unwrap();
+
+        let mut wal1 = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        (1..=3).for_each(|seq| append(&mut wal1, seq));
+        drop(wal1);
+        archive.archive(&wal_dir, 1).unwrap();
+
+        let mut wal2 = Wal::create(&*fs, wal_dir.clone(), 2).unwrap();
+        (4..=5).for_each(|seq| append(&mut wal2, seq));
+
+        let mut stream = Wal::changes_since(&fs, wal_dir.clone(), Some(&archive), 2).unwrap();
+        assert_eq!(drain(&mut stream), vec![3, 4, 5]);
+        assert_eq!(stream.last_seq(), 5);
+
+        // Starting after everything in the archive skips it entirely
+        let mut stream = Wal::changes_since(&fs, wal_dir, Some(&archive), 4).unwrap();
+        assert_eq!(drain(&mut stream), vec![5]);
+    }
+
+    #[test]
+    fn test_changes_since_follows_active_file() {
+        let (fs, wal_dir) = mem_dir();
+
+        let mut wal1 = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        append(&mut wal1, 1);
+
+        let mut stream = Wal::changes_since(&fs, wal_dir.clone(), None, 0).unwrap();
+        assert_eq!(drain(&mut stream), vec![1]);
+        assert_eq!(drain(&mut stream), Vec::<u64>::new());
+
+        // New records in the same file
+        append(&mut wal1, 2);
+        assert_eq!(drain(&mut stream), vec![2]);
+
+        // Writer rolls over to a new file
+        append(&mut wal1, 3);
+        let mut wal2 = Wal::create(&*fs, wal_dir, 2).unwrap();
+        append(&mut wal2, 4);
+        assert_eq!(drain(&mut stream), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_changes_since_reports_column_families() {
+        let (fs, wal_dir) = mem_dir();
+
+        let mut wal = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        wal.append_batch(&[
+            (0, Entry::new_normal(1, Bytes::from("a"), Bytes::from("1"))),
+            (3, Entry::new_normal(2, Bytes::from("a"), Bytes::from("2"))),
+        ])
+        .unwrap();
+        wal.append(5, &Entry::new_tombstone(3, Bytes::from("a")))
+            .unwrap();
+        wal.sync().unwrap();
+
+        let stream = Wal::changes_since(&fs, wal_dir, None, 0).unwrap();
+        let changes: Vec<(ColumnFamilyId, u64)> = stream
+            .map(|r| r.map(|(cf_id, entry)| (cf_id, entry.seq())).unwrap())
+            .collect();
+        assert_eq!(changes, vec![(0, 1), (3, 2), (5, 3)]);
+    }
+
+    #[test]
+    fn test_changes_since_waits_for_partial_record() {
+        let (fs, wal_dir) = mem_dir();
+
+        // Build two records of the same size to get their bytes
+        let mut wal = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        append(&mut wal, 1);
+        append(&mut wal, 2);
+        drop(wal);
+        let path = wal_dir.join("000000001.wal");
+        let records = fs.read(&path).unwrap();
+        let partial = records.len() / 2 + 10;
+
+        let mut file = fs.create(&path).unwrap();
+        file.write_all(&records[..partial]).unwrap();
+
+        let mut stream = Wal::changes_since(&fs, wal_dir, None, 0).unwrap();
+        assert_eq!(drain(&mut stream), vec![1]);
+
+        file.write_all(&records[partial..]).unwrap();
+        assert_eq!(drain(&mut stream), vec![2]);
+    }
+
+    #[test]
+    fn test_changes_since_skips_torn_tail_of_older_file() {
+        let (fs, wal_dir) = mem_dir();
+
+        let mut wal = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        append(&mut wal, 1);
+        append(&mut wal, 2);
+        drop(wal);
+        let path = wal_dir.join("000000001.wal");
+        let records = fs.read(&path).unwrap();
+
+        // The process crashed in the middle of record 2
+        let mut file = fs.create(&path).unwrap();
+        file.write_all(&records[..records.len() - 5]).unwrap();
+        drop(file);
+
+        let mut stream = Wal::changes_since(&fs, wal_dir.clone(), None, 0).unwrap();
+        assert_eq!(drain(&mut stream), vec![1]);
+
+        // After a restart the writer continues in a new file
+        let mut wal2 = Wal::create(&*fs, wal_dir, 2).unwrap();
+        append(&mut wal2, 2);
+        append(&mut wal2, 3);
+        assert_eq!(drain(&mut stream), vec![2, 3]);
+        assert_eq!(stream.last_seq(), 3);
+    }
+
+    #[test]
+    fn test_changes_since_purged_sequence() {
+        let (fs, wal_dir) = mem_dir();
+
+        let mut wal1 = Wal::create(&*fs, wal_dir.clone(), 1).unwrap();
+        (1..=3).for_each(|seq| append(&mut wal1, seq));
+        let mut wal2 = Wal::create(&*fs, wal_dir.clone(), 2).unwrap();
+        (4..=5).for_each(|seq| append(&mut wal2, seq));
+        Wal::delete(&*fs, wal_dir.clone(), 1).unwrap();
+
+        match Wal::changes_since(&fs, wal_dir.clone(), None, 1) {
+            Err(WalError::SequencePurged {
+                requested,
+                oldest_available,
+            }) => {
+                assert_eq!(requested, 2);
+                assert_eq!(oldest_available, 4);
+            }
+            other => panic!("Expected SequencePurged, got {:?}", other.map(|_| ())),
+        }
+
+        // Resuming right before the oldest available record is fine
+        let mut stream = Wal::changes_since(&fs, wal_dir, None, 3).unwrap();
+        assert_eq!(drain(&mut stream), vec![4, 5]);
+    }
+}
@@ -1,14 +1,21 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use thiserror::Error;
 use tracing::warn;
 
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use super::{
-    Bytes, WAL_CRC_SIZE, WAL_EXPIRE_LEN_SIZE, WAL_HEADER_SIZE, WAL_KEY_LEN_SIZE,
-    WAL_PAYLOAD_LEN_SIZE, WAL_TYPE_SIZE,
+    Bytes, WAL_BATCH_TYPE, WAL_CF_ID_SIZE, WAL_CRC_SIZE, WAL_EXPIRE_LEN_SIZE, WAL_FILE_HEADER_SIZE,
+    WAL_FORMAT_VERSION, WAL_HEADER_SIZE, WAL_INGESTION_TYPE, WAL_KEY_LEN_SIZE, WAL_MAGIC,
+    WAL_PAYLOAD_LEN_SIZE, WAL_RELOCATION_TYPE, WAL_SEQ_SIZE, WAL_TYPE_SIZE,
 };
 
-use boxkv_common::types::{EXPIRING_VALUE_TYPE, Entry, NORMAL_VALUE_TYPE, TOMBSTONE_VALUE_TYPE};
+use boxkv_common::env::SequentialFile;
+use boxkv_common::types::{
+    BLOB_INDEX_VALUE_TYPE, BlobIndex, ColumnFamilyId, EXPIRING_VALUE_TYPE, Entry, MERGE_VALUE_TYPE,
+    NORMAL_VALUE_TYPE, RANGE_TOMBSTONE_VALUE_TYPE, TOMBSTONE_VALUE_TYPE,
+};
 
 // Safety limits to prevent OOM attacks from corrupted/malicious WAL files.
 // Adjust these values based on your system requirements.
@@ -27,10 +34,27 @@ pub enum ReadError {
         actual: u32,   // The actual CRC value calculated from the payload.
     },
 
+    /// The file doesn't start with the WAL magic number, e.g. it was written
+    /// by a version without file headers.
+    #[error("Not a WAL file or written by an unsupported older version (magic {0:016x})")]
+    BadMagic(u64),
+
+    /// The file header names a format version this build cannot read.
+    #[error("Unsupported WAL format version {found} (expected {expected})")]
+    UnsupportedVersion { found: u32, expected: u32 },
+
     /// Encountered an unknown or invalid record type byte.
     #[error("Invalid record type: {0}")]
     InvalidRecordType(u8),
 
+    /// A blob index record has a value section of the wrong length.
+    #[error("Invalid blob index at seq {seq}: {len} bytes")]
+    InvalidBlobIndex { seq: u64, len: usize },
+
+    /// A batch record passed its CRC check but its entries don't decode.
+    #[error("Malformed batch record at seq {seq}: {reason}")]
+    MalformedBatch { seq: u64, reason: String },
+
     /// The key or value size exceeds the allowed limit.
     #[error(
         "Payload too large: key_len={key_len}, val_len={val_len} (max_key={max_key}, max_val={max_val})"
@@ -43,36 +67,126 @@ pub enum ReadError {
     },
 }
 
+/// A record of a WAL file, as decoded by `WalIterator::next_record()`.
+///
+/// Batch and relocation records are returned one entry at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+    /// A write to a column family.
+    Write(ColumnFamilyId, Entry),
+    /// A value moved to another blob file by blob garbage collection. It is
+    /// replayed like a write, but the value of the key didn't change.
+    Relocation(ColumnFamilyId, Entry),
+    /// An external SSTable ingested into a column family, which got the
+    /// sequence number. Its records are in the manifest, not in the WAL.
+    Ingestion(ColumnFamilyId, u64),
+}
+
+impl WalRecord {
+    /// Returns the sequence number of the record.
+    pub fn seq(&self) -> u64 {
+        match self {
+            WalRecord::Write(_, entry) | WalRecord::Relocation(_, entry) => entry.seq(),
+            WalRecord::Ingestion(_, seq) => *seq,
+        }
+    }
+
+    /// Returns the column family and entry of the record, or `None` for an
+    /// ingestion, which has no entry.
+    pub fn into_entry(self) -> Option<(ColumnFamilyId, Entry)> {
+        match self {
+            WalRecord::Write(cf_id, entry) | WalRecord::Relocation(cf_id, entry) => {
+                Some((cf_id, entry))
+            }
+            WalRecord::Ingestion(..) => None,
+        }
+    }
+}
+
 /// Iterator over `Entry` records in a WAL file.
 ///
 /// Reads and deserializes entries sequentially from the WAL binary format.
 /// Uses `BufReader` for efficient I/O.
 pub struct WalIterator {
-    reader: BufReader<File>,
+    reader: BufReader<Box<dyn SequentialFile>>,
+    /// Number of bytes consumed by fully decoded records.
+    bytes_read: u64,
+    /// Entries of the last batch or relocation record not yielded yet.
+    pending: VecDeque<WalRecord>,
+    /// Timestamp (milliseconds since the Unix epoch) of the last decoded record.
+    timestamp: u64,
+    /// Whether the file header was read and checked.
+    header_checked: bool,
 }
 
 impl WalIterator {
     /// Creates a new iterator from an open file handle.
-    pub fn new(file: File) -> Self {
+    pub fn new(file: Box<dyn SequentialFile>) -> Self {
         Self {
             reader: BufReader::new(file),
+            bytes_read: 0,
+            pending: VecDeque::new(),
+            timestamp: 0,
+            header_checked: false,
         }
     }
+
+    /// Returns when the entry last yielded was logged.
+    ///
+    /// Entries of a batch share the time of their record.
+    pub fn logged_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp)
+    }
+
+    /// Returns the number of bytes consumed by successfully decoded records.
+    ///
+    /// Partial records at the end of a truncated file are not counted.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Seeks back to the end of the last fully decoded record.
+    ///
+    /// Used when tailing a file that is still being written: a partially
+    /// appended record is discarded and re-read once the writer completes it.
+    pub fn rewind_partial(&mut self) -> Result<(), ReadError> {
+        self.reader.seek(SeekFrom::Start(self.bytes_read))?;
+        Ok(())
+    }
 }
 
 impl WalIterator {
+    /// Returns the next record.
+    ///
+    /// The `Iterator` implementation yields the entries of the same records,
+    /// without their column family, and skips ingestions.
+    pub fn next_record(&mut self) -> Option<Result<WalRecord, ReadError>> {
+        if let Some(record) = self.pending.pop_front() {
+            return Some(Ok(record));
+        }
+        self.read_next_entry().transpose()
+    }
+
     /// Reads and deserializes the next entry from the WAL.
     ///
     /// # Returns
     /// - `Ok(None)`: Clean EOF reached (no more records)
-    /// - `Ok(Some(Entry))`: Successfully read and validated entry
+    /// - `Ok(Some(WalRecord))`: Successfully read and validated entry
     /// - `Err(ReadError)`: Corruption, I/O error, or validation failure
     ///
     /// # Error Handling
     /// - Partial reads at EOF are treated as truncation (expected during crash)
     /// - CRC mismatches indicate data corruption
     /// - Oversized keys/values are rejected to prevent OOM attacks
-    fn read_next_entry(&mut self) -> Result<Option<Entry>, ReadError> {
+    fn read_next_entry(&mut self) -> Result<Option<WalRecord>, ReadError> {
+        if !self.header_checked {
+            if !self.read_file_header()? {
+                return Ok(None);
+            }
+            self.header_checked = true;
+            self.bytes_read = WAL_FILE_HEADER_SIZE as u64;
+        }
+
         // 1. Read Header
         let mut header_buf = [0u8; WAL_HEADER_SIZE];
         // Attempt to read the fixed-size header.
@@ -92,8 +206,20 @@ impl WalIterator {
                 .unwrap(),
         );
         let val_type_u8 = header_buf[WAL_CRC_SIZE + WAL_PAYLOAD_LEN_SIZE];
+        let seq_offset = WAL_CRC_SIZE + WAL_PAYLOAD_LEN_SIZE + WAL_TYPE_SIZE;
         let seq = u64::from_be_bytes(
-            header_buf[WAL_CRC_SIZE + WAL_PAYLOAD_LEN_SIZE + WAL_TYPE_SIZE..]
+            header_buf[seq_offset..seq_offset + WAL_SEQ_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+        let cf_id_offset = seq_offset + WAL_SEQ_SIZE;
+        let cf_id = ColumnFamilyId::from_be_bytes(
+            header_buf[cf_id_offset..cf_id_offset + WAL_CF_ID_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+        let timestamp = u64::from_be_bytes(
+            header_buf[cf_id_offset + WAL_CF_ID_SIZE..]
                 .try_into()
                 .unwrap(),
         );
@@ -137,6 +263,8 @@ impl WalIterator {
         hasher.update(&payload_len.to_be_bytes());
         hasher.update(&[val_type_u8]);
         hasher.update(&seq.to_be_bytes());
+        hasher.update(&cf_id.to_be_bytes());
+        hasher.update(&timestamp.to_be_bytes());
         hasher.update(&key_len.to_be_bytes());
         hasher.update(&key_buf);
         hasher.update(&val_buf);
@@ -155,21 +283,148 @@ impl WalIterator {
             });
         }
 
+        self.bytes_read += WAL_HEADER_SIZE as u64 + payload_len;
+        self.timestamp = timestamp;
+
         let key = Bytes::from(key_buf);
-        match val_type_u8 {
-            NORMAL_VALUE_TYPE => {
-                let data = Bytes::from(val_buf);
-                Ok(Some(Entry::new_normal(seq, key, data)))
-            }
-            TOMBSTONE_VALUE_TYPE => Ok(Some(Entry::new_tombstone(seq, key))),
+        if val_type_u8 == WAL_BATCH_TYPE || val_type_u8 == WAL_RELOCATION_TYPE {
+            let record = match val_type_u8 {
+                WAL_BATCH_TYPE => WalRecord::Write,
+                _ => WalRecord::Relocation,
+            };
+            let entries = Self::decode_batch(seq, Bytes::from(val_buf))?;
+            self.pending = entries
+                .into_iter()
+                .map(|(cf_id, entry)| record(cf_id, entry))
+                .collect();
+            return Ok(self.pending.pop_front());
+        }
+        if val_type_u8 == WAL_INGESTION_TYPE {
+            return Ok(Some(WalRecord::Ingestion(cf_id, seq)));
+        }
+        let entry = Self::decode_entry(val_type_u8, seq, key, Bytes::from(val_buf))?;
+        Ok(Some(WalRecord::Write(cf_id, entry)))
+    }
+
+    /// Reads and checks the file header.
+    ///
+    /// Returns `Ok(false)` for an empty file. A partial header is reported as
+    /// `UnexpectedEof`, like a partial record.
+    fn read_file_header(&mut self) -> Result<bool, ReadError> {
+        let mut header_buf = [0u8; WAL_FILE_HEADER_SIZE];
+        match self.reader.read(&mut header_buf)? {
+            0 => return Ok(false),
+            WAL_FILE_HEADER_SIZE => (),
+            n => self.reader.read_exact(&mut header_buf[n..])?,
+        }
+
+        let magic = u64::from_be_bytes(header_buf[..8].try_into().unwrap());
+        if magic != WAL_MAGIC {
+            return Err(ReadError::BadMagic(magic));
+        }
+        let version = u32::from_be_bytes(header_buf[8..].try_into().unwrap());
+        if version != WAL_FORMAT_VERSION {
+            return Err(ReadError::UnsupportedVersion {
+                found: version,
+                expected: WAL_FORMAT_VERSION,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Builds an entry from its ValueTag and Value Section.
+    fn decode_entry(tag: u8, seq: u64, key: Bytes, val: Bytes) -> Result<Entry, ReadError> {
+        match tag {
+            NORMAL_VALUE_TYPE => Ok(Entry::new_normal(seq, key, val)),
+            TOMBSTONE_VALUE_TYPE => Ok(Entry::new_tombstone(seq, key)),
             EXPIRING_VALUE_TYPE => {
-                let expire_at =
-                    u64::from_be_bytes(val_buf[..WAL_EXPIRE_LEN_SIZE].try_into().unwrap());
-                let data = Bytes::from(val_buf).slice(WAL_EXPIRE_LEN_SIZE..);
-                Ok(Some(Entry::new_expiring(seq, key, data, expire_at)))
+                let expire_at = u64::from_be_bytes(val[..WAL_EXPIRE_LEN_SIZE].try_into().unwrap());
+                let data = val.slice(WAL_EXPIRE_LEN_SIZE..);
+                Ok(Entry::new_expiring(seq, key, data, expire_at))
+            }
+            MERGE_VALUE_TYPE => Ok(Entry::new_merge(seq, key, val)),
+            RANGE_TOMBSTONE_VALUE_TYPE => Ok(Entry::new_range_tombstone(seq, key, val)),
+            BLOB_INDEX_VALUE_TYPE => BlobIndex::decode(&val)
+                .map(|index| Entry::new_blob_index(seq, key, index))
+                .ok_or(ReadError::InvalidBlobIndex {
+                    seq,
+                    len: val.len(),
+                }),
+            _ => Err(ReadError::InvalidRecordType(tag)),
+        }
+    }
+
+    /// Decodes the entries of a batch record whose first entry has `first_seq`.
+    fn decode_batch(
+        first_seq: u64,
+        body: Bytes,
+    ) -> Result<VecDeque<(ColumnFamilyId, Entry)>, ReadError> {
+        let mut cursor = BatchCursor {
+            body,
+            offset: 0,
+            first_seq,
+        };
+
+        let count = cursor.read_u32()?;
+        if count == 0 {
+            return Err(cursor.malformed("empty batch"));
+        }
+
+        let mut entries = VecDeque::with_capacity(count as usize);
+        for seq in (first_seq..).take(count as usize) {
+            let cf_id = cursor.read_u32()?;
+            let tag = cursor.read(WAL_TYPE_SIZE as u64)?[0];
+            let key_len = cursor.read_u64()?;
+            let key = cursor.read(key_len)?;
+            let val_len = cursor.read_u64()?;
+            let val = cursor.read(val_len)?;
+            if tag == EXPIRING_VALUE_TYPE && val.len() < WAL_EXPIRE_LEN_SIZE {
+                return Err(cursor.malformed("expiring value shorter than its timestamp"));
             }
-            _ => Err(ReadError::InvalidRecordType(val_type_u8)),
+            entries.push_back((cf_id, Self::decode_entry(tag, seq, key, val)?));
+        }
+
+        if cursor.offset != cursor.body.len() {
+            return Err(cursor.malformed("trailing bytes"));
         }
+        Ok(entries)
+    }
+}
+
+/// Reads the fields of a batch record body.
+struct BatchCursor {
+    body: Bytes,
+    offset: usize,
+    first_seq: u64,
+}
+
+impl BatchCursor {
+    fn malformed(&self, reason: &str) -> ReadError {
+        ReadError::MalformedBatch {
+            seq: self.first_seq,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn read(&mut self, len: u64) -> Result<Bytes, ReadError> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .filter(|&end| end <= self.body.len())
+            .ok_or_else(|| self.malformed("truncated entry"))?;
+        let bytes = self.body.slice(self.offset..end);
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ReadError> {
+        let bytes = self.read(size_of::<u32>() as u64)?;
+        Ok(u32::from_be_bytes(bytes[..].try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, ReadError> {
+        let bytes = self.read(WAL_KEY_LEN_SIZE as u64)?;
+        Ok(u64::from_be_bytes(bytes[..].try_into().unwrap()))
     }
 }
 
@@ -177,10 +432,14 @@ impl Iterator for WalIterator {
     type Item = Result<Entry, ReadError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.read_next_entry() {
-            Ok(Some(entry)) => Some(Ok(entry)),
-            Ok(None) => None,
-            Err(e) => Some(Err(e)),
+        loop {
+            match self.next_record()? {
+                Ok(record) => match record.into_entry() {
+                    Some((_, entry)) => return Some(Ok(entry)),
+                    None => continue,
+                },
+                Err(e) => return Some(Err(e)),
+            }
         }
     }
 }
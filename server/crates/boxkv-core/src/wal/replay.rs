@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info, warn};
+
+use super::reader::{ReadError, WalIterator};
+use super::{WalContext, WalError};
+
+use boxkv_common::env::FileSystem;
+use boxkv_common::types::{ColumnFamilyId, Entry};
+
+/// Number of replayed bytes between two progress log lines.
+const REPLAY_PROGRESS_INTERVAL: u64 = 64 * 1024 * 1024; // 64MB
+
+/// Progress counters of an ongoing WAL replay.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayProgress {
+    /// Number of WAL files selected for replay.
+    pub files_total: usize,
+    /// Number of WAL files fully replayed.
+    pub files_done: usize,
+    /// Combined size of all WAL files selected for replay.
+    pub bytes_total: u64,
+    /// Bytes consumed so far (completed files plus the current file).
+    pub bytes_replayed: u64,
+    /// Number of entries yielded (entries below `min_seq` are not counted).
+    pub entries_replayed: u64,
+    /// Highest sequence number yielded so far.
+    pub max_seq: u64,
+}
+
+/// Streaming iterator over the entries of all WAL files in a directory, along
+/// with the column family each one belongs to.
+///
+/// Files are visited in file ID order and records are yielded in the order they
+/// were appended, so at most one file handle and one record are held in memory at
+/// a time. Entries with `seq < min_seq` are skipped.
+///
+/// Records are **not** re-sorted by sequence number. Consumers that need the
+/// latest version per key must resolve by `seq` (see `MemTable::apply`), which
+/// gives the same result as applying entries in strict sequence order.
+///
+/// A truncated record at the end of a file is logged and the iterator moves on
+/// to the next file, matching `Wal::read_all_entries`.
+pub struct WalReplay {
+    fs: Arc<dyn FileSystem>,
+    pending: VecDeque<(u64, PathBuf)>,
+    current: Option<(u64, PathBuf, WalIterator)>,
+    min_seq: u64,
+    /// Bytes consumed by files that have already been fully replayed.
+    bytes_done: u64,
+    next_report: u64,
+    progress: ReplayProgress,
+    /// When the entry last yielded was logged.
+    logged_at: SystemTime,
+}
+
+impl WalReplay {
+    pub(super) fn new(
+        fs: &Arc<dyn FileSystem>,
+        files: Vec<(u64, PathBuf)>,
+        min_seq: u64,
+    ) -> Result<Self, WalError> {
+        let mut bytes_total = 0;
+        for (_, path) in &files {
+            bytes_total += fs.file_size(path).with_context(path)?;
+        }
+
+        Ok(Self {
+            fs: fs.clone(),
+            progress: ReplayProgress {
+                files_total: files.len(),
+                bytes_total,
+                ..Default::default()
+            },
+            pending: files.into(),
+            current: None,
+            min_seq,
+            bytes_done: 0,
+            next_report: REPLAY_PROGRESS_INTERVAL,
+            logged_at: UNIX_EPOCH,
+        })
+    }
+
+    /// Returns the progress counters as of the last yielded entry.
+    pub fn progress(&self) -> &ReplayProgress {
+        &self.progress
+    }
+
+    /// Returns when the entry last yielded was logged.
+    ///
+    /// Used by point-in-time recovery to stop at a wall-clock time.
+    pub fn logged_at(&self) -> SystemTime {
+        self.logged_at
+    }
+
+    /// Closes the current file and accounts it as fully replayed.
+    fn finish_current(&mut self) {
+        if let Some((file_id, path, iter)) = self.current.take() {
+            self.bytes_done += iter.bytes_read();
+            self.progress.files_done += 1;
+            self.progress.bytes_replayed = self.bytes_done;
+
+            debug!(file_id, ?path, "Completed replaying WAL file");
+        }
+    }
+
+    /// Emits a progress log line every `REPLAY_PROGRESS_INTERVAL` bytes.
+    fn maybe_report(&mut self) {
+        if self.progress.bytes_replayed < self.next_report {
+            return;
+        }
+        self.next_report = self.progress.bytes_replayed + REPLAY_PROGRESS_INTERVAL;
+
+        info!(
+            files_done = self.progress.files_done,
+            files_total = self.progress.files_total,
+            bytes_replayed = self.progress.bytes_replayed,
+            bytes_total = self.progress.bytes_total,
+            entries = self.progress.entries_replayed,
+            "WAL replay in progress"
+        );
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(ColumnFamilyId, Entry)>, WalError> {
+        loop {
+            if self.current.is_none() {
+                let Some((file_id, path)) = self.pending.pop_front() else {
+                    return Ok(None);
+                };
+                let file = self.fs.open_sequential(&path).with_context(&path)?;
+                self.current = Some((file_id, path, WalIterator::new(file)));
+            }
+
+            let (file_id, path, iter) = self.current.as_mut().unwrap();
+            match iter.next_record() {
+                Some(Ok(record)) => {
+                    self.progress.bytes_replayed = self.bytes_done + iter.bytes_read();
+                    let record_seq = record.seq();
+                    if record_seq < self.min_seq {
+                        continue;
+                    }
+                    let Some((cf_id, entry)) = record.into_entry() else {
+                        // Ingested files are in the manifest, only their
+                        // sequence numbers matter here.
+                        self.progress.max_seq = self.progress.max_seq.max(record_seq);
+                        continue;
+                    };
+
+                    self.progress.entries_replayed += 1;
+                    self.progress.max_seq = self.progress.max_seq.max(entry.seq());
+                    self.logged_at = iter.logged_at();
+                    self.maybe_report();
+                    return Ok(Some((cf_id, entry)));
+                }
+                Some(Err(ReadError::Io(e))) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    // Partial last record, expected after a crash mid-write.
+                    warn!(
+                        file_id = *file_id,
+                        ?path,
+                        "WAL file truncated, skipping partial record"
+                    );
+                    self.finish_current();
+                }
+                Some(Err(e)) => {
+                    let path = path.clone();
+                    // Corruption is fatal: stop the replay instead of guessing where
+                    // the next valid record starts.
+                    self.current = None;
+                    self.pending.clear();
+                    return Err(WalError::Read { path, source: e });
+                }
+                None => self.finish_current(),
+            }
+        }
+    }
+}
+
+impl Iterator for WalReplay {
+    type Item = Result<(ColumnFamilyId, Entry), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
@@ -1,12 +1,16 @@
-use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use thiserror::Error;
 use tracing::debug;
 
-use super::WAL_KEY_LEN_SIZE;
-use boxkv_common::types::{Entry, ValueType};
+use super::{
+    WAL_BATCH_COUNT_SIZE, WAL_BATCH_TYPE, WAL_FORMAT_VERSION, WAL_INGESTION_TYPE, WAL_KEY_LEN_SIZE,
+    WAL_MAGIC, WAL_RELOCATION_TYPE,
+};
+use boxkv_common::env::{FileSystem, WritableFile};
+use boxkv_common::types::{ColumnFamilyId, DEFAULT_COLUMN_FAMILY_ID, Entry, ValueType};
 
 #[derive(Debug, Error)]
 pub enum WriteError {
@@ -19,18 +23,21 @@ pub enum WriteError {
 /// Handles serialization of `Entry` records into the WAL binary format.
 /// Uses `BufWriter` to batch writes and reduce system call overhead.
 pub struct WalWriter {
-    writer: BufWriter<File>,
+    writer: BufWriter<Box<dyn WritableFile>>,
 }
 
 impl WalWriter {
-    /// Creates a new `WalWriter` for the specified file path.
+    /// Creates a new `WalWriter` for the specified file path on `fs`.
     ///
-    /// The file is created if it doesn't exist, or truncated if it does.
-    pub fn new(path: PathBuf) -> Result<Self, WriteError> {
+    /// The file is created if it doesn't exist, or truncated if it does, and
+    /// starts with the file header (written out by the first `sync()`).
+    pub fn new(fs: &dyn FileSystem, path: &Path) -> Result<Self, WriteError> {
         debug!(?path, "Creating WalWriter");
 
-        let file = File::create(path)?;
-        let writer = BufWriter::new(file);
+        let file = fs.create(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&WAL_MAGIC.to_be_bytes())?;
+        writer.write_all(&WAL_FORMAT_VERSION.to_be_bytes())?;
 
         Ok(Self { writer })
     }
@@ -39,7 +46,7 @@ impl WalWriter {
     ///
     /// # Format
     /// Writes in the following order:
-    /// 1. Header: CRC | PayloadLen | ValueTag | Seq
+    /// 1. Header: CRC | PayloadLen | ValueTag | Seq | CfId | Timestamp
     /// 2. Payload: KeyLen | Key | Value Section
     ///
     /// The Value Section format depends on the ValueType (see module-level docs).
@@ -47,21 +54,24 @@ impl WalWriter {
     /// # Durability
     /// This writes to the internal buffer only. Call `sync()` to ensure data
     /// reaches physical disk.
-    pub fn append(&mut self, entry: &Entry) -> Result<(), WriteError> {
+    pub fn append(&mut self, cf_id: ColumnFamilyId, entry: &Entry) -> Result<(), WriteError> {
         let val_type = entry.val().type_tag();
         let key_len = entry.key().len() as u64;
         let val_len = entry.val().serialized_len() as u64;
         let seq = entry.seq();
+        let timestamp = now_millis();
 
         // Calculate the payload length: Key Length + Value Length + Key Data + Value Data
         let payload_len = WAL_KEY_LEN_SIZE as u64 + key_len + val_len;
 
         // 1. Calculate CRC Checksum
-        // The CRC covers: Payload Length, Type, Sequence Number, Key Length, Value Length, Key, and Value.
+        // The CRC covers: Payload Length, Type, Sequence Number, Column Family, Timestamp, Key Length, Key, and Value.
         let mut hasher = crc32fast::Hasher::new();
         hasher.update(&payload_len.to_be_bytes());
         hasher.update(&[val_type]);
         hasher.update(&seq.to_be_bytes());
+        hasher.update(&cf_id.to_be_bytes());
+        hasher.update(&timestamp.to_be_bytes());
         hasher.update(&key_len.to_be_bytes());
         hasher.update(entry.key());
 
@@ -75,6 +85,15 @@ impl WalWriter {
                 hasher.update(&expire_at.to_be_bytes());
                 hasher.update(data);
             }
+            ValueType::Merge(operand) => {
+                hasher.update(operand);
+            }
+            ValueType::RangeTombstone { end } => {
+                hasher.update(end);
+            }
+            ValueType::BlobIndex(index) => {
+                hasher.update(&index.encode());
+            }
         }
 
         let crc = hasher.finalize();
@@ -88,6 +107,10 @@ impl WalWriter {
         self.writer.write_all(&[val_type])?;
         // [Seq: 8 bytes]
         self.writer.write_all(&seq.to_be_bytes())?;
+        // [Column Family: 4 bytes]
+        self.writer.write_all(&cf_id.to_be_bytes())?;
+        // [Timestamp: 8 bytes]
+        self.writer.write_all(&timestamp.to_be_bytes())?;
         // [Key Length: 8 bytes]
         self.writer.write_all(&key_len.to_be_bytes())?;
 
@@ -102,20 +125,145 @@ impl WalWriter {
                 self.writer.write_all(&expire_at.to_be_bytes())?;
                 self.writer.write_all(data)?;
             }
+            ValueType::Merge(operand) => {
+                self.writer.write_all(operand)?;
+            }
+            ValueType::RangeTombstone { end } => {
+                self.writer.write_all(end)?;
+            }
+            ValueType::BlobIndex(index) => {
+                self.writer.write_all(&index.encode())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes a group of entries as a single record, so that recovery sees
+    /// all of them or none.
+    ///
+    /// The entries must have consecutive sequence numbers; the header carries
+    /// the first one. See the module-level docs for the layout.
+    pub fn append_batch(&mut self, entries: &[(ColumnFamilyId, Entry)]) -> Result<(), WriteError> {
+        self.append_group(WAL_BATCH_TYPE, entries)
+    }
+
+    /// Serializes values moved by blob garbage collection as a single
+    /// record, laid out like a batch.
+    pub fn append_relocations(
+        &mut self,
+        entries: &[(ColumnFamilyId, Entry)],
+    ) -> Result<(), WriteError> {
+        self.append_group(WAL_RELOCATION_TYPE, entries)
+    }
+
+    /// Writes `entries` as one record of type `record_type` with the batch
+    /// layout.
+    fn append_group(
+        &mut self,
+        record_type: u8,
+        entries: &[(ColumnFamilyId, Entry)],
+    ) -> Result<(), WriteError> {
+        let Some((_, first)) = entries.first() else {
+            return Ok(());
+        };
+        debug_assert!(
+            entries.windows(2).all(|w| w[1].1.seq() == w[0].1.seq() + 1),
+            "Batch sequence numbers must be consecutive"
+        );
+
+        // Batch records have no key or column family of their own
+        let cf_id = DEFAULT_COLUMN_FAMILY_ID;
+        let mut body = Vec::with_capacity(
+            WAL_BATCH_COUNT_SIZE
+                + entries
+                    .iter()
+                    .map(|(_, e)| e.estimated_size())
+                    .sum::<usize>(),
+        );
+        body.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (entry_cf_id, entry) in entries {
+            body.extend_from_slice(&entry_cf_id.to_be_bytes());
+            body.push(entry.val().type_tag());
+            body.extend_from_slice(&(entry.key().len() as u64).to_be_bytes());
+            body.extend_from_slice(entry.key());
+            body.extend_from_slice(&(entry.val().serialized_len() as u64).to_be_bytes());
+            Self::encode_value(entry.val(), &mut body);
         }
+        self.write_record(record_type, first.seq(), cf_id, &body)
+    }
+
+    /// Serializes the marker of an SSTable ingested into `cf_id` at `seq`: a
+    /// record with no key and an empty Value Section.
+    pub fn append_ingestion(&mut self, cf_id: ColumnFamilyId, seq: u64) -> Result<(), WriteError> {
+        self.write_record(WAL_INGESTION_TYPE, seq, cf_id, &[])
+    }
+
+    /// Writes a record with no key, whose Value Section is `body`.
+    fn write_record(
+        &mut self,
+        record_type: u8,
+        seq: u64,
+        cf_id: ColumnFamilyId,
+        body: &[u8],
+    ) -> Result<(), WriteError> {
+        let key_len = 0u64;
+        let payload_len = (WAL_KEY_LEN_SIZE + body.len()) as u64;
+        let timestamp = now_millis();
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&payload_len.to_be_bytes());
+        hasher.update(&[record_type]);
+        hasher.update(&seq.to_be_bytes());
+        hasher.update(&cf_id.to_be_bytes());
+        hasher.update(&timestamp.to_be_bytes());
+        hasher.update(&key_len.to_be_bytes());
+        hasher.update(body);
+        let crc = hasher.finalize();
+
+        self.writer.write_all(&crc.to_be_bytes())?;
+        self.writer.write_all(&payload_len.to_be_bytes())?;
+        self.writer.write_all(&[record_type])?;
+        self.writer.write_all(&seq.to_be_bytes())?;
+        self.writer.write_all(&cf_id.to_be_bytes())?;
+        self.writer.write_all(&timestamp.to_be_bytes())?;
+        self.writer.write_all(&key_len.to_be_bytes())?;
+        self.writer.write_all(body)?;
 
         Ok(())
     }
 
+    /// Appends the Value Section of `value` to `buf`.
+    fn encode_value(value: &ValueType, buf: &mut Vec<u8>) {
+        match value {
+            ValueType::Normal(data)
+            | ValueType::Merge(data)
+            | ValueType::RangeTombstone { end: data } => buf.extend_from_slice(data),
+            ValueType::Tombstone => {}
+            ValueType::Expiring { data, expire_at } => {
+                buf.extend_from_slice(&expire_at.to_be_bytes());
+                buf.extend_from_slice(data);
+            }
+            ValueType::BlobIndex(index) => buf.extend_from_slice(&index.encode()),
+        }
+    }
+
     /// Flushes all buffered writes to disk (fsync).
     ///
     /// This ensures crash recovery can see all data written before this call.
     /// Performs:
     /// 1. `flush()` - Flushes BufWriter to OS page cache
-    /// 2. `sync_all()` - Fsyncs OS cache to physical disk
+    /// 2. `WritableFile::sync()` - Fsyncs OS cache to physical disk
     pub fn sync(&mut self) -> Result<(), WriteError> {
         self.writer.flush()?; // Flush BufWriter to OS cache
-        self.writer.get_ref().sync_all()?; // Fsync OS cache to physical disk
+        self.writer.get_mut().sync()?; // Fsync OS cache to physical disk
         Ok(())
     }
 }
+
+/// Returns the current wall-clock time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
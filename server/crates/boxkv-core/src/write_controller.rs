@@ -0,0 +1,418 @@
+//! Write stalls and backpressure for the LSM-tree write path.
+//!
+//! # Overview
+//!
+//! Writes are cheap (WAL append + MemTable insert) while flushes and compactions
+//! are not. If writers are never slowed down, a burst piles up immutable
+//! MemTables and L0 files faster than background work can absorb them, and
+//! memory and read amplification grow without bound.
+//!
+//! The `WriteController` tracks three pressure sources, each with a soft and a
+//! hard limit (see `WriteStallConfig`):
+//!
+//! ```text
+//!                 soft limit                  hard limit
+//!   ──────────────────┼───────────────────────────┼──────────────────►
+//!        Normal       │  Delayed (0 → max_delay)  │  Stopped (block, then fail)
+//! ```
+//!
+//! The Engine reports the current `WritePressure` after every flush, compaction
+//! and ingestion, and calls `before_write()` before each write takes the WAL
+//! lock. It has no background threads: a write flushes the MemTable it fills
+//! and compacts level 0 once it reaches its trigger, so pressure only builds
+//! up while those fail (or with limits below the trigger). A stalled write
+//! retries them before waiting.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use boxkv_common::config::{StorageConfig, WriteStallConfig};
+
+/// Errors returned to writers when flushes and compactions have fallen too
+/// far behind.
+#[derive(Debug, Error)]
+pub enum WriteStallError {
+    /// A hard limit was reached and did not clear within `stop_timeout_ms`.
+    #[error("Writes stopped: {cause} at {value}, hard limit is {limit}")]
+    Stopped {
+        cause: StallCause,
+        value: u64,
+        limit: u64,
+    },
+}
+
+/// Work that writers may be waiting on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallCause {
+    /// Too many immutable MemTables waiting to be flushed.
+    ImmutableMemtables,
+    /// Too many SSTables in level 0.
+    L0Files,
+    /// Too many bytes waiting to be compacted.
+    PendingCompactionBytes,
+}
+
+impl fmt::Display for StallCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StallCause::ImmutableMemtables => write!(f, "immutable memtables"),
+            StallCause::L0Files => write!(f, "L0 files"),
+            StallCause::PendingCompactionBytes => write!(f, "pending compaction bytes"),
+        }
+    }
+}
+
+/// Snapshot of the pressure sources, reported by the Engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WritePressure {
+    pub immutable_memtables: u64,
+    pub l0_files: u64,
+    pub pending_compaction_bytes: u64,
+}
+
+/// What happens to a write under the current pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallCondition {
+    /// All pressure sources are below their soft limit.
+    Normal,
+    /// A soft limit was crossed: each write sleeps for this long.
+    Delayed(Duration),
+    /// A hard limit was reached: writes wait for the pressure to drop.
+    Stopped {
+        cause: StallCause,
+        value: u64,
+        limit: u64,
+    },
+}
+
+/// Counters describing the backpressure applied so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StallStats {
+    /// Total time writers spent delayed or stopped.
+    pub stall_time: Duration,
+    /// Writes that were delayed past a soft limit.
+    pub delayed_writes: u64,
+    /// Writes that hit a hard limit (including those that eventually failed).
+    pub stopped_writes: u64,
+    /// Writes that failed with `WriteStallError::Stopped`.
+    pub failed_writes: u64,
+}
+
+/// Applies write delays and stops based on the reported `WritePressure`.
+///
+/// # Thread Safety
+///
+/// Shared by every writer. Stopped writers park on a condition variable and
+/// are woken by `set_pressure()`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let controller = WriteController::from_config(&config);
+///
+/// // After each flush or compaction
+/// controller.set_pressure(WritePressure {
+///     immutable_memtables: 2,
+///     l0_files: 12,
+///     pending_compaction_bytes: 0,
+/// });
+///
+/// // Write path
+/// controller.before_write()?;
+/// wal.append_normal(seq, key.clone(), value.clone())?;
+/// ```
+pub struct WriteController {
+    config: WriteStallConfig,
+    pressure: Mutex<WritePressure>,
+    /// Signalled whenever the pressure is updated.
+    pressure_changed: Condvar,
+
+    stall_micros: AtomicU64,
+    delayed_writes: AtomicU64,
+    stopped_writes: AtomicU64,
+    failed_writes: AtomicU64,
+}
+
+impl WriteController {
+    /// Creates a controller with the given limits and no pressure.
+    pub fn new(config: WriteStallConfig) -> Self {
+        Self {
+            config,
+            pressure: Mutex::new(WritePressure::default()),
+            pressure_changed: Condvar::new(),
+            stall_micros: AtomicU64::new(0),
+            delayed_writes: AtomicU64::new(0),
+            stopped_writes: AtomicU64::new(0),
+            failed_writes: AtomicU64::new(0),
+        }
+    }
+
+    /// Builds the controller described by the storage configuration.
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(config.write_stall.clone())
+    }
+
+    /// Reports the current pressure, waking stopped writers.
+    pub fn set_pressure(&self, pressure: WritePressure) {
+        let previous = std::mem::replace(&mut *self.pressure.lock(), pressure);
+        if previous != pressure {
+            self.pressure_changed.notify_all();
+        }
+    }
+
+    /// Returns the last reported pressure.
+    pub fn pressure(&self) -> WritePressure {
+        *self.pressure.lock()
+    }
+
+    /// Returns what would happen to a write right now.
+    pub fn condition(&self) -> StallCondition {
+        self.condition_for(&self.pressure.lock())
+    }
+
+    fn condition_for(&self, pressure: &WritePressure) -> StallCondition {
+        const MB: u64 = 1024 * 1024;
+        let config = &self.config;
+        let sources = [
+            (
+                StallCause::ImmutableMemtables,
+                pressure.immutable_memtables,
+                config.immutable_memtables_slowdown,
+                config.immutable_memtables_stop,
+            ),
+            (
+                StallCause::L0Files,
+                pressure.l0_files,
+                config.l0_files_slowdown,
+                config.l0_files_stop,
+            ),
+            (
+                StallCause::PendingCompactionBytes,
+                pressure.pending_compaction_bytes,
+                config.pending_compaction_slowdown_mb.saturating_mul(MB),
+                config.pending_compaction_stop_mb.saturating_mul(MB),
+            ),
+        ];
+
+        let mut delay = Duration::ZERO;
+        for (cause, value, soft, hard) in sources {
+            if value >= hard {
+                return StallCondition::Stopped {
+                    cause,
+                    value,
+                    limit: hard,
+                };
+            }
+            if value >= soft {
+                // Grows linearly from soft to hard, reaching `max_delay_us` just below hard.
+                let progress = (value - soft + 1) as f64 / (hard - soft) as f64;
+                let micros = (config.max_delay_us as f64 * progress) as u64;
+                delay = delay.max(Duration::from_micros(micros));
+            }
+        }
+
+        if delay.is_zero() {
+            StallCondition::Normal
+        } else {
+            StallCondition::Delayed(delay)
+        }
+    }
+
+    /// Applies backpressure to the calling writer.
+    ///
+    /// Sleeps past a soft limit, and blocks past a hard limit until the pressure
+    /// drops or `stop_timeout_ms` expires.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WriteStallError::Stopped` if a hard limit is still reached when
+    /// the timeout expires (immediately if the timeout is 0).
+    pub fn before_write(&self) -> Result<(), WriteStallError> {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(self.config.stop_timeout_ms);
+        let mut pressure = self.pressure.lock();
+        let mut stopped = false;
+
+        let result = loop {
+            match self.condition_for(&pressure) {
+                // Not stalled at all: nothing to record.
+                StallCondition::Normal if !stopped => return Ok(()),
+                StallCondition::Normal => break Ok(()),
+                StallCondition::Delayed(delay) => {
+                    drop(pressure);
+                    self.delayed_writes.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(delay);
+                    break Ok(());
+                }
+                StallCondition::Stopped {
+                    cause,
+                    value,
+                    limit,
+                } => {
+                    if !stopped {
+                        stopped = true;
+                        self.stopped_writes.fetch_add(1, Ordering::Relaxed);
+                        warn!(%cause, value, limit, "Hard limit reached, stopping writes");
+                    }
+                    if Instant::now() >= deadline {
+                        self.failed_writes.fetch_add(1, Ordering::Relaxed);
+                        break Err(WriteStallError::Stopped {
+                            cause,
+                            value,
+                            limit,
+                        });
+                    }
+                    self.pressure_changed.wait_until(&mut pressure, deadline);
+                }
+            }
+        };
+
+        let stalled = start.elapsed();
+        self.stall_micros
+            .fetch_add(stalled.as_micros() as u64, Ordering::Relaxed);
+        if stopped && result.is_ok() {
+            info!(?stalled, "Writes resumed");
+        }
+
+        result
+    }
+
+    /// Returns the backpressure counters.
+    pub fn stats(&self) -> StallStats {
+        StallStats {
+            stall_time: Duration::from_micros(self.stall_micros.load(Ordering::Relaxed)),
+            delayed_writes: self.delayed_writes.load(Ordering::Relaxed),
+            stopped_writes: self.stopped_writes.load(Ordering::Relaxed),
+            failed_writes: self.failed_writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(stop_timeout_ms: u64) -> WriteController {
+        WriteController::new(WriteStallConfig {
+            immutable_memtables_slowdown: 2,
+            immutable_memtables_stop: 4,
+            l0_files_slowdown: 10,
+            l0_files_stop: 20,
+            pending_compaction_slowdown_mb: 1,
+            pending_compaction_stop_mb: 2,
+            max_delay_us: 2000,
+            stop_timeout_ms,
+        })
+    }
+
+    #[test]
+    fn test_write_controller_conditions() {
+        let controller = controller(0);
+        assert_eq!(controller.condition(), StallCondition::Normal);
+
+        // Delay grows towards the hard limit
+        controller.set_pressure(WritePressure {
+            immutable_memtables: 2,
+            ..Default::default()
+        });
+        assert_eq!(
+            controller.condition(),
+            StallCondition::Delayed(Duration::from_micros(1000))
+        );
+        controller.set_pressure(WritePressure {
+            immutable_memtables: 3,
+            ..Default::default()
+        });
+        assert_eq!(
+            controller.condition(),
+            StallCondition::Delayed(Duration::from_micros(2000))
+        );
+
+        // Any hard limit stops writes
+        controller.set_pressure(WritePressure {
+            pending_compaction_bytes: 2 * 1024 * 1024,
+            ..Default::default()
+        });
+        assert_eq!(
+            controller.condition(),
+            StallCondition::Stopped {
+                cause: StallCause::PendingCompactionBytes,
+                value: 2 * 1024 * 1024,
+                limit: 2 * 1024 * 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_controller_delays_writes() {
+        let controller = controller(0);
+        controller.before_write().unwrap();
+        assert_eq!(controller.stats(), StallStats::default());
+
+        controller.set_pressure(WritePressure {
+            l0_files: 19,
+            ..Default::default()
+        });
+        controller.before_write().unwrap();
+
+        let stats = controller.stats();
+        assert_eq!(stats.delayed_writes, 1);
+        assert!(stats.stall_time >= Duration::from_micros(2000));
+    }
+
+    #[test]
+    fn test_write_controller_fails_at_hard_limit() {
+        let controller = controller(0);
+        controller.set_pressure(WritePressure {
+            immutable_memtables: 5,
+            ..Default::default()
+        });
+
+        match controller.before_write() {
+            Err(WriteStallError::Stopped {
+                cause,
+                value,
+                limit,
+            }) => {
+                assert_eq!(cause, StallCause::ImmutableMemtables);
+                assert_eq!(value, 5);
+                assert_eq!(limit, 4);
+            }
+            other => panic!("Expected Stopped error, got {:?}", other),
+        }
+
+        let stats = controller.stats();
+        assert_eq!(stats.stopped_writes, 1);
+        assert_eq!(stats.failed_writes, 1);
+    }
+
+    #[test]
+    fn test_write_controller_blocks_until_pressure_drops() {
+        let controller = controller(10_000);
+        controller.set_pressure(WritePressure {
+            l0_files: 25,
+            ..Default::default()
+        });
+
+        thread::scope(|s| {
+            let controller = &controller;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                controller.set_pressure(WritePressure::default());
+            });
+
+            controller.before_write().unwrap();
+        });
+
+        let stats = controller.stats();
+        assert_eq!(stats.stopped_writes, 1);
+        assert_eq!(stats.failed_writes, 0);
+        assert!(stats.stall_time >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,452 @@
+//! Client API: requests accepted by the server and their responses.
+//!
+//! Transport-independent: the network layer decodes a `Request`, hands it to
+//! `Api::handle()` and encodes the returned `Response`.
+//!
+//! Conditional writes (`CompareAndSwap`, `PutIfAbsent`, `DeleteIfEquals`) are
+//! evaluated atomically by the engine. When their condition doesn't hold, the
+//! response is `ConditionFailed` with the current value, so clients can retry
+//! without another round trip.
+//!
+//! Stored procedures are WebAssembly modules uploaded with `LoadModule`,
+//! whose functions `Invoke` runs on the server in a single transaction (see
+//! `boxkv_wasm::ProcedureRuntime`). Modules are only kept in memory: clients
+//! upload them again after a restart.
+//!
+//! `ChangesSince` reads the change feed: the mutations logged after a
+//! sequence number, in order. `handle()` answers it with the changes
+//! available now; to stream them, the network layer opens a `Subscription`
+//! with `Api::subscribe()` and sends each batch it polls. Subscribers resume
+//! after a disconnect from the `last_seq` of the last batch they received.
+//! Changes of every column family are streamed, with the values stored in
+//! blob files read back (see `boxkv_core::engine::ChangeFeed`).
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use boxkv_common::types::{ColumnFamilyId, Entry};
+use boxkv_core::engine::{ChangeFeed, Engine, EngineError};
+use boxkv_core::wal::WalError;
+use boxkv_wasm::ProcedureRuntime;
+
+/// Maximum number of changes in one `Response::Changes`.
+const MAX_CHANGES_PER_RESPONSE: usize = 1024;
+
+/// A client request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get {
+        key: Bytes,
+    },
+    Put {
+        key: Bytes,
+        value: Bytes,
+    },
+    Delete {
+        key: Bytes,
+    },
+    /// Stores `new` if the current value is `expected` (`None` for a missing key).
+    CompareAndSwap {
+        key: Bytes,
+        expected: Option<Bytes>,
+        new: Bytes,
+    },
+    /// Stores `value` if the key has no value.
+    PutIfAbsent {
+        key: Bytes,
+        value: Bytes,
+    },
+    /// Deletes the key if its current value is `expected`.
+    DeleteIfEquals {
+        key: Bytes,
+        expected: Bytes,
+    },
+    /// Compiles a stored procedure module and registers it as `name`.
+    LoadModule {
+        name: String,
+        module: Bytes,
+    },
+    /// Unregisters the stored procedure module `name`.
+    UnloadModule {
+        name: String,
+    },
+    /// Lists the loaded stored procedure modules.
+    ListModules,
+    /// Runs `procedure` of the module `module` with `args`.
+    Invoke {
+        module: String,
+        procedure: String,
+        args: Bytes,
+    },
+    /// Reads the mutations logged after sequence number `seq` (0 for all).
+    ChangesSince {
+        seq: u64,
+    },
+}
+
+/// The server's answer to a `Request`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Result of a `Get`.
+    Value(Option<Bytes>),
+    /// The write was applied at sequence number `seq`.
+    Written { seq: u64 },
+    /// A conditional write was not applied; `current` is the value it saw.
+    ConditionFailed { current: Option<Bytes> },
+    /// A module was loaded; these are the procedures it exports.
+    ModuleLoaded { procedures: Vec<String> },
+    /// A module was unloaded.
+    ModuleUnloaded,
+    /// Result of a `ListModules`.
+    Modules(Vec<String>),
+    /// A procedure succeeded: its writes were committed at sequence number
+    /// `seq` and it returned `output`.
+    Invoked { output: Bytes, seq: u64 },
+    /// Mutations from the change feed with their column family, oldest
+    /// first; `last_seq` is the sequence number to resume after. Empty when
+    /// the subscriber caught up.
+    Changes {
+        entries: Vec<(ColumnFamilyId, Entry)>,
+        last_seq: u64,
+    },
+    /// The changes following `requested - 1` were already purged; the
+    /// oldest one left is `oldest_available`.
+    SequencePurged {
+        requested: u64,
+        oldest_available: u64,
+    },
+    /// The request failed on the server.
+    Error(String),
+}
+
+/// Executes client requests against the engine.
+pub struct Api {
+    engine: Arc<Engine>,
+    procedures: Option<Arc<ProcedureRuntime>>,
+}
+
+impl Api {
+    pub fn new(engine: Arc<Engine>) -> Self {
+        Self {
+            engine,
+            procedures: None,
+        }
+    }
+
+    /// Enables the stored procedure requests, run by `procedures`.
+    pub fn with_procedures(mut self, procedures: Arc<ProcedureRuntime>) -> Self {
+        self.procedures = Some(procedures);
+        self
+    }
+
+    /// Executes `request` and returns its response.
+    pub fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Get { key } => {
+                return match self.engine.get(&key) {
+                    Ok(value) => Response::Value(value),
+                    Err(e) => Response::Error(e.to_string()),
+                };
+            }
+            Request::Put { key, value } => self.engine.put(key, value),
+            Request::Delete { key } => self.engine.delete(key),
+            Request::CompareAndSwap { key, expected, new } => {
+                self.engine.compare_and_swap(key, expected, new)
+            }
+            Request::PutIfAbsent { key, value } => self.engine.put_if_absent(key, value),
+            Request::DeleteIfEquals { key, expected } => {
+                self.engine.delete_if_equals(key, expected)
+            }
+            Request::LoadModule { .. }
+            | Request::UnloadModule { .. }
+            | Request::ListModules
+            | Request::Invoke { .. } => return self.handle_procedure(request),
+            Request::ChangesSince { seq } => {
+                return match self.subscribe(seq) {
+                    Ok(mut subscription) => subscription.poll(),
+                    Err(response) => response,
+                };
+            }
+        };
+
+        match result {
+            Ok(seq) => Response::Written { seq },
+            Err(EngineError::ConditionFailed { current }) => Response::ConditionFailed { current },
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+
+    /// Opens the change feed after sequence number `seq`, for a `ChangesSince`
+    /// request to stream.
+    ///
+    /// Returns the response to send instead if the feed can't be read, e.g.
+    /// `Response::SequencePurged`.
+    pub fn subscribe(&self, seq: u64) -> Result<Subscription, Response> {
+        match self.engine.changes_since(seq) {
+            Ok(feed) => Ok(Subscription { feed, error: None }),
+            Err(e) => Err(change_feed_error(e)),
+        }
+    }
+
+    fn handle_procedure(&self, request: Request) -> Response {
+        let Some(procedures) = &self.procedures else {
+            return Response::Error("Stored procedures are not enabled".to_string());
+        };
+        let result = match request {
+            Request::LoadModule { name, module } => procedures
+                .load_module(&name, &module)
+                .map(|procedures| Response::ModuleLoaded { procedures }),
+            Request::UnloadModule { name } => procedures
+                .unload_module(&name)
+                .map(|()| Response::ModuleUnloaded),
+            Request::ListModules => Ok(Response::Modules(procedures.module_names())),
+            Request::Invoke {
+                module,
+                procedure,
+                args,
+            } => procedures
+                .invoke(&module, &procedure, &args)
+                .map(|out| Response::Invoked {
+                    output: out.output,
+                    seq: out.seq,
+                }),
+            _ => unreachable!("not a stored procedure request"),
+        };
+        result.unwrap_or_else(|e| Response::Error(e.to_string()))
+    }
+}
+
+/// Change feed of a `ChangesSince` request, opened by `Api::subscribe()`.
+pub struct Subscription {
+    feed: ChangeFeed,
+    /// Error met after the changes of the last batch, sent by the next poll.
+    error: Option<EngineError>,
+}
+
+impl Subscription {
+    /// Returns the changes logged since the previous poll, up to
+    /// `MAX_CHANGES_PER_RESPONSE`. Polling again after an empty batch yields
+    /// the writes made in the meantime.
+    ///
+    /// A change that can't be read is reported as an error once the changes
+    /// before it were returned.
+    pub fn poll(&mut self) -> Response {
+        if let Some(e) = self.error.take() {
+            return change_feed_error(e);
+        }
+        let mut entries = Vec::new();
+        while entries.len() < MAX_CHANGES_PER_RESPONSE {
+            match self.feed.next() {
+                Some(Ok(change)) => entries.push(change),
+                Some(Err(e)) if entries.is_empty() => return change_feed_error(e),
+                Some(Err(e)) => {
+                    self.error = Some(e);
+                    break;
+                }
+                None => break,
+            }
+        }
+        // The feed is past the change that failed, which wasn't sent
+        let last_seq = match (&self.error, entries.last()) {
+            (Some(_), Some((_, entry))) => entry.seq(),
+            _ => self.feed.last_seq(),
+        };
+        Response::Changes { entries, last_seq }
+    }
+}
+
+fn change_feed_error(error: EngineError) -> Response {
+    match error {
+        EngineError::Wal(WalError::SequencePurged {
+            requested,
+            oldest_available,
+        }) => Response::SequencePurged {
+            requested,
+            oldest_available,
+        },
+        e => Response::Error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::config::{StorageConfig, WasmConfig};
+    use boxkv_core::engine::ColumnFamilyOptions;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_api_conditional_writes() {
+        let dir = TempDir::new().unwrap();
+        let engine = Engine::open(
+            dir.path(),
+            &StorageConfig::default(),
+            ColumnFamilyOptions::default(),
+        )
+        .unwrap();
+        let api = Api::new(Arc::new(engine));
+        let key = Bytes::from("lock");
+
+        let response = api.handle(Request::PutIfAbsent {
+            key: key.clone(),
+            value: Bytes::from("owner-1"),
+        });
+        assert_eq!(response, Response::Written { seq: 1 });
+
+        let response = api.handle(Request::CompareAndSwap {
+            key: key.clone(),
+            expected: None,
+            new: Bytes::from("owner-2"),
+        });
+        assert_eq!(
+            response,
+            Response::ConditionFailed {
+                current: Some(Bytes::from("owner-1"))
+            }
+        );
+
+        let response = api.handle(Request::DeleteIfEquals {
+            key: key.clone(),
+            expected: Bytes::from("owner-1"),
+        });
+        assert_eq!(response, Response::Written { seq: 2 });
+        assert_eq!(api.handle(Request::Get { key }), Response::Value(None));
+    }
+
+    #[test]
+    fn test_api_change_feed() {
+        let dir = TempDir::new().unwrap();
+        let engine = Engine::open(
+            dir.path(),
+            &StorageConfig::default(),
+            ColumnFamilyOptions::default(),
+        )
+        .unwrap();
+        let api = Api::new(Arc::new(engine));
+        let put = |key: &str| {
+            api.handle(Request::Put {
+                key: Bytes::copy_from_slice(key.as_bytes()),
+                value: Bytes::from("v"),
+            })
+        };
+        let changes = |response| match response {
+            Response::Changes { entries, last_seq } => {
+                let keys: Vec<_> = entries.iter().map(|(_, e)| e.key().clone()).collect();
+                (keys, last_seq)
+            }
+            other => panic!("unexpected response {:?}", other),
+        };
+
+        put("a");
+        put("b");
+        assert_eq!(
+            changes(api.handle(Request::ChangesSince { seq: 1 })),
+            (vec![Bytes::from("b")], 2)
+        );
+
+        let Ok(mut subscription) = api.subscribe(2) else {
+            panic!("change feed not opened");
+        };
+        assert_eq!(changes(subscription.poll()), (vec![], 2));
+        put("c");
+        put("d");
+        assert_eq!(
+            changes(subscription.poll()),
+            (vec![Bytes::from("c"), Bytes::from("d")], 4)
+        );
+
+        // The flush retires the WAL file holding these changes, without an archive
+        api.engine.flush().unwrap();
+        put("e");
+        assert_eq!(
+            api.handle(Request::ChangesSince { seq: 0 }),
+            Response::SequencePurged {
+                requested: 1,
+                oldest_available: 5
+            }
+        );
+        assert_eq!(changes(subscription.poll()), (vec![Bytes::from("e")], 5));
+    }
+
+    #[test]
+    fn test_api_stored_procedures() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(
+            Engine::open(
+                dir.path(),
+                &StorageConfig::default(),
+                ColumnFamilyOptions::default(),
+            )
+            .unwrap(),
+        );
+        let api = Api::new(engine.clone());
+        assert!(matches!(
+            api.handle(Request::ListModules),
+            Response::Error(_)
+        ));
+
+        let procedures = ProcedureRuntime::new(engine, &WasmConfig::default()).unwrap();
+        let api = api.with_procedures(Arc::new(procedures));
+        // Stores its arguments under "last"
+        let module = r#"
+            (module
+              (import "boxkv" "put" (func $put (param i32 i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "last")
+              (func (export "boxkv_abi_version") (result i32) i32.const 1)
+              (func (export "boxkv_alloc") (param i32) (result i32) i32.const 64)
+              (func (export "remember") (param $args i32) (param $len i32) (result i32)
+                (call $put (i32.const 0) (i32.const 4) (local.get $args) (local.get $len))
+                i32.const 0))
+        "#;
+        let response = api.handle(Request::LoadModule {
+            name: "memo".to_string(),
+            module: Bytes::from(module),
+        });
+        assert_eq!(
+            response,
+            Response::ModuleLoaded {
+                procedures: vec!["remember".to_string()]
+            }
+        );
+        assert_eq!(
+            api.handle(Request::ListModules),
+            Response::Modules(vec!["memo".to_string()])
+        );
+
+        let response = api.handle(Request::Invoke {
+            module: "memo".to_string(),
+            procedure: "remember".to_string(),
+            args: Bytes::from("hello"),
+        });
+        assert_eq!(
+            response,
+            Response::Invoked {
+                output: Bytes::new(),
+                seq: 1
+            }
+        );
+        assert_eq!(
+            api.handle(Request::Get {
+                key: Bytes::from("last")
+            }),
+            Response::Value(Some(Bytes::from("hello")))
+        );
+
+        assert_eq!(
+            api.handle(Request::UnloadModule {
+                name: "memo".to_string()
+            }),
+            Response::ModuleUnloaded
+        );
+        assert!(matches!(
+            api.handle(Request::Invoke {
+                module: "memo".to_string(),
+                procedure: "remember".to_string(),
+                args: Bytes::new(),
+            }),
+            Response::Error(_)
+        ));
+    }
+}
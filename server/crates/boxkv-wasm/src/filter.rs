@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use boxkv_core::compaction::{CompactionFilter, FilterDecision};
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::Result;
+use crate::module::{FILTER_EXPORT, WasmModule};
+
+const KEEP: i32 = 0;
+const REMOVE: i32 = 1;
+const CHANGE_VALUE: i32 = 2;
+
+/// `CompactionFilter` calling the `boxkv_filter` export of a module.
+///
+/// A failed call (trap, fuel exhausted, invalid result) is logged and keeps
+/// the value: a broken plugin never loses data.
+pub struct WasmCompactionFilter {
+    module: Arc<WasmModule>,
+    name: String,
+}
+
+impl WasmCompactionFilter {
+    pub(crate) fn new(module: Arc<WasmModule>) -> Self {
+        let name = format!("wasm.{}", module.name());
+        Self { module, name }
+    }
+
+    /// Runs the filter, surfacing failures instead of keeping the value.
+    pub fn try_filter(&self, level: usize, key: &[u8], value: &[u8]) -> Result<FilterDecision> {
+        let (status, result) = self
+            .module
+            .call::<_, i32>(FILTER_EXPORT, &[key, value], |at| {
+                (
+                    level as i32,
+                    at[0],
+                    key.len() as i32,
+                    at[1],
+                    value.len() as i32,
+                )
+            })?;
+        match (status, result) {
+            (KEEP, _) => Ok(FilterDecision::Keep),
+            (REMOVE, _) => Ok(FilterDecision::Remove),
+            (CHANGE_VALUE, Some(value)) => Ok(FilterDecision::ChangeValue(Bytes::from(value))),
+            (CHANGE_VALUE, None) => Err(self
+                .module
+                .invalid_result("value changed without setting a result".to_string())),
+            (status, _) => Err(self
+                .module
+                .invalid_result(format!("unknown filter decision {}", status))),
+        }
+    }
+}
+
+impl CompactionFilter for WasmCompactionFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> FilterDecision {
+        self.try_filter(level, key, value).unwrap_or_else(|e| {
+            warn!(filter = %self.name, error = %e, "Compaction filter failed, keeping the value");
+            FilterDecision::Keep
+        })
+    }
+}
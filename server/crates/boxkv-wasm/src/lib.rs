@@ -1,14 +1,154 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+//! User-supplied WebAssembly plugins, hosted with wasmtime.
+//!
+//! # Overview
+//!
+//! A plugin is a WebAssembly module implementing a `CompactionFilter`, a
+//! `MergeOperator`, or both. `WasmRuntime` loads the modules listed in the
+//! `[wasm]` section of the configuration (or registered at runtime) and hands
+//! out trait objects that can be set in `ColumnFamilyOptions`:
+//!
+//! ```ignore
+//! let runtime = WasmRuntime::from_config(&config.wasm)?;
+//! let options = ColumnFamilyOptions::default()
+//!     .with_compaction_filter(runtime.compaction_filter("purge")?)
+//!     .with_merge_operator(runtime.merge_operator("counter")?);
+//! ```
+//!
+//! # Limits
+//!
+//! Plugins are untrusted: every callback runs with a fuel budget (roughly, a
+//! number of instructions) and the linear memory of an instance is capped.
+//! A callback running out of fuel or trapping fails without affecting the
+//! host; the instance is then discarded and a fresh one is created for the
+//! next call.
+//!
+//! # ABI (version 1)
+//!
+//! Pointers and lengths are `i32` offsets into the module's memory. A module
+//! exports:
+//!
+//! - `memory`: its linear memory.
+//! - `boxkv_abi_version() -> i32`: must return `1`.
+//! - `boxkv_alloc(len: i32) -> i32`: returns a buffer of `len` bytes, which
+//!   the host fills with the inputs of the call about to be made. The buffer
+//!   only needs to stay valid until that call returns.
+//! - `boxkv_filter(level, key_ptr, key_len, value_ptr, value_len) -> i32`
+//!   (compaction filter): returns `0` to keep the value, `1` to remove it or
+//!   `2` to replace it with the result set by the call.
+//! - `boxkv_full_merge(key_ptr, key_len, existing_ptr, existing_len,
+//!   operands_ptr, operands_len) -> i32` (merge operator): `existing_len` is
+//!   `-1` when the key has no value. Returns `0` once the merged value is set
+//!   as the result, any other value failing the merge.
+//! - `boxkv_partial_merge(key_ptr, key_len, operands_ptr, operands_len) -> i32`
+//!   (optional): returns `0` once the combined operand is set as the result,
+//!   `1` if the operands can't be combined.
+//!
+//! Operands are passed oldest first, each one prefixed with its length as a
+//! little-endian `u32`. Results are set by calling the host import
+//! `boxkv.set_result(ptr: i32, len: i32)`, which copies them out.
+//!
+//! # Stored Procedures
+//!
+//! `ProcedureRuntime` runs functions of modules uploaded by clients on the
+//! server, so that multi-key logic ("move an item between two lists") takes a
+//! single round trip. Procedure modules implement the same base ABI
+//! (`memory`, `boxkv_abi_version`, `boxkv_alloc`); every other export of type
+//! `(args_ptr: i32, args_len: i32) -> i32` is a procedure. It returns `0` to
+//! commit its writes, any other status aborting the invocation, and may set
+//! an output with `boxkv.set_result`.
+//!
+//! Procedures access the default column family through host imports, all
+//! running in the invocation's transaction (reads see its own writes):
+//!
+//! - `boxkv.get(key_ptr, key_len) -> i32`: returns the length of the value,
+//!   or `-1` if the key has none.
+//! - `boxkv.scan(start_ptr, start_len, end_ptr, end_len, limit) -> i32`:
+//!   reads up to `limit` pairs with keys in `[start, end)` (`end_len` is `-1`
+//!   for no upper bound) and returns the length of their encoding: for each
+//!   pair, the key then the value, each one prefixed with its length as a
+//!   little-endian `u32`.
+//! - `boxkv.read_buffer(ptr)`: copies the output of the last `get` or `scan`
+//!   to `ptr`, which must have room for the length they returned.
+//! - `boxkv.put(key_ptr, key_len, value_ptr, value_len)` and
+//!   `boxkv.delete(key_ptr, key_len)`: buffer a write, applied on commit.
+
+mod filter;
+mod merge;
+mod module;
+mod procedure;
+mod runtime;
+
+pub use filter::WasmCompactionFilter;
+pub use merge::WasmMergeOperator;
+pub use module::{ABI_VERSION, WasmModule};
+pub use procedure::{ProcedureOutput, ProcedureRuntime};
+pub use runtime::WasmRuntime;
+
+use std::path::PathBuf;
+
+use boxkv_core::engine::EngineError;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, WasmError>;
+
+#[derive(Debug, Error)]
+pub enum WasmError {
+    #[error("Failed to read WebAssembly module {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The module is not valid WebAssembly, or imports something the host
+    /// doesn't provide.
+    #[error("Invalid WebAssembly module {module}: {reason}")]
+    Compile { module: String, reason: String },
+
+    #[error("WebAssembly module {module} implements ABI version {found}, expected {expected}")]
+    AbiVersion {
+        module: String,
+        found: i32,
+        expected: i32,
+    },
+
+    #[error("WebAssembly module {module} does not export {export}")]
+    MissingExport { module: String, export: String },
+
+    /// A callback burnt its whole fuel budget.
+    #[error("WebAssembly module {module} ran out of fuel")]
+    OutOfFuel { module: String },
+
+    /// A stored procedure ran past its time limit.
+    #[error("WebAssembly module {module} timed out")]
+    Timeout { module: String },
+
+    /// A callback trapped, or couldn't be set up (e.g. past the memory limit).
+    #[error("WebAssembly module {module} trapped: {reason}")]
+    Trap { module: String, reason: String },
+
+    /// A callback returned a status or buffer the ABI doesn't allow.
+    #[error("WebAssembly module {module} returned an invalid result: {reason}")]
+    InvalidResult { module: String, reason: String },
+
+    /// A stored procedure returned a non-zero status.
+    #[error("Procedure {procedure} of WebAssembly module {module} aborted with status {status}")]
+    Aborted {
+        module: String,
+        procedure: String,
+        status: i32,
+    },
+
+    /// The engine failed while running or committing a stored procedure.
+    #[error(transparent)]
+    Storage(#[from] EngineError),
+
+    #[error("WebAssembly module {0} is not loaded")]
+    ModuleNotFound(String),
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[error("WebAssembly module {0} is already loaded")]
+    DuplicateModule(String),
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
+    #[error("Failed to create the WebAssembly engine: {0}")]
+    Engine(String),
 }
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use boxkv_core::merge::{MergeError, MergeOperator};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::WasmError;
+use crate::module::{FULL_MERGE_EXPORT, PARTIAL_MERGE_EXPORT, WasmModule};
+
+const MERGED: i32 = 0;
+const NOT_MERGED: i32 = 1;
+/// `existing_len` passed when the key has no value.
+const NO_EXISTING_VALUE: i32 = -1;
+
+/// `MergeOperator` calling the `boxkv_full_merge` and (if exported)
+/// `boxkv_partial_merge` exports of a module.
+///
+/// A failed call is reported as `MergeError::InvalidOperand`.
+pub struct WasmMergeOperator {
+    module: Arc<WasmModule>,
+    name: String,
+}
+
+impl WasmMergeOperator {
+    pub(crate) fn new(module: Arc<WasmModule>) -> Self {
+        let name = format!("wasm.{}", module.name());
+        Self { module, name }
+    }
+
+    fn error(&self, error: WasmError) -> MergeError {
+        MergeError::InvalidOperand {
+            operator: self.name.clone(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// Encodes `operands` as a sequence of little-endian `u32` lengths, each
+/// followed by its operand.
+fn encode_operands(operands: &[Bytes]) -> Bytes {
+    let len = operands.iter().map(|op| 4 + op.len()).sum();
+    let mut buf = BytesMut::with_capacity(len);
+    for operand in operands {
+        buf.put_u32_le(operand.len() as u32);
+        buf.put_slice(operand);
+    }
+    buf.freeze()
+}
+
+impl MergeOperator for WasmMergeOperator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Bytes],
+    ) -> Result<Bytes, MergeError> {
+        let encoded = encode_operands(operands);
+        let existing_len = existing.map_or(NO_EXISTING_VALUE, |v| v.len() as i32);
+        let (status, result) = self
+            .module
+            .call::<_, i32>(
+                FULL_MERGE_EXPORT,
+                &[key, existing.unwrap_or_default(), &encoded],
+                |at| {
+                    (
+                        at[0],
+                        key.len() as i32,
+                        at[1],
+                        existing_len,
+                        at[2],
+                        encoded.len() as i32,
+                    )
+                },
+            )
+            .map_err(|e| self.error(e))?;
+        match (status, result) {
+            (MERGED, Some(value)) => Ok(Bytes::from(value)),
+            (MERGED, None) => Err(self.error(
+                self.module
+                    .invalid_result("merge succeeded without setting a result".to_string()),
+            )),
+            (status, _) => Err(self.error(
+                self.module
+                    .invalid_result(format!("merge failed with status {}", status)),
+            )),
+        }
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[Bytes]) -> Result<Option<Bytes>, MergeError> {
+        if !self.module.supports_partial_merge() {
+            return Ok(None);
+        }
+        let encoded = encode_operands(operands);
+        let (status, result) = self
+            .module
+            .call::<_, i32>(PARTIAL_MERGE_EXPORT, &[key, &encoded], |at| {
+                (at[0], key.len() as i32, at[1], encoded.len() as i32)
+            })
+            .map_err(|e| self.error(e))?;
+        match (status, result) {
+            (MERGED, Some(operand)) => Ok(Some(Bytes::from(operand))),
+            (NOT_MERGED, _) => Ok(None),
+            (MERGED, None) => Err(self.error(
+                self.module
+                    .invalid_result("merge succeeded without setting a result".to_string()),
+            )),
+            (status, _) => Err(self.error(
+                self.module
+                    .invalid_result(format!("partial merge failed with status {}", status)),
+            )),
+        }
+    }
+}
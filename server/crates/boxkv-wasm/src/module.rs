@@ -0,0 +1,374 @@
+use parking_lot::Mutex;
+use wasmtime::{
+    Caller, Engine, Instance, InstancePre, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, Trap, TypedFunc, WasmParams, WasmResults,
+};
+
+use crate::{Result, WasmError};
+
+/// Version of the plugin ABI implemented by this host (see the crate docs).
+pub const ABI_VERSION: i32 = 1;
+
+pub(crate) const FILTER_EXPORT: &str = "boxkv_filter";
+pub(crate) const FULL_MERGE_EXPORT: &str = "boxkv_full_merge";
+pub(crate) const PARTIAL_MERGE_EXPORT: &str = "boxkv_partial_merge";
+pub(crate) const MEMORY_EXPORT: &str = "memory";
+pub(crate) const ABI_VERSION_EXPORT: &str = "boxkv_abi_version";
+pub(crate) const ALLOC_EXPORT: &str = "boxkv_alloc";
+pub(crate) const HOST_MODULE: &str = "boxkv";
+
+/// Data of the store an instance lives in.
+struct HostState {
+    limits: StoreLimits,
+    /// Buffer set by the last call to `boxkv.set_result`.
+    result: Option<Vec<u8>>,
+}
+
+/// An instance along with the exports used on every call.
+struct LiveInstance {
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+/// A compiled plugin, checked against the ABI when loaded.
+///
+/// Calls are serialized on a single instance, which keeps its memory between
+/// calls. The instance is dropped when a call fails (trap, fuel exhausted,
+/// invalid result) so that a broken state never leaks into the next call.
+pub struct WasmModule {
+    name: String,
+    pre: InstancePre<HostState>,
+    fuel_per_call: u64,
+    max_memory_bytes: usize,
+    filter: bool,
+    full_merge: bool,
+    partial_merge: bool,
+    instance: Mutex<Option<LiveInstance>>,
+}
+
+impl WasmModule {
+    /// Compiles `bytes` (binary or text format) and checks that the module
+    /// implements `ABI_VERSION`, instantiating it once.
+    ///
+    /// # Errors
+    /// Returns `WasmError::Compile` if the module is invalid, imports
+    /// something else than the host functions or exports a callback with the
+    /// wrong signature, `WasmError::MissingExport` if a required export is
+    /// missing and `WasmError::AbiVersion` on a version mismatch.
+    pub(crate) fn new(
+        engine: &Engine,
+        name: &str,
+        bytes: &[u8],
+        fuel_per_call: u64,
+        max_memory_bytes: usize,
+    ) -> Result<Self> {
+        let compile_error = |e: wasmtime::Error| WasmError::Compile {
+            module: name.to_string(),
+            reason: format!("{:#}", e),
+        };
+        let module = Module::new(engine, bytes).map_err(compile_error)?;
+
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap(HOST_MODULE, "set_result", set_result)
+            .map_err(compile_error)?;
+        let pre = linker.instantiate_pre(&module).map_err(compile_error)?;
+
+        let mut module = Self {
+            name: name.to_string(),
+            pre,
+            fuel_per_call,
+            max_memory_bytes,
+            filter: false,
+            full_merge: false,
+            partial_merge: false,
+            instance: Mutex::new(None),
+        };
+        let mut live = module.instantiate()?;
+
+        let version = live
+            .instance
+            .get_typed_func::<(), i32>(&mut live.store, ABI_VERSION_EXPORT)
+            .map_err(|_| module.missing_export(ABI_VERSION_EXPORT))?;
+        let found = version
+            .call(&mut live.store, ())
+            .map_err(|e| module.trap(e))?;
+        if found != ABI_VERSION {
+            return Err(WasmError::AbiVersion {
+                module: module.name,
+                found,
+                expected: ABI_VERSION,
+            });
+        }
+
+        module.filter =
+            module.check_export::<(i32, i32, i32, i32, i32), i32>(&mut live, FILTER_EXPORT)?;
+        module.full_merge = module
+            .check_export::<(i32, i32, i32, i32, i32, i32), i32>(&mut live, FULL_MERGE_EXPORT)?;
+        module.partial_merge =
+            module.check_export::<(i32, i32, i32, i32), i32>(&mut live, PARTIAL_MERGE_EXPORT)?;
+        if !module.filter && !module.full_merge {
+            return Err(
+                module.missing_export(&format!("{} or {}", FILTER_EXPORT, FULL_MERGE_EXPORT))
+            );
+        }
+
+        *module.instance.get_mut() = Some(live);
+        Ok(module)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether the module exports `boxkv_filter`.
+    pub fn is_compaction_filter(&self) -> bool {
+        self.filter
+    }
+
+    /// Returns whether the module exports `boxkv_full_merge`.
+    pub fn is_merge_operator(&self) -> bool {
+        self.full_merge
+    }
+
+    /// Returns whether the module exports `boxkv_partial_merge`.
+    pub fn supports_partial_merge(&self) -> bool {
+        self.partial_merge
+    }
+
+    /// Returns whether `export` is there, checking its signature if so.
+    fn check_export<P: WasmParams, R: WasmResults>(
+        &self,
+        live: &mut LiveInstance,
+        export: &str,
+    ) -> Result<bool> {
+        if live.instance.get_export(&mut live.store, export).is_none() {
+            return Ok(false);
+        }
+        live.instance
+            .get_typed_func::<P, R>(&mut live.store, export)
+            .map_err(|e| WasmError::Compile {
+                module: self.name.clone(),
+                reason: format!("{}: {:#}", export, e),
+            })?;
+        Ok(true)
+    }
+
+    fn instantiate(&self) -> Result<LiveInstance> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(
+            self.pre.module().engine(),
+            HostState {
+                limits,
+                result: None,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        // Bounds the start function, if any
+        store
+            .set_fuel(self.fuel_per_call)
+            .map_err(|e| self.trap(e))?;
+
+        let instance = self.pre.instantiate(&mut store).map_err(|e| self.trap(e))?;
+        let memory = instance
+            .get_memory(&mut store, MEMORY_EXPORT)
+            .ok_or_else(|| self.missing_export(MEMORY_EXPORT))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, ALLOC_EXPORT)
+            .map_err(|_| self.missing_export(ALLOC_EXPORT))?;
+        Ok(LiveInstance {
+            store,
+            instance,
+            memory,
+            alloc,
+        })
+    }
+
+    /// Copies `inputs` contiguously into a buffer from `boxkv_alloc`, then
+    /// calls `export` with the parameters `params` builds from the offsets of
+    /// the inputs.
+    ///
+    /// Returns what the export returned and the result it set, if any.
+    pub(crate) fn call<P: WasmParams, R: WasmResults>(
+        &self,
+        export: &str,
+        inputs: &[&[u8]],
+        params: impl FnOnce(&[i32]) -> P,
+    ) -> Result<(R, Option<Vec<u8>>)> {
+        let mut slot = self.instance.lock();
+        let live = match slot.as_mut() {
+            Some(live) => live,
+            None => slot.insert(self.instantiate()?),
+        };
+        let result = self.call_instance(live, export, inputs, params);
+        if result.is_err() {
+            *slot = None;
+        }
+        result
+    }
+
+    fn call_instance<P: WasmParams, R: WasmResults>(
+        &self,
+        live: &mut LiveInstance,
+        export: &str,
+        inputs: &[&[u8]],
+        params: impl FnOnce(&[i32]) -> P,
+    ) -> Result<(R, Option<Vec<u8>>)> {
+        live.store
+            .set_fuel(self.fuel_per_call)
+            .map_err(|e| self.trap(e))?;
+        live.store.data_mut().result = None;
+        let offsets = copy_inputs(
+            &self.name,
+            &mut live.store,
+            live.memory,
+            &live.alloc,
+            inputs,
+        )?;
+
+        let func = live
+            .instance
+            .get_typed_func::<P, R>(&mut live.store, export)
+            .map_err(|_| self.missing_export(export))?;
+        let returned = func
+            .call(&mut live.store, params(&offsets))
+            .map_err(|e| self.trap(e))?;
+        Ok((returned, live.store.data_mut().result.take()))
+    }
+
+    fn trap(&self, error: wasmtime::Error) -> WasmError {
+        trap_error(&self.name, error)
+    }
+
+    fn missing_export(&self, export: &str) -> WasmError {
+        WasmError::MissingExport {
+            module: self.name.clone(),
+            export: export.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_result(&self, reason: String) -> WasmError {
+        WasmError::InvalidResult {
+            module: self.name.clone(),
+            reason,
+        }
+    }
+}
+
+/// Copies `inputs` contiguously into a buffer from the `boxkv_alloc` export,
+/// returning the offset of each one.
+pub(crate) fn copy_inputs<T>(
+    module: &str,
+    store: &mut Store<T>,
+    memory: Memory,
+    alloc: &TypedFunc<i32, i32>,
+    inputs: &[&[u8]],
+) -> Result<Vec<i32>> {
+    let total: usize = inputs.iter().map(|input| input.len()).sum();
+    let len = i32::try_from(total).map_err(|_| WasmError::Trap {
+        module: module.to_string(),
+        reason: format!("{} bytes of input don't fit in a 32-bit memory", total),
+    })?;
+    let base = alloc
+        .call(&mut *store, len)
+        .map_err(|e| trap_error(module, e))? as u32 as usize;
+
+    let memory = memory.data_mut(store);
+    if base.checked_add(total).is_none_or(|end| end > memory.len()) {
+        return Err(WasmError::InvalidResult {
+            module: module.to_string(),
+            reason: format!(
+                "{} returned a buffer of {} bytes at {}, past the end of memory",
+                ALLOC_EXPORT, total, base
+            ),
+        });
+    }
+    let mut offsets = Vec::with_capacity(inputs.len());
+    let mut offset = base;
+    for input in inputs {
+        memory[offset..offset + input.len()].copy_from_slice(input);
+        // Below memory.len(), which fits in 32 bits
+        offsets.push(offset as i32);
+        offset += input.len();
+    }
+    Ok(offsets)
+}
+
+/// Maps the failure of a call into `module` to a `WasmError`.
+pub(crate) fn trap_error(module: &str, error: wasmtime::Error) -> WasmError {
+    let module = module.to_string();
+    match error.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => WasmError::OutOfFuel { module },
+        Some(Trap::Interrupt) => WasmError::Timeout { module },
+        Some(trap) => WasmError::Trap {
+            module,
+            reason: trap.to_string(),
+        },
+        None => WasmError::Trap {
+            module,
+            reason: format!("{:#}", error),
+        },
+    }
+}
+
+/// Returns the `len` bytes at `ptr` in the memory of the calling module.
+pub(crate) fn read_guest<T>(
+    caller: &mut Caller<'_, T>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let start = ptr as u32 as usize;
+    let bytes = start
+        .checked_add(len as u32 as usize)
+        .and_then(|end| memory.data(&*caller).get(start..end))
+        .ok_or_else(|| {
+            wasmtime::Error::msg(format!(
+                "{} bytes at {} are past the end of memory",
+                len, ptr
+            ))
+        })?;
+    Ok(bytes.to_vec())
+}
+
+/// Copies `bytes` to `ptr` in the memory of the calling module.
+pub(crate) fn write_guest<T>(
+    caller: &mut Caller<'_, T>,
+    ptr: i32,
+    bytes: &[u8],
+) -> wasmtime::Result<()> {
+    let memory = guest_memory(caller)?;
+    let start = ptr as u32 as usize;
+    start
+        .checked_add(bytes.len())
+        .and_then(|end| memory.data_mut(&mut *caller).get_mut(start..end))
+        .ok_or_else(|| {
+            wasmtime::Error::msg(format!(
+                "{} bytes at {} are past the end of memory",
+                bytes.len(),
+                ptr
+            ))
+        })?
+        .copy_from_slice(bytes);
+    Ok(())
+}
+
+fn guest_memory<T>(caller: &mut Caller<'_, T>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export(MEMORY_EXPORT)
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("module exports no memory"))
+}
+
+/// Host import `boxkv.set_result(ptr, len)`: copies out the result of the
+/// current call.
+fn set_result(mut caller: Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<()> {
+    let result = read_guest(&mut caller, ptr, len)?;
+    caller.data_mut().result = Some(result);
+    Ok(())
+}
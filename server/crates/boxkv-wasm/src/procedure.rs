@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use boxkv_common::config::WasmConfig;
+use boxkv_core::engine::{Engine as StorageEngine, EngineError, OptimisticTransaction};
+use bytes::{BufMut, Bytes};
+use parking_lot::RwLock;
+use tracing::{debug, info};
+use wasmtime::{
+    Caller, Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::module::{
+    ABI_VERSION_EXPORT, ALLOC_EXPORT, HOST_MODULE, MEMORY_EXPORT, copy_inputs, read_guest,
+    trap_error, write_guest,
+};
+use crate::{ABI_VERSION, Result, WasmError};
+
+/// Interval at which the time limit of running invocations is checked.
+const TICK: Duration = Duration::from_millis(10);
+/// Times an invocation is run again after its commit conflicted.
+const MAX_CONFLICT_RETRIES: usize = 3;
+/// `end_len` passed to `boxkv.scan` for a range without upper bound.
+const UNBOUNDED: i32 = -1;
+/// Returned by `boxkv.get` for a key without value.
+const NOT_FOUND: i32 = -1;
+
+/// Data of the store of an invocation.
+struct ProcedureState {
+    limits: StoreLimits,
+    /// Transaction the host API runs in; `None` while the module is checked.
+    txn: Option<OptimisticTransaction<'static>>,
+    /// Output of the last `get` or `scan`, copied out by `read_buffer`.
+    buffer: Vec<u8>,
+    /// Buffer set by the last call to `boxkv.set_result`.
+    result: Option<Vec<u8>>,
+    /// Error of the engine that made a host call fail.
+    error: Option<EngineError>,
+}
+
+impl ProcedureState {
+    fn txn(&mut self) -> wasmtime::Result<&mut OptimisticTransaction<'static>> {
+        self.txn
+            .as_mut()
+            .ok_or_else(|| wasmtime::Error::msg("the host API is only available in procedures"))
+    }
+
+    /// Records an error of the engine, failing the host call.
+    fn fail(&mut self, error: EngineError) -> wasmtime::Error {
+        let message = error.to_string();
+        self.error = Some(error);
+        wasmtime::Error::msg(message)
+    }
+}
+
+/// A module of stored procedures, checked against the ABI when loaded.
+struct ProcedureModule {
+    pre: InstancePre<ProcedureState>,
+    procedures: Vec<String>,
+}
+
+/// What a successful invocation returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureOutput {
+    /// Result set by the procedure, empty if it set none.
+    pub output: Bytes,
+    /// Sequence number its writes were committed at.
+    pub seq: u64,
+}
+
+/// Runs stored procedures: functions of uploaded modules executed on the
+/// server against the engine's default column family.
+///
+/// Each invocation gets a fresh instance and runs in an
+/// `OptimisticTransaction`: its writes are applied atomically once the
+/// procedure returns successfully, and not at all if it fails. When the
+/// commit conflicts with another writer, the procedure is run again from
+/// scratch (up to 3 times), so procedures must not rely on state kept
+/// outside of the engine.
+///
+/// Every invocation is bounded by `procedure_timeout_ms` of wall-clock time
+/// and `procedure_max_memory_mb` of memory.
+pub struct ProcedureRuntime {
+    engine: Engine,
+    storage: Arc<StorageEngine>,
+    timeout_ticks: u64,
+    max_memory_bytes: usize,
+    modules: RwLock<HashMap<String, Arc<ProcedureModule>>>,
+}
+
+impl ProcedureRuntime {
+    /// Creates a runtime without any module, running procedures against
+    /// `storage` with the limits of `config`.
+    ///
+    /// Starts a thread advancing the runtime's clock, which exits once the
+    /// runtime is dropped.
+    pub fn new(storage: Arc<StorageEngine>, config: &WasmConfig) -> Result<Self> {
+        let mut engine_config = Config::new();
+        engine_config.epoch_interruption(true);
+        let engine =
+            Engine::new(&engine_config).map_err(|e| WasmError::Engine(format!("{:#}", e)))?;
+
+        let clock = engine.weak();
+        thread::Builder::new()
+            .name("boxkv-wasm-clock".to_string())
+            .spawn(move || {
+                while let Some(engine) = clock.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    thread::sleep(TICK);
+                }
+            })
+            .map_err(|e| WasmError::Engine(e.to_string()))?;
+
+        Ok(Self {
+            engine,
+            storage,
+            timeout_ticks: config
+                .procedure_timeout_ms
+                .div_ceil(TICK.as_millis() as u64),
+            max_memory_bytes: config.procedure_max_memory_mb.saturating_mul(1024 * 1024),
+            modules: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Compiles `bytes` (binary or text format) and registers its procedures
+    /// under `name`, returning their names.
+    ///
+    /// # Errors
+    /// Returns `WasmError::DuplicateModule` if `name` is taken,
+    /// `WasmError::MissingExport` if the module exports no procedure, and the
+    /// errors of a module failing the ABI checks (see the crate docs).
+    pub fn load_module(&self, name: &str, bytes: &[u8]) -> Result<Vec<String>> {
+        if self.modules.read().contains_key(name) {
+            return Err(WasmError::DuplicateModule(name.to_string()));
+        }
+        let module = Arc::new(self.compile(name, bytes)?);
+
+        let mut modules = self.modules.write();
+        if modules.contains_key(name) {
+            return Err(WasmError::DuplicateModule(name.to_string()));
+        }
+        modules.insert(name.to_string(), module.clone());
+        info!(
+            module = name,
+            procedures = ?module.procedures,
+            "Stored procedure module loaded"
+        );
+        Ok(module.procedures.clone())
+    }
+
+    /// Unregisters the module `name`; running invocations complete.
+    pub fn unload_module(&self, name: &str) -> Result<()> {
+        self.modules
+            .write()
+            .remove(name)
+            .ok_or_else(|| WasmError::ModuleNotFound(name.to_string()))?;
+        info!(module = name, "Stored procedure module unloaded");
+        Ok(())
+    }
+
+    /// Returns the names of the loaded modules, sorted.
+    pub fn module_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.modules.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the procedures exported by the module `name`, sorted.
+    pub fn procedures(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self.module(name)?.procedures.clone())
+    }
+
+    fn module(&self, name: &str) -> Result<Arc<ProcedureModule>> {
+        self.modules
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| WasmError::ModuleNotFound(name.to_string()))
+    }
+
+    /// Runs `procedure` of the module `name` with `args`, committing its
+    /// writes if it succeeds.
+    ///
+    /// # Errors
+    /// Returns `WasmError::Aborted` if the procedure returned a non-zero
+    /// status, `WasmError::Timeout` or `WasmError::Trap` if it ran past its
+    /// limits or trapped, and `WasmError::Storage` if the engine failed (a
+    /// `TransactionConflict` once the retries are exhausted). Nothing is
+    /// written in all of these cases.
+    pub fn invoke(&self, name: &str, procedure: &str, args: &[u8]) -> Result<ProcedureOutput> {
+        let module = self.module(name)?;
+        if !module.procedures.iter().any(|p| p == procedure) {
+            return Err(WasmError::MissingExport {
+                module: name.to_string(),
+                export: procedure.to_string(),
+            });
+        }
+
+        let mut attempt = 0;
+        loop {
+            match self.invoke_once(name, &module, procedure, args) {
+                Err(WasmError::Storage(EngineError::TransactionConflict { key, .. }))
+                    if attempt < MAX_CONFLICT_RETRIES =>
+                {
+                    attempt += 1;
+                    debug!(
+                        module = name,
+                        procedure,
+                        ?key,
+                        attempt,
+                        "Procedure conflicted, retrying"
+                    );
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn invoke_once(
+        &self,
+        name: &str,
+        module: &ProcedureModule,
+        procedure: &str,
+        args: &[u8],
+    ) -> Result<ProcedureOutput> {
+        let txn = StorageEngine::begin_optimistic_shared(&self.storage);
+        let mut store = self.new_store(Some(txn));
+        let instance = module
+            .pre
+            .instantiate(&mut store)
+            .map_err(|e| trap_error(name, e))?;
+        let missing_export = |export: &str| WasmError::MissingExport {
+            module: name.to_string(),
+            export: export.to_string(),
+        };
+        let memory = instance
+            .get_memory(&mut store, MEMORY_EXPORT)
+            .ok_or_else(|| missing_export(MEMORY_EXPORT))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, ALLOC_EXPORT)
+            .map_err(|_| missing_export(ALLOC_EXPORT))?;
+        let func = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, procedure)
+            .map_err(|_| missing_export(procedure))?;
+
+        let offsets = copy_inputs(name, &mut store, memory, &alloc, &[args])?;
+        let status = func
+            .call(&mut store, (offsets[0], args.len() as i32))
+            .map_err(|e| match store.data_mut().error.take() {
+                Some(error) => WasmError::Storage(error),
+                None => trap_error(name, e),
+            })?;
+        if status != 0 {
+            return Err(WasmError::Aborted {
+                module: name.to_string(),
+                procedure: procedure.to_string(),
+                status,
+            });
+        }
+
+        let state = store.into_data();
+        let seq = state.txn.expect("set for invocations").commit()?;
+        Ok(ProcedureOutput {
+            output: state.result.map(Bytes::from).unwrap_or_default(),
+            seq,
+        })
+    }
+
+    fn new_store(&self, txn: Option<OptimisticTransaction<'static>>) -> Store<ProcedureState> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(
+            &self.engine,
+            ProcedureState {
+                limits,
+                txn,
+                buffer: Vec::new(),
+                result: None,
+                error: None,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_epoch_deadline(self.timeout_ticks);
+        store
+    }
+
+    /// Compiles a module, checks its ABI version and lists its procedures:
+    /// the exports taking `(args_ptr, args_len)` and returning a status.
+    fn compile(&self, name: &str, bytes: &[u8]) -> Result<ProcedureModule> {
+        let compile_error = |e: wasmtime::Error| WasmError::Compile {
+            module: name.to_string(),
+            reason: format!("{:#}", e),
+        };
+        let module = Module::new(&self.engine, bytes).map_err(compile_error)?;
+        let mut linker = Linker::new(&self.engine);
+        link_host_api(&mut linker).map_err(compile_error)?;
+        let pre = linker.instantiate_pre(&module).map_err(compile_error)?;
+
+        let mut store = self.new_store(None);
+        let instance = pre
+            .instantiate(&mut store)
+            .map_err(|e| trap_error(name, e))?;
+        let missing_export = |export: &str| WasmError::MissingExport {
+            module: name.to_string(),
+            export: export.to_string(),
+        };
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, ABI_VERSION_EXPORT)
+            .map_err(|_| missing_export(ABI_VERSION_EXPORT))?;
+        let found = version
+            .call(&mut store, ())
+            .map_err(|e| trap_error(name, e))?;
+        if found != ABI_VERSION {
+            return Err(WasmError::AbiVersion {
+                module: name.to_string(),
+                found,
+                expected: ABI_VERSION,
+            });
+        }
+        if instance.get_memory(&mut store, MEMORY_EXPORT).is_none() {
+            return Err(missing_export(MEMORY_EXPORT));
+        }
+        instance
+            .get_typed_func::<i32, i32>(&mut store, ALLOC_EXPORT)
+            .map_err(|_| missing_export(ALLOC_EXPORT))?;
+
+        let mut procedures: Vec<String> = module
+            .exports()
+            .map(|export| export.name().to_string())
+            .filter(|export| !export.starts_with("boxkv_"))
+            .filter(|export| {
+                instance
+                    .get_typed_func::<(i32, i32), i32>(&mut store, export)
+                    .is_ok()
+            })
+            .collect();
+        if procedures.is_empty() {
+            return Err(missing_export("a procedure"));
+        }
+        procedures.sort();
+        Ok(ProcedureModule { pre, procedures })
+    }
+}
+
+/// Registers the host API of stored procedures under the `boxkv` module.
+fn link_host_api(linker: &mut Linker<ProcedureState>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_result",
+        |mut caller: Caller<'_, ProcedureState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let result = read_guest(&mut caller, ptr, len)?;
+            caller.data_mut().result = Some(result);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "get",
+        |mut caller: Caller<'_, ProcedureState>,
+         key_ptr: i32,
+         key_len: i32|
+         -> wasmtime::Result<i32> {
+            let key = Bytes::from(read_guest(&mut caller, key_ptr, key_len)?);
+            let state = caller.data_mut();
+            match state.txn()?.get(&key) {
+                Ok(Some(value)) => {
+                    state.buffer = value.to_vec();
+                    Ok(state.buffer.len() as i32)
+                }
+                Ok(None) => {
+                    state.buffer.clear();
+                    Ok(NOT_FOUND)
+                }
+                Err(e) => Err(state.fail(e)),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "scan",
+        |mut caller: Caller<'_, ProcedureState>,
+         start_ptr: i32,
+         start_len: i32,
+         end_ptr: i32,
+         end_len: i32,
+         limit: i32|
+         -> wasmtime::Result<i32> {
+            let start = Bytes::from(read_guest(&mut caller, start_ptr, start_len)?);
+            let end = match end_len {
+                UNBOUNDED => None,
+                _ => Some(Bytes::from(read_guest(&mut caller, end_ptr, end_len)?)),
+            };
+            let limit = usize::try_from(limit)
+                .map_err(|_| wasmtime::Error::msg(format!("negative scan limit {}", limit)))?;
+            let state = caller.data_mut();
+            let pairs = match state.txn()?.scan(&start, end.as_ref(), limit) {
+                Ok(pairs) => pairs,
+                Err(e) => return Err(state.fail(e)),
+            };
+            state.buffer.clear();
+            for (key, value) in pairs {
+                state.buffer.put_u32_le(key.len() as u32);
+                state.buffer.put_slice(&key);
+                state.buffer.put_u32_le(value.len() as u32);
+                state.buffer.put_slice(&value);
+            }
+            i32::try_from(state.buffer.len())
+                .map_err(|_| wasmtime::Error::msg("scan result past 2 GB"))
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_buffer",
+        |mut caller: Caller<'_, ProcedureState>, ptr: i32| -> wasmtime::Result<()> {
+            let buffer = std::mem::take(&mut caller.data_mut().buffer);
+            write_guest(&mut caller, ptr, &buffer)
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "put",
+        |mut caller: Caller<'_, ProcedureState>,
+         key_ptr: i32,
+         key_len: i32,
+         value_ptr: i32,
+         value_len: i32|
+         -> wasmtime::Result<()> {
+            let key = read_guest(&mut caller, key_ptr, key_len)?;
+            let value = read_guest(&mut caller, value_ptr, value_len)?;
+            caller
+                .data_mut()
+                .txn()?
+                .put(Bytes::from(key), Bytes::from(value));
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "delete",
+        |mut caller: Caller<'_, ProcedureState>,
+         key_ptr: i32,
+         key_len: i32|
+         -> wasmtime::Result<()> {
+            let key = read_guest(&mut caller, key_ptr, key_len)?;
+            caller.data_mut().txn()?.delete(Bytes::from(key));
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::config::StorageConfig;
+    use boxkv_core::engine::ColumnFamilyOptions;
+    use tempfile::TempDir;
+
+    const LISTS: &str = r#"
+        (module
+          (import "boxkv" "get" (func $get (param i32 i32) (result i32)))
+          (import "boxkv" "scan" (func $scan (param i32 i32 i32 i32 i32) (result i32)))
+          (import "boxkv" "read_buffer" (func $read_buffer (param i32)))
+          (import "boxkv" "put" (func $put (param i32 i32 i32 i32)))
+          (import "boxkv" "delete" (func $delete (param i32 i32)))
+          (import "boxkv" "set_result" (func $set_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "boxkv_abi_version") (result i32) i32.const 1)
+          (func (export "boxkv_alloc") (param i32) (result i32) i32.const 1024)
+          ;; args: [from_len: u8][from][to], moves the value of from to to
+          (func (export "move_item") (param $args i32) (param $len i32) (result i32)
+            (local $from_len i32) (local $from i32) (local $to i32) (local $to_len i32)
+            (local $val_len i32)
+            (local.set $from_len (i32.load8_u (local.get $args)))
+            (local.set $from (i32.add (local.get $args) (i32.const 1)))
+            (local.set $to (i32.add (local.get $from) (local.get $from_len)))
+            (local.set $to_len
+              (i32.sub (i32.sub (local.get $len) (i32.const 1)) (local.get $from_len)))
+            (local.set $val_len (call $get (local.get $from) (local.get $from_len)))
+            (if (i32.lt_s (local.get $val_len) (i32.const 0)) (then (return (i32.const 1))))
+            (call $read_buffer (i32.const 4096))
+            (call $put (local.get $to) (local.get $to_len) (i32.const 4096) (local.get $val_len))
+            (call $delete (local.get $from) (local.get $from_len))
+            (call $set_result (i32.const 4096) (local.get $val_len))
+            i32.const 0)
+          ;; args: the start key, returns the encoded pairs from there
+          (func (export "scan_from") (param $args i32) (param $len i32) (result i32)
+            (local $n i32)
+            (local.set $n
+              (call $scan
+                (local.get $args) (local.get $len) (i32.const 0) (i32.const -1) (i32.const 10)))
+            (call $read_buffer (i32.const 8192))
+            (call $set_result (i32.const 8192) (local.get $n))
+            i32.const 0)
+          (func (export "spin") (param i32 i32) (result i32)
+            (loop $spin (br $spin))
+            i32.const 0)
+          (func (export "put_then_trap") (param $args i32) (param $len i32) (result i32)
+            (call $put (local.get $args) (local.get $len) (local.get $args) (local.get $len))
+            unreachable)
+          ;; Fails (status 1) when 100 more pages are refused
+          (func (export "grow") (param i32 i32) (result i32)
+            (i32.lt_s (memory.grow (i32.const 100)) (i32.const 0)))
+          (func (export "helper") (param i32) (result i32) local.get 0))
+    "#;
+
+    fn setup(dir: &TempDir) -> (Arc<StorageEngine>, ProcedureRuntime) {
+        let storage = Arc::new(
+            StorageEngine::open(
+                dir.path(),
+                &StorageConfig::default(),
+                ColumnFamilyOptions::default(),
+            )
+            .unwrap(),
+        );
+        let runtime = ProcedureRuntime::new(
+            storage.clone(),
+            &WasmConfig {
+                procedure_timeout_ms: 50,
+                procedure_max_memory_mb: 1,
+                ..WasmConfig::default()
+            },
+        )
+        .unwrap();
+        (storage, runtime)
+    }
+
+    #[test]
+    fn test_procedures_commit_atomically() {
+        let dir = TempDir::new().unwrap();
+        let (storage, runtime) = setup(&dir);
+        storage
+            .put(Bytes::from("todo"), Bytes::from("milk"))
+            .unwrap();
+
+        assert_eq!(
+            runtime.load_module("lists", LISTS.as_bytes()).unwrap(),
+            vec!["grow", "move_item", "put_then_trap", "scan_from", "spin"]
+        );
+        assert_eq!(runtime.module_names(), vec!["lists"]);
+
+        let output = runtime
+            .invoke("lists", "move_item", b"\x04tododone")
+            .unwrap();
+        assert_eq!(
+            output,
+            ProcedureOutput {
+                output: Bytes::from("milk"),
+                seq: 3,
+            }
+        );
+        assert_eq!(storage.get(&Bytes::from("todo")).unwrap(), None);
+        assert_eq!(
+            storage.get(&Bytes::from("done")).unwrap(),
+            Some(Bytes::from("milk"))
+        );
+
+        // The procedure aborts once there is nothing to move
+        assert!(matches!(
+            runtime.invoke("lists", "move_item", b"\x04tododone"),
+            Err(WasmError::Aborted { status: 1, .. })
+        ));
+
+        let output = runtime.invoke("lists", "scan_from", b"a").unwrap();
+        assert_eq!(output.output.as_ref(), b"\x04\0\0\0done\x04\0\0\0milk");
+    }
+
+    #[test]
+    fn test_failed_procedures_write_nothing() {
+        let dir = TempDir::new().unwrap();
+        let (storage, runtime) = setup(&dir);
+        runtime.load_module("lists", LISTS.as_bytes()).unwrap();
+
+        assert!(matches!(
+            runtime.invoke("lists", "spin", b""),
+            Err(WasmError::Timeout { .. })
+        ));
+        assert!(matches!(
+            runtime.invoke("lists", "put_then_trap", b"key"),
+            Err(WasmError::Trap { .. })
+        ));
+        assert_eq!(storage.get(&Bytes::from("key")).unwrap(), None);
+        // Memory can't grow past the 1 MB limit
+        assert!(matches!(
+            runtime.invoke("lists", "grow", b""),
+            Err(WasmError::Aborted { status: 1, .. })
+        ));
+        assert_eq!(storage.last_seq(), 0);
+
+        assert!(matches!(
+            runtime.invoke("lists", "helper", b""),
+            Err(WasmError::MissingExport { .. })
+        ));
+        runtime.unload_module("lists").unwrap();
+        assert!(matches!(
+            runtime.invoke("lists", "spin", b""),
+            Err(WasmError::ModuleNotFound(_))
+        ));
+
+        let no_procedure = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "boxkv_abi_version") (result i32) i32.const 1)
+              (func (export "boxkv_alloc") (param i32) (result i32) i32.const 0))
+        "#;
+        assert!(matches!(
+            runtime.load_module("empty", no_procedure.as_bytes()),
+            Err(WasmError::MissingExport { .. })
+        ));
+    }
+}
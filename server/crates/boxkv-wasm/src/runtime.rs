@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use boxkv_common::config::WasmConfig;
+use parking_lot::RwLock;
+use tracing::info;
+use wasmtime::{Config, Engine};
+
+use crate::{Result, WasmCompactionFilter, WasmError, WasmMergeOperator, WasmModule};
+
+/// Registry of the plugins loaded in the process, sharing a single compiler.
+///
+/// Modules are registered under a name, either from the configuration
+/// (`from_config()`) or at runtime (`load_module()`). Filters and operators
+/// handed out keep their module alive after it is unloaded.
+pub struct WasmRuntime {
+    engine: Engine,
+    fuel_per_call: u64,
+    max_memory_bytes: usize,
+    modules: RwLock<HashMap<String, Arc<WasmModule>>>,
+}
+
+impl WasmRuntime {
+    /// Creates a runtime without any module, applying the limits of `config`
+    /// to the modules loaded later.
+    pub fn new(config: &WasmConfig) -> Result<Self> {
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true);
+        let engine =
+            Engine::new(&engine_config).map_err(|e| WasmError::Engine(format!("{:#}", e)))?;
+        Ok(Self {
+            engine,
+            fuel_per_call: config.fuel_per_call,
+            max_memory_bytes: config.max_memory_mb.saturating_mul(1024 * 1024),
+            modules: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Creates a runtime and loads the modules listed in `config`.
+    pub fn from_config(config: &WasmConfig) -> Result<Self> {
+        let runtime = Self::new(config)?;
+        for module in &config.modules {
+            runtime.load_module_file(&module.name, &module.path)?;
+        }
+        Ok(runtime)
+    }
+
+    /// Compiles `bytes` (binary or text format) and registers it as `name`.
+    ///
+    /// # Errors
+    /// Returns `WasmError::DuplicateModule` if `name` is taken, and the errors
+    /// of a module failing the ABI checks (see the crate docs).
+    pub fn load_module(&self, name: &str, bytes: &[u8]) -> Result<Arc<WasmModule>> {
+        if self.modules.read().contains_key(name) {
+            return Err(WasmError::DuplicateModule(name.to_string()));
+        }
+        // Compiled without the lock held: this may take a while
+        let module = Arc::new(WasmModule::new(
+            &self.engine,
+            name,
+            bytes,
+            self.fuel_per_call,
+            self.max_memory_bytes,
+        )?);
+
+        let mut modules = self.modules.write();
+        if modules.contains_key(name) {
+            return Err(WasmError::DuplicateModule(name.to_string()));
+        }
+        modules.insert(name.to_string(), module.clone());
+        info!(
+            module = name,
+            filter = module.is_compaction_filter(),
+            merge = module.is_merge_operator(),
+            "WebAssembly module loaded"
+        );
+        Ok(module)
+    }
+
+    /// Reads the module at `path` and registers it as `name` (see
+    /// `load_module()`).
+    pub fn load_module_file(&self, name: &str, path: &Path) -> Result<Arc<WasmModule>> {
+        let bytes = fs::read(path).map_err(|source| WasmError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.load_module(name, &bytes)
+    }
+
+    /// Unregisters the module `name`.
+    pub fn unload_module(&self, name: &str) -> Result<Arc<WasmModule>> {
+        let module = self
+            .modules
+            .write()
+            .remove(name)
+            .ok_or_else(|| WasmError::ModuleNotFound(name.to_string()))?;
+        info!(module = name, "WebAssembly module unloaded");
+        Ok(module)
+    }
+
+    pub fn module(&self, name: &str) -> Result<Arc<WasmModule>> {
+        self.modules
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| WasmError::ModuleNotFound(name.to_string()))
+    }
+
+    /// Returns the names of the loaded modules, sorted.
+    pub fn module_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.modules.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns a compaction filter backed by the module `name`.
+    ///
+    /// # Errors
+    /// Returns `WasmError::MissingExport` if the module doesn't export
+    /// `boxkv_filter`.
+    pub fn compaction_filter(&self, name: &str) -> Result<Arc<WasmCompactionFilter>> {
+        let module = self.module(name)?;
+        if !module.is_compaction_filter() {
+            return Err(WasmError::MissingExport {
+                module: name.to_string(),
+                export: crate::module::FILTER_EXPORT.to_string(),
+            });
+        }
+        Ok(Arc::new(WasmCompactionFilter::new(module)))
+    }
+
+    /// Returns a merge operator backed by the module `name`.
+    ///
+    /// # Errors
+    /// Returns `WasmError::MissingExport` if the module doesn't export
+    /// `boxkv_full_merge`.
+    pub fn merge_operator(&self, name: &str) -> Result<Arc<WasmMergeOperator>> {
+        let module = self.module(name)?;
+        if !module.is_merge_operator() {
+            return Err(WasmError::MissingExport {
+                module: name.to_string(),
+                export: crate::module::FULL_MERGE_EXPORT.to_string(),
+            });
+        }
+        Ok(Arc::new(WasmMergeOperator::new(module)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boxkv_common::config::{StorageConfig, WasmModuleConfig};
+    use boxkv_core::compaction::{CompactionFilter, FilterDecision};
+    use boxkv_core::engine::{ColumnFamilyOptions, Engine};
+    use boxkv_core::merge::{MergeError, MergeOperator};
+    use bytes::Bytes;
+    use tempfile::TempDir;
+
+    /// Removes values starting with "x", replaces values starting with "u" by
+    /// "changed" and spins forever on values starting with "l".
+    const FILTER: &str = r#"
+        (module
+          (import "boxkv" "set_result" (func $set_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 16) "changed")
+          (func (export "boxkv_abi_version") (result i32) i32.const 1)
+          (func (export "boxkv_alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "boxkv_filter")
+            (param $level i32) (param $key i32) (param $key_len i32)
+            (param $val i32) (param $val_len i32) (result i32)
+            (local $first i32)
+            (if (i32.eqz (local.get $val_len)) (then (return (i32.const 0))))
+            (local.set $first (i32.load8_u (local.get $val)))
+            (if (i32.eq (local.get $first) (i32.const 120)) (then (return (i32.const 1))))
+            (if (i32.eq (local.get $first) (i32.const 117))
+              (then
+                (call $set_result (i32.const 16) (i32.const 7))
+                (return (i32.const 2))))
+            (if (i32.eq (local.get $first) (i32.const 108)) (then (loop $spin (br $spin))))
+            i32.const 0))
+    "#;
+
+    /// Concatenates the existing value and the operands.
+    const CONCAT: &str = r#"
+        (module
+          (import "boxkv" "set_result" (func $set_result (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "boxkv_abi_version") (result i32) i32.const 1)
+          (func (export "boxkv_alloc") (param i32) (result i32) i32.const 1024)
+          (func $full_merge (export "boxkv_full_merge")
+            (param $key i32) (param $key_len i32) (param $ex i32) (param $ex_len i32)
+            (param $ops i32) (param $ops_len i32) (result i32)
+            (local $out i32) (local $end i32) (local $len i32)
+            (local.set $out (i32.const 32768))
+            (if (i32.ge_s (local.get $ex_len) (i32.const 0))
+              (then
+                (memory.copy (local.get $out) (local.get $ex) (local.get $ex_len))
+                (local.set $out (i32.add (local.get $out) (local.get $ex_len)))))
+            (local.set $end (i32.add (local.get $ops) (local.get $ops_len)))
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (local.get $ops) (local.get $end)))
+                (local.set $len (i32.load (local.get $ops)))
+                (memory.copy
+                  (local.get $out)
+                  (i32.add (local.get $ops) (i32.const 4))
+                  (local.get $len))
+                (local.set $out (i32.add (local.get $out) (local.get $len)))
+                (local.set $ops
+                  (i32.add (local.get $ops) (i32.add (local.get $len) (i32.const 4))))
+                (br $next)))
+            (call $set_result
+              (i32.const 32768)
+              (i32.sub (local.get $out) (i32.const 32768)))
+            i32.const 0)
+          (func (export "boxkv_partial_merge")
+            (param $key i32) (param $key_len i32) (param $ops i32) (param $ops_len i32)
+            (result i32)
+            (call $full_merge
+              (local.get $key) (local.get $key_len) (i32.const 0) (i32.const -1)
+              (local.get $ops) (local.get $ops_len))))
+    "#;
+
+    fn runtime() -> WasmRuntime {
+        WasmRuntime::new(&WasmConfig {
+            fuel_per_call: 100_000,
+            max_memory_mb: 1,
+            ..WasmConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_compaction_filter() {
+        let runtime = runtime();
+        runtime.load_module("filter", FILTER.as_bytes()).unwrap();
+        let filter = runtime.compaction_filter("filter").unwrap();
+        assert_eq!(filter.name(), "wasm.filter");
+
+        assert_eq!(filter.filter(1, b"k", b"value"), FilterDecision::Keep);
+        assert_eq!(filter.filter(1, b"k", b""), FilterDecision::Keep);
+        assert_eq!(filter.filter(1, b"k", b"xdead"), FilterDecision::Remove);
+        assert_eq!(
+            filter.filter(1, b"k", b"update"),
+            FilterDecision::ChangeValue(Bytes::from("changed"))
+        );
+
+        // Running out of fuel fails the call, which keeps the value
+        assert!(matches!(
+            filter.try_filter(1, b"k", b"loop"),
+            Err(WasmError::OutOfFuel { .. })
+        ));
+        assert_eq!(filter.filter(1, b"k", b"loop"), FilterDecision::Keep);
+        // A fresh instance serves the next calls
+        assert_eq!(filter.filter(1, b"k", b"xdead"), FilterDecision::Remove);
+
+        assert!(matches!(
+            runtime.merge_operator("filter"),
+            Err(WasmError::MissingExport { .. })
+        ));
+    }
+
+    #[test]
+    fn test_merge_operator() {
+        let runtime = runtime();
+        runtime.load_module("concat", CONCAT.as_bytes()).unwrap();
+        let operator = runtime.merge_operator("concat").unwrap();
+        assert_eq!(operator.name(), "wasm.concat");
+
+        let operands = [Bytes::from("b"), Bytes::from(""), Bytes::from("cd")];
+        assert_eq!(
+            operator.full_merge(b"k", Some(b"a"), &operands).unwrap(),
+            Bytes::from("abcd")
+        );
+        assert_eq!(
+            operator.full_merge(b"k", None, &operands).unwrap(),
+            Bytes::from("bcd")
+        );
+        assert_eq!(
+            operator.partial_merge(b"k", &operands).unwrap(),
+            Some(Bytes::from("bcd"))
+        );
+
+        // Inputs past the memory of the instance fail the merge
+        let huge = [Bytes::from(vec![0u8; 128 * 1024])];
+        assert!(matches!(
+            operator.full_merge(b"k", None, &huge),
+            Err(MergeError::InvalidOperand { .. })
+        ));
+
+        let dir = TempDir::new().unwrap();
+        let engine = Engine::open(
+            dir.path(),
+            &StorageConfig::default(),
+            ColumnFamilyOptions::default().with_merge_operator(operator),
+        )
+        .unwrap();
+        engine.put(Bytes::from("k"), Bytes::from("1")).unwrap();
+        engine.merge(Bytes::from("k"), Bytes::from("2")).unwrap();
+        engine.merge(Bytes::from("k"), Bytes::from("3")).unwrap();
+        assert_eq!(
+            engine.get(&Bytes::from("k")).unwrap(),
+            Some(Bytes::from("123"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_modules_are_rejected() {
+        let runtime = runtime();
+        let version_2 = FILTER.replace("(result i32) i32.const 1)", "(result i32) i32.const 2)");
+        assert!(matches!(
+            runtime.load_module("v2", version_2.as_bytes()),
+            Err(WasmError::AbiVersion {
+                found: 2,
+                expected: 1,
+                ..
+            })
+        ));
+
+        let no_callback = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "boxkv_abi_version") (result i32) i32.const 1)
+              (func (export "boxkv_alloc") (param i32) (result i32) i32.const 0))
+        "#;
+        assert!(matches!(
+            runtime.load_module("empty", no_callback.as_bytes()),
+            Err(WasmError::MissingExport { .. })
+        ));
+
+        let unknown_import = r#"(module (import "env" "exit" (func (param i32))))"#;
+        assert!(matches!(
+            runtime.load_module("import", unknown_import.as_bytes()),
+            Err(WasmError::Compile { .. })
+        ));
+        assert!(matches!(
+            runtime.load_module("garbage", b"\0asm garbage"),
+            Err(WasmError::Compile { .. })
+        ));
+
+        // 32 pages (2 MB) are above the 1 MB limit
+        let too_big = FILTER.replace(
+            "(memory (export \"memory\") 1)",
+            "(memory (export \"memory\") 32)",
+        );
+        assert!(matches!(
+            runtime.load_module("big", too_big.as_bytes()),
+            Err(WasmError::Trap { .. })
+        ));
+
+        assert!(runtime.module_names().is_empty());
+    }
+
+    #[test]
+    fn test_modules_load_from_config() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("filter.wat"), FILTER).unwrap();
+        fs::write(dir.path().join("concat.wat"), CONCAT).unwrap();
+        let module = |name: &str| WasmModuleConfig {
+            name: name.to_string(),
+            path: dir.path().join(format!("{}.wat", name)),
+        };
+
+        let runtime = WasmRuntime::from_config(&WasmConfig {
+            modules: vec![module("filter"), module("concat")],
+            ..WasmConfig::default()
+        })
+        .unwrap();
+        assert_eq!(runtime.module_names(), vec!["concat", "filter"]);
+        assert!(matches!(
+            runtime.load_module("filter", FILTER.as_bytes()),
+            Err(WasmError::DuplicateModule(_))
+        ));
+
+        let filter = runtime.compaction_filter("filter").unwrap();
+        runtime.unload_module("filter").unwrap();
+        assert!(matches!(
+            runtime.compaction_filter("filter"),
+            Err(WasmError::ModuleNotFound(_))
+        ));
+        // Still usable after being unloaded
+        assert_eq!(filter.filter(1, b"k", b"xdead"), FilterDecision::Remove);
+
+        assert!(matches!(
+            WasmRuntime::from_config(&WasmConfig {
+                modules: vec![module("missing")],
+                ..WasmConfig::default()
+            }),
+            Err(WasmError::Io { .. })
+        ));
+    }
+}